@@ -0,0 +1,33 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use geneva::{Direction, Packet, Strategy};
+
+/// Builds an IPv4 packet carrying a TCP segment with the given flags and a 1400-byte payload.
+fn tcp_packet(flags: u8) -> Packet {
+    let mut bytes = vec![
+        0x45, 0x00, 0x05, 0xa0, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1, 10, 0,
+        0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00, 0x50, flags,
+        0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+    ];
+    bytes.resize(1440, 0);
+    Packet::new(bytes)
+}
+
+fn pass_through(c: &mut Criterion) {
+    let strategy: Strategy = r#"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1024},)-| \/"#
+        .parse()
+        .unwrap();
+    let ack = tcp_packet(0x10);
+
+    let mut group = c.benchmark_group("pass_through");
+    group.bench_function("apply", |b| {
+        b.iter(|| strategy.apply(black_box(ack.clone()), Direction::Outbound))
+    });
+    group.bench_function("apply_ref", |b| {
+        b.iter(|| strategy.apply_ref(black_box(&ack), Direction::Outbound))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pass_through);
+criterion_main!(benches);
@@ -0,0 +1,134 @@
+//! Applies a Geneva strategy to every packet of a capture read from stdin, and writes the
+//! resulting packets to stdout as a new capture.
+//!
+//! The first argument is the strategy; the optional second argument is the direction to apply it
+//! in, either `outbound` (the default) or `inbound`. The input may be in the pcap or pcapng
+//! format, but the output is always a raw IP pcap. For instance, this splits the HTTP request in
+//! the `request.pcap` fixture into two segments, sending the second one first:
+//!
+//! ```text
+//! cargo run --features pcap --example pcap_filter -- \
+//!     '[TCP:flags:PA]-fragment{tcp:8:False}-| \/' \
+//!     < geneva/testdata/request.pcap > fragmented.pcap
+//! ```
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+use geneva::pcap::{read_packets, write_packets};
+use geneva::{Direction, Strategy};
+
+/// Runs every packet read from `input` through the strategy, writing the results to `output`.
+fn filter(
+    strategy: &Strategy,
+    direction: Direction,
+    input: impl Read,
+    output: impl Write,
+) -> geneva::Result<()> {
+    let packets = strategy.process(read_packets(input)?, direction)?;
+    write_packets(output, &packets)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let (strategy, direction) = match &args[1..] {
+        [strategy] => (strategy, "outbound"),
+        [strategy, direction] => (strategy, direction.as_str()),
+        _ => {
+            eprintln!(
+                "usage: {} <strategy> [outbound|inbound] < in.pcap > out.pcap",
+                args[0]
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let direction = match direction {
+        "outbound" => Direction::Outbound,
+        "inbound" => Direction::Inbound,
+        _ => {
+            eprintln!("unknown direction: {}", direction);
+            return ExitCode::FAILURE;
+        }
+    };
+    let strategy: Strategy = match strategy.parse() {
+        Ok(strategy) => strategy,
+        Err(e) => {
+            eprintln!("invalid strategy: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let stdout = io::BufWriter::new(io::stdout().lock());
+    match filter(&strategy, direction, io::stdin().lock(), stdout) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::Path;
+
+    use super::*;
+
+    /// Runs the fixture with a SYN, an ACK, and an HTTP request through the strategy, returning
+    /// the packets written out.
+    fn run(strategy: &str, direction: Direction) -> Vec<geneva::Packet> {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/request.pcap");
+        let mut output = vec![];
+        let strategy = strategy.parse().unwrap();
+        filter(
+            &strategy,
+            direction,
+            File::open(fixture).unwrap(),
+            &mut output,
+        )
+        .unwrap();
+        read_packets(&output[..]).unwrap()
+    }
+
+    #[test]
+    fn unmatched_packets_pass_through() {
+        let packets = run(r"[TCP:flags:R]-drop-| \/", Direction::Outbound);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(run(r"[TCP:flags:S]-drop-| \/", Direction::Inbound), packets);
+    }
+
+    #[test]
+    fn fragments_are_written_in_order() {
+        let strategy = r"[TCP:flags:PA]-fragment{tcp:8:False}(tamper{TCP:window:replace:7},)-| \/";
+        let packets = run(strategy, Direction::Outbound);
+        assert_eq!(packets.len(), 4);
+
+        // The request is split after eight bytes, and the second segment is written first.
+        assert_eq!(
+            packets[2].payload(),
+            Some(&b"TP/1.1\r\nHost: example.com\r\n\r\n"[..])
+        );
+        assert_eq!(packets[3].payload(), Some(&b"GET / HT"[..]));
+        assert_eq!(
+            &packets[2].tcp_header().unwrap()[14..16],
+            &7u16.to_be_bytes()
+        );
+        assert_ne!(
+            &packets[3].tcp_header().unwrap()[14..16],
+            &7u16.to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn duplicates_follow_the_original() {
+        let packets = run(
+            r"[TCP:flags:S]-duplicate(,tamper{TCP:flags:add:R})-| \/",
+            Direction::Outbound,
+        );
+        assert_eq!(packets.len(), 4);
+        assert_eq!(packets[0].tcp_header().unwrap()[13], 0x02);
+        assert_eq!(packets[1].tcp_header().unwrap()[13], 0x06);
+    }
+}
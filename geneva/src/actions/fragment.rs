@@ -1,9 +1,12 @@
-use std::fmt;
+use core::fmt;
 
+use crate::checksum::update_ipv4_checksum;
 use crate::errors::*;
-use crate::Packet;
+use crate::packet::{IP_FRAG_OFFSET, IP_MORE_FRAGMENTS, PROTO_IPV4, PROTO_TCP, PROTO_UDP};
+use crate::prelude::*;
+use crate::{Packet, PacketMeta};
 
-use super::{Action, GenevaAction};
+use super::{fix_lengths, Action, GenevaAction};
 
 /// An [Action] that takes the original packet and fragments it, then applies separate `Action`s to
 /// each fragment.
@@ -18,7 +21,26 @@ use super::{Action, GenevaAction};
 /// payload. The second fragment will contain the other 52 bytes. (You can also indicate that the
 /// fragments be returned out-of-order; i.e., reversed, by specifying "False" for the _inOrder_
 /// argument in the syntax above.)
-#[derive(Debug, Clone)]
+///
+/// `a1` is given whichever fragment is sent first, which is the second one if _inOrder_ is false,
+/// and the packets it yields are all returned before those of `a2`. Each fragment's
+/// [order_hint](PacketMeta::order_hint) still says which piece of the original it is.
+///
+/// A TCP segment with no payload, or whose payload is exactly _offset_ bytes long, is passed to
+/// `a1` as it is, but an offset of zero, or one past the end of the payload, is an error.
+///
+/// UDP has no segmentation of its own, so fragmenting a UDP datagram splits it into two IP
+/// fragments instead: the first carries the UDP header and _offset_ bytes of its payload, and the
+/// second carries the rest. IP fragment offsets count eight-byte blocks, so _offset_ must be a
+/// multiple of eight. The UDP header is left as it is, since its length and checksum cover the
+/// whole datagram once it has been reassembled.
+///
+/// Fragmenting at the IP layer (protocol 4) splits whatever the IPv4 header carries, transport
+/// header included, with _offset_ rounded down to a multiple of eight; an offset below eight is an
+/// error. A datagram that is already a fragment can be split again, since the second piece carries
+/// on from the original's fragment offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FragmentAction {
     protocol: u16,
     fragment_size: u16,
@@ -28,8 +50,26 @@ pub struct FragmentAction {
     right_action: Box<GenevaAction>,
 }
 
+/// Returns an error if `protocol` isn't one of the layers that `fragment` can split.
+fn check_protocol(protocol: u16) -> Result<()> {
+    if protocol > 0xff || ![PROTO_IPV4, PROTO_TCP, PROTO_UDP].contains(&(protocol as u8)) {
+        return Err(invalid_protocol(protocol));
+    }
+    Ok(())
+}
+
+/// Returns the error for a `fragment` of a layer that it can't split.
+fn invalid_protocol(protocol: u16) -> Error {
+    Error::InvalidValue {
+        field: "fragment:protocol".to_string(),
+        value: protocol.to_string(),
+    }
+}
+
 impl FragmentAction {
     /// Creates a new `FragmentAction`.
+    ///
+    /// Returns an error if `protocol` is not the protocol number of IPv4, TCP, or UDP.
     pub fn new(
         protocol: u16,
         fragment_size: u16,
@@ -38,7 +78,7 @@ impl FragmentAction {
         left_action: GenevaAction,
         right_action: GenevaAction,
     ) -> Result<Self> {
-        // XXX: need to check values for correctness
+        check_protocol(protocol)?;
         Ok(Self {
             protocol,
             fragment_size,
@@ -48,11 +88,304 @@ impl FragmentAction {
             right_action: Box::new(right_action),
         })
     }
+
+    /// Returns an error if the action's protocol can't be fragmented, as can happen when it was
+    /// deserialized rather than built with [new](Self::new), or if running it would fail whatever
+    /// the packet: an offset of zero, a UDP offset that isn't a multiple of eight, or an IP offset
+    /// below eight.
+    pub(crate) fn validate(&self) -> Result<()> {
+        check_protocol(self.protocol)?;
+        let size = self.fragment_size;
+        let valid = match self.protocol as u8 {
+            PROTO_UDP => size > 0 && size.is_multiple_of(8),
+            PROTO_IPV4 => ip_fragment_offset(size) > 0,
+            _ => size > 0,
+        };
+        if !valid {
+            return Err(Error::InvalidValue {
+                field: "fragment:offset".to_string(),
+                value: size.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns how many bytes of the payload go into the first fragment.
+    pub(crate) fn fragment_size(&self) -> u16 {
+        self.fragment_size
+    }
+
+    /// Returns the actions applied to the first and second fragments.
+    pub(crate) fn children(&self) -> Vec<&GenevaAction> {
+        vec![&self.left_action, &self.right_action]
+    }
+
+    /// Returns mutable references to the actions applied to the first and second fragments.
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut GenevaAction> {
+        vec![&mut self.left_action, &mut self.right_action]
+    }
+}
+
+impl FragmentAction {
+    /// Returns how many packets this action would yield for `pkt`; see [GenevaAction::fanout].
+    pub(crate) fn fanout(&self, pkt: &Packet) -> usize {
+        let longer_than = |data: Option<&[u8]>, len| data.is_some_and(|data| data.len() > len);
+        let size = self.fragment_size as usize;
+        let splits = pkt.ip_version() == Some(4)
+            && match self.protocol {
+                p if p == PROTO_TCP as u16 => {
+                    pkt.tcp_header().is_some() && longer_than(pkt.payload(), size)
+                }
+                p if p == PROTO_UDP as u16 => {
+                    pkt.udp_header().is_some() && longer_than(pkt.payload(), size)
+                }
+                p if p == PROTO_IPV4 as u16 => {
+                    longer_than(pkt.ip_payload(), ip_fragment_offset(self.fragment_size))
+                }
+                _ => false,
+            };
+        if splits {
+            self.left_action
+                .fanout(pkt)
+                .saturating_add(self.right_action.fanout(pkt))
+        } else {
+            self.left_action.fanout(pkt)
+        }
+    }
+
+    /// Splits a TCP segment's payload after `fragment_size` bytes, producing two segments that
+    /// each carry a full copy of the IP and TCP headers. Returns `Ok(None)` if the packet is not a
+    /// TCP segment carried by IPv4, if it has no payload, or if its payload is exactly
+    /// `fragment_size` bytes long and so has nothing to put in the second segment.
+    ///
+    /// Returns an error if `fragment_size` is zero or longer than the payload, since either would
+    /// leave one of the segments with nothing in it.
+    fn tcp_segment(&self, pkt: &Packet) -> Result<Option<(Packet, Packet)>> {
+        if pkt.ip_version() != Some(4) {
+            return Ok(None);
+        }
+
+        let (link_header, ip_header, tcp_header, payload) = match (
+            pkt.link_header(),
+            pkt.ip_header(),
+            pkt.tcp_header(),
+            pkt.payload(),
+        ) {
+            (Some(link_header), Some(ip_header), Some(tcp_header), Some(payload)) => {
+                (link_header, ip_header, tcp_header, payload)
+            }
+            _ => return Ok(None),
+        };
+        let headers = [link_header, ip_header, tcp_header];
+
+        let offset = self.fragment_size as usize;
+        if payload.is_empty() {
+            return Ok(None);
+        }
+        if offset == 0 || offset > payload.len() {
+            return Err(Error::InvalidValue {
+                field: "fragment:offset".to_string(),
+                value: format!("{} (for a {}-byte payload)", offset, payload.len()),
+            });
+        }
+        if offset == payload.len() {
+            return Ok(None);
+        }
+
+        let seq = u32::from_be_bytes([tcp_header[4], tcp_header[5], tcp_header[6], tcp_header[7]]);
+
+        // Both segments keep the original's metadata, apart from where they belong in it.
+        let meta = |order_hint| PacketMeta {
+            order_hint: Some(order_hint),
+            ..pkt.meta().clone()
+        };
+        let first = build_segment(pkt, headers, seq, &payload[..offset], meta(0));
+        let second = build_segment(
+            pkt,
+            headers,
+            seq.wrapping_add(offset as u32),
+            &payload[offset..],
+            meta(1),
+        );
+        Ok(Some((first, second)))
+    }
+
+    /// Splits a UDP datagram into two IPv4 fragments, the first holding the UDP header and
+    /// `fragment_size` bytes of its payload. Returns `Ok(None)` in the same cases as
+    /// [tcp_segment](Self::tcp_segment), and an error in the same cases or if `fragment_size`
+    /// isn't a multiple of eight.
+    fn udp_fragment(&self, pkt: &Packet) -> Result<Option<(Packet, Packet)>> {
+        if pkt.ip_version() != Some(4) {
+            return Ok(None);
+        }
+
+        let (udp_header, payload) = match (pkt.udp_header(), pkt.payload()) {
+            (Some(udp_header), Some(payload)) => (udp_header, payload),
+            _ => return Ok(None),
+        };
+
+        let offset = self.fragment_size as usize;
+        if payload.is_empty() {
+            return Ok(None);
+        }
+        if offset == 0 || offset > payload.len() || !offset.is_multiple_of(8) {
+            return Err(Error::InvalidValue {
+                field: "fragment:offset".to_string(),
+                value: format!(
+                    "{} (for a {}-byte UDP payload; it must be a multiple of 8)",
+                    offset,
+                    payload.len()
+                ),
+            });
+        }
+        if offset == payload.len() {
+            return Ok(None);
+        }
+
+        Ok(Some(split_datagram(pkt, udp_header.len() + offset)))
+    }
+
+    /// Splits an IPv4 datagram into two fragments, the first holding `fragment_size` bytes of the
+    /// IP payload, rounded down to a multiple of eight. Unlike the other protocols, whatever the
+    /// IP header carries is split, so a datagram that is already a fragment can be split again.
+    /// Returns `Ok(None)` if the packet isn't IPv4, if its IP payload is empty, or if the payload
+    /// is exactly as long as the first fragment would be.
+    ///
+    /// Returns an error if `fragment_size` is less than eight or longer than the IP payload.
+    fn ip_fragment(&self, pkt: &Packet) -> Result<Option<(Packet, Packet)>> {
+        let data = match pkt.ip_payload() {
+            Some(data) if pkt.ip_version() == Some(4) && !data.is_empty() => data,
+            _ => return Ok(None),
+        };
+
+        let offset = ip_fragment_offset(self.fragment_size);
+        if offset == 0 || offset > data.len() {
+            return Err(Error::InvalidValue {
+                field: "fragment:offset".to_string(),
+                value: format!(
+                    "{} (for a {}-byte IP payload; it is rounded down to a multiple of 8)",
+                    self.fragment_size,
+                    data.len()
+                ),
+            });
+        }
+        if offset == data.len() {
+            return Ok(None);
+        }
+        Ok(Some(split_datagram(pkt, offset)))
+    }
+}
+
+/// Returns where an IP `fragment` with the given size splits the IP payload: IP fragment offsets
+/// count eight-byte blocks, so the size is rounded down to a multiple of eight.
+fn ip_fragment_offset(fragment_size: u16) -> usize {
+    fragment_size as usize / 8 * 8
+}
+
+/// Splits the IP payload of an IPv4 packet after `at` bytes, which must be a multiple of eight,
+/// into two fragments. The first has the more-fragments flag set; the second carries on from where
+/// the first left off and keeps the original's flag, so that a fragment can be split again. Both
+/// keep the packet's other flags and its metadata, apart from where they belong in it.
+///
+/// The packet must have an IPv4 header and more than `at` bytes of IP payload.
+fn split_datagram(pkt: &Packet, at: usize) -> (Packet, Packet) {
+    let link_header = pkt.link_header().unwrap_or_default();
+    let ip_header = pkt.ip_header().unwrap_or_default();
+    let data = pkt.ip_payload().unwrap_or_default();
+
+    let word = u16::from_be_bytes([ip_header[6], ip_header[7]]);
+    let (flags, offset) = (word & !IP_FRAG_OFFSET, word & IP_FRAG_OFFSET);
+    let meta = |order_hint| PacketMeta {
+        order_hint: Some(order_hint),
+        ..pkt.meta().clone()
+    };
+    let first = build_fragment(
+        pkt,
+        [link_header, ip_header],
+        flags | IP_MORE_FRAGMENTS | offset,
+        &data[..at],
+        meta(0),
+    );
+    let second = build_fragment(
+        pkt,
+        [link_header, ip_header],
+        flags | (offset + (at / 8) as u16),
+        &data[at..],
+        meta(1),
+    );
+    (first, second)
+}
+
+/// Assembles a packet with the given metadata, and the link type of `original`, from the given
+/// link-layer, IPv4, and TCP headers and payload, setting the sequence number and fixing up the IP
+/// total length and both checksums.
+fn build_segment(
+    original: &Packet,
+    [link_header, ip_header, tcp_header]: [&[u8]; 3],
+    seq: u32,
+    payload: &[u8],
+    meta: PacketMeta,
+) -> Packet {
+    let seq_at = link_header.len() + ip_header.len() + 4;
+    let mut bytes = [link_header, ip_header, tcp_header, payload].concat();
+    bytes[seq_at..seq_at + 4].copy_from_slice(&seq.to_be_bytes());
+
+    let mut pkt = original.with_bytes(bytes);
+    *pkt.meta_mut() = meta;
+    fix_lengths(&mut pkt);
+    pkt
+}
+
+/// Assembles an IPv4 fragment with the given metadata, and the link type of `original`, from the
+/// given link-layer and IPv4 headers and data, setting the flags and fragment offset field to
+/// `frag` and fixing up the total length and header checksum.
+fn build_fragment(
+    original: &Packet,
+    [link_header, ip_header]: [&[u8]; 2],
+    frag: u16,
+    data: &[u8],
+    meta: PacketMeta,
+) -> Packet {
+    let ip_at = link_header.len();
+    let mut bytes = [link_header, ip_header, data].concat();
+    let total_len = (ip_header.len() + data.len()) as u16;
+    bytes[ip_at + 2..ip_at + 4].copy_from_slice(&total_len.to_be_bytes());
+    bytes[ip_at + 6..ip_at + 8].copy_from_slice(&frag.to_be_bytes());
+
+    let mut pkt = original.with_bytes(bytes);
+    *pkt.meta_mut() = meta;
+    update_ipv4_checksum(&mut pkt);
+    pkt
 }
 
 impl Action for FragmentAction {
-    fn run(&self, _pkt: Packet) -> Result<Vec<Packet>> {
-        unimplemented!()
+    fn run(&self, pkt: Packet) -> Result<Vec<Packet>> {
+        let fragments = match self.protocol {
+            p if p == PROTO_TCP as u16 => self.tcp_segment(&pkt)?,
+            p if p == PROTO_UDP as u16 => self.udp_fragment(&pkt)?,
+            p if p == PROTO_IPV4 as u16 => self.ip_fragment(&pkt)?,
+            _ => return Err(invalid_protocol(self.protocol)),
+        };
+
+        // A packet that can't be fragmented is passed to the left action as-is.
+        let (first, second) = match fragments {
+            Some(fragments) => fragments,
+            None => return self.left_action.run(pkt),
+        };
+
+        let (first, second) = if self.in_order {
+            (first, second)
+        } else {
+            (second, first)
+        };
+
+        let mut result = self.left_action.run(first)?;
+        result.append(&mut self.right_action.run(second)?);
+        Ok(result)
+    }
+
+    fn may_drop(&self) -> bool {
+        self.left_action.may_drop() || self.right_action.may_drop()
     }
 }
 
@@ -76,10 +409,143 @@ impl fmt::Display for FragmentAction {
     }
 }
 
+impl From<FragmentAction> for GenevaAction {
+    fn from(a: FragmentAction) -> Self {
+        Self::Fragment(a)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::actions::{DropAction, SendAction};
+    use crate::checksum::{ipv4_checksum, tcp_checksum};
+
+    /// Builds an IPv4 packet carrying a TCP segment with sequence number 1000 and the given
+    /// payload.
+    fn tcp_packet(payload: &[u8]) -> Packet {
+        let ip_header = [
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        let tcp_header = [
+            0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00, 0x50, 0x18,
+            0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let mut pkt = Packet::new([&ip_header[..], &tcp_header, payload].concat());
+        fix_lengths(&mut pkt);
+        pkt
+    }
+
+    fn segment(offset: u16, in_order: bool) -> FragmentAction {
+        FragmentAction::new(
+            6,
+            offset,
+            in_order,
+            0,
+            SendAction::default().into(),
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    fn seq(pkt: &Packet) -> u32 {
+        let b = &pkt.tcp_header().unwrap()[4..8];
+        u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+    }
+
+    /// Builds an IPv4 packet carrying a DNS query for `example.com`, with the UDP length and
+    /// checksum filled in.
+    fn dns_query() -> Packet {
+        let ip_header = [
+            0x45, 0x00, 0x00, 0x00, 0x12, 0x34, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        let udp_header = [0xd4, 0x31, 0x00, 0x35, 0x00, 0x00, 0x00, 0x00];
+        let query = [
+            &[
+                0xab, 0xcd, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ][..],
+            b"\x07example\x03com\x00",
+            &[0x00, 0x01, 0x00, 0x01],
+        ]
+        .concat();
+        let mut pkt = Packet::new([&ip_header[..], &udp_header, &query].concat());
+        fix_lengths(&mut pkt);
+        pkt
+    }
+
+    fn udp_fragment(offset: u16, in_order: bool) -> FragmentAction {
+        FragmentAction::new(
+            17,
+            offset,
+            in_order,
+            0,
+            SendAction::default().into(),
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    fn ip_fragment(offset: u16, in_order: bool) -> FragmentAction {
+        FragmentAction::new(
+            4,
+            offset,
+            in_order,
+            0,
+            SendAction::default().into(),
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    /// Builds a [tcp_packet] without the don't-fragment flag, so that [reassemble] gives it back.
+    fn fragmentable_tcp_packet(payload: &[u8]) -> Packet {
+        let mut pkt = tcp_packet(payload);
+        pkt.as_mut_slice()[6] = 0;
+        update_ipv4_checksum(&mut pkt);
+        pkt
+    }
+
+    /// Puts IPv4 fragments back together the way a receiver would, checking that they fit, and
+    /// returns the reassembled datagram.
+    fn reassemble(fragments: &[Packet]) -> Vec<u8> {
+        let frag = |pkt: &Packet| {
+            let b = &pkt.as_slice()[6..8];
+            u16::from_be_bytes([b[0], b[1]])
+        };
+        let mut fragments: Vec<_> = fragments.iter().collect();
+        fragments.sort_by_key(|pkt| frag(pkt) & IP_FRAG_OFFSET);
+
+        let mut reassembled = fragments[0].as_slice()[..20].to_vec();
+        for (i, fragment) in fragments.iter().enumerate() {
+            let (ip_header, data) = fragment.as_slice().split_at(20);
+            assert_eq!(ipv4_checksum(ip_header), 0);
+            assert_eq!(ip_header[2..4], (fragment.len() as u16).to_be_bytes());
+            assert_eq!(
+                (frag(fragment) & IP_FRAG_OFFSET) as usize * 8,
+                reassembled.len() - 20
+            );
+            let last = i == fragments.len() - 1;
+            assert_eq!(frag(fragment) & IP_MORE_FRAGMENTS == 0, last);
+            reassembled.extend_from_slice(data);
+        }
+
+        // What's left of the header is the original's, apart from the fields fragmentation sets.
+        let len = reassembled.len() as u16;
+        reassembled[2..4].copy_from_slice(&len.to_be_bytes());
+        reassembled[6..8].copy_from_slice(&[0, 0]);
+        reassembled[10..12].copy_from_slice(&[0, 0]);
+        let checksum = ipv4_checksum(&reassembled[..20]);
+        reassembled[10..12].copy_from_slice(&checksum.to_be_bytes());
+        reassembled
+    }
+
+    fn assert_checksums_valid(pkt: &Packet) {
+        let (ip_header, segment) = pkt.as_slice().split_at(20);
+        assert_eq!(ipv4_checksum(ip_header), 0);
+        assert_eq!(tcp_checksum(ip_header, segment), 0);
+    }
 
     #[test]
     fn fragment_str() {
@@ -99,13 +565,374 @@ mod tests {
         a.in_order = false;
         assert_eq!(a.to_string(), "fragment{6:12:False}");
 
-        a.left_action = Box::new(DropAction::default().into());
+        *a.left_action = DropAction::default().into();
         assert_eq!(a.to_string(), "fragment{6:12:False}(drop,)");
 
-        a.right_action = Box::new(DropAction::default().into());
+        *a.right_action = DropAction::default().into();
         assert_eq!(a.to_string(), "fragment{6:12:False}(drop,drop)");
 
-        a.left_action = Box::new(SendAction::default().into());
+        *a.left_action = SendAction::default().into();
         assert_eq!(a.to_string(), "fragment{6:12:False}(,drop)");
     }
+
+    #[test]
+    fn invalid_protocols_are_rejected() {
+        let new = |protocol| {
+            FragmentAction::new(
+                protocol,
+                8,
+                true,
+                0,
+                SendAction::default().into(),
+                SendAction::default().into(),
+            )
+        };
+        for protocol in [4, 6, 17] {
+            assert!(new(protocol).is_ok(), "{}", protocol);
+        }
+        for protocol in [0, 1, 41, 262] {
+            let result = new(protocol);
+            assert!(
+                matches!(result, Err(Error::InvalidValue { .. })),
+                "{}",
+                protocol
+            );
+        }
+    }
+
+    #[test]
+    fn validate_agrees_with_run() {
+        let action = |protocol, offset| FragmentAction {
+            protocol,
+            fragment_size: offset,
+            in_order: true,
+            _overlap: 0,
+            left_action: Box::new(SendAction::default().into()),
+            right_action: Box::new(SendAction::default().into()),
+        };
+        for (protocol, offset, valid) in [
+            (6, 8, true),
+            (6, 0, false),
+            (17, 8, true),
+            (17, 0, false),
+            (17, 12, false),
+            (4, 12, true),
+            (4, 7, false),
+            (4, 0, false),
+            (1, 8, false),
+        ] {
+            let action = action(protocol, offset);
+            assert_eq!(action.validate().is_ok(), valid, "{}", action);
+            // The packets are long enough that only the action itself can be at fault.
+            let pkt = match protocol {
+                17 => dns_query(),
+                _ => fragmentable_tcp_packet(&[0; 40]),
+            };
+            assert_eq!(action.run(pkt).is_ok(), valid, "{}", action);
+        }
+    }
+
+    #[test]
+    fn tcp_segment_in_order() {
+        let payload: Vec<u8> = (0..40).collect();
+        let result = segment(16, true).run(tcp_packet(&payload)).unwrap();
+        assert_eq!(result.len(), 2);
+
+        assert_eq!(seq(&result[0]), 1000);
+        assert_eq!(seq(&result[1]), 1016);
+
+        assert_eq!(result[0].len(), 40 + 16);
+        assert_eq!(result[1].len(), 40 + 24);
+        assert_eq!(result[0].as_slice()[2..4], 56u16.to_be_bytes());
+        assert_eq!(result[1].as_slice()[2..4], 64u16.to_be_bytes());
+
+        assert_eq!(result[0].as_slice()[40..], payload[..16]);
+        assert_eq!(result[1].as_slice()[40..], payload[16..]);
+
+        assert_checksums_valid(&result[0]);
+        assert_checksums_valid(&result[1]);
+    }
+
+    #[test]
+    fn tcp_segment_out_of_order() {
+        let payload: Vec<u8> = (0..40).collect();
+        let result = segment(16, false).run(tcp_packet(&payload)).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(seq(&result[0]), 1016);
+        assert_eq!(seq(&result[1]), 1000);
+
+        // The order hints still say where each segment came from.
+        assert_eq!(result[0].meta().order_hint, Some(1));
+        assert_eq!(result[1].meta().order_hint, Some(0));
+    }
+
+    #[test]
+    fn duplicate_of_fragment_order() {
+        use crate::actions::DuplicateAction;
+
+        let payload: Vec<u8> = (0..40).collect();
+        let pkt = tcp_packet(&payload);
+
+        // duplicate(fragment{6:16:inOrder}(send,send),send)
+        for (in_order, seqs, hints) in [
+            (true, [1000, 1016, 1000], [Some(0), Some(1), None]),
+            (false, [1016, 1000, 1000], [Some(1), Some(0), None]),
+        ] {
+            let a =
+                DuplicateAction::new(segment(16, in_order).into(), SendAction::default().into());
+            let result = a.run(pkt.clone()).unwrap();
+            assert_eq!(result.iter().map(seq).collect::<Vec<_>>(), seqs);
+            let order_hints: Vec<_> = result.iter().map(|p| p.meta().order_hint).collect();
+            assert_eq!(order_hints, hints);
+            assert_eq!(result[2], pkt);
+        }
+
+        // The right subtree's fragments come after the left subtree's packet.
+        let a = DuplicateAction::new(SendAction::default().into(), segment(16, true).into());
+        let result = a.run(pkt.clone()).unwrap();
+        assert_eq!(
+            result.iter().map(seq).collect::<Vec<_>>(),
+            [1000, 1000, 1016]
+        );
+        assert_eq!(result[0], pkt);
+    }
+
+    #[test]
+    fn delayed_fragments_keep_their_place() {
+        use crate::actions::{DuplicateAction, SleepAction};
+        use core::time::Duration;
+
+        // duplicate(fragment{6:16:False}(sleep{1},send),send)
+        let fragment = FragmentAction::new(
+            6,
+            16,
+            false,
+            0,
+            SleepAction::new(Duration::from_secs(1), SendAction::default().into()).into(),
+            SendAction::default().into(),
+        )
+        .unwrap();
+        let a = DuplicateAction::new(fragment.into(), SendAction::default().into());
+
+        let payload: Vec<u8> = (0..40).collect();
+        let result = a.run(tcp_packet(&payload)).unwrap();
+        assert_eq!(
+            result.iter().map(seq).collect::<Vec<_>>(),
+            [1016, 1000, 1000]
+        );
+        let delays: Vec<_> = result.iter().map(|p| p.meta().delay).collect();
+        assert_eq!(delays, [Some(Duration::from_secs(1)), None, None]);
+    }
+
+    #[test]
+    fn tcp_segment_keeps_metadata() {
+        let payload: Vec<u8> = (0..40).collect();
+        let mut pkt = tcp_packet(&payload);
+        pkt.meta_mut().delay = Some(core::time::Duration::from_secs(1));
+
+        let result = segment(16, true).run(pkt).unwrap();
+        for (i, fragment) in result.iter().enumerate() {
+            assert_eq!(fragment.meta().order_hint, Some(i as u32));
+            assert_eq!(
+                fragment.meta().delay,
+                Some(core::time::Duration::from_secs(1))
+            );
+        }
+    }
+
+    #[test]
+    fn tcp_segment_dispatches_actions() {
+        let payload: Vec<u8> = (0..40).collect();
+        let a = FragmentAction::new(
+            6,
+            16,
+            true,
+            0,
+            DropAction::default().into(),
+            SendAction::default().into(),
+        )
+        .unwrap();
+        let result = a.run(tcp_packet(&payload)).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(seq(&result[0]), 1016);
+    }
+
+    #[test]
+    fn tcp_segment_keeps_link_layer() {
+        let payload: Vec<u8> = (0..40).collect();
+        let mut frame = vec![
+            0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x81, 0x00, 0, 100, 0x08, 0,
+        ];
+        frame.extend_from_slice(tcp_packet(&payload).as_slice());
+        let pkt = Packet::with_link_type(frame, crate::LinkType::Ethernet);
+
+        let result = segment(16, true).run(pkt.clone()).unwrap();
+        assert_eq!(result.len(), 2);
+        for (fragment, (start, range)) in result.iter().zip([(1000, 0..16), (1016, 16..40)]) {
+            assert_eq!(fragment.link_type(), crate::LinkType::Ethernet);
+            assert_eq!(fragment.link_header(), Some(&pkt.as_slice()[..18]));
+            assert_eq!(seq(fragment), start);
+            assert_eq!(fragment.payload(), Some(&payload[range]));
+            let (ip_header, segment) = fragment.as_slice()[18..].split_at(20);
+            assert_eq!(ipv4_checksum(ip_header), 0);
+            assert_eq!(tcp_checksum(ip_header, segment), 0);
+        }
+    }
+
+    #[test]
+    fn tcp_segment_exact_payload_is_untouched() {
+        let pkt = tcp_packet(&[0; 16]);
+        let result = segment(16, true).run(pkt.clone()).unwrap();
+        assert_eq!(result, vec![pkt]);
+
+        let pkt = tcp_packet(&[]);
+        let result = segment(16, true).run(pkt.clone()).unwrap();
+        assert_eq!(result, vec![pkt]);
+    }
+
+    #[test]
+    fn tcp_segment_refuses_bad_sizes() {
+        let pkt = tcp_packet(&[0; 8]);
+        assert!(matches!(
+            segment(16, true).run(pkt.clone()),
+            Err(Error::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            segment(0, true).run(pkt),
+            Err(Error::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn udp_fragment_reassembles() {
+        let pkt = dns_query();
+        let result = udp_fragment(16, true).run(pkt.clone()).unwrap();
+        assert_eq!(result.len(), 2);
+
+        // The first fragment has the UDP header, untouched, and 16 bytes of the query.
+        assert_eq!(result[0].udp_header(), pkt.udp_header());
+        assert_eq!(result[0].payload(), Some(&pkt.payload().unwrap()[..16]));
+        assert_eq!(result[0].len(), 20 + 8 + 16);
+
+        // The second starts three blocks in, and has no UDP header of its own.
+        assert_eq!(result[1].as_slice()[6..8], [0x00, 0x03]);
+        assert_eq!(result[1].udp_header(), None);
+        assert_eq!(result[1].as_slice()[20..], pkt.payload().unwrap()[16..]);
+
+        assert_eq!(reassemble(&result), pkt.as_slice());
+        assert_eq!(GenevaAction::from(udp_fragment(16, true)).fanout(&pkt), 2);
+    }
+
+    #[test]
+    fn udp_fragment_out_of_order() {
+        let pkt = dns_query();
+        let result = udp_fragment(8, false).run(pkt.clone()).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].meta().order_hint, Some(1));
+        assert_eq!(result[1].meta().order_hint, Some(0));
+        assert_eq!(result[1].udp_header(), pkt.udp_header());
+
+        assert_eq!(reassemble(&result), pkt.as_slice());
+    }
+
+    #[test]
+    fn udp_fragment_keeps_dont_fragment_flag() {
+        let mut pkt = dns_query();
+        pkt.as_mut_slice()[6] = 0x40;
+        fix_lengths(&mut pkt);
+
+        let result = udp_fragment(16, true).run(pkt).unwrap();
+        assert_eq!(result[0].as_slice()[6..8], [0x60, 0x00]);
+        assert_eq!(result[1].as_slice()[6..8], [0x40, 0x03]);
+    }
+
+    #[test]
+    fn udp_fragment_refuses_bad_sizes() {
+        let pkt = dns_query();
+        for offset in [0, 12, 32] {
+            assert!(
+                matches!(
+                    udp_fragment(offset, true).run(pkt.clone()),
+                    Err(Error::InvalidValue { .. })
+                ),
+                "{}",
+                offset
+            );
+        }
+
+        // A TCP segment isn't a UDP datagram, so it's passed on as it is.
+        let pkt = tcp_packet(&[0; 40]);
+        let result = udp_fragment(16, true).run(pkt.clone()).unwrap();
+        assert_eq!(result, vec![pkt]);
+    }
+
+    #[test]
+    fn ip_fragment_reassembles() {
+        let pkt = fragmentable_tcp_packet(&(0..40).collect::<Vec<u8>>());
+
+        // 20 is rounded down to 16, which splits the TCP header itself.
+        let result = ip_fragment(20, true).run(pkt.clone()).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].len(), 20 + 16);
+        assert_eq!(result[0].as_slice()[20..], pkt.as_slice()[20..36]);
+        assert_eq!(result[0].tcp_header(), None);
+        assert_eq!(result[1].as_slice()[6..8], [0x00, 0x02]);
+        assert_eq!(result[1].as_slice()[20..], pkt.as_slice()[36..]);
+
+        assert_eq!(reassemble(&result), pkt.as_slice());
+        assert_eq!(GenevaAction::from(ip_fragment(20, true)).fanout(&pkt), 2);
+
+        // The IP layer doesn't care what it carries.
+        let pkt = dns_query();
+        let result = ip_fragment(8, true).run(pkt.clone()).unwrap();
+        assert_eq!(result[0].udp_header(), pkt.udp_header());
+        assert_eq!(reassemble(&result), pkt.as_slice());
+    }
+
+    #[test]
+    fn ip_fragment_out_of_order() {
+        let pkt = fragmentable_tcp_packet(&[0xaa; 40]);
+        let result = ip_fragment(8, false).run(pkt.clone()).unwrap();
+        assert_eq!(result[0].meta().order_hint, Some(1));
+        assert_eq!(result[1].meta().order_hint, Some(0));
+        assert_eq!(result[1].len(), 20 + 8);
+        assert_eq!(reassemble(&result), pkt.as_slice());
+    }
+
+    #[test]
+    fn ip_fragment_splits_fragments_again() {
+        let pkt = fragmentable_tcp_packet(&[0xaa; 40]);
+        let result = ip_fragment(8, true).run(pkt.clone()).unwrap();
+
+        // The second piece starts one block in; splitting it carries on from there, and only its
+        // second half is the last fragment.
+        let again = ip_fragment(16, true).run(result[1].clone()).unwrap();
+        assert_eq!(again[0].as_slice()[6..8], [0x20, 0x01]);
+        assert_eq!(again[1].as_slice()[6..8], [0x00, 0x03]);
+
+        let pieces = [result[0].clone(), again[0].clone(), again[1].clone()];
+        assert_eq!(reassemble(&pieces), pkt.as_slice());
+    }
+
+    #[test]
+    fn ip_fragment_refuses_bad_sizes() {
+        let pkt = fragmentable_tcp_packet(&[0; 40]);
+        for offset in [0, 7, 64] {
+            assert!(
+                matches!(
+                    ip_fragment(offset, true).run(pkt.clone()),
+                    Err(Error::InvalidValue { .. })
+                ),
+                "{}",
+                offset
+            );
+        }
+
+        // An IP payload exactly as long as the first fragment, and anything that isn't IPv4,
+        // are passed on as they are.
+        let pkt = tcp_packet(&[0; 4]);
+        assert_eq!(ip_fragment(24, true).run(pkt.clone()).unwrap(), vec![pkt]);
+        let pkt = Packet::new(vec![0x60, 0, 0, 0]);
+        assert_eq!(ip_fragment(8, true).run(pkt.clone()).unwrap(), vec![pkt]);
+    }
 }
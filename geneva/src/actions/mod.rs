@@ -1,9 +1,16 @@
 //! Describes the actions that can be applied to a given packet.
 //!
 //! See the top-level documentation for more details.
-use std::fmt;
+use alloc::sync::Arc;
+use core::cell::Cell;
+use core::fmt;
+use core::iter;
+use core::time::Duration;
 
+use crate::checksum::{update_ipv4_checksum, update_tcp_checksum, update_udp_checksum};
 use crate::errors::*;
+use crate::packet::PROTO_UDP;
+use crate::prelude::*;
 use crate::triggers::{GenevaTrigger, Trigger};
 use crate::Packet;
 
@@ -11,16 +18,84 @@ mod fragment;
 pub use fragment::FragmentAction;
 
 mod tamper;
-pub use tamper::TamperAction;
+pub use tamper::{TamperAction, TamperMode, TamperUndo};
 
 /// Describes a Geneva action, or the steps to perform to manipulate a packet.
+///
+/// Types outside of this crate can implement `Action` too, and then be used in an action tree by
+/// wrapping them in a [CustomAction].
 pub trait Action: fmt::Display {
     /// Runs this action on the given packet, producing zero or more potentially-modified packets.
+    ///
+    /// The packets are returned in the order they are to be sent. An action with two children,
+    /// such as [DuplicateAction] or [FragmentAction], returns every packet that its first child
+    /// produced before any that its second child did; their [metadata](crate::PacketMeta) is
+    /// passed along for the transmitter but never reorders them.
     fn run(&self, pkt: Packet) -> Result<Vec<Packet>>;
+
+    /// Returns `true` if running this action could discard a packet; that is, if it contains a
+    /// `drop` action anywhere. This is worked out from the action alone, so it may be `true` even
+    /// for a packet that never reaches the `drop`.
+    fn may_drop(&self) -> bool {
+        false
+    }
+}
+
+/// Visits each action in a tree of actions; see [GenevaAction::walk].
+///
+/// Any `FnMut(&GenevaAction)` closure is also a visitor.
+pub trait ActionVisitor {
+    /// Called once for each action in the tree.
+    fn visit(&mut self, action: &GenevaAction);
+}
+
+impl<F: FnMut(&GenevaAction)> ActionVisitor for F {
+    fn visit(&mut self, action: &GenevaAction) {
+        self(action)
+    }
+}
+
+/// Brings a packet's length fields back in line with its size, then recomputes its checksums.
+///
+/// Actions that add or remove bytes call this once they are done. The whole buffer is taken to
+/// be the IP datagram, so the IPv4 total length (or the IPv6 payload length) and, for UDP, the
+/// datagram length are set to match it. Packets that don't start with an IP header, or that are
+/// too large for the length fields, are left alone.
+pub(crate) fn fix_lengths(pkt: &mut Packet) {
+    let layers = match pkt.layers() {
+        Some(layers) => layers.clone(),
+        None => return,
+    };
+
+    let len = pkt.len();
+    let (ip_len, length_field) = match layers.ip_version {
+        4 => (len - layers.ip.start, 2..4),
+        _ => (len - layers.ip.end, 4..6),
+    };
+    let (ip_len, transport_len) = match (
+        u16::try_from(ip_len),
+        u16::try_from(len - layers.transport.start),
+    ) {
+        (Ok(ip_len), Ok(transport_len)) => (ip_len, transport_len),
+        _ => return,
+    };
+
+    let bytes = pkt.as_mut_slice();
+    bytes[layers.ip.start + length_field.start..layers.ip.start + length_field.end]
+        .copy_from_slice(&ip_len.to_be_bytes());
+    if layers.protocol == PROTO_UDP && layers.payload.is_some() {
+        let udp_len = layers.transport.start + 4..layers.transport.start + 6;
+        bytes[udp_len].copy_from_slice(&transport_len.to_be_bytes());
+    }
+
+    update_ipv4_checksum(pkt);
+    update_tcp_checksum(pkt);
+    update_udp_checksum(pkt);
 }
 
 /// Represents one of the Geneva actions.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GenevaAction {
     /// The `send` action.
     Send(SendAction),
@@ -36,6 +111,206 @@ pub enum GenevaAction {
 
     /// The `tamper` action.
     Tamper(TamperAction),
+
+    /// The `sleep` action.
+    Sleep(SleepAction),
+
+    /// An action defined outside of this crate.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(CustomAction),
+}
+
+impl GenevaAction {
+    /// Calls `visitor` on this action and then on each of its subordinate actions, all the way down
+    /// the tree. Actions are visited depth-first, with a parent before its children and left
+    /// branches before right ones.
+    ///
+    /// ```
+    /// use geneva::{GenevaAction, Strategy};
+    ///
+    /// let strategy: Strategy = r"[TCP:flags:S]-duplicate(tamper{TCP:flags:add:R},drop)-| \/"
+    ///     .parse()
+    ///     .unwrap();
+    /// let mut names = vec![];
+    /// strategy.outbound.unwrap()[0]
+    ///     .root_action
+    ///     .walk(&mut |action: &GenevaAction| names.push(action.to_string()));
+    /// assert_eq!(names[1..], ["tamper{TCP:flags:add:R}", "", "drop"]);
+    /// ```
+    pub fn walk<V: ActionVisitor + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit(self);
+        for child in self.children() {
+            child.walk(visitor);
+        }
+    }
+
+    /// Returns the number of actions on the longest path from this action down to a leaf,
+    /// counting both ends. A `send` or `drop` has a depth of 1.
+    pub fn depth(&self) -> usize {
+        1 + self
+            .children()
+            .into_iter()
+            .map(GenevaAction::depth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the actions at the ends of the branches of the tree rooted at this action, from left
+    /// to right: the `send`, `drop`, and custom actions, except that a `tamper` or `sleep` with
+    /// only a `send` after it is the end of its branch itself.
+    ///
+    /// ```
+    /// use geneva::GenevaAction;
+    ///
+    /// let action: GenevaAction = "duplicate(tamper{TCP:flags:add:R},fragment{tcp:8:True}(drop,))"
+    ///     .parse()
+    ///     .unwrap();
+    /// let leaves: Vec<_> = action.leaves().map(|leaf| leaf.to_string()).collect();
+    /// assert_eq!(leaves, ["tamper{TCP:flags:add:R}", "drop", ""]);
+    /// ```
+    pub fn leaves(&self) -> impl Iterator<Item = &GenevaAction> {
+        let mut stack = vec![self];
+        iter::from_fn(move || {
+            while let Some(action) = stack.pop() {
+                match action.children().as_slice() {
+                    [] | [GenevaAction::Send(_)] => return Some(action),
+                    children => stack.extend(children.iter().rev()),
+                }
+            }
+            None
+        })
+    }
+
+    /// Returns the number of actions in the tree rooted at this action, including this one and
+    /// any elided `send`s.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        self.walk(&mut |_: &GenevaAction| count += 1);
+        count
+    }
+
+    /// Returns how many packets this action would yield for `pkt`, without running it.
+    ///
+    /// `duplicate` yields whatever both of its actions yield, `drop` yields nothing, and a
+    /// `fragment` yields whatever both of its actions yield if it would split `pkt`. The count is
+    /// worked out from `pkt` alone: the actions below a `tamper` or `fragment` are assumed to see
+    /// `pkt` itself, and a custom action is assumed to yield one packet.
+    pub fn fanout(&self, pkt: &Packet) -> usize {
+        match self {
+            Self::Send(_) | Self::Custom(_) => 1,
+            Self::Drop(_) => 0,
+            Self::Duplicate(a) => a.left.fanout(pkt).saturating_add(a.right.fanout(pkt)),
+            Self::Fragment(a) => a.fanout(pkt),
+            Self::Tamper(_) | Self::Sleep(_) => self
+                .children()
+                .iter()
+                .fold(0, |n, a| n.saturating_add(a.fanout(pkt))),
+        }
+    }
+
+    /// Returns the most packets this action could yield for any one packet, worked out from the
+    /// actions alone.
+    ///
+    /// This is [fanout](Self::fanout) with every `fragment` assumed to split its packet, so
+    /// `duplicate` and `fragment` yield whatever both of their actions could, and `drop` yields
+    /// nothing. A custom action can yield any number of packets, so there is no bound (and this
+    /// returns `None`) if one appears anywhere in the tree.
+    ///
+    /// ```
+    /// use geneva::GenevaAction;
+    ///
+    /// let action: GenevaAction = "duplicate(fragment{tcp:8:True}(,duplicate),drop)"
+    ///     .parse()
+    ///     .unwrap();
+    /// assert_eq!(action.max_fanout(), Some(3));
+    /// ```
+    pub fn max_fanout(&self) -> Option<usize> {
+        match self {
+            Self::Send(_) => Some(1),
+            Self::Custom(_) => None,
+            Self::Drop(_) => Some(0),
+            _ => self
+                .children()
+                .iter()
+                .try_fold(0usize, |n, a| Some(n.saturating_add(a.max_fanout()?))),
+        }
+    }
+
+    /// Returns an error for the first action in the tree rooted at this one that would fail on
+    /// any packet it applies to, such as a `tamper` of a field that its protocol doesn't have.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match self {
+            Self::Tamper(a) => a.validate()?,
+            Self::Fragment(a) => a.validate()?,
+            _ => {}
+        }
+        self.children()
+            .into_iter()
+            .try_for_each(GenevaAction::validate)
+    }
+
+    /// Returns this action's subordinate actions, from left to right.
+    pub(crate) fn children(&self) -> Vec<&GenevaAction> {
+        match self {
+            Self::Send(_) | Self::Drop(_) => vec![],
+            Self::Duplicate(a) => vec![&a.left, &a.right],
+            Self::Fragment(a) => a.children(),
+            Self::Tamper(a) => a.children(),
+            Self::Sleep(a) => vec![&a.action],
+            Self::Custom(_) => vec![],
+        }
+    }
+
+    /// Returns mutable references to this action's subordinate actions, from left to right.
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut GenevaAction> {
+        match self {
+            Self::Send(_) | Self::Drop(_) => vec![],
+            Self::Duplicate(a) => vec![&mut a.left, &mut a.right],
+            Self::Fragment(a) => a.children_mut(),
+            Self::Tamper(a) => a.children_mut(),
+            Self::Sleep(a) => vec![&mut a.action],
+            Self::Custom(_) => vec![],
+        }
+    }
+}
+
+impl GenevaAction {
+    /// Returns a simpler action that yields the same packets as this one.
+    ///
+    /// Working from the leaves up:
+    ///
+    /// - an action other than `duplicate` whose subordinate actions all drop their packets is
+    ///   itself replaced by `drop`, since nothing it does can be seen;
+    /// - `duplicate(drop,a)` and `duplicate(a,drop)` become just `a`;
+    /// - a `tamper` in `replace` mode whose subordinate action replaces the same field is removed,
+    ///   since its value is overwritten straight away.
+    ///
+    /// The only observable difference is that an action that would have failed on a packet no
+    /// longer gets the chance to if its output was going to be dropped anyway.
+    pub(crate) fn canonicalize(&self) -> GenevaAction {
+        let mut action = self.clone();
+        for child in action.children_mut() {
+            *child = child.canonicalize();
+        }
+
+        let is_drop = |a: &GenevaAction| matches!(a, Self::Drop(_));
+        match action {
+            Self::Duplicate(a) => match (*a.left, *a.right) {
+                (Self::Drop(_), other) | (other, Self::Drop(_)) => other,
+                (left, right) => DuplicateAction::new(left, right).into(),
+            },
+            Self::Fragment(_) | Self::Tamper(_) | Self::Sleep(_)
+                if action.children().into_iter().all(is_drop) =>
+            {
+                DropAction::default().into()
+            }
+            Self::Tamper(a) => match a.children()[0] {
+                Self::Tamper(next) if a.is_overwritten_by(next) => next.clone().into(),
+                _ => a.into(),
+            },
+            action => action,
+        }
+    }
 }
 
 impl Action for GenevaAction {
@@ -46,6 +321,20 @@ impl Action for GenevaAction {
             Self::Duplicate(a) => a.run(pkt),
             Self::Fragment(a) => a.run(pkt),
             Self::Tamper(a) => a.run(pkt),
+            Self::Sleep(a) => a.run(pkt),
+            Self::Custom(a) => a.run(pkt),
+        }
+    }
+
+    fn may_drop(&self) -> bool {
+        match self {
+            Self::Send(a) => a.may_drop(),
+            Self::Drop(a) => a.may_drop(),
+            Self::Duplicate(a) => a.may_drop(),
+            Self::Fragment(a) => a.may_drop(),
+            Self::Tamper(a) => a.may_drop(),
+            Self::Sleep(a) => a.may_drop(),
+            Self::Custom(a) => a.may_drop(),
         }
     }
 }
@@ -58,12 +347,15 @@ impl fmt::Display for GenevaAction {
             Self::Duplicate(a) => a.fmt(f),
             Self::Fragment(a) => a.fmt(f),
             Self::Tamper(a) => a.fmt(f),
+            Self::Sleep(a) => a.fmt(f),
+            Self::Custom(a) => a.fmt(f),
         }
     }
 }
 
 /// An [Action] that passes the given packet on without modification.
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SendAction {}
 
 impl Action for SendAction {
@@ -90,7 +382,12 @@ impl From<SendAction> for GenevaAction {
 /// The `duplicate(a1, a2)` action copies the original packet, then applies [Action] `a1` to the
 /// original and `a2` to the copy. For example, if `a1` and `a2` are both "[send](SendAction)"
 /// actions, then the action will yield two packets identical to the first.
-#[derive(Debug, Clone)]
+///
+/// All of the packets that `a1` yields come first, in the order `a1` gave them, followed by those
+/// of `a2`. This holds however deeply the two are nested: in `duplicate(fragment{6:8:True},)`, for
+/// instance, both fragments are sent before the unfragmented copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DuplicateAction {
     left: Box<GenevaAction>,
     right: Box<GenevaAction>,
@@ -107,7 +404,8 @@ impl DuplicateAction {
 
 impl Action for DuplicateAction {
     fn run(&self, pkt: Packet) -> Result<Vec<Packet>> {
-        let dupe = Packet::new(pkt.as_slice().to_vec());
+        // Clone the whole packet so that the copy starts with the same metadata.
+        let dupe = pkt.clone();
 
         let mut result = vec![];
 
@@ -119,6 +417,10 @@ impl Action for DuplicateAction {
 
         Ok(result)
     }
+
+    fn may_drop(&self) -> bool {
+        self.left.may_drop() || self.right.may_drop()
+    }
 }
 
 impl fmt::Display for DuplicateAction {
@@ -141,13 +443,18 @@ impl From<DuplicateAction> for GenevaAction {
 }
 
 /// An [Action] that drops the given packet.
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DropAction {}
 
 impl Action for DropAction {
     fn run(&self, _: Packet) -> Result<Vec<Packet>> {
         Ok(vec![])
     }
+
+    fn may_drop(&self) -> bool {
+        true
+    }
 }
 
 impl fmt::Display for DropAction {
@@ -162,35 +469,273 @@ impl From<DropAction> for GenevaAction {
     }
 }
 
+/// An [Action] that delays a packet, then applies another action to it.
+///
+/// Since the library never blocks, `sleep{secs}(a1)` doesn't wait itself. Instead, it runs `a1`
+/// and adds the delay to the [delay](crate::PacketMeta::delay) of every packet that `a1` yields,
+/// so that whatever transmits them can wait before sending them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SleepAction {
+    duration: Duration,
+    action: Box<GenevaAction>,
+}
+
+impl SleepAction {
+    /// Creates a new `SleepAction` that delays packets by `duration` and then applies `action`.
+    pub fn new(duration: Duration, action: GenevaAction) -> Self {
+        Self {
+            duration,
+            action: Box::new(action),
+        }
+    }
+
+    /// Returns how long packets are delayed.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl Action for SleepAction {
+    fn run(&self, pkt: Packet) -> Result<Vec<Packet>> {
+        let mut result = self.action.run(pkt)?;
+        for pkt in &mut result {
+            let delay = pkt.meta().delay.unwrap_or_default();
+            pkt.meta_mut().delay = Some(delay.saturating_add(self.duration));
+        }
+        Ok(result)
+    }
+
+    fn may_drop(&self) -> bool {
+        self.action.may_drop()
+    }
+}
+
+impl fmt::Display for SleepAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Like tamper, a subordinate "send" is elided entirely.
+        let action = self.action.to_string();
+        let args = if action.is_empty() {
+            "".to_string()
+        } else {
+            format!("({},)", action)
+        };
+        write!(f, "sleep{{{}}}{}", self.duration.as_secs_f64(), args)
+    }
+}
+
+impl From<SleepAction> for GenevaAction {
+    fn from(a: SleepAction) -> Self {
+        Self::Sleep(a)
+    }
+}
+
+/// An [Action] defined outside of this crate.
+///
+/// Wrapping a type that implements [Action] in a `CustomAction` lets it appear anywhere in an
+/// action tree. The wrapped action's `Display` output is its syntax in a strategy string, and must
+/// be either a bare name (`reverse`) or a name followed by arguments in braces (`reverse{tcp}`).
+/// To parse it back, register the name with an [ActionRegistry](crate::ActionRegistry).
+///
+/// Custom actions have no subordinate actions, and two of them are equal if they display the
+/// same. They are skipped by `serde`, so a strategy that contains one can't be serialized.
+#[derive(Clone)]
+pub struct CustomAction(Arc<dyn Action + Send + Sync>);
+
+impl CustomAction {
+    /// Wraps `action` so that it can be used in an action tree.
+    pub fn new(action: impl Action + Send + Sync + 'static) -> Self {
+        Self(Arc::new(action))
+    }
+}
+
+impl Action for CustomAction {
+    fn run(&self, pkt: Packet) -> Result<Vec<Packet>> {
+        self.0.run(pkt)
+    }
+
+    fn may_drop(&self) -> bool {
+        self.0.may_drop()
+    }
+}
+
+impl fmt::Display for CustomAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Debug for CustomAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CustomAction")
+            .field(&self.0.to_string())
+            .finish()
+    }
+}
+
+impl PartialEq for CustomAction {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for CustomAction {}
+
+impl From<CustomAction> for GenevaAction {
+    fn from(a: CustomAction) -> Self {
+        Self::Custom(a)
+    }
+}
+
 /// Represents a Geneva (trigger, action) pair.
 ///
 /// Technically, Geneva uses the term "action tree" to refer to the tree of actions in the tuple
 /// (trigger, action tree). In other words, `root_action` here is what they call the "action
 /// tree". They have no name for the (trigger, action tree) tuple, which this type actually
 /// represents.
+///
+/// If the trigger has a nonzero [gas](Trigger::gas), the action tree only fires that many times;
+/// after that, it no longer matches any packets. A gas of zero means the tree can fire an
+/// unlimited number of times.
+///
+/// The count belongs to the tree itself, so each tree in a [Strategy](crate::Strategy) has gas of
+/// its own, even if the same tree appears in both forests: firing on outbound packets never uses
+/// up the gas of an inbound tree. A clone starts with as much gas as the original has left.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ActionTree {
     /// The [Trigger] that, if matched, will fire this action tree.
     pub trigger: GenevaTrigger,
 
     /// The root [Action] of the tree. It may have subordinate actions that it calls.
     pub root_action: Box<GenevaAction>,
+
+    /// How many times this action tree has been applied.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    fired: Cell<usize>,
 }
 
 impl ActionTree {
-    /// Returns `true` if this action tree's trigger matches the given [Packet].
+    /// Creates a new `ActionTree`.
+    pub fn new(trigger: GenevaTrigger, root_action: GenevaAction) -> Self {
+        Self {
+            trigger,
+            root_action: Box::new(root_action),
+            fired: Cell::new(0),
+        }
+    }
+
+    /// Returns `true` if this action tree's trigger matches the given [Packet] and the trigger
+    /// has not run out of gas.
     pub fn matches(&self, pkt: &Packet) -> bool {
-        self.trigger.matches(pkt)
+        !self.is_exhausted() && self.trigger.matches(pkt)
     }
 
     /// Applies this action tree to the [Packet], returning zero or more potentially-modified packets.
+    ///
+    /// Each call consumes one unit of the trigger's gas.
     pub fn apply(&self, pkt: Packet) -> Result<Vec<Packet>> {
+        self.fired.set(self.fired.get().saturating_add(1));
         self.root_action.run(pkt)
     }
+
+    /// Returns the depth of the tree's actions; see [GenevaAction::depth].
+    pub fn depth(&self) -> usize {
+        self.root_action.depth()
+    }
+
+    /// Returns how many packets [apply](Self::apply) would yield for `pkt` if the tree were
+    /// applied wherever it matches, without running any actions or consuming gas. A packet that
+    /// the tree doesn't match passes through, so it counts as one. See [GenevaAction::fanout].
+    pub fn fanout(&self, pkt: &Packet) -> usize {
+        if self.matches(pkt) {
+            self.root_action.fanout(pkt)
+        } else {
+            1
+        }
+    }
+
+    /// Returns the most packets [apply](Self::apply) could yield for any one packet, which is at
+    /// least one since a packet that the tree doesn't match passes through, or `None` if the tree
+    /// has a custom action. See [GenevaAction::max_fanout].
+    pub fn max_fanout(&self) -> Option<usize> {
+        self.root_action.max_fanout().map(|n| n.max(1))
+    }
+
+    /// Returns a copy of the tree with its actions [canonicalized](GenevaAction::canonicalize).
+    /// Like a clone, the copy starts with as much gas as the tree has left.
+    pub(crate) fn canonicalize(&self) -> Self {
+        Self {
+            trigger: self.trigger.clone(),
+            root_action: Box::new(self.root_action.canonicalize()),
+            fired: self.fired.clone(),
+        }
+    }
+
+    /// Returns `true` if the trigger's gas has been used up.
+    pub fn is_exhausted(&self) -> bool {
+        let gas = self.trigger.gas();
+        gas > 0 && self.fired.get() >= gas
+    }
+}
+
+/// Two action trees are equal if they have the same trigger and actions, regardless of how many
+/// times either of them has fired.
+impl PartialEq for ActionTree {
+    fn eq(&self, other: &Self) -> bool {
+        self.trigger == other.trigger && self.root_action == other.root_action
+    }
+}
+
+impl Eq for ActionTree {}
+
+/// Builds an [ActionTree] by pairing a trigger with a root action.
+///
+/// If no root action is given, the tree simply sends the packets that it matches.
+///
+/// ```
+/// use geneva::{ActionTreeBuilder, DropAction, TCPField, TCPTrigger};
+///
+/// let trigger = TCPTrigger::new(TCPField::Flags, "R".to_string(), 0).unwrap();
+/// let tree = ActionTreeBuilder::new(trigger)
+///     .action(DropAction::default())
+///     .build();
+/// assert_eq!(tree.to_string(), "[TCP:flags:R]-drop-|");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ActionTreeBuilder {
+    trigger: GenevaTrigger,
+    root_action: GenevaAction,
+}
+
+impl ActionTreeBuilder {
+    /// Creates a new `ActionTreeBuilder` for an action tree that fires on `trigger`.
+    pub fn new(trigger: impl Into<GenevaTrigger>) -> Self {
+        Self {
+            trigger: trigger.into(),
+            root_action: SendAction::default().into(),
+        }
+    }
+
+    /// Sets the root action of the tree.
+    pub fn action(mut self, action: impl Into<GenevaAction>) -> Self {
+        self.root_action = action.into();
+        self
+    }
+
+    /// Builds the [ActionTree].
+    pub fn build(self) -> ActionTree {
+        ActionTree::new(self.trigger, self.root_action)
+    }
 }
 
 impl fmt::Display for ActionTree {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // A tree needs an action between its dashes, so a root "send" can't be elided like the
+        // subordinate ones are.
+        if let GenevaAction::Send(_) = *self.root_action {
+            return write!(f, "{}-send-|", self.trigger);
+        }
         write!(f, "{}-{}-|", self.trigger, self.root_action)
     }
 }
@@ -199,12 +744,65 @@ impl fmt::Display for ActionTree {
 mod tests {
     use super::*;
 
+    #[test]
+    fn builder_defaults_to_send() {
+        let trigger = crate::TCPTrigger::new(crate::TCPField::Flags, "S".to_string(), 0).unwrap();
+        let tree = ActionTreeBuilder::new(trigger).build();
+        assert!(matches!(*tree.root_action, GenevaAction::Send(_)));
+    }
+
     #[test]
     fn send_str() {
         let a = SendAction::default();
         assert_eq!(a.to_string(), "");
     }
 
+    #[test]
+    fn send_children_round_trip() {
+        let send = || GenevaAction::from(SendAction::default());
+        let drop = || GenevaAction::from(DropAction::default());
+        let tamper = |action| {
+            TamperAction::new(
+                "TCP".to_string(),
+                "flags".to_string(),
+                "R".to_string(),
+                TamperMode::Replace,
+                action,
+            )
+            .unwrap()
+            .into()
+        };
+        let fragment = |left, right| {
+            FragmentAction::new(6, 8, true, 0, left, right)
+                .unwrap()
+                .into()
+        };
+        let sleep = |action| SleepAction::new(Duration::from_millis(500), action).into();
+
+        let actions: Vec<GenevaAction> = vec![
+            send(),
+            DuplicateAction::new(send(), send()).into(),
+            DuplicateAction::new(send(), drop()).into(),
+            DuplicateAction::new(drop(), send()).into(),
+            fragment(send(), send()),
+            fragment(send(), drop()),
+            fragment(drop(), send()),
+            tamper(send()),
+            tamper(tamper(send())),
+            sleep(send()),
+            sleep(tamper(send())),
+            DuplicateAction::new(tamper(send()), sleep(send())).into(),
+        ];
+        for action in actions {
+            let trigger = crate::TCPTrigger::new(crate::TCPField::Flags, "S".to_string(), 0);
+            let tree = ActionTree::new(trigger.unwrap().into(), action);
+            let s = format!(r"{} \/", tree);
+            let strategy = crate::parse_strategy(&s).unwrap_or_else(|e| panic!("{}: {}", s, e));
+            assert_eq!(strategy.to_string(), s);
+            assert_eq!(strategy.outbound.unwrap()[0], tree, "{}", s);
+        }
+    }
+
     #[test]
     fn drop_str() {
         let a = DropAction::default();
@@ -217,13 +815,13 @@ mod tests {
             DuplicateAction::new(SendAction::default().into(), SendAction::default().into());
         assert_eq!(a.to_string(), "duplicate");
 
-        a.left = Box::new(DropAction::default().into());
+        *a.left = DropAction::default().into();
         assert_eq!(a.to_string(), "duplicate(drop,)");
 
-        a.right = Box::new(DropAction::default().into());
+        *a.right = DropAction::default().into();
         assert_eq!(a.to_string(), "duplicate(drop,drop)");
 
-        a.left = Box::new(SendAction::default().into());
+        *a.left = SendAction::default().into();
         assert_eq!(a.to_string(), "duplicate(,drop)");
     }
 
@@ -239,6 +837,289 @@ mod tests {
         );
     }
 
+    /// Counts the `drop` actions in a tree.
+    #[derive(Default)]
+    struct DropCounter(usize);
+
+    impl ActionVisitor for DropCounter {
+        fn visit(&mut self, action: &GenevaAction) {
+            if let GenevaAction::Drop(_) = action {
+                self.0 += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn walk_visits_every_action() {
+        let strategy: crate::Strategy =
+            r"[TCP:flags:PA]-duplicate(tamper{TCP:flags:add:R}(duplicate(drop,fragment{6:8:True}(drop,sleep{1}(drop,))),),drop)-| \/"
+                .parse()
+                .unwrap();
+        let root = &strategy.outbound.unwrap()[0].root_action;
+
+        let mut counter = DropCounter::default();
+        root.walk(&mut counter);
+        assert_eq!(counter.0, 4);
+
+        let mut visited = 0;
+        root.walk(&mut |_: &GenevaAction| visited += 1);
+        assert_eq!(visited, 9);
+
+        let mut counter = DropCounter::default();
+        GenevaAction::from(SendAction::default()).walk(&mut counter);
+        assert_eq!(counter.0, 0);
+    }
+
+    #[test]
+    fn leaves() {
+        let action = GenevaAction::from(DuplicateAction::new(
+            SendAction::default().into(),
+            DropAction::default().into(),
+        ));
+        let leaves: Vec<_> = action.leaves().collect();
+        assert!(matches!(
+            leaves[..],
+            [GenevaAction::Send(_), GenevaAction::Drop(_)]
+        ));
+
+        let strategy: crate::Strategy =
+            r"[TCP:flags:PA]-duplicate(tamper{TCP:flags:add:R}(duplicate(drop,fragment{6:8:True}(drop,sleep{1}(drop,))),),sleep{2})-| \/"
+                .parse()
+                .unwrap();
+        let root = &strategy.outbound.unwrap()[0].root_action;
+        let leaves: Vec<_> = root.leaves().map(|leaf| leaf.to_string()).collect();
+        assert_eq!(leaves, ["drop", "drop", "drop", "sleep{2}"]);
+
+        let send = GenevaAction::from(SendAction::default());
+        assert_eq!(send.leaves().count(), 1);
+    }
+
+    #[test]
+    fn depth_and_node_count() {
+        // The example from the Geneva paper.
+        let strategy: crate::Strategy =
+            r"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA}(send),send)-| \/ [TCP:flags:R]-drop-|"
+                .parse()
+                .unwrap();
+        let tree = &strategy.outbound.as_ref().unwrap()[0];
+        assert_eq!(tree.depth(), 3);
+        assert_eq!(tree.root_action.node_count(), 4);
+
+        let tree = &strategy.inbound.as_ref().unwrap()[0];
+        assert_eq!(tree.depth(), 1);
+        assert_eq!(tree.root_action.node_count(), 1);
+
+        // Depth follows the deepest branch, whichever side it is on.
+        let strategy: crate::Strategy =
+            r"[TCP:flags:PA]-duplicate(,fragment{6:8:True}(drop,sleep{1}(tamper{TCP:seq:corrupt},)))-| \/"
+                .parse()
+                .unwrap();
+        let tree = &strategy.outbound.as_ref().unwrap()[0];
+        assert_eq!(tree.depth(), 5);
+        assert_eq!(tree.root_action.node_count(), 7);
+    }
+
+    #[test]
+    fn fanout() {
+        let tree = |s: &str| {
+            let strategy: crate::Strategy = format!(r"[TCP:flags:PA]-{}-| \/", s).parse().unwrap();
+            strategy.outbound.unwrap().remove(0)
+        };
+        let mut bytes = vec![
+            0x45, 0, 0, 56, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0,
+            80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        bytes.resize(56, b'x');
+        let pkt = Packet::new(bytes);
+
+        for (s, expected) in [
+            ("duplicate(send,duplicate(send,send))", 3),
+            ("duplicate(drop,duplicate(send,send))", 2),
+            ("drop", 0),
+            ("tamper{TCP:flags:add:R}(duplicate,)", 2),
+            ("sleep{1}(drop,)", 0),
+            // The payload is 16 bytes long, so only a split after fewer than 16 of them happens.
+            ("fragment{6:8:True}(duplicate,)", 3),
+            ("fragment{6:16:True}(duplicate,)", 2),
+        ] {
+            let tree = tree(s);
+            assert_eq!(tree.fanout(&pkt), expected, "{}", s);
+            assert_eq!(tree.apply(pkt.clone()).unwrap().len(), expected, "{}", s);
+        }
+
+        // Packets that the tree doesn't fire on pass through, and working it out uses no gas.
+        let syn: crate::Strategy = r"[TCP:flags:S:1]-drop-| \/".parse().unwrap();
+        let syn = &syn.outbound.unwrap()[0];
+        assert_eq!(syn.fanout(&pkt), 1);
+        let mut bytes = pkt.as_slice().to_vec();
+        bytes[33] = 0x02;
+        let pkt = Packet::new(bytes);
+        assert_eq!(syn.fanout(&pkt), 0);
+        assert_eq!(syn.fanout(&pkt), 0);
+    }
+
+    #[test]
+    fn max_fanout() {
+        let tree = |s: &str| {
+            let strategy: crate::Strategy = format!(r"[TCP:flags:PA]-{}-| \/", s).parse().unwrap();
+            strategy.outbound.unwrap().remove(0)
+        };
+
+        for (s, expected) in [
+            ("send", 1),
+            ("drop", 0),
+            ("duplicate(duplicate,duplicate)", 4),
+            ("duplicate(fragment{6:8:True}(duplicate,),drop)", 3),
+            (
+                "fragment{6:8:False}(fragment{6:4:True},duplicate(drop,sleep{1}))",
+                3,
+            ),
+            (
+                "tamper{TCP:flags:add:R}(duplicate(,fragment{6:8:True}(drop,drop)))",
+                1,
+            ),
+            (
+                "sleep{1}(duplicate(tamper{TCP:seq:corrupt}(drop),duplicate))",
+                2,
+            ),
+        ] {
+            assert_eq!(tree(s).root_action.max_fanout(), Some(expected), "{}", s);
+        }
+
+        // A tree that drops everything it fires on still passes other packets through.
+        assert_eq!(tree("drop").max_fanout(), Some(1));
+        assert_eq!(tree("duplicate(,duplicate)").max_fanout(), Some(3));
+
+        // A custom action could yield any number of packets, so it leaves the tree unbounded.
+        struct Twice;
+        impl fmt::Display for Twice {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("twice")
+            }
+        }
+        impl Action for Twice {
+            fn run(&self, pkt: Packet) -> Result<Vec<Packet>> {
+                Ok(vec![pkt.clone(), pkt])
+            }
+        }
+        let custom = GenevaAction::from(CustomAction::new(Twice));
+        assert_eq!(custom.max_fanout(), None);
+        let action = GenevaAction::from(DuplicateAction::new(custom, DropAction {}.into()));
+        assert_eq!(action.max_fanout(), None);
+
+        // The bound holds whether or not a fragment splits the packet.
+        let mut bytes = vec![
+            0x45, 0, 0, 56, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0,
+            80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        bytes.resize(56, b'x');
+        let pkt = Packet::new(bytes);
+        for s in [
+            "fragment{6:8:True}(duplicate,)",
+            "fragment{6:16:True}(duplicate,)",
+        ] {
+            let tree = tree(s);
+            assert_eq!(tree.max_fanout(), Some(3), "{}", s);
+            assert!(
+                Some(tree.apply(pkt.clone()).unwrap().len()) <= tree.max_fanout(),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn canonicalize_actions() {
+        let canonical = |s: &str| {
+            let strategy: crate::Strategy = format!(r"[TCP:flags:S]-{}-| \/", s).parse().unwrap();
+            let tree = &strategy.outbound.unwrap()[0];
+            tree.root_action.canonicalize().to_string()
+        };
+
+        for (s, expected) in [
+            ("duplicate", "duplicate"),
+            ("duplicate(drop,)", ""),
+            ("duplicate(,drop)", ""),
+            ("duplicate(drop,drop)", "drop"),
+            ("tamper{TCP:flags:add:R}(drop,)", "drop"),
+            ("sleep{1}(drop,)", "drop"),
+            ("fragment{6:8:True}(drop,drop)", "drop"),
+            ("fragment{6:8:True}(drop,)", "fragment{6:8:True}(drop,)"),
+            (
+                "duplicate(tamper{TCP:window:replace:1}(drop,),tamper{TCP:window:corrupt})",
+                "tamper{TCP:window:corrupt}",
+            ),
+            (
+                "tamper{TCP:window:replace:1}(tamper{TCP:window:replace:2},)",
+                "tamper{TCP:window:replace:2}",
+            ),
+            (
+                "tamper{TCP:window:replace:1}(tamper{TCP:window:add:2},)",
+                "tamper{TCP:window:replace:1}(tamper{TCP:window:add:2},)",
+            ),
+            (
+                "tamper{TCP:window:replace:1}(tamper{TCP:seq:replace:2},)",
+                "tamper{TCP:window:replace:1}(tamper{TCP:seq:replace:2},)",
+            ),
+            (
+                "duplicate(duplicate(drop,sleep{1}(drop,)),fragment{6:8:False}(duplicate(drop,),))",
+                "fragment{6:8:False}",
+            ),
+        ] {
+            assert_eq!(canonical(s), expected, "{}", s);
+        }
+    }
+
+    #[test]
+    fn sleep_str() {
+        let a = SleepAction::new(Duration::from_secs(1), SendAction::default().into());
+        assert_eq!(a.to_string(), "sleep{1}");
+
+        let a = SleepAction::new(Duration::from_millis(250), DropAction::default().into());
+        assert_eq!(a.to_string(), "sleep{0.25}(drop,)");
+    }
+
+    #[test]
+    fn sleep_delays_every_output() {
+        let pkt = Packet::new(vec![0x45, 0, 0, 20]);
+        assert_eq!(pkt.meta().delay, None);
+
+        let dupe = DuplicateAction::new(SendAction::default().into(), SendAction::default().into());
+        let inner = SleepAction::new(Duration::from_millis(500), dupe.into());
+        let a = SleepAction::new(Duration::from_secs(1), inner.into());
+
+        let result = a.run(pkt.clone()).unwrap();
+        assert_eq!(result.len(), 2);
+        for out in &result {
+            assert_eq!(out, &pkt);
+            assert_eq!(out.meta().delay, Some(Duration::from_millis(1500)));
+        }
+
+        let a = SleepAction::new(Duration::from_secs(1), DropAction::default().into());
+        assert!(a.run(pkt).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fix_lengths_matches_buffer() {
+        // A UDP datagram from 10.0.0.1 to 10.0.0.2 whose payload has grown by two bytes.
+        let mut pkt = Packet::new(vec![
+            0x45, 0x00, 0x00, 0x1f, 0x00, 0x01, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x14, 0xe9, 0x00, 0x35, 0x00, 0x0b, 0x00, 0x00, 1, 2, 3, 4, 5,
+        ]);
+        fix_lengths(&mut pkt);
+
+        let bytes = pkt.as_slice();
+        assert_eq!(bytes[2..4], 33u16.to_be_bytes());
+        assert_eq!(bytes[24..26], 13u16.to_be_bytes());
+        assert_eq!(crate::checksum::ipv4_checksum(&bytes[..20]), 0);
+        assert_ne!(bytes[26..28], [0, 0]);
+
+        // Anything that isn't IP is left alone.
+        let mut pkt = Packet::new(vec![0, 1, 2, 3, 4]);
+        fix_lengths(&mut pkt);
+        assert_eq!(pkt.as_slice(), &[0, 1, 2, 3, 4]);
+    }
+
     #[test]
     fn send_result() {
         let a = SendAction::default();
@@ -264,6 +1145,31 @@ mod tests {
         assert_eq!(result.len(), 0);
     }
 
+    #[test]
+    fn may_drop() {
+        let send = || GenevaAction::from(SendAction::default());
+        let drop = || GenevaAction::from(DropAction::default());
+
+        assert!(!SendAction::default().may_drop());
+        assert!(DropAction::default().may_drop());
+        assert!(!DuplicateAction::new(send(), send()).may_drop());
+        assert!(DuplicateAction::new(send(), drop()).may_drop());
+
+        let fragment = FragmentAction::new(6, 8, true, 0, send(), drop()).unwrap();
+        assert!(fragment.may_drop());
+        let tamper = TamperAction::new(
+            "TCP".to_string(),
+            "flags".to_string(),
+            "R".to_string(),
+            TamperMode::Replace,
+            fragment.into(),
+        )
+        .unwrap();
+        let sleep = SleepAction::new(Duration::from_secs(1), tamper.into());
+        assert!(GenevaAction::from(sleep).may_drop());
+        assert!(!GenevaAction::from(SleepAction::new(Duration::from_secs(1), send())).may_drop());
+    }
+
     #[test]
     fn duplicate_send_result() {
         let a = DuplicateAction::new(SendAction::default().into(), SendAction::default().into());
@@ -277,4 +1183,20 @@ mod tests {
 
         assert_eq!(result, vec![pkt.clone(), pkt]);
     }
+
+    #[test]
+    fn duplicate_keeps_metadata() {
+        let meta = crate::PacketMeta {
+            delay: Some(Duration::from_millis(20)),
+            order_hint: Some(1),
+        };
+        let pkt = Packet::with_meta(vec![0, 1, 2, 3, 4], meta.clone());
+
+        let a = DuplicateAction::new(SendAction::default().into(), SendAction::default().into());
+        let result = a.run(pkt).unwrap();
+        assert_eq!(result.len(), 2);
+        for out in &result {
+            assert_eq!(out.meta(), &meta);
+        }
+    }
 }
@@ -1,21 +1,62 @@
-use std::fmt;
+use alloc::borrow::Cow;
+use core::fmt;
+use core::net::IpAddr;
+use core::ops::Range;
+use core::str::FromStr;
 
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use crate::checksum::{
+    ipv4_checksum, update_ipv4_checksum, update_tcp_checksum, update_udp_checksum,
+};
+use crate::dns::{self, DNS_PORT};
+use crate::encoding::{decode_bytes, decode_hex};
 use crate::errors::*;
+use crate::packet::{Layers, IP_FRAG_OFFSET, PROTO_TCP, PROTO_UDP};
+use crate::prelude::*;
+use crate::triggers::{bracketed, parse_ip_addr, parse_tcp_flags, IPField, TCPField, UDPField};
 use crate::Packet;
 
-use super::{Action, GenevaAction};
+use super::{fix_lengths, Action, GenevaAction};
+
+/// The kind of the TCP no-operation option, which is used to pad out the options.
+const TCP_OPTION_NOP: u8 = 1;
+
+/// The type of the IPv4 no-operation option, which is used to pad out the options.
+const IP_OPTION_NOP: u8 = 1;
+
+/// The bit of the TCP flags that marks the urgent pointer as significant.
+const TCP_FLAG_URG: u8 = 0x20;
 
 /// Describes the way that the `tamper` action can manipulate a packet.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TamperMode {
-    /// Replaces the value of a packet field with the given value.
+    /// Replaces the value of a packet field with the given value. For TCP flags, the given flags
+    /// are set and all others are cleared.
     Replace,
 
     /// Replaces the value of a packet field with a randomly-generated value.
     Corrupt,
 
-    /// Adds the value to a packet field.
+    /// Adds the value to a packet field. For TCP flags, this sets the given flags without
+    /// disturbing the others.
     Add,
+
+    /// Clears the given bits of a flags field, leaving the others untouched. This is only supported
+    /// for TCP flags.
+    Clear,
+
+    /// Rewrites a DNS name to use a compression pointer. This is only supported for the `qd`
+    /// (question) field of a DNS query.
+    Compress,
+
+    /// Recomputes a field from the rest of the packet, undoing whatever broke it: checksums are
+    /// set to their correct values, and the IP total length and UDP length to the length of the
+    /// datagram. This is only supported for the IP `chksum` and `len` fields, the TCP and UDP
+    /// `chksum` fields, and the UDP `len` field.
+    Fix,
 }
 
 impl fmt::Display for TamperMode {
@@ -24,22 +65,68 @@ impl fmt::Display for TamperMode {
             Self::Replace => f.write_str("replace"),
             Self::Corrupt => f.write_str("corrupt"),
             Self::Add => f.write_str("add"),
+            Self::Clear => f.write_str("clear"),
+            Self::Compress => f.write_str("compress"),
+            Self::Fix => f.write_str("fix"),
+        }
+    }
+}
+
+impl FromStr for TamperMode {
+    type Err = Error;
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s {
+            "replace" => Ok(Self::Replace),
+            "corrupt" => Ok(Self::Corrupt),
+            "add" => Ok(Self::Add),
+            "clear" => Ok(Self::Clear),
+            "compress" => Ok(Self::Compress),
+            "fix" => Ok(Self::Fix),
+            _ => Err(Error::Parse(s.to_string())),
         }
     }
 }
 
 /// An [Action] that modifies packets (typically values in the packet header).
-#[derive(Debug, Clone)]
+///
+/// In `corrupt` mode the replacement data comes from a random number generator. By default this
+/// is seeded from system entropy on every run; use [TamperAction::with_seed] to make the output
+/// reproducible. (Without the `std` feature there is no source of entropy, so each run is seeded
+/// from a counter instead, which varies the output but makes it easy to predict.)
+///
+/// A tamper has a single child action, which it displays the way Geneva does: `tamper{...}(a1,)`,
+/// with a trailing comma, or just `tamper{...}` if the child is `send`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TamperAction {
     protocol: String,
     field: String,
     new_value: String,
     mode: TamperMode,
     action: Box<GenevaAction>,
+    seed: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    urgent_flag: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    stale_checksums: bool,
+}
+
+/// Returns the short, lowercase name of `field`, the way triggers name it: field names ignore case,
+/// and `source`, `destination`, `sourceport`, and `destinationport` stand for `src`, `dst`,
+/// `sport`, and `dport`. Names that no trigger uses are only lowercased.
+fn field_name(protocol: &str, field: &str) -> String {
+    let name = match protocol.to_ascii_lowercase().as_str() {
+        "ip" => field.parse::<IPField>().ok().map(|f| f.to_string()),
+        "tcp" => field.parse::<TCPField>().ok().map(|f| f.to_string()),
+        "udp" => field.parse::<UDPField>().ok().map(|f| f.to_string()),
+        _ => None,
+    };
+    name.unwrap_or_else(|| field.to_ascii_lowercase())
 }
 
 impl TamperAction {
-    /// Creates a new `TamperAction`.
+    /// Creates a new `TamperAction`. As in triggers, the field name ignores case and may be one
+    /// of the longer aliases, such as `source` for `src`.
     pub fn new(
         protocol: String,
         field: String,
@@ -48,33 +135,2259 @@ impl TamperAction {
         action: GenevaAction,
     ) -> Result<Self> {
         Ok(Self {
+            field: field_name(&protocol, &field),
             protocol,
-            field,
             new_value,
             mode,
             action: Box::new(action),
+            seed: None,
+            urgent_flag: false,
+            stale_checksums: false,
         })
     }
+
+    /// Seeds the random number generator used by `corrupt` mode, so that every run of this action
+    /// produces the same "random" values.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Makes a tamper of the TCP urgent pointer also set the URG flag whenever it leaves the
+    /// pointer nonzero, since receivers ignore the pointer without it. Tampers with other fields
+    /// are unaffected. In a strategy, this is written `+urg` after the mode, as in
+    /// `tamper{TCP:urgptr:replace+urg:5}`.
+    pub fn with_urgent_flag(mut self) -> Self {
+        self.urgent_flag = true;
+        self
+    }
+
+    /// Leaves the IPv4, TCP, and UDP checksums of tampered packets as they were, instead of fixing
+    /// them up to match the new contents. This is for producing deliberately broken packets, such
+    /// as when fuzzing a receiver. Lengths are still fixed up, and a tamper of a checksum field
+    /// itself still writes the new value. In a strategy, this is written `+stale` after the mode,
+    /// as in `tamper{TCP:seq:corrupt+stale}`.
+    pub fn with_stale_checksums(mut self) -> Self {
+        self.stale_checksums = true;
+        self
+    }
+
+    /// Returns the action applied to the tampered packet.
+    pub(crate) fn children(&self) -> Vec<&GenevaAction> {
+        vec![&self.action]
+    }
+
+    /// Returns a mutable reference to the action applied to the tampered packet.
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut GenevaAction> {
+        vec![&mut self.action]
+    }
+
+    /// Returns `true` if running `next` straight after this action overwrites everything this
+    /// action did; that is, both of them replace the same field, and nothing else.
+    ///
+    /// Replacing the TCP data offset or the IP header length resizes the header, and a shrink
+    /// followed by a grow loses options that the grow alone would keep, so those never count.
+    /// Neither does a tamper that may set URG as well as the urgent pointer.
+    pub(crate) fn is_overwritten_by(&self, next: &TamperAction) -> bool {
+        self.mode == TamperMode::Replace
+            && next.mode == TamperMode::Replace
+            && self.protocol.eq_ignore_ascii_case(&next.protocol)
+            && self.field == next.field
+            && !matches!(self.field.as_str(), "dataofs" | "ihl")
+            && !self.urgent_flag
+            && !next.urgent_flag
+            && !next.stale_checksums
+    }
+}
+
+/// How the bytes of a header field should be interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    /// An unsigned big-endian integer.
+    Integer,
+    /// An IPv4 or IPv6 address, depending on the width of the field.
+    Address,
+    /// A set of TCP flags, written as letters (see [parse_tcp_flags]).
+    Flags,
+}
+
+/// The location of a header field within its layer.
+#[derive(Debug, Clone)]
+struct FieldSpec {
+    range: Range<usize>,
+    kind: FieldKind,
+}
+
+impl FieldSpec {
+    fn new(range: Range<usize>, kind: FieldKind) -> Self {
+        Self { range, kind }
+    }
+}
+
+/// Returns the location of the named field within an IPv4 header.
+fn ip_field(field: &str) -> Option<FieldSpec> {
+    use FieldKind::*;
+    let spec = match field {
+        "tos" => FieldSpec::new(1..2, Integer),
+        "len" => FieldSpec::new(2..4, Integer),
+        "id" => FieldSpec::new(4..6, Integer),
+        "ttl" => FieldSpec::new(8..9, Integer),
+        "protocol" => FieldSpec::new(9..10, Integer),
+        "chksum" => FieldSpec::new(10..12, Integer),
+        "src" => FieldSpec::new(12..16, Address),
+        "dst" => FieldSpec::new(16..20, Address),
+        _ => return None,
+    };
+    Some(spec)
+}
+
+/// Returns the location of the named field within an IPv6 header, for the IPv4 fields that have
+/// an equivalent there: `len` is the payload length, `protocol` the next header, and `ttl` the hop
+/// limit. (The traffic class, which `tos` stands for, straddles two bytes, and so is handled on
+/// its own.)
+fn ipv6_field(field: &str) -> Option<FieldSpec> {
+    use FieldKind::*;
+    let spec = match field {
+        "len" => FieldSpec::new(4..6, Integer),
+        "protocol" => FieldSpec::new(6..7, Integer),
+        "ttl" => FieldSpec::new(7..8, Integer),
+        "src" => FieldSpec::new(8..24, Address),
+        "dst" => FieldSpec::new(24..40, Address),
+        _ => return None,
+    };
+    Some(spec)
+}
+
+/// Returns the location of the named field within a TCP header.
+fn tcp_field(field: &str) -> Option<FieldSpec> {
+    use FieldKind::*;
+    let spec = match field {
+        "sport" => FieldSpec::new(0..2, Integer),
+        "dport" => FieldSpec::new(2..4, Integer),
+        "seq" => FieldSpec::new(4..8, Integer),
+        "ack" => FieldSpec::new(8..12, Integer),
+        "flags" => FieldSpec::new(13..14, Flags),
+        "window" => FieldSpec::new(14..16, Integer),
+        "chksum" => FieldSpec::new(16..18, Integer),
+        "urgptr" => FieldSpec::new(18..20, Integer),
+        _ => return None,
+    };
+    Some(spec)
+}
+
+/// Returns the location of the named field within a UDP header.
+fn udp_field(field: &str) -> Option<FieldSpec> {
+    use FieldKind::*;
+    let spec = match field {
+        "sport" => FieldSpec::new(0..2, Integer),
+        "dport" => FieldSpec::new(2..4, Integer),
+        "len" => FieldSpec::new(4..6, Integer),
+        "chksum" => FieldSpec::new(6..8, Integer),
+        _ => return None,
+    };
+    Some(spec)
+}
+
+impl TamperAction {
+    /// Parses `new_value` as the type expected by the field being tampered.
+    fn new_value<T: FromStr>(&self) -> Result<T> {
+        self.new_value.parse().map_err(|_| self.invalid_value())
+    }
+
+    /// Returns the error for a `new_value` that doesn't make sense for this action's field.
+    fn invalid_value(&self) -> Error {
+        Error::InvalidValue {
+            field: format!("{}:{}", self.protocol, self.field),
+            value: self.new_value.clone(),
+        }
+    }
+
+    /// Returns the error for a field that this action's protocol does not have.
+    fn unknown_field(&self) -> Error {
+        Error::UnknownField {
+            protocol: self.protocol.clone(),
+            field: self.field.clone(),
+        }
+    }
+
+    /// Parses `new_value` as a set of TCP flags.
+    fn new_flags(&self) -> Result<u8> {
+        parse_tcp_flags(&self.new_value).map_err(|_| self.invalid_value())
+    }
+
+    /// Returns the error for a mode that this action's field does not support.
+    fn unsupported(&self) -> Error {
+        Error::Unsupported {
+            field: format!("{}:{}", self.protocol, self.field),
+            operation: self.mode.to_string(),
+        }
+    }
+
+    /// Returns the random number generator to use for `corrupt` mode.
+    fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            #[cfg(feature = "std")]
+            None => StdRng::from_entropy(),
+            #[cfg(not(feature = "std"))]
+            None => {
+                use core::sync::atomic::{AtomicU32, Ordering};
+                static RUNS: AtomicU32 = AtomicU32::new(0);
+                StdRng::seed_from_u64(RUNS.fetch_add(1, Ordering::Relaxed).into())
+            }
+        }
+    }
+
+    /// Applies this action's mode to a four-bit field, returning the new value. The field is
+    /// four bits wide, so `add` and `corrupt` wrap around at 16.
+    fn tamper_nibble(&self, value: u8) -> Result<u8> {
+        let mut field = [value];
+        self.tamper_field(&FieldSpec::new(0..1, FieldKind::Integer), &mut field)?;
+        match self.mode {
+            TamperMode::Replace if field[0] > 0x0f => Err(self.invalid_value()),
+            _ => Ok(field[0] & 0x0f),
+        }
+    }
+
+    /// Applies this action's mode to the bytes of a single header field.
+    fn tamper_field(&self, spec: &FieldSpec, field: &mut [u8]) -> Result<()> {
+        match self.mode {
+            TamperMode::Replace => match spec.kind {
+                FieldKind::Address => match parse_ip_addr(&self.new_value) {
+                    Some(IpAddr::V4(addr)) if field.len() == 4 => {
+                        field.copy_from_slice(&addr.octets())
+                    }
+                    Some(IpAddr::V6(addr)) if field.len() == 16 => {
+                        field.copy_from_slice(&addr.octets())
+                    }
+                    _ => return Err(self.invalid_value()),
+                },
+                FieldKind::Integer => {
+                    let value: u64 = self.new_value()?;
+                    if field.len() < 8 && value >> (8 * field.len()) != 0 {
+                        return Err(self.invalid_value());
+                    }
+                    field.copy_from_slice(&value.to_be_bytes()[8 - field.len()..]);
+                }
+                FieldKind::Flags => field[0] = self.new_flags()?,
+            },
+            // Any four (or, for IPv6, sixteen) bytes make an address, but one that happens to be
+            // the old address wouldn't corrupt anything.
+            TamperMode::Corrupt if spec.kind == FieldKind::Address => {
+                let mut rng = self.rng();
+                let old = field.to_vec();
+                while *field == *old {
+                    rng.fill_bytes(field);
+                }
+            }
+            TamperMode::Corrupt => self.rng().fill_bytes(field),
+            TamperMode::Add if spec.kind == FieldKind::Flags => {
+                field[0] |= self.new_flags()?;
+            }
+            TamperMode::Add => {
+                if spec.kind != FieldKind::Integer {
+                    return Err(self.unsupported());
+                }
+
+                // Sum in 64 bits, then keep only the low bytes so the field wraps around at its
+                // own width.
+                let mut current = [0u8; 8];
+                current[8 - field.len()..].copy_from_slice(field);
+                let value = u64::from_be_bytes(current).wrapping_add(self.new_value()?);
+                field.copy_from_slice(&value.to_be_bytes()[8 - field.len()..]);
+            }
+            TamperMode::Clear if spec.kind == FieldKind::Flags => {
+                field[0] &= !self.new_flags()?;
+            }
+            TamperMode::Clear | TamperMode::Compress | TamperMode::Fix => {
+                return Err(self.unsupported())
+            }
+        }
+        Ok(())
+    }
+
+    /// Tampers with the IPv4 header of the packet, then fixes up the header checksum (and, if the
+    /// addresses changed, the TCP or UDP checksum). IPv6 headers are handled by
+    /// [tamper_ipv6](Self::tamper_ipv6), and other packets are left untouched.
+    fn tamper_ip(&self, pkt: &mut Packet) -> Result<()> {
+        match self.field.as_str() {
+            "frag" => return self.tamper_ip_frag(pkt),
+            "version" => return self.tamper_ip_version(pkt),
+            "ihl" => return self.tamper_ip_ihl(pkt),
+            _ => {}
+        }
+        let spec = ip_field(&self.field).ok_or_else(|| self.unknown_field())?;
+        if self.mode == TamperMode::Fix {
+            return self.fix_ip(pkt);
+        }
+
+        let header = match pkt.layers() {
+            Some(layers) if layers.ip_version == 4 => layers.ip.clone(),
+            Some(layers) if layers.ip_version == 6 => {
+                return self.tamper_ipv6(pkt, layers.ip.start)
+            }
+            _ => return Ok(()),
+        };
+        let header = &mut pkt.as_mut_slice()[header];
+
+        self.tamper_field(&spec, &mut header[spec.range.clone()])?;
+
+        // Tampering with the checksum itself is only meaningful if it isn't immediately fixed.
+        if self.field != "chksum" {
+            update_ipv4_checksum(pkt);
+        }
+
+        // The addresses are part of the TCP and UDP pseudo-headers.
+        if spec.kind == FieldKind::Address {
+            update_tcp_checksum(pkt);
+            refresh_udp_checksum(pkt);
+        }
+
+        Ok(())
+    }
+
+    /// Tampers with the IPv6 header that starts at `header`, using the IPv4 field names the way IP
+    /// triggers do, then fixes up the TCP or UDP checksum if the addresses changed. Fields without
+    /// an IPv6 equivalent, such as `id`, are left untouched.
+    fn tamper_ipv6(&self, pkt: &mut Packet, header: usize) -> Result<()> {
+        let header = &mut pkt.as_mut_slice()[header..header + 40];
+        if self.field == "tos" {
+            let mut class = [header[0] << 4 | header[1] >> 4];
+            self.tamper_field(&FieldSpec::new(0..1, FieldKind::Integer), &mut class)?;
+            header[0] = (header[0] & 0xf0) | class[0] >> 4;
+            header[1] = class[0] << 4 | (header[1] & 0x0f);
+            return Ok(());
+        }
+        let spec = match ipv6_field(&self.field) {
+            Some(spec) => spec,
+            None => return Ok(()),
+        };
+
+        self.tamper_field(&spec, &mut header[spec.range.clone()])?;
+        if spec.kind == FieldKind::Address {
+            update_tcp_checksum(pkt);
+            refresh_udp_checksum(pkt);
+        }
+        Ok(())
+    }
+
+    /// Tampers with the IP version, then fixes up the IPv4 header checksum. Packets without an
+    /// IPv4 header are left untouched.
+    fn tamper_ip_version(&self, pkt: &mut Packet) -> Result<()> {
+        let header = match pkt.layers() {
+            Some(layers) if layers.ip_version == 4 => layers.ip.clone(),
+            _ => return Ok(()),
+        };
+        let version = self.tamper_nibble(pkt.as_slice()[header.start] >> 4)?;
+
+        // The packet may no longer parse as IPv4, so the header is fixed up where it was.
+        let header = &mut pkt.as_mut_slice()[header];
+        header[0] = version << 4 | (header[0] & 0x0f);
+        fix_ipv4_header_checksum(header);
+        Ok(())
+    }
+
+    /// Tampers with the IPv4 header length, then resizes the header to match it, the same way
+    /// as for the TCP data offset: NOP options are added to the end of the header when it grows,
+    /// and options are cut off the end when it shrinks, so an IHL below 5 leaves a 20-byte
+    /// header behind. The total length and header checksum are fixed up either way. Packets
+    /// without an IPv4 header are left untouched.
+    fn tamper_ip_ihl(&self, pkt: &mut Packet) -> Result<()> {
+        let layers = match pkt.layers() {
+            Some(layers) if layers.ip_version == 4 => layers.clone(),
+            _ => return Ok(()),
+        };
+        let header = layers.ip.start;
+        let ihl = self.tamper_nibble(pkt.as_slice()[header] & 0x0f)?;
+
+        let old_len = layers.ip.len();
+        let new_len = 4 * ihl.max(5) as usize;
+        let total_len = u16::try_from(layers.transport.end + new_len - old_len - header)
+            .map_err(|_| self.invalid_value())?;
+
+        let bytes = pkt.as_mut_vec();
+        bytes.truncate(layers.transport.end);
+        if new_len > old_len {
+            let nops = core::iter::repeat_n(IP_OPTION_NOP, new_len - old_len);
+            bytes.splice(layers.ip.end..layers.ip.end, nops);
+        } else {
+            bytes.drain(header + new_len..layers.ip.end);
+        }
+
+        let header = &mut bytes[header..header + new_len];
+        header[0] = (header[0] & 0xf0) | ihl;
+        header[2..4].copy_from_slice(&total_len.to_be_bytes());
+        fix_ipv4_header_checksum(header);
+        Ok(())
+    }
+
+    /// Recomputes the IPv4 total length or header checksum. The total length is set to cover all
+    /// of the bytes after the start of the IP header, the way [fix_lengths] would, and the
+    /// checksum is then fixed up to match, as it is for the other fields. Packets without an IPv4
+    /// header are left untouched.
+    fn fix_ip(&self, pkt: &mut Packet) -> Result<()> {
+        let header = match pkt.layers() {
+            Some(layers) if layers.ip_version == 4 => layers.ip.start,
+            Some(layers) if layers.ip_version == 6 => return self.fix_ipv6(pkt, layers.ip.start),
+            _ => return Ok(()),
+        };
+        match self.field.as_str() {
+            "chksum" => {}
+            "len" => {
+                let len = u16::try_from(pkt.len() - header).map_err(|_| Error::MalformedPacket)?;
+                pkt.as_mut_slice()[header + 2..header + 4].copy_from_slice(&len.to_be_bytes());
+            }
+            _ => return Err(self.unsupported()),
+        }
+        update_ipv4_checksum(pkt);
+        Ok(())
+    }
+
+    /// Fixes up the payload length of the IPv6 header that starts at `header`. IPv6 headers have
+    /// no checksum, so there is nothing to fix for `chksum`.
+    fn fix_ipv6(&self, pkt: &mut Packet, header: usize) -> Result<()> {
+        match self.field.as_str() {
+            "chksum" => {}
+            "len" => {
+                let len = pkt.len() - header - 40;
+                let len = u16::try_from(len).map_err(|_| Error::MalformedPacket)?;
+                pkt.as_mut_slice()[header + 4..header + 6].copy_from_slice(&len.to_be_bytes());
+            }
+            _ => return Err(self.unsupported()),
+        }
+        Ok(())
+    }
+
+    /// Tampers with the 13-bit fragment offset of an IPv4 header, leaving the flags that share its
+    /// two bytes alone, then fixes up the header checksum. Packets without an IPv4 header are left
+    /// untouched.
+    ///
+    /// `add` and `corrupt` wrap around at 8192, the same as the field does.
+    fn tamper_ip_frag(&self, pkt: &mut Packet) -> Result<()> {
+        let header = match pkt.layers() {
+            Some(layers) if layers.ip_version == 4 => layers.ip.start,
+            _ => return Ok(()),
+        };
+        let bytes = &mut pkt.as_mut_slice()[header + 6..header + 8];
+        let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+
+        let mut frag = (word & IP_FRAG_OFFSET).to_be_bytes();
+        self.tamper_field(&FieldSpec::new(0..2, FieldKind::Integer), &mut frag)?;
+        let frag = u16::from_be_bytes(frag);
+        if self.mode == TamperMode::Replace && frag > IP_FRAG_OFFSET {
+            return Err(self.invalid_value());
+        }
+        bytes.copy_from_slice(&(word & !IP_FRAG_OFFSET | frag & IP_FRAG_OFFSET).to_be_bytes());
+
+        update_ipv4_checksum(pkt);
+        Ok(())
+    }
+
+    /// Returns the bytes that `new_value` represents when replacing a payload: a string starting
+    /// with `0x` is decoded as hex, as are [`hex:` and `b64:` values](crate::encoding), and
+    /// anything else is used as-is.
+    fn payload_value(&self) -> Result<Vec<u8>> {
+        let value = match self.new_value.strip_prefix("0x") {
+            Some(hex) => decode_hex(hex),
+            None => decode_bytes(&self.new_value).map(Cow::into_owned),
+        };
+        value.ok_or_else(|| self.invalid_value())
+    }
+
+    /// Replaces the data carried by the transport layer of an IPv4 packet, then fixes up the
+    /// lengths and checksums to match. Any link-layer padding after the datagram is dropped.
+    fn replace_payload(&self, pkt: &mut Packet, layers: &Layers, data: Vec<u8>) -> Result<()> {
+        let payload = match &layers.payload {
+            Some(payload) => payload.clone(),
+            None => return Ok(()),
+        };
+        if u16::try_from(payload.start + data.len() - layers.ip.start).is_err() {
+            return Err(self.invalid_value());
+        }
+
+        let bytes = pkt.as_mut_vec();
+        bytes.truncate(payload.end);
+        bytes.splice(payload, data);
+        fix_lengths(pkt);
+        Ok(())
+    }
+
+    /// Tampers with the data carried by the TCP segment, then fixes up the lengths and checksums.
+    /// Packets without a TCP header are left untouched.
+    fn tamper_tcp_payload(&self, pkt: &mut Packet) -> Result<()> {
+        let layers = match pkt.layers() {
+            Some(layers)
+                if layers.ip_version == 4
+                    && layers.protocol == PROTO_TCP
+                    && layers.payload.is_some() =>
+            {
+                layers.clone()
+            }
+            _ => return Ok(()),
+        };
+
+        match self.mode {
+            TamperMode::Corrupt => {
+                let payload = layers.payload.unwrap();
+                self.rng().fill_bytes(&mut pkt.as_mut_slice()[payload]);
+                update_tcp_checksum(pkt);
+                Ok(())
+            }
+            TamperMode::Replace => self.replace_payload(pkt, &layers, self.payload_value()?),
+            TamperMode::Add | TamperMode::Clear | TamperMode::Compress | TamperMode::Fix => {
+                Err(self.unsupported())
+            }
+        }
+    }
+
+    /// Tampers with the TCP data offset, then resizes the header to match it: NOP options are
+    /// added to the end of the header when it grows, and options are cut off the end when it
+    /// shrinks. Since only the options can go, a data offset below 5 leaves a 20-byte header
+    /// behind, which no longer parses (so its checksum is left alone). Packets without a TCP header
+    /// are left untouched.
+    fn tamper_tcp_dataofs(&self, pkt: &mut Packet) -> Result<()> {
+        let layers = match pkt.layers() {
+            Some(layers)
+                if layers.ip_version == 4
+                    && layers.protocol == PROTO_TCP
+                    && layers.payload.is_some() =>
+            {
+                layers.clone()
+            }
+            _ => return Ok(()),
+        };
+        let header = layers.transport.start;
+        let payload = layers.payload.clone().unwrap();
+
+        let dataofs = self.tamper_nibble(pkt.as_slice()[header + 12] >> 4)?;
+
+        let old_len = payload.start - header;
+        let new_len = 4 * dataofs.max(5) as usize;
+        if u16::try_from(layers.transport.end + new_len - old_len - layers.ip.start).is_err() {
+            return Err(self.invalid_value());
+        }
+
+        let bytes = pkt.as_mut_vec();
+        bytes.truncate(layers.transport.end);
+        if new_len > old_len {
+            let nops = core::iter::repeat_n(TCP_OPTION_NOP, new_len - old_len);
+            bytes.splice(payload.start..payload.start, nops);
+        } else {
+            bytes.drain(header + new_len..payload.start);
+        }
+        bytes[header + 12] = dataofs << 4 | (bytes[header + 12] & 0x0f);
+
+        fix_lengths(pkt);
+        Ok(())
+    }
+
+    /// Tampers with a DNS message carried by an IPv4 UDP datagram to or from port 53. Unlike the
+    /// other protocols, packets that don't carry a DNS message are an error.
+    fn tamper_dns(&self, pkt: &mut Packet) -> Result<()> {
+        if self.field != "qd" || self.mode != TamperMode::Compress {
+            return Err(self.unsupported());
+        }
+
+        let layers = match (pkt.layers(), pkt.udp_header()) {
+            (Some(layers), Some(header)) if layers.ip_version == 4 => {
+                let sport = u16::from_be_bytes([header[0], header[1]]);
+                let dport = u16::from_be_bytes([header[2], header[3]]);
+                if sport != DNS_PORT && dport != DNS_PORT {
+                    return Err(Error::MalformedPacket);
+                }
+                layers.clone()
+            }
+            _ => return Err(Error::MalformedPacket),
+        };
+
+        let msg = dns::compress_question(pkt.payload().unwrap_or_default())
+            .ok_or(Error::MalformedPacket)?;
+        self.replace_payload(pkt, &layers, msg)
+    }
+
+    /// Tampers with the TCP header of the packet, then fixes up the TCP checksum. Packets without
+    /// a TCP header are left untouched.
+    fn tamper_tcp(&self, pkt: &mut Packet) -> Result<()> {
+        match self.field.as_str() {
+            "load" => return self.tamper_tcp_payload(pkt),
+            "dataofs" => return self.tamper_tcp_dataofs(pkt),
+            _ => {}
+        }
+
+        let spec = tcp_field(&self.field).ok_or_else(|| self.unknown_field())?;
+        if self.mode == TamperMode::Fix {
+            if self.field != "chksum" {
+                return Err(self.unsupported());
+            }
+            update_tcp_checksum(pkt);
+            return Ok(());
+        }
+
+        let header_start = match pkt.layers() {
+            Some(layers)
+                if layers.ip_version == 4
+                    && layers.protocol == PROTO_TCP
+                    && layers.payload.is_some() =>
+            {
+                layers.transport.start
+            }
+            _ => return Ok(()),
+        };
+        let header = &mut pkt.as_mut_slice()[header_start..header_start + 20];
+
+        self.tamper_field(&spec, &mut header[spec.range.clone()])?;
+        if self.urgent_flag && self.field == "urgptr" && header[18..20] != [0, 0] {
+            header[13] |= TCP_FLAG_URG;
+        }
+
+        if self.field != "chksum" {
+            update_tcp_checksum(pkt);
+        }
+
+        Ok(())
+    }
+
+    /// Tampers with the UDP header of the packet, then fixes up the UDP checksum. Packets without
+    /// a UDP header are left untouched.
+    fn tamper_udp(&self, pkt: &mut Packet) -> Result<()> {
+        let spec = udp_field(&self.field).ok_or_else(|| self.unknown_field())?;
+        if self.mode == TamperMode::Fix {
+            match self.field.as_str() {
+                "chksum" => update_udp_checksum(pkt),
+                // The datagram runs to the end of the packet, the same as after a fragment.
+                "len" if pkt.udp_header().is_some() => fix_lengths(pkt),
+                "len" => {}
+                _ => return Err(self.unsupported()),
+            }
+            return Ok(());
+        }
+
+        let header_start = match pkt.layers() {
+            Some(layers) if layers.protocol == PROTO_UDP && layers.payload.is_some() => {
+                layers.transport.start
+            }
+            _ => return Ok(()),
+        };
+        let header = &mut pkt.as_mut_slice()[header_start..header_start + 8];
+        self.tamper_field(&spec, &mut header[spec.range.clone()])?;
+
+        if self.field != "chksum" {
+            refresh_udp_checksum(pkt);
+        }
+        Ok(())
+    }
+}
+
+/// Recomputes the UDP checksum of an IP packet, unless it is an IPv4 packet whose checksum is zero:
+/// that means the sender didn't compute one, so receivers don't check it, and tampering with the
+/// datagram shouldn't add one. IPv6 requires the checksum, so there it is always recomputed.
+fn refresh_udp_checksum(pkt: &mut Packet) {
+    let unchecked = pkt.ip_version() == Some(4)
+        && pkt
+            .udp_header()
+            .is_some_and(|header| header[6..8] == [0, 0]);
+    if pkt.udp_header().is_some() && !unchecked {
+        update_udp_checksum(pkt);
+    }
+}
+
+/// Recomputes the checksum of an IPv4 header, whether or not the packet still parses as IPv4.
+fn fix_ipv4_header_checksum(header: &mut [u8]) {
+    header[10..12].copy_from_slice(&[0, 0]);
+    let checksum = ipv4_checksum(header);
+    header[10..12].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Returns the offsets of the IPv4 header checksum and of the TCP or UDP checksum of `pkt`, for
+/// whichever of the two it has.
+fn checksum_offsets(pkt: &Packet) -> [Option<usize>; 2] {
+    let layers = match pkt.layers() {
+        Some(layers) => layers,
+        None => return [None, None],
+    };
+    let ip = (layers.ip_version == 4).then_some(layers.ip.start + 10);
+    let transport = match layers.protocol {
+        _ if layers.payload.is_none() => None,
+        PROTO_TCP => Some(layers.transport.start + 16),
+        PROTO_UDP => Some(layers.transport.start + 6),
+        _ => None,
+    };
+    [ip, transport]
+}
+
+impl TamperAction {
+    /// Returns an error if this action would fail on any packet it could tamper with: its protocol
+    /// has no such field, the field doesn't support the mode, or the new value doesn't fit.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let spec = match (self.protocol.to_lowercase().as_str(), self.field.as_str()) {
+            ("ip", "frag") => FieldSpec::new(0..2, FieldKind::Integer),
+            ("ip", "version" | "ihl") => FieldSpec::new(0..1, FieldKind::Integer),
+            ("ip", field) => ip_field(field).ok_or_else(|| self.unknown_field())?,
+            ("tcp", "load") => {
+                return match self.mode {
+                    TamperMode::Replace => self.payload_value().map(drop),
+                    TamperMode::Corrupt => Ok(()),
+                    _ => Err(self.unsupported()),
+                }
+            }
+            ("tcp", "dataofs") => FieldSpec::new(0..1, FieldKind::Integer),
+            ("tcp", field) => tcp_field(field).ok_or_else(|| self.unknown_field())?,
+            ("udp", field) => udp_field(field).ok_or_else(|| self.unknown_field())?,
+            ("dns", _) if self.field == "qd" && self.mode == TamperMode::Compress => return Ok(()),
+            ("dns", _) => return Err(self.unsupported()),
+            _ => return Err(self.unknown_field()),
+        };
+        if self.mode == TamperMode::Fix {
+            return match (self.protocol.to_lowercase().as_str(), self.field.as_str()) {
+                ("ip", "chksum" | "len") | ("tcp" | "udp", "chksum") | ("udp", "len") => Ok(()),
+                _ => Err(self.unsupported()),
+            };
+        }
+        // Corrupting a field can't fail, and would only use up randomness here.
+        if self.mode == TamperMode::Corrupt {
+            return Ok(());
+        }
+        // An address of either family is fine, since it only has to fit the packets of its own.
+        if spec.kind == FieldKind::Address && self.mode == TamperMode::Replace {
+            return parse_ip_addr(&self.new_value)
+                .map(drop)
+                .ok_or_else(|| self.invalid_value());
+        }
+
+        // Tamper with a blank field of the right size to check the mode and value.
+        let mut field = vec![0; spec.range.len()];
+        self.tamper_field(&spec, &mut field)?;
+        let max = match self.field.as_str() {
+            "dataofs" | "version" | "ihl" => 0x0f,
+            "frag" => IP_FRAG_OFFSET as u64,
+            _ => u64::MAX,
+        };
+        let value = field.iter().fold(0u64, |value, &b| (value << 8) | b as u64);
+        if self.mode == TamperMode::Replace && value > max {
+            return Err(self.invalid_value());
+        }
+        Ok(())
+    }
+
+    /// Tampers with the packet, without running the subordinate action.
+    fn tamper(&self, pkt: &mut Packet) -> Result<()> {
+        let before = self.stale_checksums.then(|| {
+            let bytes = pkt.as_slice();
+            checksum_offsets(pkt).map(|offset| offset.map(|i| (i, [bytes[i], bytes[i + 1]])))
+        });
+
+        match self.protocol.to_lowercase().as_str() {
+            "ip" => self.tamper_ip(pkt)?,
+            "tcp" => self.tamper_tcp(pkt)?,
+            "udp" => self.tamper_udp(pkt)?,
+            "dns" => self.tamper_dns(pkt)?,
+            _ => return Err(self.unknown_field()),
+        }
+
+        // Put back the checksums that were fixed up, but not one that was itself the target (or
+        // one that has moved, say because the transport protocol changed).
+        let before = match before {
+            Some(before) => before,
+            None => return Ok(()),
+        };
+        let target = match (self.protocol.to_lowercase().as_str(), self.field.as_str()) {
+            ("ip", "chksum") => Some(0),
+            ("tcp" | "udp", "chksum") => Some(1),
+            _ => None,
+        };
+        let after = checksum_offsets(pkt);
+        for (layer, (offset, old)) in after.into_iter().zip(before).enumerate() {
+            match old {
+                Some((i, old)) if offset == Some(i) && target != Some(layer) => {
+                    pkt.as_mut_slice()[i..i + 2].copy_from_slice(&old)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Tampers with the packet the same way [run](Action::run) does, but also returns a
+    /// [TamperUndo] that puts the packet back the way it was.
+    ///
+    /// Only this action is applied; the subordinate action is not run, since it may turn the
+    /// packet into any number of others. `corrupt` mode can't be undone in any meaningful sense
+    /// (the point is that the old value is lost), so it is an error.
+    ///
+    /// ```
+    /// use geneva::{Packet, SendAction, TamperAction, TamperMode};
+    ///
+    /// let tamper = TamperAction::new(
+    ///     "IP".to_string(),
+    ///     "ttl".to_string(),
+    ///     "1".to_string(),
+    ///     TamperMode::Replace,
+    ///     SendAction::default().into(),
+    /// )
+    /// .unwrap();
+    ///
+    /// let original = Packet::new(vec![
+    ///     0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2,
+    /// ]);
+    /// let (mut pkt, undo) = tamper.run_reversible(original.clone()).unwrap();
+    /// assert_eq!(pkt.as_slice()[8], 1);
+    ///
+    /// undo.undo(&mut pkt).unwrap();
+    /// assert_eq!(pkt.as_slice(), original.as_slice());
+    /// ```
+    pub fn run_reversible(&self, mut pkt: Packet) -> Result<(Packet, TamperUndo)> {
+        if self.mode == TamperMode::Corrupt {
+            return Err(Error::Unsupported {
+                field: format!("{}:{}", self.protocol, self.field),
+                operation: "undoing corrupt".to_string(),
+            });
+        }
+
+        let before = pkt.as_slice().to_vec();
+        self.tamper(&mut pkt)?;
+
+        let after = pkt.as_slice();
+        let prior = if before.len() == after.len() {
+            let changed = before.iter().zip(after).enumerate();
+            Prior::Bytes(
+                changed
+                    .filter(|(_, (old, new))| old != new)
+                    .map(|(i, (old, _))| (i, *old))
+                    .collect(),
+            )
+        } else {
+            Prior::Packet(before)
+        };
+        let len = after.len();
+        Ok((pkt, TamperUndo { prior, len }))
+    }
 }
 
 impl Action for TamperAction {
-    fn run(&self, _pkt: Packet) -> Result<Vec<Packet>> {
-        unimplemented!()
+    fn run(&self, mut pkt: Packet) -> Result<Vec<Packet>> {
+        self.tamper(&mut pkt)?;
+        self.action.run(pkt)
+    }
+
+    fn may_drop(&self) -> bool {
+        self.action.may_drop()
+    }
+}
+
+/// Restores a packet that was tampered with by [TamperAction::run_reversible].
+///
+/// It records the bytes that the tamper overwrote, including any checksums or lengths that were
+/// fixed up as a result. If the tamper changed the size of the packet, the whole original packet
+/// is kept instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TamperUndo {
+    prior: Prior,
+    len: usize,
+}
+
+/// What a packet looked like before it was tampered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Prior {
+    /// The offsets and old values of the bytes that changed.
+    Bytes(Vec<(usize, u8)>),
+    /// The whole packet.
+    Packet(Vec<u8>),
+}
+
+impl TamperUndo {
+    /// Puts `pkt` back the way it was before it was tampered with.
+    ///
+    /// Returns [Error::MalformedPacket] if `pkt` isn't the size that the tamper left it, in which
+    /// case it can't be the packet that was tampered with and is left alone.
+    pub fn undo(&self, pkt: &mut Packet) -> Result<()> {
+        if pkt.len() != self.len {
+            return Err(Error::MalformedPacket);
+        }
+
+        match &self.prior {
+            Prior::Bytes(prior) => {
+                let bytes = pkt.as_mut_slice();
+                for &(i, old) in prior {
+                    bytes[i] = old;
+                }
+            }
+            Prior::Packet(prior) => *pkt.as_mut_vec() = prior.clone(),
+        }
+        Ok(())
     }
 }
 
 impl fmt::Display for TamperAction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let new_value = if let TamperMode::Replace = self.mode {
-            format!(":{}", self.new_value)
-        } else {
+        let new_value = match self.mode {
+            // The colons of an IPv6 address would end the value early, so it goes in brackets.
+            TamperMode::Replace
+                if self.protocol.eq_ignore_ascii_case("ip")
+                    && matches!(self.field.as_str(), "src" | "dst") =>
+            {
+                format!(":{}", bracketed(&self.new_value))
+            }
+            TamperMode::Replace | TamperMode::Add | TamperMode::Clear => {
+                format!(":{}", self.new_value)
+            }
+            TamperMode::Corrupt | TamperMode::Compress | TamperMode::Fix => "".to_string(),
+        };
+
+        // Like the other actions, a subordinate "send" is elided entirely.
+        let action = self.action.to_string();
+        let args = if action.is_empty() {
             "".to_string()
+        } else {
+            format!("({},)", action)
+        };
+
+        let options = match (self.urgent_flag, self.stale_checksums) {
+            (true, true) => "+urg+stale",
+            (true, false) => "+urg",
+            (false, true) => "+stale",
+            (false, false) => "",
         };
 
         write!(
             f,
-            "tamper{{{}:{}:{}{}}}({},)",
-            self.protocol, self.field, self.mode, new_value, self.action
+            "tamper{{{}:{}:{}{}{}}}{}",
+            self.protocol, self.field, self.mode, options, new_value, args
+        )
+    }
+}
+
+impl From<TamperAction> for GenevaAction {
+    fn from(a: TamperAction) -> Self {
+        Self::Tamper(a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::{DropAction, SendAction};
+    use crate::checksum::{ipv4_checksum, tcp_checksum, udp_checksum, update_udp_checksum};
+    use core::net::{Ipv4Addr, Ipv6Addr};
+
+    /// A 20-byte IPv4 header from 192.168.1.10 to 10.0.0.2 with a TTL of 128 and a valid
+    /// checksum.
+    fn ipv4_packet() -> Packet {
+        Packet::new(vec![
+            0x45, 0x00, 0x00, 0x14, 0x1c, 0x46, 0x40, 0x00, 0x80, 0x06, 0x12, 0xea, 192, 168, 1,
+            10, 10, 0, 0, 2,
+        ])
+    }
+
+    /// An IPv4 packet carrying a 20-byte TCP SYN segment from 10.0.0.1:12345 to 10.0.0.2:80.
+    fn tcp_packet() -> Packet {
+        Packet::new(vec![
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x26, 0xcd, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00,
+            0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+        ])
+    }
+
+    fn assert_tcp_checksum_valid(pkt: &Packet) {
+        let (ip_header, segment) = pkt.as_slice().split_at(20);
+        assert_eq!(tcp_checksum(ip_header, segment), 0);
+    }
+
+    fn corrupt(protocol: &str, field: &str, seed: u64) -> TamperAction {
+        TamperAction::new(
+            protocol.to_string(),
+            field.to_string(),
+            "".to_string(),
+            TamperMode::Corrupt,
+            SendAction::default().into(),
+        )
+        .unwrap()
+        .with_seed(seed)
+    }
+
+    /// Asserts that `tampered` differs from `original` somewhere in `range`, and nowhere else
+    /// except for the bytes in `allowed` (e.g., a checksum).
+    fn assert_only_changed(
+        original: &Packet,
+        tampered: &Packet,
+        range: Range<usize>,
+        allowed: Range<usize>,
+    ) {
+        let (original, tampered) = (original.as_slice(), tampered.as_slice());
+        assert_eq!(original.len(), tampered.len());
+        assert_ne!(original[range.clone()], tampered[range.clone()]);
+        for i in 0..original.len() {
+            if !range.contains(&i) && !allowed.contains(&i) {
+                assert_eq!(original[i], tampered[i], "byte {} changed", i);
+            }
+        }
+    }
+
+    fn replace(protocol: &str, field: &str, value: &str) -> TamperAction {
+        TamperAction::new(
+            protocol.to_string(),
+            field.to_string(),
+            value.to_string(),
+            TamperMode::Replace,
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn field_names_are_normalized() {
+        let ttl = replace("IP", "TTL", "1");
+        assert_eq!(ttl.to_string(), "tamper{IP:ttl:replace:1}");
+        assert_eq!(ttl.run(tcp_packet()).unwrap()[0].as_slice()[8], 1);
+
+        let src = replace("IP", "source", "1.2.3.4");
+        assert_eq!(src.to_string(), "tamper{IP:src:replace:1.2.3.4}");
+        assert_eq!(
+            src.run(tcp_packet()).unwrap()[0].as_slice()[12..16],
+            [1, 2, 3, 4]
+        );
+
+        let sport = replace("TCP", "SourcePort", "513");
+        assert_eq!(sport.to_string(), "tamper{TCP:sport:replace:513}");
+        let pkt = sport.run(tcp_packet()).unwrap().remove(0);
+        assert_eq!(pkt.as_slice()[20..22], [0x02, 0x01]);
+        assert_tcp_checksum_valid(&pkt);
+
+        assert_eq!(
+            replace("UDP", "destinationport", "53").to_string(),
+            "tamper{UDP:dport:replace:53}"
+        );
+    }
+
+    #[test]
+    fn tamper_urgent_pointer() {
+        let pkt = replace("TCP", "urgptr", "513")
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.as_slice()[38..40], [0x02, 0x01]);
+        assert_eq!(pkt.as_slice()[33], 0x02);
+        assert_tcp_checksum_valid(&pkt);
+
+        let urgent = replace("TCP", "urgptr", "513").with_urgent_flag();
+        let pkt = urgent.run(tcp_packet()).unwrap().remove(0);
+        assert_eq!(pkt.as_slice()[38..40], [0x02, 0x01]);
+        assert_eq!(pkt.as_slice()[33], 0x22);
+        assert_tcp_checksum_valid(&pkt);
+
+        // A zero pointer doesn't need the flag.
+        let pkt = replace("TCP", "urgptr", "0")
+            .with_urgent_flag()
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.as_slice()[33], 0x02);
+        assert!(replace("TCP", "urgptr", "65536").run(tcp_packet()).is_err());
+    }
+
+    #[test]
+    fn ipv6_addresses_are_bracketed() {
+        assert_eq!(
+            replace("IP", "src", "2001:db8::1").to_string(),
+            "tamper{IP:src:replace:[2001:db8::1]}"
+        );
+        assert_eq!(
+            replace("IP", "dst", "[2001:db8::1]").to_string(),
+            "tamper{IP:dst:replace:[2001:db8::1]}"
+        );
+        assert_eq!(
+            replace("IP", "dst", "10.0.0.1").to_string(),
+            "tamper{IP:dst:replace:10.0.0.1}"
+        );
+    }
+
+    #[test]
+    fn tamper_str() {
+        assert_eq!(
+            replace("TCP", "flags", "SA").to_string(),
+            "tamper{TCP:flags:replace:SA}"
+        );
+        assert_eq!(
+            corrupt("IP", "ttl", 0).to_string(),
+            "tamper{IP:ttl:corrupt}"
+        );
+        assert_eq!(
+            add("TCP", "seq", "100").to_string(),
+            "tamper{TCP:seq:add:100}"
+        );
+
+        let mut a = replace("IP", "ttl", "64");
+        *a.action = DropAction::default().into();
+        assert_eq!(a.to_string(), "tamper{IP:ttl:replace:64}(drop,)");
+    }
+
+    #[test]
+    fn tamper_mode_from_str() {
+        assert!(matches!(
+            TamperMode::from_str("replace"),
+            Ok(TamperMode::Replace)
+        ));
+        assert!(matches!(
+            TamperMode::from_str("corrupt"),
+            Ok(TamperMode::Corrupt)
+        ));
+        assert!(matches!(TamperMode::from_str("add"), Ok(TamperMode::Add)));
+        assert!(matches!(
+            TamperMode::from_str("compress"),
+            Ok(TamperMode::Compress)
+        ));
+        assert!(matches!(
+            TamperMode::from_str("clear"),
+            Ok(TamperMode::Clear)
+        ));
+        assert!(matches!(TamperMode::from_str("fix"), Ok(TamperMode::Fix)));
+        assert!(TamperMode::from_str("bogus").is_err());
+    }
+
+    #[test]
+    fn replace_then_undo() {
+        for (tamper, pkt) in [
+            (replace("IP", "ttl", "1"), ipv4_packet()),
+            (replace("IP", "src", "192.168.1.1"), tcp_packet()),
+            (replace("TCP", "window", "0"), tcp_packet()),
+            (replace("TCP", "flags", "RA"), tcp_packet()),
+            (replace("TCP", "load", "GET / HTTP/1.1"), tcp_packet()),
+        ] {
+            let (mut tampered, undo) = tamper.run_reversible(pkt.clone()).unwrap();
+            assert_ne!(tampered.as_slice(), pkt.as_slice(), "{}", tamper);
+
+            undo.undo(&mut tampered).unwrap();
+            assert_eq!(tampered.as_slice(), pkt.as_slice(), "{}", tamper);
+        }
+    }
+
+    #[test]
+    fn add_then_undo() {
+        let add = TamperAction::new(
+            "TCP".to_string(),
+            "seq".to_string(),
+            "4294967295".to_string(),
+            TamperMode::Add,
+            DropAction::default().into(),
+        )
+        .unwrap();
+
+        // The subordinate drop doesn't run.
+        let (mut pkt, undo) = add.run_reversible(tcp_packet()).unwrap();
+        assert_eq!(pkt.as_slice()[24..28], 999u32.to_be_bytes());
+        assert_tcp_checksum_valid(&pkt);
+
+        undo.undo(&mut pkt).unwrap();
+        assert_eq!(pkt, tcp_packet());
+    }
+
+    #[test]
+    fn undo_checks_the_packet() {
+        let (_, undo) = replace("TCP", "load", "abc")
+            .run_reversible(tcp_packet())
+            .unwrap();
+        let mut other = tcp_packet();
+        assert!(matches!(undo.undo(&mut other), Err(Error::MalformedPacket)));
+        assert_eq!(other, tcp_packet());
+
+        // Nothing to undo for a packet the tamper didn't touch.
+        let pkt = Packet::new(vec![0x60, 0, 0, 0]);
+        let (mut tampered, undo) = replace("IP", "ttl", "1")
+            .run_reversible(pkt.clone())
+            .unwrap();
+        undo.undo(&mut tampered).unwrap();
+        assert_eq!(tampered, pkt);
+    }
+
+    #[test]
+    fn corrupt_cannot_be_undone() {
+        let result = corrupt("TCP", "seq", 1).run_reversible(tcp_packet());
+        assert!(matches!(result, Err(Error::Unsupported { .. })));
+    }
+
+    #[test]
+    fn sample_checksum_is_valid() {
+        assert_eq!(ipv4_checksum(ipv4_packet().as_slice()), 0);
+    }
+
+    #[test]
+    fn replace_ip_ttl() {
+        let result = replace("IP", "ttl", "64").run(ipv4_packet());
+        assert!(result.is_ok());
+
+        let result = result.unwrap();
+        assert_eq!(result.len(), 1);
+
+        let bytes = result[0].as_slice();
+        assert_eq!(bytes[8], 64);
+        assert_eq!(ipv4_checksum(bytes), 0);
+    }
+
+    #[test]
+    fn replace_ip_frag_keeps_flags() {
+        // ipv4_packet() has the don't-fragment flag set and a zero offset.
+        let pkt = replace("IP", "frag", "185").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[6..8], [0x40, 0xb9]);
+        assert_eq!(ipv4_checksum(pkt[0].as_slice()), 0);
+        assert_only_changed(&ipv4_packet(), &pkt[0], 7..8, 10..12);
+
+        let mut bytes = ipv4_packet().as_slice().to_vec();
+        bytes[6..8].copy_from_slice(&[0xff, 0xff]);
+        let pkt = replace("IP", "frag", "0").run(Packet::new(bytes)).unwrap();
+        assert_eq!(pkt[0].as_slice()[6..8], [0xe0, 0x00]);
+
+        let pkt = replace("IP", "frag", "8191").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[6..8], [0x5f, 0xff]);
+        let result = replace("IP", "frag", "8192").run(ipv4_packet());
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+        assert!(replace("IP", "frag", "8192").validate().is_err());
+
+        // Adding wraps around within the offset, without carrying into the flags.
+        let pkt = add("IP", "frag", "8193").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[6..8], [0x40, 0x01]);
+
+        let pkt = corrupt("IP", "frag", 7).run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[6] & 0xe0, 0x40);
+        assert_eq!(ipv4_checksum(pkt[0].as_slice()), 0);
+    }
+
+    #[test]
+    fn replace_ip_id() {
+        let pkt = replace("IP", "id", "65535").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[4..6], [0xff, 0xff]);
+        assert_eq!(ipv4_checksum(pkt[0].as_slice()), 0);
+        assert_only_changed(&ipv4_packet(), &pkt[0], 4..6, 10..12);
+        assert!(replace("IP", "id", "65536").run(ipv4_packet()).is_err());
+    }
+
+    #[test]
+    fn replace_ip_fields() {
+        let pkt = replace("IP", "tos", "16").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[1], 16);
+
+        let pkt = replace("IP", "id", "4660").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[4..6], [0x12, 0x34]);
+
+        let pkt = replace("IP", "src", "1.2.3.4").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[12..16], [1, 2, 3, 4]);
+        assert_eq!(ipv4_checksum(pkt[0].as_slice()), 0);
+
+        let pkt = replace("IP", "dst", "5.6.7.8").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[16..20], [5, 6, 7, 8]);
+        assert_eq!(ipv4_checksum(pkt[0].as_slice()), 0);
+    }
+
+    #[test]
+    fn replace_invalid_value() {
+        let result = replace("IP", "ttl", "256").run(ipv4_packet());
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+        let result = replace("IP", "src", "1.2.3").run(ipv4_packet());
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn replace_unknown_field() {
+        let result = replace("IP", "bogus", "1").run(ipv4_packet());
+        assert!(matches!(result, Err(Error::UnknownField { .. })));
+    }
+
+    #[test]
+    fn replace_tcp_fields() {
+        let pkt = replace("TCP", "window", "1024").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[34..36], [0x04, 0x00]);
+
+        let pkt = replace("TCP", "seq", "4294967295")
+            .run(tcp_packet())
+            .unwrap();
+        assert_eq!(pkt[0].as_slice()[24..28], [0xff; 4]);
+
+        assert!(replace("TCP", "sport", "65536").run(tcp_packet()).is_err());
+    }
+
+    fn dataofs(mode: TamperMode, value: &str) -> TamperAction {
+        TamperAction::new(
+            "TCP".to_string(),
+            "dataofs".to_string(),
+            value.to_string(),
+            mode,
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    /// A copy of [tcp_packet] with a 4-byte MSS option and a 3-byte payload.
+    fn tcp_packet_with_option() -> Packet {
+        let mut bytes = tcp_packet().as_slice().to_vec();
+        bytes[32] = 0x60;
+        bytes.extend_from_slice(&[0x02, 0x04, 0x05, 0xb4, b'a', b'b', b'c']);
+        let mut pkt = Packet::new(bytes);
+        fix_lengths(&mut pkt);
+        pkt
+    }
+
+    #[test]
+    fn overwritten_tampers_canonicalize_to_the_same_packets() {
+        let nested = |first: TamperAction, second: TamperAction| {
+            let first = TamperAction {
+                action: Box::new(second.into()),
+                ..first
+            };
+            GenevaAction::from(first)
+        };
+        for (first, second, collapses) in [
+            (
+                replace("TCP", "window", "1"),
+                replace("TCP", "window", "2"),
+                true,
+            ),
+            (
+                replace("TCP", "window", "1"),
+                replace("TCP", "WINDOW", "2"),
+                true,
+            ),
+            (
+                dataofs(TamperMode::Replace, "5"),
+                dataofs(TamperMode::Replace, "6"),
+                false,
+            ),
+            (replace("IP", "ihl", "5"), replace("IP", "ihl", "6"), false),
+            (
+                replace("TCP", "urgptr", "1").with_urgent_flag(),
+                replace("TCP", "urgptr", "0"),
+                false,
+            ),
+            (
+                replace("TCP", "urgptr", "1"),
+                replace("TCP", "urgptr", "2").with_urgent_flag(),
+                false,
+            ),
+        ] {
+            let action = nested(first, second.clone());
+            let canonical = action.canonicalize();
+            assert_eq!(canonical == second.clone().into(), collapses, "{}", action);
+            for pkt in [tcp_packet(), tcp_packet_with_option()] {
+                let (a, b) = (action.run(pkt.clone()), canonical.run(pkt));
+                assert_eq!(a.unwrap(), b.unwrap(), "{}", action);
+            }
+        }
+    }
+
+    #[test]
+    fn replace_ip_version() {
+        let pkt = replace("IP", "version", "6")
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+        let bytes = pkt.as_slice();
+        assert_eq!(bytes[0], 0x65);
+        assert_eq!(ipv4_checksum(&bytes[..20]), 0);
+        assert_only_changed(&tcp_packet(), &pkt, 0..1, 10..12);
+
+        assert!(replace("IP", "version", "16").run(tcp_packet()).is_err());
+    }
+
+    #[test]
+    fn tamper_ip_ihl_pads_options() {
+        let original = tcp_packet_with_payload(b"hello");
+        let pkt = replace("IP", "ihl", "7")
+            .run(original.clone())
+            .unwrap()
+            .remove(0);
+        let bytes = pkt.as_slice();
+        assert_eq!(pkt.len(), original.len() + 8);
+        assert_eq!(bytes[0], 0x47);
+        assert_eq!(bytes[2..4], (pkt.len() as u16).to_be_bytes());
+        assert_eq!(bytes[20..28], [IP_OPTION_NOP; 8]);
+        assert_eq!(pkt.ip_header().unwrap().len(), 28);
+        assert_eq!(pkt.tcp_header().unwrap(), original.tcp_header().unwrap());
+        assert_eq!(pkt.payload(), Some(&b"hello"[..]));
+        assert_eq!(ipv4_checksum(&bytes[..28]), 0);
+        let (ip_header, segment) = bytes.split_at(28);
+        assert_eq!(tcp_checksum(ip_header, segment), 0);
+
+        // Shrinking the header again cuts the options back off.
+        let pkt = add("IP", "ihl", "14").run(pkt).unwrap().remove(0);
+        assert_eq!(pkt.as_slice(), original.as_slice());
+    }
+
+    #[test]
+    fn ip_ihl_below_minimum() {
+        let pkt = replace("IP", "ihl", "2")
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+        let bytes = pkt.as_slice();
+        assert_eq!(pkt.len(), tcp_packet().len());
+        assert_eq!(bytes[0], 0x42);
+        assert_eq!(ipv4_checksum(&bytes[..20]), 0);
+        assert_eq!(pkt.ip_version(), None);
+
+        assert!(replace("IP", "ihl", "16").run(tcp_packet()).is_err());
+    }
+
+    #[test]
+    fn increase_tcp_dataofs() {
+        let pkt = dataofs(TamperMode::Replace, "8")
+            .run(tcp_packet_with_option())
+            .unwrap()
+            .remove(0);
+        let bytes = pkt.as_slice();
+        assert_eq!(pkt.len(), 20 + 32 + 3);
+        assert_eq!(bytes[2..4], 55u16.to_be_bytes());
+        assert_eq!(bytes[32] >> 4, 8);
+        assert_eq!(pkt.tcp_header().unwrap().len(), 32);
+        assert_eq!(bytes[40..44], [0x02, 0x04, 0x05, 0xb4]);
+        assert_eq!(bytes[44..52], [TCP_OPTION_NOP; 8]);
+        assert_eq!(pkt.payload(), Some(&b"abc"[..]));
+        assert_eq!(ipv4_checksum(&bytes[..20]), 0);
+        assert_tcp_checksum_valid(&pkt);
+
+        let pkt = dataofs(TamperMode::Add, "1")
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.tcp_header().unwrap().len(), 24);
+        assert_eq!(pkt.payload(), Some(&[][..]));
+        assert_tcp_checksum_valid(&pkt);
+    }
+
+    #[test]
+    fn decrease_tcp_dataofs() {
+        let pkt = dataofs(TamperMode::Replace, "5")
+            .run(tcp_packet_with_option())
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.len(), 20 + 20 + 3);
+        assert_eq!(pkt.as_slice()[2..4], 43u16.to_be_bytes());
+        assert_eq!(pkt.payload(), Some(&b"abc"[..]));
+        assert_tcp_checksum_valid(&pkt);
+
+        // The fixed part of the header stays, even if the data offset says otherwise.
+        let pkt = dataofs(TamperMode::Replace, "2")
+            .run(tcp_packet_with_option())
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.len(), 20 + 20 + 3);
+        assert_eq!(pkt.as_slice()[32] >> 4, 2);
+        assert_eq!(pkt.tcp_header(), None);
+    }
+
+    #[test]
+    fn tcp_dataofs_stays_in_range() {
+        assert!(matches!(
+            dataofs(TamperMode::Replace, "16").run(tcp_packet()),
+            Err(Error::InvalidValue { .. })
+        ));
+
+        // 5 + 12 wraps around to 1.
+        let pkt = dataofs(TamperMode::Add, "12")
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.as_slice()[32] >> 4, 1);
+        assert_eq!(pkt.len(), 40);
+
+        for seed in 0..8 {
+            let pkt = corrupt("TCP", "dataofs", seed)
+                .run(tcp_packet())
+                .unwrap()
+                .remove(0);
+            let dataofs = (pkt.as_slice()[32] >> 4) as usize;
+            assert_eq!(pkt.len(), 20 + 4 * dataofs.max(5));
+        }
+
+        // The other bits of the byte are left alone.
+        let mut pkt = tcp_packet();
+        pkt.as_mut_slice()[32] |= 0x01;
+        let pkt = dataofs(TamperMode::Replace, "6")
+            .run(pkt)
+            .unwrap()
+            .remove(0);
+        assert_eq!(pkt.as_slice()[32], 0x61);
+    }
+
+    #[test]
+    fn replace_tcp_window_fixes_checksum() {
+        let pkt = replace("TCP", "window", "1024").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[34..36], 1024u16.to_be_bytes());
+        assert_tcp_checksum_valid(&pkt[0]);
+    }
+
+    #[test]
+    fn tcp_tampering_fixes_checksum() {
+        assert_tcp_checksum_valid(&add("TCP", "seq", "100").run(tcp_packet()).unwrap()[0]);
+        assert_tcp_checksum_valid(&corrupt("TCP", "ack", 1).run(tcp_packet()).unwrap()[0]);
+    }
+
+    #[test]
+    fn replace_ip_address_fixes_tcp_checksum() {
+        let pkt = replace("IP", "dst", "192.0.2.1").run(tcp_packet()).unwrap();
+        assert_eq!(ipv4_checksum(&pkt[0].as_slice()[..20]), 0);
+        assert_tcp_checksum_valid(&pkt[0]);
+    }
+
+    #[test]
+    fn stale_checksums_are_left_alone() {
+        let original = tcp_packet();
+        let stale = replace("TCP", "window", "1024").with_stale_checksums();
+        let pkt = stale.run(original.clone()).unwrap().remove(0);
+        assert_eq!(pkt.as_slice()[34..36], 1024u16.to_be_bytes());
+        assert_eq!(pkt.as_slice()[36..38], original.as_slice()[36..38]);
+        let (ip_header, segment) = pkt.as_slice().split_at(20);
+        assert_ne!(tcp_checksum(ip_header, segment), 0);
+
+        // By default the same tamper fixes the checksum.
+        let pkt = replace("TCP", "window", "1024")
+            .run(original.clone())
+            .unwrap();
+        assert_tcp_checksum_valid(&pkt[0]);
+
+        // Changing an address would fix up both checksums.
+        let stale = replace("IP", "src", "192.0.2.1").with_stale_checksums();
+        let pkt = stale.run(original.clone()).unwrap().remove(0);
+        assert_eq!(pkt.as_slice()[12..16], [192, 0, 2, 1]);
+        assert_only_changed(&original, &pkt, 12..16, 0..0);
+        assert_ne!(ipv4_checksum(pkt.as_slice()), 0);
+
+        // Lengths are still fixed up when the payload grows.
+        let stale = replace("TCP", "load", "hello").with_stale_checksums();
+        let pkt = stale.run(original.clone()).unwrap().remove(0);
+        assert_eq!(pkt.as_slice()[2..4], 45u16.to_be_bytes());
+        assert_eq!(pkt.as_slice()[10..12], original.as_slice()[10..12]);
+        assert_eq!(pkt.as_slice()[36..38], original.as_slice()[36..38]);
+
+        // A tamper of the checksum itself still writes it.
+        let stale = replace("TCP", "chksum", "4660").with_stale_checksums();
+        let pkt = stale.run(original.clone()).unwrap();
+        assert_eq!(pkt[0].as_slice()[36..38], [0x12, 0x34]);
+
+        // The stale tamper doesn't undo the checksum fixed by an earlier one.
+        let fixed = replace("TCP", "window", "1");
+        assert!(fixed.is_overwritten_by(&replace("TCP", "window", "2")));
+        assert!(!fixed.is_overwritten_by(&replace("TCP", "window", "2").with_stale_checksums()));
+    }
+
+    #[test]
+    fn replace_tcp_checksum_is_kept() {
+        let pkt = replace("TCP", "chksum", "4660").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[36..38], [0x12, 0x34]);
+    }
+
+    /// Builds an IPv4 packet carrying a TCP segment with the given payload.
+    fn tcp_packet_with_payload(payload: &[u8]) -> Packet {
+        let mut bytes = tcp_packet().as_slice().to_vec();
+        bytes.extend_from_slice(payload);
+        let total_len = bytes.len() as u16;
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        let mut pkt = Packet::new(bytes);
+        update_ipv4_checksum(&mut pkt);
+        update_tcp_checksum(&mut pkt);
+        pkt
+    }
+
+    #[test]
+    fn corrupt_tcp_payload() {
+        let original = tcp_packet_with_payload(b"GET / HTTP/1.1");
+        let result = corrupt("TCP", "load", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 40..54, 36..38);
+        assert_eq!(ipv4_checksum(&result[0].as_slice()[..20]), 0);
+        assert_tcp_checksum_valid(&result[0]);
+    }
+
+    #[test]
+    fn replace_tcp_payload_same_length() {
+        let result = replace("TCP", "load", "hello")
+            .run(tcp_packet_with_payload(b"world"))
+            .unwrap();
+        assert_eq!(result[0], tcp_packet_with_payload(b"hello"));
+    }
+
+    #[test]
+    fn replace_tcp_payload_changes_length() {
+        let result = replace("TCP", "load", "longerpayload")
+            .run(tcp_packet_with_payload(b"short"))
+            .unwrap();
+        let pkt = &result[0];
+        assert_eq!(pkt.len(), 40 + 13);
+        assert_eq!(pkt.as_slice()[2..4], 53u16.to_be_bytes());
+        assert_eq!(pkt.payload(), Some(&b"longerpayload"[..]));
+        assert_eq!(ipv4_checksum(&pkt.as_slice()[..20]), 0);
+        assert_tcp_checksum_valid(pkt);
+
+        let result = replace("TCP", "load", "x")
+            .run(tcp_packet_with_payload(b"short"))
+            .unwrap();
+        assert_eq!(result[0], tcp_packet_with_payload(b"x"));
+    }
+
+    #[test]
+    fn replace_payload_drops_padding() {
+        let mut bytes = tcp_packet_with_payload(b"short").as_slice().to_vec();
+        bytes.extend_from_slice(&[0; 6]);
+
+        let result = replace("TCP", "load", "a much longer payload")
+            .run(Packet::new(bytes))
+            .unwrap();
+        let pkt = &result[0];
+        assert_eq!(pkt.len(), 40 + 21);
+        assert_eq!(pkt.as_slice()[2..4], 61u16.to_be_bytes());
+        assert_eq!(pkt.payload(), Some(&b"a much longer payload"[..]));
+        assert_eq!(ipv4_checksum(&pkt.as_slice()[..20]), 0);
+        assert_tcp_checksum_valid(pkt);
+    }
+
+    #[test]
+    fn replace_tcp_payload_with_hex() {
+        let result = replace("TCP", "load", "0xdeadbeef")
+            .run(tcp_packet_with_payload(b""))
+            .unwrap();
+        assert_eq!(result[0].payload(), Some(&[0xde, 0xad, 0xbe, 0xef][..]));
+
+        assert!(replace("TCP", "load", "0xabc")
+            .run(tcp_packet_with_payload(b""))
+            .is_err());
+        assert!(replace("TCP", "load", "0xzz")
+            .run(tcp_packet_with_payload(b""))
+            .is_err());
+    }
+
+    #[test]
+    fn replace_tcp_payload_with_encoded_bytes() {
+        let result = replace("TCP", "load", "b64:AP8NCg==")
+            .run(tcp_packet_with_payload(b"hello"))
+            .unwrap();
+        assert_eq!(
+            result[0],
+            tcp_packet_with_payload(&[0x00, 0xff, 0x0d, 0x0a])
+        );
+
+        let result = replace("TCP", "load", "hex:00ff0d0a")
+            .run(tcp_packet_with_payload(b"hello"))
+            .unwrap();
+        assert_eq!(
+            result[0],
+            tcp_packet_with_payload(&[0x00, 0xff, 0x0d, 0x0a])
+        );
+
+        for value in ["b64:A", "hex:0"] {
+            let result = replace("TCP", "load", value).run(tcp_packet_with_payload(b""));
+            assert!(
+                matches!(result, Err(Error::InvalidValue { .. })),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn add_to_payload_fails() {
+        assert!(add("TCP", "load", "1")
+            .run(tcp_packet_with_payload(b"a"))
+            .is_err());
+    }
+
+    fn compress(protocol: &str, field: &str) -> TamperAction {
+        TamperAction::new(
+            protocol.to_string(),
+            field.to_string(),
+            "".to_string(),
+            TamperMode::Compress,
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    /// Builds an IPv4 packet carrying a UDP datagram from port 5353 to `dport` with the given
+    /// payload.
+    fn udp_packet(dport: u16, payload: &[u8]) -> Packet {
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        bytes[2..4].copy_from_slice(&(28 + payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&5353u16.to_be_bytes());
+        bytes.extend_from_slice(&dport.to_be_bytes());
+        bytes.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(payload);
+        let mut pkt = Packet::new(bytes);
+        update_ipv4_checksum(&mut pkt);
+        update_udp_checksum(&mut pkt);
+        pkt
+    }
+
+    /// A DNS query for `www.example.com`.
+    const DNS_QUERY: &[u8] =
+        b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x03www\x07example\x03com\x00\x00\x01\x00\x01";
+
+    #[test]
+    fn compress_str() {
+        assert_eq!(compress("DNS", "qd").to_string(), "tamper{DNS:qd:compress}");
+    }
+
+    #[test]
+    fn compress_dns_question() {
+        let result = compress("DNS", "qd")
+            .run(udp_packet(53, DNS_QUERY))
+            .unwrap();
+        let pkt = &result[0];
+
+        let mut expected = DNS_QUERY[..12].to_vec();
+        expected.extend_from_slice(b"\x03www\xc0\x16\x00\x01\x00\x01\x07example\x03com\x00");
+        assert_eq!(pkt.payload(), Some(&expected[..]));
+
+        // Lengths and checksums match the new, longer payload.
+        assert_eq!(pkt.len(), 28 + expected.len());
+        assert_eq!(pkt.as_slice()[2..4], (pkt.len() as u16).to_be_bytes());
+        assert_eq!(
+            pkt.as_slice()[24..26],
+            (8 + expected.len() as u16).to_be_bytes()
+        );
+        assert_eq!(ipv4_checksum(&pkt.as_slice()[..20]), 0);
+        assert_eq!(*pkt, udp_packet(53, &expected));
+    }
+
+    #[test]
+    fn compress_requires_dns() {
+        let not_dns = |pkt| matches!(compress("DNS", "qd").run(pkt), Err(Error::MalformedPacket));
+        assert!(not_dns(udp_packet(80, DNS_QUERY)));
+        assert!(not_dns(udp_packet(53, b"hello")));
+        assert!(not_dns(tcp_packet()));
+        assert!(compress("DNS", "bogus")
+            .run(udp_packet(53, DNS_QUERY))
+            .is_err());
+        assert!(compress("TCP", "seq").run(tcp_packet()).is_err());
+        assert!(replace("DNS", "qd", "1")
+            .run(udp_packet(53, DNS_QUERY))
+            .is_err());
+    }
+
+    /// Returns `true` if the UDP checksum of an IPv4 packet is correct.
+    fn udp_checksum_valid(pkt: &Packet) -> bool {
+        let bytes = pkt.as_slice();
+        udp_checksum(&bytes[..20], &bytes[20..]) == 0xffff
+    }
+
+    #[test]
+    fn replace_udp_dport() {
+        let result = replace("UDP", "dport", "5353")
+            .run(udp_packet(53, DNS_QUERY))
+            .unwrap();
+        assert_eq!(result[0].as_slice()[22..24], 5353u16.to_be_bytes());
+        assert!(udp_checksum_valid(&result[0]));
+        assert_eq!(result[0], udp_packet(5353, DNS_QUERY));
+
+        assert!(replace("UDP", "dport", "65536")
+            .run(udp_packet(53, DNS_QUERY))
+            .is_err());
+    }
+
+    #[test]
+    fn tamper_udp_fields() {
+        let pkt = udp_packet(53, b"hello");
+
+        let result = add("UDP", "sport", "1").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[20..22], 5354u16.to_be_bytes());
+        assert!(udp_checksum_valid(&result[0]));
+
+        // The length is left wrong, but the checksum still covers the new header.
+        let result = replace("UDP", "len", "100").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[24..26], 100u16.to_be_bytes());
+        assert_eq!(result[0].len(), pkt.len());
+        assert!(udp_checksum_valid(&result[0]));
+        let result = fix("UDP", "len").run(result[0].clone()).unwrap();
+        assert_eq!(result[0], pkt);
+        let result = fix("UDP", "len").run(tcp_packet()).unwrap();
+        assert_eq!(result[0], tcp_packet());
+
+        let result = corrupt("UDP", "dport", 1).run(pkt.clone()).unwrap();
+        assert_ne!(result[0].as_slice()[22..24], pkt.as_slice()[22..24]);
+        assert_eq!(result[0].as_slice()[..22], pkt.as_slice()[..22]);
+        assert!(udp_checksum_valid(&result[0]));
+
+        let result = replace("UDP", "chksum", "4660").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[26..28], [0x12, 0x34]);
+        let result = fix("UDP", "chksum").run(result[0].clone()).unwrap();
+        assert_eq!(result[0], pkt);
+
+        assert!(clear("UDP", "sport", "1").run(pkt.clone()).is_err());
+        assert!(matches!(
+            replace("UDP", "load", "x").run(pkt.clone()),
+            Err(Error::UnknownField { .. })
+        ));
+
+        // Packets without a UDP header are left alone.
+        assert_eq!(
+            replace("UDP", "dport", "1").run(tcp_packet()).unwrap(),
+            [tcp_packet()]
+        );
+    }
+
+    #[test]
+    fn zero_udp_checksum_stays_zero() {
+        let mut bytes = udp_packet(53, b"hello").as_slice().to_vec();
+        bytes[26..28].copy_from_slice(&[0, 0]);
+        let pkt = Packet::new(bytes);
+
+        let result = replace("UDP", "dport", "5353").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[22..24], 5353u16.to_be_bytes());
+        assert_eq!(result[0].as_slice()[26..28], [0, 0]);
+
+        let result = replace("IP", "dst", "10.0.0.3").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[26..28], [0, 0]);
+
+        // Fixing the checksum turns it on.
+        let result = fix("UDP", "chksum").run(pkt).unwrap();
+        assert!(udp_checksum_valid(&result[0]));
+    }
+
+    fn clear(protocol: &str, field: &str, value: &str) -> TamperAction {
+        TamperAction::new(
+            protocol.to_string(),
+            field.to_string(),
+            value.to_string(),
+            TamperMode::Clear,
+            SendAction::default().into(),
         )
+        .unwrap()
+    }
+
+    /// The offset of the flags byte in [tcp_packet].
+    const FLAGS: usize = 33;
+
+    #[test]
+    fn add_tcp_flags_preserves_existing_flags() {
+        // tcp_packet() is a SYN.
+        let pkt = add("TCP", "flags", "R").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[FLAGS], 0x02 | 0x04);
+        assert_tcp_checksum_valid(&pkt[0]);
+
+        let pkt = add("TCP", "flags", "SA").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[FLAGS], 0x12);
+        assert_tcp_checksum_valid(&pkt[0]);
+
+        assert_only_changed(
+            &tcp_packet(),
+            &add("TCP", "flags", "PA").run(tcp_packet()).unwrap()[0],
+            FLAGS..FLAGS + 1,
+            36..38,
+        );
+    }
+
+    #[test]
+    fn clear_tcp_flags_preserves_other_flags() {
+        let syn_ack = add("TCP", "flags", "A")
+            .run(tcp_packet())
+            .unwrap()
+            .remove(0);
+
+        let pkt = clear("TCP", "flags", "S").run(syn_ack.clone()).unwrap();
+        assert_eq!(pkt[0].as_slice()[FLAGS], 0x10);
+        assert_tcp_checksum_valid(&pkt[0]);
+
+        // Clearing a flag that isn't set changes nothing.
+        let pkt = clear("TCP", "flags", "R").run(syn_ack.clone()).unwrap();
+        assert_eq!(pkt[0], syn_ack);
+    }
+
+    #[test]
+    fn replace_tcp_flags() {
+        // tcp_packet() is a SYN.
+        let mut pkt = replace("TCP", "flags", "SA").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[FLAGS], 0x12);
+        assert_tcp_checksum_valid(&pkt[0]);
+        assert_only_changed(&tcp_packet(), &pkt[0], FLAGS..FLAGS + 1, 36..38);
+
+        // The old flags are all replaced, whatever order the letters come in.
+        let pkt = replace("TCP", "flags", "AR").run(pkt.remove(0)).unwrap();
+        assert_eq!(pkt[0].as_slice()[FLAGS], 0x14);
+        assert_tcp_checksum_valid(&pkt[0]);
+
+        // An empty value clears every flag.
+        let pkt = replace("TCP", "flags", "").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[FLAGS], 0);
+        assert_tcp_checksum_valid(&pkt[0]);
+
+        for invalid in ["X", "s", "S A"] {
+            let result = replace("TCP", "flags", invalid).run(tcp_packet());
+            assert!(
+                matches!(result, Err(Error::InvalidValue { .. })),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_flag_tampering() {
+        assert!(add("TCP", "flags", "X").run(tcp_packet()).is_err());
+        assert!(clear("TCP", "flags", "s").run(tcp_packet()).is_err());
+        assert!(clear("TCP", "seq", "1").run(tcp_packet()).is_err());
+        assert!(clear("IP", "ttl", "1").run(ipv4_packet()).is_err());
+        assert_eq!(
+            clear("TCP", "flags", "R").to_string(),
+            "tamper{TCP:flags:clear:R}"
+        );
+    }
+
+    fn fix(protocol: &str, field: &str) -> TamperAction {
+        TamperAction::new(
+            protocol.to_string(),
+            field.to_string(),
+            "".to_string(),
+            TamperMode::Fix,
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn fix_str() {
+        assert_eq!(fix("IP", "chksum").to_string(), "tamper{IP:chksum:fix}");
+    }
+
+    #[test]
+    fn fix_broken_checksums() {
+        let original = tcp_packet_with_payload(b"hello");
+        let broken = corrupt("IP", "chksum", 1).run(original.clone()).unwrap();
+        assert_ne!(ipv4_checksum(&broken[0].as_slice()[..20]), 0);
+        let fixed = fix("IP", "chksum").run(broken[0].clone()).unwrap();
+        assert_eq!(fixed[0].as_slice(), original.as_slice());
+
+        let broken = corrupt("TCP", "chksum", 1).run(original.clone()).unwrap();
+        assert_ne!(broken[0].as_slice(), original.as_slice());
+        let fixed = fix("TCP", "chksum").run(broken[0].clone()).unwrap();
+        assert_eq!(fixed[0].as_slice(), original.as_slice());
+        assert_tcp_checksum_valid(&fixed[0]);
+
+        // A checksum that's already right stays that way.
+        let fixed = fix("TCP", "chksum").run(original.clone()).unwrap();
+        assert_eq!(fixed[0].as_slice(), original.as_slice());
+    }
+
+    #[test]
+    fn fix_ip_len() {
+        let original = tcp_packet_with_payload(b"hello");
+        let broken = replace("IP", "len", "20").run(original.clone()).unwrap();
+        assert_eq!(broken[0].payload(), None);
+
+        let fixed = fix("IP", "len").run(broken[0].clone()).unwrap();
+        assert_eq!(fixed[0].as_slice(), original.as_slice());
+        assert_eq!(fixed[0].payload(), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn fix_other_fields_fails() {
+        for tamper in [
+            fix("IP", "ttl"),
+            fix("IP", "frag"),
+            fix("TCP", "seq"),
+            fix("TCP", "load"),
+        ] {
+            let result = tamper.run(tcp_packet());
+            assert!(
+                matches!(result, Err(Error::Unsupported { .. })),
+                "{}",
+                tamper
+            );
+        }
+        assert!(matches!(
+            fix("IP", "nope").run(tcp_packet()),
+            Err(Error::UnknownField { .. })
+        ));
+    }
+
+    #[test]
+    fn corrupt_ip_ttl() {
+        let original = ipv4_packet();
+        let result = corrupt("IP", "ttl", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 8..9, 10..12);
+        assert_eq!(ipv4_checksum(result[0].as_slice()), 0);
+    }
+
+    #[test]
+    fn corrupt_ip_addresses() {
+        let original = ipv4_packet();
+        let result = corrupt("IP", "src", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 12..16, 10..12);
+
+        let result = corrupt("IP", "dst", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 16..20, 10..12);
+    }
+
+    #[test]
+    fn corrupt_ip_address_is_a_new_address() {
+        let original = tcp_packet();
+        let src =
+            |pkt: &Packet| Ipv4Addr::from(<[u8; 4]>::try_from(&pkt.as_slice()[12..16]).unwrap());
+        for seed in 0..32 {
+            let result = corrupt("IP", "src", seed).run(original.clone()).unwrap();
+            let addr = src(&result[0]);
+            assert_ne!(addr, src(&original));
+            assert_eq!(addr.to_string().parse::<Ipv4Addr>().unwrap(), addr);
+            assert_eq!(ipv4_checksum(&result[0].as_slice()[..20]), 0);
+            assert_tcp_checksum_valid(&result[0]);
+        }
+
+        let result = corrupt("IP", "dst", 1).run(udp_packet(53, b"hi")).unwrap();
+        let (ip_header, datagram) = result[0].as_slice().split_at(20);
+        // A datagram with a valid checksum sums to zero, which `udp_checksum` gives as 0xffff.
+        assert_eq!(udp_checksum(ip_header, datagram), 0xffff);
+    }
+
+    /// An IPv6 packet carrying the TCP SYN segment of [tcp_packet] from 2001:db8::1 to
+    /// 2001:db8::2, with a hop limit of 64 and a valid checksum.
+    fn ipv6_tcp_packet() -> Packet {
+        let mut bytes = vec![0x60, 0x00, 0x00, 0x00, 0x00, 20, 6, 64];
+        bytes.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&tcp_packet().as_slice()[20..]);
+        let mut pkt = Packet::new(bytes);
+        update_tcp_checksum(&mut pkt);
+        pkt
+    }
+
+    /// An IPv6 packet carrying a UDP datagram to port 53 with a valid checksum.
+    fn ipv6_udp_packet(payload: &[u8]) -> Packet {
+        let mut pkt = ipv6_tcp_packet().as_slice()[..40].to_vec();
+        pkt[4..6].copy_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        pkt[6] = PROTO_UDP;
+        pkt.extend_from_slice(&[0x14, 0xe9, 0x00, 0x35]);
+        pkt.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        pkt.extend_from_slice(&[0, 0]);
+        pkt.extend_from_slice(payload);
+        let mut pkt = Packet::new(pkt);
+        update_udp_checksum(&mut pkt);
+        pkt
+    }
+
+    fn ipv6_checksums_valid(pkt: &Packet) -> bool {
+        let (ip_header, transport) = pkt.as_slice().split_at(40);
+        match ip_header[6] {
+            PROTO_TCP => tcp_checksum(ip_header, transport) == 0,
+            _ => udp_checksum(ip_header, transport) == 0xffff,
+        }
+    }
+
+    #[test]
+    fn replace_ipv6_addresses() {
+        let addr = "2001:db8::99".parse::<Ipv6Addr>().unwrap();
+        for value in ["2001:db8::99", "[2001:db8::99]"] {
+            let result = replace("IP", "src", value).run(ipv6_tcp_packet()).unwrap();
+            assert_eq!(result[0].as_slice()[8..24], addr.octets());
+            assert!(ipv6_checksums_valid(&result[0]));
+        }
+        let result = replace("IP", "dst", "2001:db8::99")
+            .run(ipv6_udp_packet(b"hi"))
+            .unwrap();
+        assert_eq!(result[0].as_slice()[24..40], addr.octets());
+        assert!(ipv6_checksums_valid(&result[0]));
+
+        // An address has to be of the packet's own family.
+        let result = replace("IP", "src", "10.0.0.1").run(ipv6_tcp_packet());
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+        let result = replace("IP", "src", "2001:db8::99").run(tcp_packet());
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+        assert!(replace("IP", "src", "2001:db8::99").validate().is_ok());
+        assert!(replace("IP", "src", "[10.0.0.1]").validate().is_err());
+    }
+
+    #[test]
+    fn corrupt_ipv6_addresses() {
+        let original = ipv6_tcp_packet();
+        for seed in 0..8 {
+            let result = corrupt("IP", "src", seed).run(original.clone()).unwrap();
+            assert_only_changed(&original, &result[0], 8..24, 56..58);
+            assert!(ipv6_checksums_valid(&result[0]));
+        }
+
+        let original = ipv6_udp_packet(b"hi");
+        let result = corrupt("IP", "dst", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 24..40, 46..48);
+        assert!(ipv6_checksums_valid(&result[0]));
+    }
+
+    #[test]
+    fn tamper_ipv6_header_fields() {
+        let pkt = ipv6_tcp_packet();
+        let result = replace("IP", "ttl", "1").run(pkt.clone()).unwrap();
+        assert_only_changed(&pkt, &result[0], 7..8, 0..0);
+        assert_eq!(result[0].as_slice()[7], 1);
+
+        let result = replace("IP", "protocol", "17").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[6], 17);
+
+        // The traffic class straddles the version and the flow label.
+        let mut bytes = pkt.as_slice().to_vec();
+        bytes[1..4].copy_from_slice(&[0x0a, 0xbc, 0xde]);
+        let result = replace("IP", "tos", "184").run(Packet::new(bytes)).unwrap();
+        assert_eq!(result[0].as_slice()[..4], [0x6b, 0x8a, 0xbc, 0xde]);
+
+        let result = replace("IP", "len", "99").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[4..6], [0, 99]);
+        let result = fix("IP", "len").run(result[0].clone()).unwrap();
+        assert_eq!(result[0], pkt);
+
+        // IPv6 has no identification field.
+        let result = replace("IP", "id", "1").run(pkt.clone()).unwrap();
+        assert_eq!(result[0], pkt);
+    }
+
+    #[test]
+    fn tamper_ipv6_udp_header() {
+        let pkt = ipv6_udp_packet(b"query");
+        let result = replace("UDP", "dport", "5353").run(pkt.clone()).unwrap();
+        assert_eq!(result[0].as_slice()[42..44], 5353u16.to_be_bytes());
+        assert!(ipv6_checksums_valid(&result[0]));
+
+        // Unlike IPv4, IPv6 has no way to leave out the UDP checksum, so a zero one is fixed too.
+        let mut bytes = pkt.as_slice().to_vec();
+        bytes[46..48].copy_from_slice(&[0, 0]);
+        let result = replace("UDP", "dport", "5353")
+            .run(Packet::new(bytes))
+            .unwrap();
+        assert!(ipv6_checksums_valid(&result[0]));
+    }
+
+    #[test]
+    fn corrupt_tcp_fields() {
+        let original = tcp_packet();
+        let result = corrupt("TCP", "window", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 34..36, 36..38);
+
+        let result = corrupt("TCP", "seq", 1).run(original.clone()).unwrap();
+        assert_only_changed(&original, &result[0], 24..28, 36..38);
+    }
+
+    #[test]
+    fn corrupt_with_seed_is_reproducible() {
+        let a = corrupt("TCP", "seq", 42).run(tcp_packet()).unwrap();
+        let b = corrupt("TCP", "seq", 42).run(tcp_packet()).unwrap();
+        assert_eq!(a, b);
+    }
+
+    fn add(protocol: &str, field: &str, value: &str) -> TamperAction {
+        TamperAction::new(
+            protocol.to_string(),
+            field.to_string(),
+            value.to_string(),
+            TamperMode::Add,
+            SendAction::default().into(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn add_tcp_seq() {
+        let pkt = add("TCP", "seq", "100").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[24..28], 1100u32.to_be_bytes());
+    }
+
+    #[test]
+    fn add_ip_ttl() {
+        let pkt = add("IP", "ttl", "10").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[8], 138);
+        assert_eq!(ipv4_checksum(pkt[0].as_slice()), 0);
+    }
+
+    #[test]
+    fn add_wraps_around() {
+        // The TTL starts at 128, so adding 200 wraps around to 72.
+        let pkt = add("IP", "ttl", "200").run(ipv4_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[8], 72);
+
+        // Adding 2^32 - 1 to a 32-bit field is the same as subtracting one.
+        let pkt = add("TCP", "seq", "4294967295").run(tcp_packet()).unwrap();
+        assert_eq!(pkt[0].as_slice()[24..28], 999u32.to_be_bytes());
+    }
+
+    #[test]
+    fn add_to_address_fails() {
+        let result = add("IP", "src", "1").run(ipv4_packet());
+        assert!(matches!(result, Err(Error::Unsupported { .. })));
+    }
+
+    #[test]
+    fn add_non_numeric_value_fails() {
+        let result = add("IP", "ttl", "many").run(ipv4_packet());
+        assert!(matches!(
+            result,
+            Err(Error::InvalidValue { field, value }) if field == "IP:ttl" && value == "many"
+        ));
+    }
+
+    #[test]
+    fn unknown_field_fails() {
+        let result = replace("TCP", "bogus", "1").run(tcp_packet());
+        assert!(matches!(
+            result,
+            Err(Error::UnknownField { protocol, field }) if protocol == "TCP" && field == "bogus"
+        ));
+
+        let result = replace("SCTP", "sport", "1").run(tcp_packet());
+        assert!(matches!(result, Err(Error::UnknownField { .. })));
+    }
+
+    #[test]
+    fn invalid_flags_fail() {
+        let result = add("TCP", "flags", "Z").run(tcp_packet());
+        assert!(matches!(result, Err(Error::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn non_ip_packet_is_untouched() {
+        let pkt = Packet::new(vec![0x10, 0, 0, 0]);
+        let result = replace("IP", "ttl", "64").run(pkt.clone()).unwrap();
+        assert_eq!(result, vec![pkt]);
+    }
+
+    #[test]
+    fn validate_agrees_with_run() {
+        for tamper in [
+            replace("TCP", "flags", "SA"),
+            replace("TCP", "flags", "Q"),
+            replace("TCP", "seq", "4294967296"),
+            replace("TCP", "load", "hex:abc"),
+            replace("TCP", "load", "0x1234"),
+            replace("TCP", "dataofs", "15"),
+            replace("TCP", "dataofs", "16"),
+            replace("TCP", "options", "1"),
+            replace("IP", "src", "10.0.0.256"),
+            replace("IP", "ttl", "64"),
+            replace("IP", "ihl", "15"),
+            replace("IP", "ihl", "16"),
+            replace("IP", "version", "16"),
+            fix("IP", "version"),
+            replace("UDP", "dport", "53"),
+            add("TCP", "load", "x"),
+            add("IP", "dst", "1"),
+            add("TCP", "window", "-1"),
+            clear("TCP", "flags", "R"),
+            clear("TCP", "ack", "1"),
+            corrupt("TCP", "urgptr", 1),
+            corrupt("TCP", "load", 1),
+            corrupt("TCP", "nope", 1),
+            fix("IP", "chksum"),
+            fix("IP", "len"),
+            fix("IP", "ttl"),
+            fix("TCP", "chksum"),
+            fix("TCP", "dataofs"),
+            fix("TCP", "load"),
+            replace("UDP", "len", "65536"),
+            clear("UDP", "sport", "1"),
+            corrupt("UDP", "load", 1),
+            fix("UDP", "chksum"),
+            fix("UDP", "len"),
+        ] {
+            // A tamper only gets as far as checking its value on a packet it applies to.
+            let pkt = match tamper.protocol.as_str() {
+                "UDP" => udp_packet(53, b"hello"),
+                _ => tcp_packet_with_payload(b"hello"),
+            };
+            let run = tamper.run(pkt);
+            assert_eq!(tamper.validate().is_ok(), run.is_ok(), "{}", tamper);
+            if let Err(e) = tamper.validate() {
+                assert_eq!(e.to_string(), run.unwrap_err().to_string(), "{}", tamper);
+            }
+        }
     }
 }
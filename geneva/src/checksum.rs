@@ -0,0 +1,194 @@
+//! Functions for computing the Internet checksums used by IPv4, TCP, and UDP.
+//!
+//! These all use the standard one's-complement sum over 16-bit words described in RFC 1071. They
+//! operate on plain byte slices, so they can be used on any buffer, not just a [Packet].
+//!
+//! The checksum field in the bytes being summed must be zeroed before computing a checksum to
+//! write into a header. (Summing a header whose checksum is already correct yields zero, which can
+//! be used to validate it; this does not hold for UDP, where a computed checksum of zero is
+//! transmitted as `0xffff`.)
+use crate::packet::{PROTO_TCP, PROTO_UDP};
+use crate::Packet;
+
+/// Sums `bytes` as big-endian 16-bit words, padding an odd trailing byte with zero.
+fn sum_words(bytes: &[u8]) -> u32 {
+    bytes
+        .chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)]) as u32)
+        .sum()
+}
+
+/// Folds the carries of a 32-bit sum back into 16 bits and returns its one's complement.
+fn finish(mut sum: u32) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Sums the pseudo-header that TCP and UDP checksums cover. The addresses are taken from
+/// `ip_header`, which may be either an IPv4 or an IPv6 header.
+fn pseudo_header_sum(ip_header: &[u8], protocol: u8, len: usize) -> u32 {
+    let addresses = if ip_header[0] >> 4 == 6 {
+        &ip_header[8..40]
+    } else {
+        &ip_header[12..20]
+    };
+    sum_words(addresses) + protocol as u32 + (len >> 16) as u32 + (len & 0xffff) as u32
+}
+
+/// Computes the checksum of an IPv4 header.
+pub fn ipv4_checksum(header: &[u8]) -> u16 {
+    finish(sum_words(header))
+}
+
+/// Computes the checksum of a TCP segment (header and payload) carried by the given IPv4 or IPv6
+/// header.
+pub fn tcp_checksum(ip_header: &[u8], tcp_segment: &[u8]) -> u16 {
+    let pseudo_header = pseudo_header_sum(ip_header, PROTO_TCP, tcp_segment.len());
+    finish(pseudo_header + sum_words(tcp_segment))
+}
+
+/// Computes the checksum of a UDP datagram (header and payload) carried by the given IPv4 or IPv6
+/// header.
+///
+/// Since a UDP checksum of zero means that the sender did not compute one, a computed checksum of
+/// zero is returned as `0xffff` instead.
+pub fn udp_checksum(ip_header: &[u8], udp_datagram: &[u8]) -> u16 {
+    let pseudo_header = pseudo_header_sum(ip_header, PROTO_UDP, udp_datagram.len());
+    match finish(pseudo_header + sum_words(udp_datagram)) {
+        0 => 0xffff,
+        checksum => checksum,
+    }
+}
+
+/// Recomputes the header checksum of an IPv4 packet. Other packets are left untouched.
+pub(crate) fn update_ipv4_checksum(pkt: &mut Packet) {
+    let header = match pkt.layers() {
+        Some(layers) if layers.ip_version == 4 => layers.ip.clone(),
+        _ => return,
+    };
+
+    let header = &mut pkt.as_mut_slice()[header];
+    header[10..12].copy_from_slice(&[0, 0]);
+    let checksum = ipv4_checksum(header);
+    header[10..12].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Recomputes the TCP checksum of an IPv4 or IPv6 packet carrying a TCP segment. Other packets are
+/// left untouched.
+pub(crate) fn update_tcp_checksum(pkt: &mut Packet) {
+    let layers = match pkt.layers() {
+        Some(layers) if layers.protocol == PROTO_TCP && layers.payload.is_some() => layers.clone(),
+        _ => return,
+    };
+
+    let bytes = &mut pkt.as_mut_slice()[..layers.transport.end];
+    let (ip_header, segment) = bytes.split_at_mut(layers.transport.start);
+    segment[16..18].copy_from_slice(&[0, 0]);
+    let checksum = tcp_checksum(&ip_header[layers.ip], segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Recomputes the UDP checksum of an IPv4 or IPv6 packet carrying a UDP datagram. Other packets are
+/// left untouched.
+pub(crate) fn update_udp_checksum(pkt: &mut Packet) {
+    let layers = match pkt.layers() {
+        Some(layers) if layers.protocol == PROTO_UDP && layers.payload.is_some() => layers.clone(),
+        _ => return,
+    };
+
+    let bytes = &mut pkt.as_mut_slice()[..layers.transport.end];
+    let (ip_header, datagram) = bytes.split_at_mut(layers.transport.start);
+    datagram[6..8].copy_from_slice(&[0, 0]);
+    let checksum = udp_checksum(&ip_header[layers.ip], datagram);
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zeroes the two bytes at `offset` and returns the value they held.
+    fn take_checksum(bytes: &mut [u8], offset: usize) -> u16 {
+        let checksum = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+        bytes[offset..offset + 2].copy_from_slice(&[0, 0]);
+        checksum
+    }
+
+    /// The IPv4 header of a UDP packet from 192.168.0.1 to 192.168.0.199, as used in the
+    /// Wikipedia article on the IPv4 header checksum.
+    const WIKIPEDIA_IPV4_HEADER: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    /// A DNS query for `example.com` from 192.168.0.1:53000 to 8.8.8.8:53.
+    const DNS_QUERY: [u8; 57] = [
+        0x45, 0x00, 0x00, 0x39, 0xab, 0xcd, 0x00, 0x00, 0x40, 0x11, 0xfe, 0x2d, 0xc0, 0xa8, 0x00,
+        0x01, 0x08, 0x08, 0x08, 0x08, 0xcf, 0x08, 0x00, 0x35, 0x00, 0x25, 0x7e, 0x0b, 0x12, 0x34,
+        0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x07, 0x65, 0x78, 0x61, 0x6d,
+        0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x01, 0x00, 0x01,
+    ];
+
+    /// A TCP SYN with an MSS option from 192.168.1.2:54321 to 93.184.216.34:80.
+    const TCP_SYN: [u8; 44] = [
+        0x45, 0x00, 0x00, 0x2c, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0x27, 0x01, 0xc0, 0xa8, 0x01,
+        0x02, 0x5d, 0xb8, 0xd8, 0x22, 0xd4, 0x31, 0x00, 0x50, 0x1a, 0x2b, 0x3c, 0x4d, 0x00, 0x00,
+        0x00, 0x00, 0x60, 0x02, 0xfa, 0xf0, 0x7a, 0xb6, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4,
+    ];
+
+    #[test]
+    fn ipv4_known_vector() {
+        let mut header = WIKIPEDIA_IPV4_HEADER;
+        assert_eq!(ipv4_checksum(&header), 0);
+
+        let expected = take_checksum(&mut header, 10);
+        assert_eq!(expected, 0xb861);
+        assert_eq!(ipv4_checksum(&header), expected);
+    }
+
+    #[test]
+    fn ipv4_udp_known_vector() {
+        let mut bytes = DNS_QUERY;
+        assert_eq!(ipv4_checksum(&bytes[..20]), 0);
+
+        let expected = take_checksum(&mut bytes, 26);
+        let (ip_header, datagram) = bytes.split_at(20);
+        assert_eq!(udp_checksum(ip_header, datagram), expected);
+    }
+
+    #[test]
+    fn ipv4_tcp_known_vector() {
+        let mut bytes = TCP_SYN;
+        assert_eq!(ipv4_checksum(&bytes[..20]), 0);
+
+        let (ip_header, segment) = bytes.split_at(20);
+        assert_eq!(tcp_checksum(ip_header, segment), 0);
+
+        let expected = take_checksum(&mut bytes, 36);
+        let (ip_header, segment) = bytes.split_at(20);
+        assert_eq!(tcp_checksum(ip_header, segment), expected);
+    }
+
+    #[test]
+    fn ipv6_udp_checksum() {
+        let mut ip_header = vec![0x60, 0, 0, 0, 0, 12, 17, 64];
+        ip_header.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        ip_header.extend_from_slice(&[0; 11]);
+        ip_header.push(1);
+        ip_header.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        ip_header.extend_from_slice(&[0; 11]);
+        ip_header.push(2);
+
+        let datagram = [
+            0x9c, 0x40, 0x00, 0x35, 0x00, 0x0c, 0x00, 0x00, b'p', b'i', b'n', b'g',
+        ];
+        assert_eq!(udp_checksum(&ip_header, &datagram), 0x291b);
+    }
+
+    #[test]
+    fn odd_length_is_padded() {
+        assert_eq!(ipv4_checksum(&[0x01]), !0x0100);
+    }
+}
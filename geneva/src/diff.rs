@@ -0,0 +1,321 @@
+//! Structural comparison of Geneva strategies.
+//!
+//! [strategy_diff] lines up the action trees of two strategies, forest by forest, and reports the
+//! trees that one of them has and the other doesn't, and the trees whose trigger or action changed.
+//! This is mostly useful for seeing what a [mutation](crate::mutate) did to a strategy.
+use core::fmt;
+
+use crate::actions::ActionTree;
+use crate::prelude::*;
+use crate::strategy::{root_action_string, Direction, Strategy};
+
+/// One difference between two strategies, as found by [strategy_diff]. Trees are written the way
+/// they are in a strategy string, and `index` is the tree's position in its forest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry {
+    /// The second strategy has a tree, at `index` in its forest, that the first doesn't.
+    Added {
+        direction: Direction,
+        index: usize,
+        tree: String,
+    },
+
+    /// The first strategy has a tree, at `index` in its forest, that the second doesn't.
+    Removed {
+        direction: Direction,
+        index: usize,
+        tree: String,
+    },
+
+    /// The tree at `index` in the first strategy's forest has a different trigger in the second.
+    TriggerChanged {
+        direction: Direction,
+        index: usize,
+        from: String,
+        to: String,
+    },
+
+    /// The tree at `index` in the first strategy's forest has a different root action in the
+    /// second. A root `send` is written out as `send`.
+    ActionChanged {
+        direction: Direction,
+        index: usize,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added {
+                direction,
+                index,
+                tree,
+            } => write!(f, "+ {}[{}] {}", direction, index, tree),
+            Self::Removed {
+                direction,
+                index,
+                tree,
+            } => write!(f, "- {}[{}] {}", direction, index, tree),
+            Self::TriggerChanged {
+                direction,
+                index,
+                from,
+                to,
+            } => write!(f, "~ {}[{}] trigger {} -> {}", direction, index, from, to),
+            Self::ActionChanged {
+                direction,
+                index,
+                from,
+                to,
+            } => write!(f, "~ {}[{}] action {} -> {}", direction, index, from, to),
+        }
+    }
+}
+
+/// Returns the differences between the action trees of `a` and `b`, going from `a` to `b`: first
+/// for the outbound forests, then for the inbound ones, each in the order of their trees. It is
+/// empty if the strategies are equal, apart from their [limits](Strategy::limits), which aren't
+/// compared.
+///
+/// Trees that are kept in both strategies are lined up first, so inserting a tree reports just
+/// that tree rather than a change to every tree after it. Of the trees that are left over at the
+/// same spot, one that was removed and one that was added are reported as a change if they have
+/// the same trigger or the same action, and as a removal and an addition otherwise. Every entry
+/// is written the way strategy strings are, so a change to state that they don't show, such as
+/// the [seed](crate::TamperAction::with_seed) of a tamper, isn't reported.
+///
+/// ```
+/// use geneva::{strategy_diff, Strategy};
+///
+/// let a: Strategy = r"[TCP:flags:S]-drop-| \/".parse().unwrap();
+/// let b: Strategy = r"[TCP:flags:SA]-drop-| \/ [TCP:flags:R]-drop-|".parse().unwrap();
+/// let diff: Vec<_> = strategy_diff(&a, &b).iter().map(ToString::to_string).collect();
+/// assert_eq!(
+///     diff,
+///     [
+///         "~ outbound[0] trigger [TCP:flags:S] -> [TCP:flags:SA]",
+///         "+ inbound[0] [TCP:flags:R]-drop-|",
+///     ]
+/// );
+/// ```
+pub fn strategy_diff(a: &Strategy, b: &Strategy) -> Vec<DiffEntry> {
+    let mut diff = vec![];
+    for direction in [Direction::Outbound, Direction::Inbound] {
+        let (a, b) = (
+            a.forest(direction).unwrap_or_default(),
+            b.forest(direction).unwrap_or_default(),
+        );
+        diff_forests(direction, a, b, &mut diff);
+    }
+    diff
+}
+
+/// Appends the differences between two forests to `diff`.
+fn diff_forests(
+    direction: Direction,
+    a: &[ActionTree],
+    b: &[ActionTree],
+    diff: &mut Vec<DiffEntry>,
+) {
+    // kept[i][j] is the most trees that a[i..] and b[j..] have in common, in order.
+    let mut kept = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            kept[i][j] = if a[i] == b[j] {
+                kept[i + 1][j + 1] + 1
+            } else {
+                kept[i + 1][j].max(kept[i][j + 1])
+            };
+        }
+    }
+
+    // Walk through both forests, collecting the trees between two kept ones.
+    let (mut i, mut j) = (0, 0);
+    let (mut removed, mut added) = (vec![], vec![]);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            diff_gap(direction, a, b, &removed, &added, diff);
+            removed.clear();
+            added.clear();
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && kept[i + 1][j] >= kept[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            added.push(j);
+            j += 1;
+        }
+    }
+    diff_gap(direction, a, b, &removed, &added, diff);
+}
+
+/// Appends the differences for the trees `removed` from `a` and `added` from `b` at the same spot
+/// to `diff`, pairing them up in order as changes where they can be.
+fn diff_gap(
+    direction: Direction,
+    a: &[ActionTree],
+    b: &[ActionTree],
+    removed: &[usize],
+    added: &[usize],
+    diff: &mut Vec<DiffEntry>,
+) {
+    let mut added = added.iter().peekable();
+    for &i in removed {
+        let (old, new) = match added.peek() {
+            Some(&&j) if a[i].trigger == b[j].trigger || a[i].root_action == b[j].root_action => {
+                added.next();
+                (&a[i], &b[j])
+            }
+            _ => {
+                diff.push(DiffEntry::Removed {
+                    direction,
+                    index: i,
+                    tree: a[i].to_string(),
+                });
+                continue;
+            }
+        };
+
+        // Trees can differ in state that isn't written out, such as the seed of a corrupting
+        // tamper; a change that would show up as the same string on both sides is left out.
+        let (from, to) = (old.trigger.to_string(), new.trigger.to_string());
+        if from != to {
+            diff.push(DiffEntry::TriggerChanged {
+                direction,
+                index: i,
+                from,
+                to,
+            });
+        }
+        let (from, to) = (root_action_string(old), root_action_string(new));
+        if from != to {
+            diff.push(DiffEntry::ActionChanged {
+                direction,
+                index: i,
+                from,
+                to,
+            });
+        }
+    }
+    for &j in added {
+        diff.push(DiffEntry::Added {
+            direction,
+            index: j,
+            tree: b[j].to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::GenevaAction;
+    use crate::mutate::mutate;
+    use crate::parse_strategy;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn diff(a: &str, b: &str) -> Vec<String> {
+        let (a, b) = (parse_strategy(a).unwrap(), parse_strategy(b).unwrap());
+        strategy_diff(&a, &b)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn equal_strategies_have_no_diff() {
+        let s = r"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/ [TCP:flags:R]-drop-|";
+        assert!(diff(s, s).is_empty());
+        assert!(diff(r"\/", r"\/").is_empty());
+    }
+
+    #[test]
+    fn single_field_changes() {
+        let s = r"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1},)-| [TCP:flags:R]-drop-| \/";
+        assert_eq!(
+            diff(
+                s,
+                r"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:2},)-| [TCP:flags:R]-drop-| \/"
+            ),
+            ["~ outbound[0] action duplicate(tamper{TCP:window:replace:1},) -> duplicate(tamper{TCP:window:replace:2},)"]
+        );
+        assert_eq!(
+            diff(
+                s,
+                r"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1},)-| [TCP:flags:RA]-drop-| \/"
+            ),
+            ["~ outbound[1] trigger [TCP:flags:R] -> [TCP:flags:RA]"]
+        );
+        assert_eq!(
+            diff(
+                s,
+                r"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1},)-| [TCP:flags:R]-send-| \/"
+            ),
+            ["~ outbound[1] action drop -> send"]
+        );
+    }
+
+    #[test]
+    fn added_and_removed_trees() {
+        let s = r"[TCP:flags:S]-drop-| [TCP:flags:R]-drop-| \/";
+        assert_eq!(
+            diff(
+                s,
+                r"[TCP:flags:S]-drop-| [IP:ttl:1]-drop-| [TCP:flags:R]-drop-| \/"
+            ),
+            ["+ outbound[1] [IP:ttl:1]-drop-|"]
+        );
+        assert_eq!(
+            diff(s, r"[TCP:flags:R]-drop-| \/"),
+            ["- outbound[0] [TCP:flags:S]-drop-|"]
+        );
+
+        // A tree with nothing in common with the one it replaces isn't a change to it.
+        assert_eq!(
+            diff(s, r"[TCP:flags:S]-drop-| [UDP:dport:53]-duplicate-| \/"),
+            [
+                "- outbound[1] [TCP:flags:R]-drop-|",
+                "+ outbound[1] [UDP:dport:53]-duplicate-|",
+            ]
+        );
+
+        // Moving a tree to the other forest removes it from one and adds it to the other.
+        assert_eq!(
+            diff(s, r"[TCP:flags:S]-drop-| \/ [TCP:flags:R]-drop-|"),
+            [
+                "- outbound[1] [TCP:flags:R]-drop-|",
+                "+ inbound[0] [TCP:flags:R]-drop-|",
+            ]
+        );
+    }
+
+    #[test]
+    fn hidden_changes_are_left_out() {
+        let a = parse_strategy(r"[TCP:flags:S]-tamper{TCP:seq:corrupt}-| \/").unwrap();
+        let mut b = a.clone();
+        let tree = &mut b.outbound.as_mut().unwrap()[0];
+        if let GenevaAction::Tamper(tamper) = &*tree.root_action {
+            *tree.root_action = tamper.clone().with_seed(7).into();
+        }
+        assert_ne!(a, b);
+        assert!(strategy_diff(&a, &b).is_empty());
+    }
+
+    #[test]
+    fn diff_of_a_mutation() {
+        let original =
+            parse_strategy(r"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/").unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..50 {
+            let mut mutated = original.clone();
+            mutate(&mut mutated, &mut rng);
+            let diff = strategy_diff(&original, &mutated);
+            assert_eq!(diff.is_empty(), mutated == original, "{}", mutated);
+            assert_eq!(strategy_diff(&original, &mutated), diff);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Helpers for working with DNS messages carried in the payload of a UDP datagram.
+//!
+//! Only as much of the DNS wire format (RFC 1035) as the triggers and actions need is implemented
+//! here: the fixed header and the names in the question section.
+use crate::prelude::*;
+use crate::Packet;
+
+/// The well-known UDP port for DNS.
+pub(crate) const DNS_PORT: u16 = 53;
+
+/// The length of the fixed DNS message header.
+pub(crate) const HEADER_LEN: usize = 12;
+
+/// The largest offset that a compression pointer can refer to.
+const MAX_POINTER: usize = 0x3fff;
+
+/// How many compression pointers [read_name] follows before giving up on a name, so that a loop
+/// of pointers can't hang it.
+const MAX_POINTER_HOPS: usize = 16;
+
+/// Reads the uncompressed name starting at `offset`, returning its labels and the offset just
+/// past the terminating zero-length label. Returns `None` if the name is truncated or contains a
+/// compression pointer.
+pub(crate) fn read_labels(msg: &[u8], mut offset: usize) -> Option<(Vec<&[u8]>, usize)> {
+    let mut labels = vec![];
+    loop {
+        let len = *msg.get(offset)? as usize;
+        offset += 1;
+        match len {
+            0 => return Some((labels, offset)),
+            // The top two bits of a label length are reserved for pointers and extended labels.
+            _ if len & 0xc0 != 0 => return None,
+            _ => {
+                labels.push(msg.get(offset..offset + len)?);
+                offset += len;
+            }
+        }
+    }
+}
+
+/// Reads the name starting at `offset`, following any compression pointers, and returns its
+/// labels and the offset just past where the name ends in place (that is, just past the first
+/// pointer, if there is one). Returns `None` if the name is truncated or malformed, or has too
+/// many pointers.
+pub(crate) fn read_name(msg: &[u8], mut offset: usize) -> Option<(Vec<&[u8]>, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    let mut hops = 0;
+    loop {
+        let len = *msg.get(offset)? as usize;
+        match len {
+            0 => return Some((labels, end.unwrap_or(offset + 1))),
+            _ if len & 0xc0 == 0xc0 => {
+                hops += 1;
+                if hops > MAX_POINTER_HOPS {
+                    return None;
+                }
+                end.get_or_insert(offset + 2);
+                offset = u16::from_be_bytes([*msg.get(offset)?, *msg.get(offset + 1)?]) as usize
+                    & MAX_POINTER;
+            }
+            _ if len & 0xc0 != 0 => return None,
+            _ => {
+                labels.push(msg.get(offset + 1..offset + 1 + len)?);
+                offset += 1 + len;
+            }
+        }
+    }
+}
+
+/// Returns the DNS message carried by a UDP datagram to or from port 53, or `None` if the packet
+/// isn't one or its payload is too short to hold a DNS header.
+pub(crate) fn udp_message(pkt: &Packet) -> Option<&[u8]> {
+    let header = pkt.udp_header()?;
+    let sport = u16::from_be_bytes([header[0], header[1]]);
+    let dport = u16::from_be_bytes([header[2], header[3]]);
+    if sport != DNS_PORT && dport != DNS_PORT {
+        return None;
+    }
+    pkt.payload().filter(|msg| msg.len() >= HEADER_LEN)
+}
+
+/// Rewrites the first question's name to use a compression pointer.
+///
+/// The first label stays where it is, but the rest of the name moves to the end of the message and
+/// is replaced with a pointer to it. Resolvers that follow the pointer see the same name, while
+/// anything that only looks at the question's inline labels sees just the first of them. Names with
+/// fewer than two labels have nothing to compress, so the message is returned unchanged.
+///
+/// Any names in later sections that point into the question will point to the wrong place
+/// afterwards, so this is only useful for queries. Returns `None` if `msg` is not a DNS message
+/// with at least one question.
+pub(crate) fn compress_question(msg: &[u8]) -> Option<Vec<u8>> {
+    if msg.len() < HEADER_LEN || u16::from_be_bytes([msg[4], msg[5]]) == 0 {
+        return None;
+    }
+
+    let (labels, end) = read_labels(msg, HEADER_LEN)?;
+    if labels.len() < 2 {
+        return Some(msg.to_vec());
+    }
+
+    let mut out = msg[..HEADER_LEN].to_vec();
+    out.push(labels[0].len() as u8);
+    out.extend_from_slice(labels[0]);
+    let pointer_at = out.len();
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&msg[end..]);
+
+    let pointer = out.len();
+    if pointer > MAX_POINTER {
+        return None;
+    }
+    out[pointer_at..pointer_at + 2].copy_from_slice(&(0xc000 | pointer as u16).to_be_bytes());
+
+    for label in &labels[1..] {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query for `www.example.com` (type A, class IN).
+    fn query() -> Vec<u8> {
+        let mut msg = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x03www\x07example\x03com\x00");
+        msg.extend_from_slice(&[0, 1, 0, 1]);
+        msg
+    }
+
+    #[test]
+    fn read_question_labels() {
+        let msg = query();
+        let (labels, end) = read_labels(&msg, HEADER_LEN).unwrap();
+        assert_eq!(labels, vec![&b"www"[..], b"example", b"com"]);
+        assert_eq!(end, msg.len() - 4);
+    }
+
+    #[test]
+    fn read_truncated_or_compressed_labels() {
+        assert!(read_labels(b"\x03ww", 0).is_none());
+        assert!(read_labels(b"\x03www", 0).is_none());
+        assert!(read_labels(b"\x03www\xc0\x0c", 0).is_none());
+    }
+
+    #[test]
+    fn read_names() {
+        let msg = query();
+        assert_eq!(read_name(&msg, HEADER_LEN), read_labels(&msg, HEADER_LEN));
+
+        // A compressed name ends after its first pointer, but still has all of its labels.
+        let compressed = compress_question(&msg).unwrap();
+        let (labels, end) = read_name(&compressed, HEADER_LEN).unwrap();
+        assert_eq!(labels, vec![&b"www"[..], b"example", b"com"]);
+        assert_eq!(end, HEADER_LEN + 6);
+
+        assert!(read_name(b"\x03www\xc0", 0).is_none());
+        assert!(read_name(b"\x03www\xc0\x00", 0).is_none());
+        assert!(read_name(b"\x03www\x80\x00", 0).is_none());
+    }
+
+    #[test]
+    fn compress_query() {
+        let compressed = compress_question(&query()).unwrap();
+
+        let mut expected = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(b"\x03www\xc0\x16");
+        expected.extend_from_slice(&[0, 1, 0, 1]);
+        expected.extend_from_slice(b"\x07example\x03com\x00");
+        assert_eq!(compressed, expected);
+    }
+
+    #[test]
+    fn compress_single_label() {
+        let mut msg = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x03com\x00\x00\x01\x00\x01");
+        assert_eq!(compress_question(&msg).unwrap(), msg);
+    }
+
+    #[test]
+    fn compress_requires_question() {
+        let mut msg = query();
+        msg[5] = 0;
+        assert!(compress_question(&msg).is_none());
+        assert!(compress_question(&msg[..8]).is_none());
+    }
+}
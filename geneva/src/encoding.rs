@@ -0,0 +1,116 @@
+//! Decoding the byte strings that triggers and actions take as values.
+//!
+//! Strategy strings are text, so a payload with bytes that can't be typed (or that the grammar
+//! doesn't allow) is written in an encoding instead: `hex:` followed by pairs of hex digits, or
+//! `b64:` followed by standard base64 (with or without its `=` padding). Any other value stands
+//! for its own UTF-8 bytes.
+use alloc::borrow::Cow;
+
+use crate::prelude::*;
+
+/// Returns the bytes that `value` stands for, or `None` if it names an encoding but isn't valid
+/// in it.
+pub(crate) fn decode_bytes(value: &str) -> Option<Cow<'_, [u8]>> {
+    if let Some(hex) = value.strip_prefix("hex:") {
+        decode_hex(hex).map(Cow::Owned)
+    } else if let Some(base64) = value.strip_prefix("b64:") {
+        decode_base64(base64).map(Cow::Owned)
+    } else {
+        Some(Cow::Borrowed(value.as_bytes()))
+    }
+}
+
+/// Decodes a string of hex digits into bytes, or returns `None` if it isn't valid hex.
+pub(crate) fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` would accept a sign, so check the digits first.
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decodes standard base64 into bytes, or returns `None` if it isn't valid base64.
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let digits = s.trim_end_matches('=');
+    let padding = s.len() - digits.len();
+    if padding > 2 || (padding > 0 && !s.len().is_multiple_of(4)) || digits.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    let (mut bits, mut len) = (0u32, 0);
+    for c in digits.bytes() {
+        let digit = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | digit as u32;
+        len += 6;
+        if len >= 8 {
+            len -= 8;
+            bytes.push((bits >> len) as u8);
+            bits &= (1 << len) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_values() {
+        assert_eq!(decode_bytes("hello").unwrap(), &b"hello"[..]);
+        assert_eq!(decode_bytes("").unwrap(), &b""[..]);
+        // The prefixes only mean something with the colon.
+        assert_eq!(decode_bytes("hex").unwrap(), &b"hex"[..]);
+    }
+
+    #[test]
+    fn hex_values() {
+        assert_eq!(
+            decode_bytes("hex:deadBEEF").unwrap(),
+            &[0xde, 0xad, 0xbe, 0xef][..]
+        );
+        assert_eq!(decode_bytes("hex:").unwrap(), &b""[..]);
+        for invalid in ["hex:abc", "hex:zz", "hex:+1"] {
+            assert_eq!(decode_bytes(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn base64_values() {
+        // The test vectors from RFC 4648.
+        for (encoded, decoded) in [
+            ("", ""),
+            ("Zg==", "f"),
+            ("Zm8=", "fo"),
+            ("Zm9v", "foo"),
+            ("Zm9vYg==", "foob"),
+            ("Zm9vYmE=", "fooba"),
+            ("Zm9vYmFy", "foobar"),
+            ("Zm9vYg", "foob"),
+        ] {
+            let value = format!("b64:{}", encoded);
+            assert_eq!(
+                decode_bytes(&value).unwrap(),
+                decoded.as_bytes(),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(decode_bytes("b64:AP/+").unwrap(), &[0x00, 0xff, 0xfe][..]);
+
+        for invalid in ["b64:Z", "b64:Zg=", "b64:Zg===", "b64:Zm9v!", "b64:=Zg="] {
+            assert_eq!(decode_bytes(invalid), None, "{}", invalid);
+        }
+    }
+}
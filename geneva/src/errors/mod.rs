@@ -1,16 +1,36 @@
-use std::fmt;
+use core::fmt;
 
+#[cfg(feature = "parser")]
 use crate::parser;
+use crate::prelude::*;
 
 /// `Result` is a type that represents either success ([`Ok`](Self::Ok)) or failure ([`Err`](Self::Err)).
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// The error type for Geneva operations.
 #[derive(Debug, Clone)]
 pub enum Error {
     /// An error parsing a Geneva rule.
     Parse(String),
-    Syntax(pest::error::Error<parser::Rule>),
+    /// A syntax error in a Geneva strategy string.
+    #[cfg(feature = "parser")]
+    Syntax(Box<pest::error::Error<parser::Rule>>),
+    /// A trigger or action names a header field that the protocol does not have.
+    UnknownField { protocol: String, field: String },
+    /// A packet could not be handled because it is not the kind of packet an action expects.
+    MalformedPacket,
+    /// A value that does not make sense for the field it applies to; `field` is given as
+    /// `protocol:field`.
+    InvalidValue { field: String, value: String },
+    /// An action was asked to do something that the field it applies to does not allow, such as
+    /// clearing bits of a sequence number; `field` is given as `protocol:field`.
+    Unsupported { field: String, operation: String },
+    /// An error reading a packet capture file.
+    Capture(String),
+    /// An error sending a packet onto the network.
+    Transmit(String),
+    /// Applying a strategy would go beyond one of its [Limits](crate::Limits).
+    LimitExceeded(String),
 }
 
 impl fmt::Display for Error {
@@ -18,22 +38,36 @@ impl fmt::Display for Error {
         use Error::*;
         match self {
             Parse(s) => write!(f, "parse error: \"{}\"", s),
+            #[cfg(feature = "parser")]
             Syntax(s) => write!(f, "{}", s),
+            UnknownField { protocol, field } => write!(f, "unknown field {}:{}", protocol, field),
+            MalformedPacket => f.write_str("malformed packet"),
+            InvalidValue { field, value } => {
+                write!(f, "invalid value for {}: \"{}\"", field, value)
+            }
+            Unsupported { field, operation } => {
+                write!(f, "{} does not support {}", field, operation)
+            }
+            Capture(s) => write!(f, "capture error: {}", s),
+            Transmit(s) => write!(f, "transmit error: {}", s),
+            LimitExceeded(s) => write!(f, "limit exceeded: {}", s),
         }
     }
 }
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
         match self {
-            Self::Parse(_) => None,
-            Self::Syntax(s) => Some(s),
+            #[cfg(feature = "parser")]
+            Self::Syntax(s) => Some(s.as_ref()),
+            _ => None,
         }
     }
 }
 
+#[cfg(feature = "parser")]
 impl From<pest::error::Error<parser::Rule>> for Error {
     fn from(e: pest::error::Error<parser::Rule>) -> Self {
-        Self::Syntax(e)
+        Self::Syntax(Box::new(e))
     }
 }
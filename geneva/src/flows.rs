@@ -0,0 +1,129 @@
+//! Bounded per-flow state.
+//!
+//! Stateful triggers and the [Reassembler](crate::Reassembler) remember things about each flow
+//! they see, but a long-running program sees flows without end, and many of them (port scans, or
+//! connections whose FIN was lost) never say that they are over. A [FlowTable] keeps the state
+//! of a fixed number of flows and forgets the least recently used one to make room for another.
+use alloc::collections::BTreeMap;
+
+use crate::FlowKey;
+
+/// A map from flows to their state that holds at most a fixed number of flows.
+#[derive(Debug, Clone)]
+pub(crate) struct FlowTable<V> {
+    /// Each flow's state, with the time it was last used.
+    entries: BTreeMap<FlowKey, (u64, V)>,
+
+    /// The flows in the order they were last used, keyed by that time.
+    order: BTreeMap<u64, FlowKey>,
+
+    /// The time to give the next flow that is used; it counts uses, not seconds.
+    clock: u64,
+
+    capacity: usize,
+}
+
+impl<V> FlowTable<V> {
+    /// Creates an empty table that holds at most `capacity` flows (and at least one).
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Returns the number of flows in the table.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the state of `key`, marking the flow as the most recently used.
+    pub(crate) fn get(&mut self, key: &FlowKey) -> Option<&mut V> {
+        let (used, value) = self.entries.get_mut(key)?;
+        self.order.remove(used);
+        *used = self.clock;
+        self.order.insert(self.clock, *key);
+        self.clock += 1;
+        Some(value)
+    }
+
+    /// Sets the state of `key`, forgetting the least recently used flow if the table is full.
+    pub(crate) fn insert(&mut self, key: FlowKey, value: V) {
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (self.clock, value));
+        self.order.insert(self.clock, key);
+        self.clock += 1;
+    }
+
+    /// Returns the state of `key`, first inserting the state that `f` returns if the table does
+    /// not have the flow yet.
+    pub(crate) fn get_or_insert_with(&mut self, key: FlowKey, f: impl FnOnce() -> V) -> &mut V {
+        if !self.entries.contains_key(&key) {
+            self.insert(key, f());
+        }
+        self.get(&key).expect("the flow was just inserted")
+    }
+
+    /// Forgets the state of `key`, returning it if the table had it.
+    pub(crate) fn remove(&mut self, key: &FlowKey) -> Option<V> {
+        let (used, value) = self.entries.remove(key)?;
+        self.order.remove(&used);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::net::{IpAddr, Ipv4Addr};
+
+    fn flow(sport: u16) -> FlowKey {
+        FlowKey {
+            src: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            dst: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            sport,
+            dport: 80,
+            protocol: 6,
+        }
+    }
+
+    #[test]
+    fn least_recently_used_flow_is_forgotten() {
+        let mut table = FlowTable::new(2);
+        table.insert(flow(1), "one");
+        table.insert(flow(2), "two");
+        assert_eq!(table.get(&flow(1)), Some(&mut "one"));
+
+        // Flow 2 was used less recently than flow 1.
+        table.insert(flow(3), "three");
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&flow(2)), None);
+        assert_eq!(table.get(&flow(1)), Some(&mut "one"));
+        assert_eq!(table.get(&flow(3)), Some(&mut "three"));
+
+        // Replacing a flow's state doesn't make room for it by forgetting another.
+        table.insert(flow(1), "uno");
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&flow(3)), Some(&mut "three"));
+    }
+
+    #[test]
+    fn removed_flows_free_their_place() {
+        let mut table = FlowTable::new(2);
+        table.insert(flow(1), 1);
+        table.insert(flow(2), 2);
+        assert_eq!(table.remove(&flow(1)), Some(1));
+        assert_eq!(table.remove(&flow(1)), None);
+
+        table.insert(flow(3), 3);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.get(&flow(2)), Some(&mut 2));
+    }
+}
@@ -3,7 +3,8 @@
 //!
 //! Geneva is both a method to describe ways of manipulating packets to attempt to circumvent
 //! censorship, and a genetic algoritmm (GENetic EVAsion) that one can deploy to discover new
-//! circumventions. (This crate does not implement the genetic algorithm.) More broadly, one can
+//! circumventions. (This crate does not implement the genetic algorithm itself, although the
+//! [mutate] module provides its mutation step.) More broadly, one can
 //! encode arbitrary instructions for packet manipulation using Geneva rules as a sort of "standard
 //! syntax", although the use case outside of censorship circumvention may be somewhat tenuous.
 //!
@@ -62,6 +63,44 @@
 //! _not_ fire for packets that have, i.e., both SYN and ACK set.) If the packet is not a TCP
 //! packet, or the flags do not match exactly, then this trigger will not fire.
 //!
+//! Protocol and field names are not case-sensitive, and a few fields have longer aliases that
+//! other tools use: `source` and `destination` for the IP addresses, and `sourceport` and
+//! `destinationport` for the TCP and UDP ports. Strategies are always written back out with the
+//! short, lowercase names.
+//!
+//! A trigger's value can be prefixed with `!` to match packets whose field does _not_ match, and
+//! numeric fields can be compared with `<`, `<=`, `>`, or `>=` instead of matched exactly. For
+//! instance, `[IP:ttl:<64]` fires for any packet with a TTL below 64. TCP sequence and
+//! acknowledgement numbers can be matched relative to the start of the connection by writing
+//! `rel:` before the value, so `[TCP:seq:rel:1]` fires for the first segment of data after the
+//! SYN. Similarly, the `effwindow` field is the TCP window scaled by the window scale option from
+//! the SYN, so `[TCP:effwindow:>65535]` fires once a connection's window grows past what the field
+//! alone can hold. A TCP option trigger with no value matches on whether the option is there at
+//! all, so `[TCP:options-sackok:]` fires for segments that offer selective acknowledgements. No
+//! other field may be left without a value, and a comparison always needs one.
+//!
+//! A TCP or UDP `load` trigger fires when the payload contains its value anywhere, the way a censor
+//! looks for keywords, so `[TCP:load:example.com]` matches any request for that host. IP and ICMP
+//! `load` triggers only fire when the whole payload is their value. Payloads (the `load` field of
+//! triggers and `tamper`) can contain bytes that a strategy string can't, so their values may be
+//! encoded: `hex:` followed by hex digits, or `b64:` followed by base64. For instance,
+//! `[TCP:load:hex:16030100]` matches a payload with those four bytes in it.
+//!
+//! IP address triggers take a single address or a CIDR network, such as `[IP:dst:10.0.0.0/8]`.
+//! IPv6 addresses go in brackets, since their colons would otherwise end the value:
+//! `[IP:src:[2001:db8::1]]` or `[IP:dst:[2001:db8::]/32]`.
+//!
+//! The IP trigger also has a `wirelen` field, which isn't in any header: it is the length of the
+//! packet as captured, so `[IP:wirelen:>1400]` fires for large packets even if their headers are
+//! truncated or wrong.
+//!
+//! Besides the IP, TCP, UDP, and ICMP headers, triggers can look inside DNS messages carried over
+//! UDP port 53; `[DNS:qname:example.com]` fires for queries for `example.com`.
+//!
+//! Triggers look at one packet at a time, so a `[TCP:load:...]` trigger won't match data that is
+//! split across several TCP segments. A [Reassembler] can stitch each stream back together and
+//! match `load` triggers against everything the stream has carried so far.
+//!
 //! # Actions
 //!
 //! An action simply encodes steps to manipulate a packet. There are a number of actions described in
@@ -86,7 +125,8 @@
 //!
 //! The "duplicate" action copies the original packet, then applies action `a1` to the original and
 //! `a2` to the copy. For example, if `a1` and `a2` are both "send" actions, then the action will
-//! yield two packets identical to the first. The syntax is:
+//! yield two packets identical to the first. Every packet that `a1` yields comes before every
+//! packet that `a2` yields, however many each of them produces. The syntax is:
 //!
 //! `duplicate(a1, a2)`
 //!
@@ -100,19 +140,56 @@
 //! the same IP header as the original packet (aside from the fields that must be fixed) and then
 //! the first eight bytes of the payload. The second fragment will contain the other 52 bytes. (You
 //! can also indicate that the fragments be returned out-of-order; i.e., reversed, by specifying
-//! "False" for the "inOrder" argument.) The syntax is:
+//! "False" for the "inOrder" argument.) Whichever fragment is to be sent first goes to `a1`, and
+//! as with "duplicate", the packets that `a1` yields come before those of `a2`. The syntax is:
 //!
 //! `fragment{protocol:offset:inOrder}(a1, a2)`
 //!
+//! Fragmenting a UDP datagram (protocol 17) IP-fragments it, with the UDP header and "offset"
+//! bytes of its payload in the first fragment, so the offset must be a multiple of eight.
+//! Fragmenting at the IP layer (protocol 4, or `ip`) splits the IP payload itself, whatever it
+//! carries, with the offset rounded down to a multiple of eight.
+//!
 //! ## tamper
 //!
 //! The "tamper" action takes the original packet and modifies it in some fashion, depending on the
 //! protocol, field, and mode given. There are two modes: replace and corrupt. The "replace" mode
 //! will replace the value of the given field with newValue, while the "corrupt" mode will replace
 //! the value with random data. (Note that there are other modes that the Python code supports that
-//! are not defined in the original Geneva paper.) The syntax is:
+//! are not defined in the original Geneva paper.) This library adds a "fix" mode, which recomputes
+//! a checksum, the IP total length, or the UDP length from the rest of the packet, as in
+//! `tamper{IP:chksum:fix}`, undoing an earlier corruption. The syntax is:
+//!
+//! `tamper{protocol:field:mode[:newValue]}(a1,)`
+//!
+//! As in Geneva, the trailing comma is part of the canonical form, though it may be left out when
+//! parsing; a tamper followed only by `send` is written with no parentheses at all.
+//!
+//! The mode may be followed by options, each written with a `+`: `+urg` makes a tamper of the
+//! TCP urgent pointer set the URG flag too (see [TamperAction::with_urgent_flag]), and `+stale`
+//! leaves the checksums of the tampered packet unfixed (see [TamperAction::with_stale_checksums]).
+//! For instance, `tamper{TCP:urgptr:replace+urg:5}` or `tamper{TCP:seq:corrupt+stale}`.
+//!
+//! IP tampers work on IPv6 packets too, with the field names that IP triggers use for them: the
+//! addresses, `ttl` for the hop limit, `protocol` for the next header, `len` for the payload
+//! length, and `tos` for the traffic class. As in triggers, IPv6 addresses go in brackets, as in
+//! `tamper{IP:dst:replace:[2001:db8::1]}`. UDP tampers work on IPv6 datagrams as well, and since
+//! IPv6 requires the UDP checksum, they always fix it up there, even if it was zero.
+//!
+//! ## sleep
+//!
+//! The "sleep" action delays the packet by the given number of seconds (which may be fractional)
+//! before applying action `a1` to it. This library never blocks, so the delay is recorded in the
+//! packet's [metadata](PacketMeta::delay), and it's up to whatever sends the packet to honor it.
+//! The syntax is:
 //!
-//! `tamper{protocol:field:mode[:newValue]}(a1)`
+//! `sleep{seconds}(a1)`
+//!
+//! ## Custom actions
+//!
+//! Actions that Geneva doesn't define can be added by implementing [Action] and wrapping the type
+//! in a [CustomAction]. To parse strategies that use them, register each one's name with an
+//! [ActionRegistry] and call [parse_strategy_with].
 //!
 //! Additionally, note that not all actions are valid for both inbound and outbound directions. The
 //! Python code mentions that "branching actions are not supported on inbound trees". Practically,
@@ -121,15 +198,75 @@
 //!
 //! See <https://censorship.ai> for more information about Geneva itself.
 //!
+//! # Features
+//!
+//! - `std` (on by default): uses the standard library. Without it, the crate only needs `alloc`,
+//!   so packets, triggers, actions, and strategies built in code can be used on `no_std` targets.
+//! - `parser` (on by default): parses strategy strings with [parse_strategy] and [FromStr], which
+//!   [GenevaAction] and [GenevaTrigger] also implement for a single action or trigger. This needs
+//!   `std`.
+//! - `serde`: implements `Serialize` and `Deserialize` for [Strategy] and everything it contains,
+//!   so that strategies can be saved to disk in formats such as JSON.
+//! - `pcap`: adds the `pcap` module, which applies a strategy to every packet in a pcap or pcapng
+//!   capture file.
+//! - `net`: adds the `net` module, which sends packets onto the network through raw sockets.
+//! - `pnet`: adds the `pnet` module, which converts between [Packet] and the packet types of the
+//!   `pnet` crate.
+//!
 //! [geneva]: https://geneva.cs.umd.edu/papers/geneva_ccs19.pdf
+//! [FromStr]: core::str::FromStr
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(feature = "parser")]
 extern crate pest;
+#[cfg(feature = "parser")]
 #[macro_use]
 extern crate pest_derive;
 
+/// The `alloc` types that the standard library's prelude would otherwise provide.
+mod prelude {
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec::Vec;
+    pub(crate) use alloc::{format, vec};
+}
+
 pub mod actions;
 #[doc(inline)]
 pub use actions::*;
 
+pub mod checksum;
+
+pub mod diff;
+#[doc(inline)]
+pub use diff::{strategy_diff, DiffEntry};
+
+mod dns;
+
+mod encoding;
+
+mod flows;
+
+pub mod mutate;
+
+#[cfg(feature = "net")]
+pub mod net;
+
+pub mod packet;
+
+#[cfg(feature = "pcap")]
+pub mod pcap;
+#[doc(inline)]
+pub use packet::{FlowKey, LinkType, Packet, PacketMeta};
+
+#[cfg(feature = "pnet")]
+pub mod pnet;
+
+pub mod reassembly;
+#[doc(inline)]
+pub use reassembly::Reassembler;
+
 pub mod errors;
 #[doc(inline)]
 pub use crate::errors::*;
@@ -142,42 +279,7 @@ pub mod triggers;
 #[doc(inline)]
 pub use triggers::*;
 
+#[cfg(feature = "parser")]
 mod parser;
+#[cfg(feature = "parser")]
 pub use parser::*;
-
-/// Represents a network packet as a vector of raw bytes.
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct Packet(Vec<u8>);
-
-impl Packet {
-    /// Creates a new Packet by consuming the vector. This operation does not copy or allocate.
-    pub fn new(p: Vec<u8>) -> Self {
-        Self(p)
-    }
-
-    /// Creates a new Packet by copying the slice into itself.
-    pub fn new_from_slice(s: &[u8]) -> Self {
-        Self(s.to_vec())
-    }
-
-    /// Returns the number of bytes in the packet.
-    pub fn len(&self) -> usize {
-        self.0.len()
-    }
-
-    /// Returns `true` if the packet is zero-length.
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
-    }
-
-    /// Extracts a slice containing the entire packet.
-    pub fn as_slice(&self) -> &[u8] {
-        &self.0
-    }
-}
-
-impl From<Vec<u8>> for Packet {
-    fn from(v: Vec<u8>) -> Self {
-        Self(v)
-    }
-}
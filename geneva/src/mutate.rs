@@ -0,0 +1,448 @@
+//! Random mutation of Geneva strategies.
+//!
+//! Geneva discovers new strategies by evolving a population of them with a genetic algorithm. This
+//! module provides the mutation and recombination steps of that algorithm: [mutate] makes one
+//! small, random change to a [Strategy], and [crossover] combines two parent strategies into a
+//! child. Both produce strategies that are still valid, so their string forms can always be parsed
+//! back with [parse_strategy](crate::parse_strategy).
+//!
+//! The random number generator is passed in by the caller, so seeding it makes the results
+//! reproducible.
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore};
+
+use crate::actions::{
+    ActionTree, DropAction, DuplicateAction, FragmentAction, GenevaAction, SendAction,
+    TamperAction, TamperMode,
+};
+use crate::packet::PROTO_TCP;
+use crate::prelude::*;
+use crate::strategy::{Direction, Forest, Strategy};
+use crate::triggers::{
+    GenevaTrigger, IPField, IPTrigger, TCPField, TCPTrigger, UDPField, UDPTrigger,
+};
+
+/// How many levels of actions a newly-generated action tree may have below its root.
+const MAX_DEPTH: usize = 3;
+
+/// The flag combinations that randomly-generated TCP flag triggers match on.
+const TCP_FLAGS: &[&str] = &["S", "SA", "A", "PA", "FA", "R", "RA"];
+
+/// The individual flags that randomly-generated tamper actions set or clear.
+const TCP_FLAG_BITS: &[&str] = &["F", "S", "R", "P", "A", "U", "E", "C"];
+
+/// Well-known ports that randomly-generated port triggers match on.
+const PORTS: &[u16] = &[22, 53, 80, 443, 8080];
+
+/// Header fields that tamper actions can rewrite, along with their width in bits.
+const TAMPER_FIELDS: &[(&str, &str, u32)] = &[
+    ("IP", "tos", 8),
+    ("IP", "id", 16),
+    ("IP", "ttl", 8),
+    ("TCP", "seq", 32),
+    ("TCP", "ack", 32),
+    ("TCP", "window", 16),
+    ("TCP", "chksum", 16),
+    ("TCP", "urgptr", 16),
+];
+
+/// Randomly mutates `strategy` in place.
+///
+/// One of the following mutations is applied to a randomly-chosen forest:
+///
+/// * a new, randomly-generated action tree is added;
+/// * an action tree is removed;
+/// * an action tree's trigger is replaced with a new one; or
+/// * a randomly-chosen action in an action tree, along with everything below it, is replaced with
+///   a new subtree.
+///
+/// If the chosen forest is empty, a new action tree is always added. Action trees in the inbound
+/// forest never contain branching actions (`duplicate` or `fragment`).
+///
+/// ```
+/// use geneva::mutate::mutate;
+/// use geneva::{parse_strategy, Strategy};
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// let mut rng = StdRng::seed_from_u64(1);
+/// let mut strategy = Strategy::default();
+/// for _ in 0..10 {
+///     mutate(&mut strategy, &mut rng);
+/// }
+/// assert_eq!(parse_strategy(&strategy.to_string()).unwrap(), strategy);
+/// ```
+pub fn mutate(strategy: &mut Strategy, rng: &mut impl RngCore) {
+    let (direction, forest) = if rng.gen_bool(0.5) {
+        (Direction::Outbound, &mut strategy.outbound)
+    } else {
+        (Direction::Inbound, &mut strategy.inbound)
+    };
+    let branching = matches!(direction, Direction::Outbound);
+
+    let trees = match forest {
+        Some(trees) if !trees.is_empty() => trees,
+        _ => {
+            forest
+                .get_or_insert_with(Forest::new)
+                .push(random_tree(rng, branching));
+            return;
+        }
+    };
+
+    let index = rng.gen_range(0..trees.len());
+    match rng.gen_range(0..4) {
+        0 => {
+            let position = rng.gen_range(0..=trees.len());
+            trees.insert(position, random_tree(rng, branching));
+        }
+        1 => {
+            trees.remove(index);
+            if trees.is_empty() {
+                *forest = None;
+            }
+        }
+        2 => trees[index].trigger = random_trigger(rng),
+        _ => {
+            let root = &mut trees[index].root_action;
+            let target = rng.gen_range(0..root.node_count());
+            let (action, depth) = match nth_action(root, target, 0) {
+                Ok(found) => found,
+                Err(_) => unreachable!("target is always less than the number of actions"),
+            };
+            *action = random_action(rng, MAX_DEPTH.saturating_sub(depth), branching, depth == 0);
+        }
+    }
+}
+
+/// Recombines two parent strategies into a new child strategy.
+///
+/// The child is made in one of two ways:
+///
+/// * it takes its outbound forest from one parent and its inbound forest from the other; or
+/// * it is a copy of one parent in which a randomly-chosen action has been replaced by a copy of a
+///   randomly-chosen action subtree from the same forest of the other parent.
+///
+/// Subtrees are only exchanged between forests of the same direction, and a bare `send` is never
+/// spliced in, so the child always contains something from both parents (unless the parents have
+/// nothing to exchange). If there is no subtree to exchange, the forests are swapped instead.
+///
+/// ```
+/// use geneva::mutate::crossover;
+/// use geneva::parse_strategy;
+/// use rand::rngs::StdRng;
+/// use rand::SeedableRng;
+///
+/// let a = parse_strategy(r"[TCP:flags:S]-drop-| \/").unwrap();
+/// let b = parse_strategy(r"\/ [TCP:flags:R]-drop-|").unwrap();
+///
+/// let child = crossover(&a, &b, &mut StdRng::seed_from_u64(0));
+/// assert_eq!(parse_strategy(&child.to_string()).unwrap(), child);
+/// ```
+pub fn crossover(a: &Strategy, b: &Strategy, rng: &mut impl RngCore) -> Strategy {
+    let (base, donor) = if rng.gen_bool(0.5) { (a, b) } else { (b, a) };
+
+    if rng.gen_bool(0.5) {
+        if let Some(child) = splice(base, donor, rng) {
+            return child;
+        }
+    }
+
+    Strategy {
+        outbound: base.outbound.clone(),
+        inbound: donor.inbound.clone(),
+        limits: base.limits,
+    }
+}
+
+/// Copies `base`, replacing one of its actions with a subtree taken from `donor`. Returns `None`
+/// if the two strategies have no forest in common, or the donor has only `send` actions there.
+fn splice(base: &Strategy, donor: &Strategy, rng: &mut impl RngCore) -> Option<Strategy> {
+    let mut child = base.clone();
+
+    let (trees, donors) = if rng.gen_bool(0.5) {
+        (child.outbound.as_mut()?, donor.outbound.as_ref()?)
+    } else {
+        (child.inbound.as_mut()?, donor.inbound.as_ref()?)
+    };
+
+    // Pick the subtree to splice in from every non-send action in the donor forest.
+    let mut candidates = vec![];
+    for tree in donors {
+        tree.root_action.walk(&mut |action: &GenevaAction| {
+            if !matches!(action, GenevaAction::Send(_)) {
+                candidates.push(action.clone());
+            }
+        });
+    }
+    let subtree = candidates.choose(rng)?.clone();
+
+    let index = rng.gen_range(0..trees.len());
+    let root = &mut trees[index].root_action;
+    let target = rng.gen_range(0..root.node_count());
+    if let Ok((action, _)) = nth_action(root, target, 0) {
+        *action = subtree;
+    }
+
+    Some(child)
+}
+
+/// Generates a random action tree. Branching actions are only used if `branching` is `true`.
+fn random_tree(rng: &mut impl RngCore, branching: bool) -> ActionTree {
+    ActionTree::new(
+        random_trigger(rng),
+        random_action(rng, MAX_DEPTH, branching, true),
+    )
+}
+
+/// Generates a random trigger with unlimited gas.
+fn random_trigger(rng: &mut impl RngCore) -> GenevaTrigger {
+    let trigger = match rng.gen_range(0..6) {
+        0 => {
+            TCPTrigger::new(TCPField::Flags, choose(rng, TCP_FLAGS).to_string(), 0).map(Into::into)
+        }
+        1 => TCPTrigger::new(TCPField::DestPort, choose(rng, PORTS).to_string(), 0).map(Into::into),
+        2 => {
+            TCPTrigger::new(TCPField::SourcePort, choose(rng, PORTS).to_string(), 0).map(Into::into)
+        }
+        3 => TCPTrigger::new(TCPField::Window, rng.gen::<u16>().to_string(), 0).map(Into::into),
+        4 => IPTrigger::new(IPField::TTL, rng.gen::<u8>().to_string(), 0).map(Into::into),
+        _ => UDPTrigger::new(UDPField::DestPort, choose(rng, PORTS).to_string(), 0).map(Into::into),
+    };
+    trigger.expect("randomly-generated trigger values are always valid")
+}
+
+/// Generates a random action with at most `depth` levels of actions below it.
+///
+/// A root action is never `send`, since an action tree that only sends the packet does nothing.
+fn random_action(
+    rng: &mut impl RngCore,
+    depth: usize,
+    branching: bool,
+    root: bool,
+) -> GenevaAction {
+    let first = if root { 1 } else { 0 };
+    let last = match (depth, branching) {
+        (0, _) => 2,
+        (_, false) => 3,
+        (_, true) => 5,
+    };
+    let depth = depth.saturating_sub(1);
+
+    match rng.gen_range(first..last) {
+        0 => SendAction::default().into(),
+        1 => DropAction::default().into(),
+        2 => {
+            let action = random_action(rng, depth, branching, false);
+            random_tamper(rng, action).into()
+        }
+        3 => DuplicateAction::new(
+            random_action(rng, depth, branching, false),
+            random_action(rng, depth, branching, false),
+        )
+        .into(),
+        _ => FragmentAction::new(
+            PROTO_TCP as u16,
+            rng.gen_range(1..=64),
+            rng.gen_bool(0.5),
+            0,
+            random_action(rng, depth, branching, false),
+            random_action(rng, depth, branching, false),
+        )
+        .expect("TCP is always a valid protocol to fragment")
+        .into(),
+    }
+}
+
+/// Generates a random tamper action that passes the tampered packet to `action`.
+fn random_tamper(rng: &mut impl RngCore, action: GenevaAction) -> TamperAction {
+    let (protocol, field, value, mode) = if rng.gen_ratio(1, TAMPER_FIELDS.len() as u32 + 1) {
+        let mode = if rng.gen_bool(0.5) {
+            TamperMode::Add
+        } else {
+            TamperMode::Clear
+        };
+        ("TCP", "flags", choose(rng, TCP_FLAG_BITS).to_string(), mode)
+    } else {
+        let (protocol, field, bits) = *choose(rng, TAMPER_FIELDS);
+        if rng.gen_bool(0.5) {
+            let max = u32::MAX >> (32 - bits);
+            let value = rng.gen_range(0..=max).to_string();
+            (protocol, field, value, TamperMode::Replace)
+        } else {
+            (protocol, field, String::new(), TamperMode::Corrupt)
+        }
+    };
+
+    TamperAction::new(protocol.to_string(), field.to_string(), value, mode, action)
+        .expect("randomly-generated tamper actions are always valid")
+}
+
+/// Returns a random element of a non-empty slice.
+fn choose<'a, T>(rng: &mut impl RngCore, items: &'a [T]) -> &'a T {
+    items.choose(rng).expect("items is never empty")
+}
+
+/// Finds the `n`th action, in pre-order, of the tree rooted at `action`, whose depth in the
+/// overall tree is `depth`. Returns the action and its depth, or the number of actions still to be
+/// skipped if the tree has `n` or fewer actions.
+fn nth_action(
+    action: &mut GenevaAction,
+    n: usize,
+    depth: usize,
+) -> core::result::Result<(&mut GenevaAction, usize), usize> {
+    if n == 0 {
+        return Ok((action, depth));
+    }
+
+    let mut remaining = n - 1;
+    for child in action.children_mut() {
+        match nth_action(child, remaining, depth + 1) {
+            Ok(found) => return Ok(found),
+            Err(left) => remaining = left,
+        }
+    }
+    Err(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_strategy;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn has_branching(action: &mut GenevaAction) -> bool {
+        matches!(
+            action,
+            GenevaAction::Duplicate(_) | GenevaAction::Fragment(_)
+        ) || action.children_mut().into_iter().any(has_branching)
+    }
+
+    #[test]
+    fn mutated_strategies_parse() {
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut strategy = parse_strategy(
+                r"[TCP:flags:S]-duplicate(tamper{TCP:flags:add:A},)-| \/ [TCP:flags:R]-drop-|",
+            )
+            .unwrap();
+
+            for _ in 0..50 {
+                mutate(&mut strategy, &mut rng);
+                let s = strategy.to_string();
+                let parsed = parse_strategy(&s).unwrap_or_else(|e| panic!("{}: {}", s, e));
+                assert_eq!(parsed, strategy, "{}", s);
+
+                let forests = strategy.outbound.iter().chain(strategy.inbound.iter());
+                for tree in forests.flatten() {
+                    assert!(tree.depth() <= MAX_DEPTH + 1, "{}", s);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mutation_is_deterministic() {
+        let run = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut strategy = Strategy::default();
+            for _ in 0..20 {
+                mutate(&mut strategy, &mut rng);
+            }
+            strategy.to_string()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn empty_strategy_gains_a_tree() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut strategy = Strategy::default();
+        mutate(&mut strategy, &mut rng);
+
+        let trees = strategy.outbound.iter().chain(strategy.inbound.iter());
+        assert_eq!(trees.map(Vec::len).sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn inbound_trees_do_not_branch() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut strategy = Strategy::default();
+        for _ in 0..200 {
+            mutate(&mut strategy, &mut rng);
+        }
+
+        for tree in strategy.inbound.iter_mut().flatten() {
+            assert!(!has_branching(&mut tree.root_action), "{}", tree);
+        }
+    }
+
+    #[test]
+    fn crossover_children_come_from_both_parents() {
+        let a = parse_strategy(concat!(
+            r"[TCP:flags:S]-tamper{TCP:window:replace:1111}-| \/ ",
+            r"[TCP:flags:R]-tamper{TCP:window:replace:2222}-|"
+        ))
+        .unwrap();
+        let b = parse_strategy(concat!(
+            r"[UDP:dport:53]-tamper{IP:ttl:replace:22}(tamper{IP:ttl:replace:33},)-| \/ ",
+            r"[UDP:sport:53]-tamper{IP:ttl:replace:44}-|"
+        ))
+        .unwrap();
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut spliced = 0;
+        for _ in 0..100 {
+            let child = crossover(&a, &b, &mut rng);
+            let s = child.to_string();
+            assert_eq!(parse_strategy(&s).unwrap(), child, "{}", s);
+            assert!(s.contains("TCP:"), "{}", s);
+            assert!(s.contains("UDP:") || s.contains("IP:ttl"), "{}", s);
+
+            let swapped = [(&a, &b), (&b, &a)]
+                .iter()
+                .any(|(x, y)| child.outbound == x.outbound && child.inbound == y.inbound);
+            if !swapped {
+                spliced += 1;
+            }
+        }
+        assert!(spliced > 0);
+        assert!(spliced < 100);
+    }
+
+    #[test]
+    fn crossover_of_mutated_strategies_parses() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let (mut a, mut b) = (Strategy::default(), Strategy::default());
+        for _ in 0..100 {
+            mutate(&mut a, &mut rng);
+            mutate(&mut b, &mut rng);
+
+            let child = crossover(&a, &b, &mut rng);
+            let s = child.to_string();
+            assert_eq!(parse_strategy(&s).unwrap(), child, "{}", s);
+        }
+    }
+
+    #[test]
+    fn nth_action_walks_in_pre_order() {
+        let mut action: GenevaAction = DuplicateAction::new(
+            DuplicateAction::new(DropAction::default().into(), SendAction::default().into()).into(),
+            DropAction::default().into(),
+        )
+        .into();
+        assert_eq!(action.node_count(), 5);
+
+        let depths: Vec<_> = (0..5)
+            .map(|n| nth_action(&mut action, n, 0).unwrap().1)
+            .collect();
+        assert_eq!(depths, vec![0, 1, 2, 2, 1]);
+        assert!(matches!(
+            nth_action(&mut action, 2, 0).unwrap().0,
+            GenevaAction::Drop(_)
+        ));
+        assert_eq!(nth_action(&mut action, 5, 0).unwrap_err(), 0);
+    }
+}
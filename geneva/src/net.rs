@@ -0,0 +1,101 @@
+//! Sending packets onto the network.
+//!
+//! This module is only available with the `net` feature. A [Transmitter] writes the packets that
+//! a strategy produces directly onto the wire through raw IP sockets, which usually requires root
+//! privileges (or `CAP_NET_RAW` on Linux).
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::errors::*;
+use crate::Packet;
+
+/// The protocol number for raw sockets that send packets with their IP header already in place.
+const IPPROTO_RAW: i32 = 255;
+
+/// Sends IP packets onto the network through raw sockets.
+///
+/// Each packet is sent exactly as it is, IP header included, to the destination address in its
+/// header; only a link layer (see [LinkType](crate::LinkType)) is left off. The IPv4 and IPv6
+/// sockets are opened the first time a packet of that version is sent, so a host without IPv6 can
+/// still send IPv4 packets.
+///
+/// ```no_run
+/// use geneva::net::Transmitter;
+/// use geneva::{Direction, Packet, Strategy};
+///
+/// # fn example(strategy: &Strategy, pkt: Packet) -> geneva::Result<()> {
+/// let mut transmitter = Transmitter::new();
+/// for pkt in strategy.apply(pkt, Direction::Outbound)? {
+///     transmitter.send(&pkt)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Transmitter {
+    v4: Option<Socket>,
+    v6: Option<Socket>,
+}
+
+impl Transmitter {
+    /// Creates a new `Transmitter`. No sockets are opened until the first packet is sent.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `pkt` onto the network.
+    ///
+    /// Returns [Error::MalformedPacket] if the packet does not start with a well-formed IP header,
+    /// or [Error::Transmit] if the socket could not be opened or the packet could not be sent.
+    pub fn send(&mut self, pkt: &Packet) -> Result<()> {
+        let (_, dst) = pkt.addresses().ok_or(Error::MalformedPacket)?;
+        let (socket, domain) = match dst {
+            IpAddr::V4(_) => (&mut self.v4, Domain::IPV4),
+            IpAddr::V6(_) => (&mut self.v6, Domain::IPV6),
+        };
+
+        let socket = match socket {
+            Some(socket) => socket,
+            None => socket.insert(open(domain).map_err(transmit_error)?),
+        };
+
+        // The socket supplies its own link layer.
+        let datagram = pkt.ip_datagram();
+        let addr = SockAddr::from(SocketAddr::new(dst, 0));
+        let sent = socket.send_to(datagram, &addr).map_err(transmit_error)?;
+        if sent != datagram.len() {
+            return Err(Error::Transmit(format!(
+                "sent {} of {} bytes",
+                sent,
+                datagram.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens a raw socket that sends packets with a caller-supplied IP header.
+fn open(domain: Domain) -> io::Result<Socket> {
+    Socket::new(domain, Type::RAW, Some(Protocol::from(IPPROTO_RAW)))
+}
+
+/// Returns the error for a socket operation that failed.
+fn transmit_error(e: io::Error) -> Error {
+    Error::Transmit(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_ip_packets_are_rejected() {
+        let mut transmitter = Transmitter::new();
+        let result = transmitter.send(&Packet::new(vec![0, 1, 2, 3]));
+        assert!(matches!(result, Err(Error::MalformedPacket)));
+        assert!(transmitter.v4.is_none() && transmitter.v6.is_none());
+    }
+}
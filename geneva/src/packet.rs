@@ -0,0 +1,820 @@
+//! This module provides the [Packet] type that triggers and actions operate on.
+//!
+//! A `Packet` is a buffer of raw bytes that starts with an IP (v4 or v6) header, or, if its
+//! [LinkType] says so, with an Ethernet header that carries one. The first time any of the layer
+//! accessors (e.g., [Packet::tcp_header]) is called, the packet works out where each protocol
+//! layer begins and caches the result, so that triggers and actions do not need to re-parse the
+//! headers every time they look at a packet. [Packet::parse] does that work up front instead, and
+//! refuses bytes whose IP header doesn't hold together.
+use core::cell::OnceCell;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::ops::Range;
+use core::time::Duration;
+
+use crate::errors::*;
+use crate::prelude::*;
+use crate::strategy::Direction;
+
+/// The IP protocol number assigned to ICMP.
+pub(crate) const PROTO_ICMP: u8 = 1;
+
+/// The IP protocol number assigned to IPv4 (i.e., IP-in-IP encapsulation).
+pub(crate) const PROTO_IPV4: u8 = 4;
+
+/// The IP protocol number assigned to TCP.
+pub(crate) const PROTO_TCP: u8 = 6;
+
+/// The IP protocol number assigned to UDP.
+pub(crate) const PROTO_UDP: u8 = 17;
+
+/// The IP protocol number assigned to ICMPv6.
+pub(crate) const PROTO_ICMPV6: u8 = 58;
+
+/// The bits of the IPv4 flags and fragment offset word that hold the fragment offset.
+pub(crate) const IP_FRAG_OFFSET: u16 = 0x1fff;
+
+/// The bit of the IPv4 flags and fragment offset word that says more fragments follow.
+pub(crate) const IP_MORE_FRAGMENTS: u16 = 0x2000;
+
+/// The length of an Ethernet header, without any VLAN tags.
+const ETHERNET_HEADER_LEN: usize = 14;
+
+/// EtherTypes that matter when looking for the IP header of an Ethernet frame.
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+/// Returns where the IP header of an Ethernet II frame begins, skipping over any 802.1Q (or
+/// 802.1ad) VLAN tags, or `None` if the frame doesn't carry IP.
+pub(crate) fn ethernet_payload_offset(frame: &[u8]) -> Option<usize> {
+    let mut offset = ETHERNET_HEADER_LEN - 2;
+    loop {
+        let ethertype = frame.get(offset..offset + 2)?;
+        match u16::from_be_bytes([ethertype[0], ethertype[1]]) {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => return Some(offset + 2),
+            ETHERTYPE_VLAN | ETHERTYPE_QINQ => offset += 4,
+            _ => return None,
+        }
+    }
+}
+
+/// The link layer, if any, that comes before the IP header of a [Packet].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    /// There is no link layer: the packet starts with its IP header.
+    #[default]
+    Raw,
+    /// The packet is an Ethernet II frame, whose header (and any 802.1Q VLAN tags) comes before
+    /// the IP header.
+    Ethernet,
+}
+
+/// The locations of the protocol layers within a packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Layers {
+    /// The IP version (4 or 6).
+    pub ip_version: u8,
+
+    /// The IP header, including any IPv4 options.
+    pub ip: Range<usize>,
+
+    /// The IP protocol number (or IPv6 next header) of the transport layer.
+    pub protocol: u8,
+
+    /// The IP payload, which is the transport header plus its data. It ends where the IP header
+    /// says the datagram ends, so any trailing link-layer padding is excluded.
+    pub transport: Range<usize>,
+
+    /// The transport layer's payload, if the transport header is one that could be parsed.
+    pub payload: Option<Range<usize>>,
+}
+
+impl Layers {
+    /// Works out where each layer of `bytes` begins, returning `None` if `bytes` does not start
+    /// with a link layer of the given type followed by a well-formed IP header.
+    fn parse(bytes: &[u8], link_type: LinkType) -> Option<Self> {
+        let offset = match link_type {
+            LinkType::Raw => 0,
+            LinkType::Ethernet => ethernet_payload_offset(bytes)?,
+        };
+        let mut layers = Self::parse_ip(bytes.get(offset..)?)?;
+
+        let shift = |range: &mut Range<usize>| *range = range.start + offset..range.end + offset;
+        shift(&mut layers.ip);
+        shift(&mut layers.transport);
+        if let Some(payload) = &mut layers.payload {
+            shift(payload);
+        }
+        Some(layers)
+    }
+
+    /// Works out where each layer of `bytes` begins, returning `None` if `bytes` does not start
+    /// with a well-formed IP header.
+    fn parse_ip(bytes: &[u8]) -> Option<Self> {
+        let (ip_version, ip, protocol, end, first_fragment) = match bytes.first()? >> 4 {
+            4 => {
+                if bytes.len() < 20 {
+                    return None;
+                }
+
+                let ihl = (bytes[0] & 0x0f) as usize * 4;
+                if ihl < 20 || bytes.len() < ihl {
+                    return None;
+                }
+
+                let total_len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+                let end = if (ihl..=bytes.len()).contains(&total_len) {
+                    total_len
+                } else {
+                    bytes.len()
+                };
+
+                // Only the first fragment of a datagram carries the transport header.
+                let fragment_offset = u16::from_be_bytes([bytes[6], bytes[7]]) & IP_FRAG_OFFSET;
+                (4, 0..ihl, bytes[9], end, fragment_offset == 0)
+            }
+            6 => {
+                if bytes.len() < 40 {
+                    return None;
+                }
+
+                let payload_len = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+                let end = 40 + payload_len.min(bytes.len() - 40);
+                (6, 0..40, bytes[6], end, true)
+            }
+            _ => return None,
+        };
+
+        let transport = ip.end..end;
+        let segment = &bytes[transport.clone()];
+
+        let transport_header_len = match protocol {
+            _ if !first_fragment => None,
+            PROTO_TCP if segment.len() >= 20 => {
+                let data_offset = (segment[12] >> 4) as usize * 4;
+                if data_offset >= 20 && segment.len() >= data_offset {
+                    Some(data_offset)
+                } else {
+                    None
+                }
+            }
+            PROTO_UDP if segment.len() >= 8 => Some(8),
+            PROTO_ICMP if ip_version == 4 && segment.len() >= 8 => Some(8),
+            _ => None,
+        };
+        let payload = transport_header_len.map(|len| transport.start + len..transport.end);
+
+        Some(Self {
+            ip_version,
+            ip,
+            protocol,
+            transport,
+            payload,
+        })
+    }
+
+    /// Returns the location of the transport header, if one could be parsed.
+    fn transport_header(&self) -> Option<Range<usize>> {
+        self.payload
+            .as_ref()
+            .map(|payload| self.transport.start..payload.start)
+    }
+}
+
+/// Identifies one direction of a TCP or UDP flow by its 5-tuple, as returned by
+/// [Packet::flow_key]. The packets going the other way have the [reversed](FlowKey::reversed)
+/// key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FlowKey {
+    /// The IP source address.
+    pub src: IpAddr,
+
+    /// The IP destination address.
+    pub dst: IpAddr,
+
+    /// The TCP or UDP source port.
+    pub sport: u16,
+
+    /// The TCP or UDP destination port.
+    pub dport: u16,
+
+    /// The IP protocol number of the transport layer.
+    pub protocol: u8,
+}
+
+impl FlowKey {
+    /// Returns the key of the flow's other direction, with the addresses and ports swapped.
+    pub fn reversed(&self) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+            sport: self.dport,
+            dport: self.sport,
+            protocol: self.protocol,
+        }
+    }
+}
+
+/// Information about a [Packet] that isn't part of its bytes.
+///
+/// Actions use this to tell whatever transmits the packet how it should be sent.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PacketMeta {
+    /// How long to wait before sending the packet, as set by the
+    /// [sleep](crate::SleepAction) action.
+    pub delay: Option<Duration>,
+
+    /// Where the packet belongs among the pieces that the original packet was split into, as set
+    /// by the [fragment](crate::FragmentAction) action: `0` for the piece that carries the start
+    /// of the original payload, `1` for the piece after it. Since fragments can be emitted
+    /// out of order, this lets a transmitter tell which piece is which without parsing them.
+    pub order_hint: Option<u32>,
+}
+
+/// Represents a network packet as a vector of raw bytes.
+///
+/// Two packets are equal if they have the same bytes, regardless of their [PacketMeta].
+#[derive(Clone)]
+pub struct Packet {
+    bytes: Vec<u8>,
+    link_type: LinkType,
+    layers: OnceCell<Option<Layers>>,
+    meta: PacketMeta,
+}
+
+impl Packet {
+    /// Creates a new Packet by consuming the vector. This operation does not copy or allocate.
+    pub fn new(p: Vec<u8>) -> Self {
+        Self {
+            bytes: p,
+            link_type: LinkType::Raw,
+            layers: OnceCell::new(),
+            meta: PacketMeta::default(),
+        }
+    }
+
+    /// Creates a new Packet whose bytes start with the given kind of link layer, rather than with
+    /// the IP header. Triggers and actions look past the link layer to the IP header, wherever it
+    /// begins, and actions that produce new packets give them the same link layer.
+    ///
+    /// ```
+    /// use geneva::{LinkType, Packet};
+    ///
+    /// let mut frame = vec![0xff; 12];
+    /// frame.extend_from_slice(&[0x08, 0x00]);
+    /// frame.extend_from_slice(&[
+    ///     0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2,
+    /// ]);
+    /// let pkt = Packet::with_link_type(frame, LinkType::Ethernet);
+    /// assert_eq!(pkt.ip_version(), Some(4));
+    /// assert_eq!(pkt.link_header().unwrap().len(), 14);
+    /// ```
+    pub fn with_link_type(p: Vec<u8>, link_type: LinkType) -> Self {
+        Self {
+            link_type,
+            ..Self::new(p)
+        }
+    }
+
+    /// Creates a new Packet that carries the given metadata. Like [Packet::new], this does not
+    /// copy or allocate.
+    pub fn with_meta(p: Vec<u8>, meta: PacketMeta) -> Self {
+        Self {
+            meta,
+            ..Self::new(p)
+        }
+    }
+
+    /// Creates a new Packet from bytes that must start with a well-formed IPv4 or IPv6 header,
+    /// whose lengths all fit in `p`. Unlike [Packet::new], which takes any bytes and leaves
+    /// triggers and actions to find out what they hold, this parses the packet's layers up front.
+    ///
+    /// Returns [Error::MalformedPacket] if the IP version isn't 4 or 6, if the header is shorter
+    /// than its minimum, or if the header or datagram lengths that it gives run past the end of
+    /// `p`. Bytes past the end of the datagram, such as link-layer padding, are allowed.
+    ///
+    /// ```
+    /// use geneva::Packet;
+    ///
+    /// let bytes = vec![0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2];
+    /// assert!(Packet::parse(bytes.clone()).is_ok());
+    /// assert!(Packet::parse(bytes[..19].to_vec()).is_err());
+    /// ```
+    pub fn parse(p: Vec<u8>) -> Result<Self> {
+        let valid = match p.first().map(|b| b >> 4) {
+            Some(4) if p.len() >= 20 => {
+                let ihl = (p[0] & 0x0f) as usize * 4;
+                let total_len = u16::from_be_bytes([p[2], p[3]]) as usize;
+                ihl >= 20 && (ihl..=p.len()).contains(&total_len)
+            }
+            Some(6) if p.len() >= 40 => {
+                let payload_len = u16::from_be_bytes([p[4], p[5]]) as usize;
+                payload_len <= p.len() - 40
+            }
+            _ => false,
+        };
+        let layers = match Layers::parse(&p, LinkType::Raw) {
+            Some(layers) if valid => layers,
+            _ => return Err(Error::MalformedPacket),
+        };
+
+        let pkt = Self::new(p);
+        let _ = pkt.layers.set(Some(layers));
+        Ok(pkt)
+    }
+
+    /// Creates a new Packet by copying the slice into itself.
+    pub fn new_from_slice(s: &[u8]) -> Self {
+        Self::new(s.to_vec())
+    }
+
+    /// Returns the number of bytes in the packet.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns `true` if the packet is zero-length.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Extracts a slice containing the entire packet.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Extracts a mutable slice containing the entire packet.
+    ///
+    /// Since the caller may change any of the headers, the packet's layers will be parsed again
+    /// the next time they are needed.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.layers = OnceCell::new();
+        &mut self.bytes
+    }
+
+    /// Returns the kind of link layer that the packet starts with.
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    /// Returns the link-layer header that comes before the IP header (which is empty for a
+    /// [raw](LinkType::Raw) packet), or `None` if the IP header can't be found.
+    pub fn link_header(&self) -> Option<&[u8]> {
+        self.layers().map(|l| &self.bytes[..l.ip.start])
+    }
+
+    /// Returns the IP datagram that the packet carries, without its link layer. If the IP header
+    /// can't be found, the whole packet is returned.
+    #[cfg(any(feature = "pcap", feature = "net", test))]
+    pub(crate) fn ip_datagram(&self) -> &[u8] {
+        let start = self.layers().map_or(0, |l| l.ip.start);
+        &self.bytes[start..]
+    }
+
+    /// Creates a packet with the same link type and metadata as this one, but with other bytes.
+    pub(crate) fn with_bytes(&self, bytes: Vec<u8>) -> Packet {
+        Self {
+            link_type: self.link_type,
+            meta: self.meta.clone(),
+            ..Self::new(bytes)
+        }
+    }
+
+    /// Returns the packet's metadata.
+    pub fn meta(&self) -> &PacketMeta {
+        &self.meta
+    }
+
+    /// Returns a mutable reference to the packet's metadata.
+    pub fn meta_mut(&mut self) -> &mut PacketMeta {
+        &mut self.meta
+    }
+
+    /// Returns a mutable reference to the packet's underlying bytes, so that it can be resized.
+    pub(crate) fn as_mut_vec(&mut self) -> &mut Vec<u8> {
+        self.layers = OnceCell::new();
+        &mut self.bytes
+    }
+
+    /// Returns the locations of the packet's layers, parsing them if necessary.
+    pub(crate) fn layers(&self) -> Option<&Layers> {
+        self.layers
+            .get_or_init(|| Layers::parse(&self.bytes, self.link_type))
+            .as_ref()
+    }
+
+    /// Returns the packet's IP version, or `None` if the packet does not start with a well-formed
+    /// IPv4 or IPv6 header.
+    pub fn ip_version(&self) -> Option<u8> {
+        self.layers().map(|l| l.ip_version)
+    }
+
+    /// Returns the protocol number of the layer carried by the IP header (the next header, for
+    /// IPv6).
+    pub fn ip_protocol(&self) -> Option<u8> {
+        self.layers().map(|l| l.protocol)
+    }
+
+    /// Returns the IP header, including any IPv4 options.
+    pub fn ip_header(&self) -> Option<&[u8]> {
+        self.layers().map(|l| &self.bytes[l.ip.clone()])
+    }
+
+    /// Returns everything the IP header carries; that is, the transport header and its payload.
+    pub fn ip_payload(&self) -> Option<&[u8]> {
+        self.layers().map(|l| &self.bytes[l.transport.clone()])
+    }
+
+    /// Returns the TCP header, including any options, if this is a TCP packet.
+    pub fn tcp_header(&self) -> Option<&[u8]> {
+        let layers = self.layers().filter(|l| l.protocol == PROTO_TCP)?;
+        Some(&self.bytes[layers.transport_header()?])
+    }
+
+    /// Returns the UDP header, if this is a UDP packet.
+    pub fn udp_header(&self) -> Option<&[u8]> {
+        let layers = self.layers().filter(|l| l.protocol == PROTO_UDP)?;
+        Some(&self.bytes[layers.transport_header()?])
+    }
+
+    /// Returns the first eight bytes of the ICMP message (the type, code, checksum, and the
+    /// four bytes that depend on the type), if this is an IPv4 ICMP packet.
+    pub fn icmp_header(&self) -> Option<&[u8]> {
+        let layers = self.layers().filter(|l| l.protocol == PROTO_ICMP)?;
+        Some(&self.bytes[layers.transport_header()?])
+    }
+
+    /// Returns the data carried by the transport layer, if the transport header could be parsed.
+    pub fn payload(&self) -> Option<&[u8]> {
+        let payload = self.layers()?.payload.clone()?;
+        Some(&self.bytes[payload])
+    }
+
+    /// Returns the packet's IP source and destination addresses.
+    pub(crate) fn addresses(&self) -> Option<(IpAddr, IpAddr)> {
+        let ip = self.ip_header()?;
+        let (src, dst): (IpAddr, IpAddr) = match self.ip_version()? {
+            4 => {
+                let src: [u8; 4] = ip[12..16].try_into().ok()?;
+                let dst: [u8; 4] = ip[16..20].try_into().ok()?;
+                (Ipv4Addr::from(src).into(), Ipv4Addr::from(dst).into())
+            }
+            _ => {
+                let src: [u8; 16] = ip[8..24].try_into().ok()?;
+                let dst: [u8; 16] = ip[24..40].try_into().ok()?;
+                (Ipv6Addr::from(src).into(), Ipv6Addr::from(dst).into())
+            }
+        };
+        Some((src, dst))
+    }
+
+    /// Returns the key of the TCP or UDP flow that the packet belongs to, or `None` if it isn't a
+    /// TCP or UDP packet whose transport header could be parsed.
+    ///
+    /// ```
+    /// use geneva::Packet;
+    ///
+    /// let pkt = Packet::new(vec![
+    ///     0x45, 0, 0, 28, 0, 1, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0xd4, 0x31, 0, 53,
+    ///     0, 8, 0, 0,
+    /// ]);
+    /// let key = pkt.flow_key().unwrap();
+    /// assert_eq!((key.sport, key.dport, key.protocol), (54321, 53, 17));
+    /// ```
+    pub fn flow_key(&self) -> Option<FlowKey> {
+        let header = self.tcp_header().or_else(|| self.udp_header())?;
+        let (src, dst) = self.addresses()?;
+        Some(FlowKey {
+            src,
+            dst,
+            sport: u16::from_be_bytes([header[0], header[1]]),
+            dport: u16::from_be_bytes([header[2], header[3]]),
+            protocol: self.ip_protocol()?,
+        })
+    }
+
+    /// Works out whether the packet is leaving or arriving at the host that owns `local_addrs`,
+    /// for use with [Strategy::apply](crate::Strategy::apply).
+    ///
+    /// A packet sent from one of the local addresses is [outbound](Direction::Outbound); otherwise,
+    /// a packet sent to one of them is [inbound](Direction::Inbound). Returns `None` if the packet
+    /// does not start with a well-formed IP header, or if neither of its addresses is local.
+    pub fn infer_direction(&self, local_addrs: &[IpAddr]) -> Option<Direction> {
+        let (src, dst) = self.addresses()?;
+        if local_addrs.contains(&src) {
+            Some(Direction::Outbound)
+        } else if local_addrs.contains(&dst) {
+            Some(Direction::Inbound)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Debug for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Packet").field(&self.bytes).finish()
+    }
+}
+
+impl PartialEq for Packet {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for Packet {}
+
+impl From<Vec<u8>> for Packet {
+    fn from(v: Vec<u8>) -> Self {
+        Self::new(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IPv4 packet carrying a TCP segment with a 4-byte MSS option and a 5-byte payload.
+    fn tcp_packet() -> Packet {
+        Packet::new(vec![
+            0x45, 0x00, 0x00, 0x31, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00,
+            0x60, 0x18, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x02, 0x04, 0x05, 0xb4, b'h', b'e',
+            b'l', b'l', b'o',
+        ])
+    }
+
+    /// An IPv4 packet carrying a UDP datagram with a 3-byte payload, followed by two bytes of
+    /// link-layer padding.
+    fn udp_packet() -> Packet {
+        Packet::new(vec![
+            0x45, 0x00, 0x00, 0x1f, 0x00, 0x01, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x14, 0xe9, 0x00, 0x35, 0x00, 0x0b, 0x00, 0x00, 1, 2, 3, 0, 0,
+        ])
+    }
+
+    /// An IPv6 packet carrying a 20-byte TCP segment with no payload.
+    fn ipv6_tcp_packet() -> Packet {
+        let mut bytes = vec![0x60, 0, 0, 0, 0, 20, 6, 64];
+        bytes.extend_from_slice(&[0; 32]);
+        bytes.extend_from_slice(&[
+            0x30, 0x39, 0x00, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0,
+        ]);
+        Packet::new(bytes)
+    }
+
+    #[test]
+    fn parse_valid_packets() {
+        for pkt in [tcp_packet(), udp_packet(), ipv6_tcp_packet()] {
+            let parsed = Packet::parse(pkt.as_slice().to_vec()).unwrap();
+            assert_eq!(parsed, pkt);
+            assert_eq!(parsed.layers(), pkt.layers());
+            assert_eq!(parsed.payload(), pkt.payload());
+        }
+    }
+
+    #[test]
+    fn parse_truncated_packets() {
+        let bytes = tcp_packet().as_slice().to_vec();
+        // Cut inside the IP header, and inside the datagram that the header describes.
+        for len in [0, 12, 19, 40, 48] {
+            assert!(
+                matches!(
+                    Packet::parse(bytes[..len].to_vec()),
+                    Err(Error::MalformedPacket)
+                ),
+                "{}",
+                len
+            );
+        }
+
+        // An IPv4 header that says it's longer than the datagram, and an IPv6 payload length past
+        // the end of the packet.
+        let mut bytes = tcp_packet().as_slice().to_vec();
+        bytes[0] = 0x4f;
+        bytes[2..4].copy_from_slice(&20u16.to_be_bytes());
+        assert!(Packet::parse(bytes).is_err());
+        let mut bytes = ipv6_tcp_packet().as_slice().to_vec();
+        bytes[5] = 21;
+        assert!(Packet::parse(bytes).is_err());
+    }
+
+    #[test]
+    fn parse_wrong_version() {
+        for version in [0x00, 0x50, 0x70, 0xf0] {
+            let mut bytes = tcp_packet().as_slice().to_vec();
+            bytes[0] = version | 0x05;
+            assert!(Packet::parse(bytes.clone()).is_err(), "{:#x}", version);
+
+            // The unchecked constructor takes them anyway.
+            assert_eq!(Packet::new(bytes).ip_version(), None);
+        }
+    }
+
+    #[test]
+    fn flow_keys() {
+        let key = tcp_packet().flow_key().unwrap();
+        let expected = FlowKey {
+            src: Ipv4Addr::new(10, 0, 0, 1).into(),
+            dst: Ipv4Addr::new(10, 0, 0, 2).into(),
+            sport: 12345,
+            dport: 80,
+            protocol: PROTO_TCP,
+        };
+        assert_eq!(key, expected);
+        assert_eq!(
+            key.reversed(),
+            FlowKey {
+                src: expected.dst,
+                dst: expected.src,
+                sport: 80,
+                dport: 12345,
+                protocol: PROTO_TCP,
+            }
+        );
+        assert_eq!(key.reversed().reversed(), key);
+
+        let key = udp_packet().flow_key().unwrap();
+        assert_eq!((key.sport, key.dport, key.protocol), (5353, 53, PROTO_UDP));
+
+        let key = ipv6_tcp_packet().flow_key().unwrap();
+        assert_eq!(key.src, IpAddr::from(Ipv6Addr::UNSPECIFIED));
+        assert_eq!((key.sport, key.dport), (12345, 80));
+
+        // Without a transport header there are no ports to go on.
+        assert_eq!(
+            Packet::new(tcp_packet().as_slice()[..20].to_vec()).flow_key(),
+            None
+        );
+        assert_eq!(Packet::new(vec![0, 1, 2, 3]).flow_key(), None);
+    }
+
+    #[test]
+    fn meta_is_kept_but_not_compared() {
+        let pkt = tcp_packet();
+        assert_eq!(pkt.meta(), &PacketMeta::default());
+
+        let meta = PacketMeta {
+            delay: Some(Duration::from_millis(10)),
+            order_hint: Some(1),
+        };
+        let tagged = Packet::with_meta(pkt.as_slice().to_vec(), meta.clone());
+        assert_eq!(tagged.meta(), &meta);
+        assert_eq!(tagged.clone().meta(), &meta);
+        assert_eq!(tagged, pkt);
+
+        // Changing the bytes doesn't touch the metadata.
+        let mut tagged = tagged;
+        tagged.as_mut_slice()[8] = 1;
+        assert_eq!(tagged.meta(), &meta);
+    }
+
+    #[test]
+    fn ip_header() {
+        let pkt = tcp_packet();
+        assert_eq!(pkt.ip_version(), Some(4));
+        assert_eq!(pkt.ip_protocol(), Some(PROTO_TCP));
+        assert_eq!(pkt.ip_header(), Some(&pkt.as_slice()[..20]));
+        assert_eq!(pkt.ip_payload(), Some(&pkt.as_slice()[20..]));
+    }
+
+    /// Wraps `pkt` in an Ethernet frame with the given VLAN tags.
+    fn ethernet_frame(pkt: &Packet, ethertype: u16, vlans: &[u16]) -> Packet {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1];
+        for &vlan in vlans {
+            frame.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+            frame.extend_from_slice(&vlan.to_be_bytes());
+        }
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(pkt.as_slice());
+        Packet::with_link_type(frame, LinkType::Ethernet)
+    }
+
+    #[test]
+    fn ethernet_layers() {
+        let ip = tcp_packet();
+        assert_eq!(ip.link_type(), LinkType::Raw);
+        assert_eq!(ip.link_header(), Some(&[][..]));
+
+        for vlans in [&[][..], &[100], &[100, 200]] {
+            let frame = ethernet_frame(&ip, ETHERTYPE_IPV4, vlans);
+            let link_len = 14 + 4 * vlans.len();
+            assert_eq!(frame.link_type(), LinkType::Ethernet);
+            assert_eq!(frame.link_header(), Some(&frame.as_slice()[..link_len]));
+            assert_eq!(frame.ip_header(), ip.ip_header());
+            assert_eq!(frame.tcp_header(), ip.tcp_header());
+            assert_eq!(frame.payload(), Some(&b"hello"[..]));
+            assert_eq!(frame.ip_datagram(), ip.as_slice());
+            assert_eq!(frame.with_bytes(vec![]).link_type(), LinkType::Ethernet);
+        }
+
+        // An ARP frame doesn't carry IP, and a raw packet isn't read as a frame.
+        let arp = ethernet_frame(&ip, 0x0806, &[]);
+        assert_eq!(arp.ip_version(), None);
+        assert_eq!(arp.link_header(), None);
+        assert_eq!(arp.ip_datagram(), arp.as_slice());
+        let frame = ethernet_frame(&ip, ETHERTYPE_IPV4, &[]);
+        assert_eq!(Packet::new(frame.as_slice().to_vec()).ip_version(), None);
+    }
+
+    #[test]
+    fn tcp_header_includes_options() {
+        let pkt = tcp_packet();
+        assert_eq!(pkt.tcp_header(), Some(&pkt.as_slice()[20..44]));
+        assert_eq!(pkt.udp_header(), None);
+        assert_eq!(pkt.payload(), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn udp_header_excludes_padding() {
+        let pkt = udp_packet();
+        assert_eq!(pkt.udp_header(), Some(&pkt.as_slice()[20..28]));
+        assert_eq!(pkt.tcp_header(), None);
+        assert_eq!(pkt.ip_payload(), Some(&pkt.as_slice()[20..31]));
+        assert_eq!(pkt.payload(), Some(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn icmp_header() {
+        let mut bytes = udp_packet().as_slice().to_vec();
+        bytes[9] = PROTO_ICMP;
+        let pkt = Packet::new(bytes);
+        assert_eq!(pkt.icmp_header(), Some(&pkt.as_slice()[20..28]));
+        assert_eq!(pkt.udp_header(), None);
+        assert_eq!(pkt.payload(), Some(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn ipv6_layers() {
+        let pkt = ipv6_tcp_packet();
+        assert_eq!(pkt.ip_version(), Some(6));
+        assert_eq!(pkt.ip_header(), Some(&pkt.as_slice()[..40]));
+        assert_eq!(pkt.tcp_header(), Some(&pkt.as_slice()[40..]));
+        assert_eq!(pkt.payload(), Some(&[][..]));
+    }
+
+    #[test]
+    fn infer_direction() {
+        let pkt = tcp_packet();
+        let local: IpAddr = Ipv4Addr::new(10, 0, 0, 1).into();
+        let remote: IpAddr = Ipv4Addr::new(10, 0, 0, 2).into();
+        let other: IpAddr = Ipv4Addr::new(192, 0, 2, 1).into();
+
+        assert_eq!(pkt.infer_direction(&[local]), Some(Direction::Outbound));
+        assert_eq!(
+            pkt.infer_direction(&[other, remote]),
+            Some(Direction::Inbound)
+        );
+        assert_eq!(pkt.infer_direction(&[other]), None);
+        assert_eq!(pkt.infer_direction(&[]), None);
+        assert_eq!(Packet::new(vec![0x45, 0]).infer_direction(&[local]), None);
+
+        let v6 = ipv6_tcp_packet();
+        assert_eq!(
+            v6.infer_direction(&[Ipv6Addr::UNSPECIFIED.into()]),
+            Some(Direction::Outbound)
+        );
+        assert_eq!(v6.infer_direction(&[local]), None);
+    }
+
+    #[test]
+    fn non_first_fragment_has_no_transport_header() {
+        let mut bytes = udp_packet().as_slice().to_vec();
+        bytes[6..8].copy_from_slice(&[0x00, 0x01]);
+        let pkt = Packet::new(bytes);
+        assert!(pkt.ip_header().is_some());
+        assert_eq!(pkt.udp_header(), None);
+        assert_eq!(pkt.payload(), None);
+    }
+
+    #[test]
+    fn malformed_packets_have_no_layers() {
+        for bytes in [vec![], vec![0x45, 0x00], vec![0x55; 40], vec![0x60; 39]] {
+            let pkt = Packet::new(bytes);
+            assert_eq!(pkt.ip_version(), None);
+            assert_eq!(pkt.ip_header(), None);
+            assert_eq!(pkt.payload(), None);
+        }
+    }
+
+    #[test]
+    fn mutation_invalidates_layers() {
+        let mut pkt = tcp_packet();
+        assert!(pkt.tcp_header().is_some());
+
+        pkt.as_mut_slice()[9] = PROTO_UDP;
+        assert_eq!(pkt.tcp_header(), None);
+        assert!(pkt.udp_header().is_some());
+    }
+
+    #[test]
+    fn equality_ignores_cached_layers() {
+        let a = tcp_packet();
+        let b = tcp_packet();
+        assert!(a.tcp_header().is_some());
+        assert_eq!(a, b);
+    }
+}
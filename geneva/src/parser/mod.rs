@@ -1,22 +1,140 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::actions::{
-    ActionTree, DropAction, DuplicateAction, FragmentAction, GenevaAction, SendAction, TamperAction,
+    Action, ActionTree, CustomAction, DropAction, DuplicateAction, FragmentAction, GenevaAction,
+    SendAction, SleepAction, TamperAction, TamperMode,
 };
 use crate::errors::*;
-use crate::triggers::{GenevaTrigger, IPField, IPTrigger, TCPField, TCPTrigger};
+use crate::packet::{PROTO_ICMP, PROTO_IPV4, PROTO_TCP, PROTO_UDP};
+use crate::triggers::{
+    Comparison, DNSField, DNSTrigger, GenevaTrigger, ICMPField, ICMPTrigger, IPField, IPTrigger,
+    TCPField, TCPTrigger, UDPField, UDPTrigger,
+};
 use crate::Strategy;
 
-use pest::{iterators::Pairs, Parser};
+use pest::{
+    iterators::{Pair, Pairs},
+    Parser,
+};
 
 #[derive(Parser)]
 #[grammar = "parser/geneva.pest"]
 struct GenevaParser;
 
+/// Parses a strategy string. Use [parse_strategy_with] to parse strategies that contain custom
+/// actions.
 pub fn parse_strategy(s: &str) -> Result<Strategy> {
+    parse_strategy_with(s, &ActionRegistry::default())
+}
+
+/// The parsers for [custom actions](CustomAction) that may appear in a strategy string, and how
+/// deeply its actions may be nested.
+///
+/// ```
+/// use geneva::{parse_strategy_with, Action, ActionRegistry, Packet, Result};
+/// use std::fmt;
+///
+/// /// Sends the packet `n` times.
+/// struct Repeat(usize);
+///
+/// impl Action for Repeat {
+///     fn run(&self, pkt: Packet) -> Result<Vec<Packet>> {
+///         Ok(vec![pkt; self.0])
+///     }
+/// }
+///
+/// impl fmt::Display for Repeat {
+///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+///         write!(f, "repeat{{{}}}", self.0)
+///     }
+/// }
+///
+/// let registry = ActionRegistry::new().register("repeat", |args| {
+///     let n = args.unwrap_or("2").parse().map_err(|_| geneva::Error::Parse("bad repeat".into()))?;
+///     Ok(Repeat(n))
+/// });
+///
+/// let s = r"[TCP:flags:S]-repeat{3}-| \/";
+/// let strategy = parse_strategy_with(s, &registry).unwrap();
+/// assert_eq!(strategy.to_string(), s);
+/// ```
+pub struct ActionRegistry {
+    parsers: HashMap<String, Box<ActionParser>>,
+    max_depth: usize,
+}
+
+/// How deeply actions may be nested unless an [ActionRegistry] says otherwise.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+impl Default for ActionRegistry {
+    fn default() -> Self {
+        Self {
+            parsers: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Builds a custom action from the arguments between its braces, if it had any.
+type ActionParser = dyn Fn(Option<&str>) -> Result<CustomAction>;
+
+impl ActionRegistry {
+    /// Creates an empty `ActionRegistry`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `parser` for actions called `name`.
+    ///
+    /// When the strategy string contains `name` or `name{args}`, `parser` is passed `None` or
+    /// `Some(args)` respectively and returns the action to use. Registering the same name again
+    /// replaces the earlier parser. Names must start with a letter and may only contain letters,
+    /// digits, and underscores, and can't be one of the built-in actions.
+    pub fn register<A, F>(mut self, name: &str, parser: F) -> Self
+    where
+        A: Action + Send + Sync + 'static,
+        F: Fn(Option<&str>) -> Result<A> + 'static,
+    {
+        let parser = move |args: Option<&str>| parser(args).map(CustomAction::new);
+        self.parsers.insert(name.to_string(), Box::new(parser));
+        self
+    }
+
+    /// Sets how many levels of parentheses the actions of a strategy may be nested in, which is
+    /// [DEFAULT_MAX_DEPTH] unless this is called. `duplicate(tamper{TCP:flags:replace:R}(drop),)`
+    /// is nested two levels deep, for instance.
+    ///
+    /// The parser recurses once for each level, so this keeps a hostile strategy string from
+    /// overflowing the stack; deeper strategies fail to parse with [Error::Parse].
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Builds the custom action called `name` from its arguments.
+    fn parse(&self, name: &str, args: Option<&str>) -> Result<CustomAction> {
+        match self.parsers.get(name) {
+            Some(parser) => parser(args),
+            None => Err(Error::Parse(format!("unknown action: {}", name))),
+        }
+    }
+}
+
+impl fmt::Debug for ActionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.parsers.keys()).finish()
+    }
+}
+
+/// Parses a strategy string that may contain the custom actions in `registry`.
+pub fn parse_strategy_with(s: &str, registry: &ActionRegistry) -> Result<Strategy> {
+    check_depth(s, registry.max_depth)?;
     let mut parsed_strategy = GenevaParser::parse(Rule::strategy, s)?;
 
-    let forests = parsed_strategy.next().unwrap();
+    let forests = next_pair(&mut parsed_strategy, "strategy")?;
     let mut strategy = Strategy::default();
 
     let mut forest = vec![];
@@ -24,7 +142,7 @@ pub fn parse_strategy(s: &str) -> Result<Strategy> {
         match f.as_rule() {
             Rule::forest => {
                 for action_tree in f.into_inner() {
-                    let at = parse_action_tree(&mut action_tree.into_inner())?;
+                    let at = parse_action_tree(&mut action_tree.into_inner(), registry)?;
                     forest.push(at);
                 }
             }
@@ -45,14 +163,73 @@ pub fn parse_strategy(s: &str) -> Result<Strategy> {
                 // unneeded, but rustc complains since it doesn't know this is a terminal match.
                 forest = vec![];
             }
-            _ => unreachable!(),
+            _ => return Err(unexpected(&f)),
         }
     }
 
     Ok(strategy)
 }
 
-fn parse_action_tree(f: &mut Pairs<Rule>) -> Result<ActionTree> {
+impl FromStr for GenevaAction {
+    type Err = Error;
+
+    /// Parses a single action, such as `tamper{TCP:flags:replace:SA}(send)`. Custom actions
+    /// aren't known here, so they fail to parse.
+    fn from_str(s: &str) -> Result<Self> {
+        check_depth(s, DEFAULT_MAX_DEPTH)?;
+        let mut parsed = GenevaParser::parse(Rule::single_action, s)?;
+        let action = next_pair(&mut parsed, "action")?;
+        let action = next_pair(&mut action.into_inner(), "action")?;
+        parse_action(&mut action.into_inner(), &ActionRegistry::default())
+    }
+}
+
+impl FromStr for GenevaTrigger {
+    type Err = Error;
+
+    /// Parses a single trigger, such as `[TCP:flags:S]`.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parsed = GenevaParser::parse(Rule::single_trigger, s)?;
+        let trigger = next_pair(&mut parsed, "trigger")?;
+        let trigger = next_pair(&mut trigger.into_inner(), "trigger")?;
+        parse_trigger(&mut trigger.into_inner())
+    }
+}
+
+/// Returns an error if the actions in `s` are nested in more than `max_depth` levels of
+/// parentheses.
+///
+/// This has to happen before `s` reaches the grammar, whose parser recurses just as deeply. The
+/// arguments between braces and comments are skipped, since only they can hold parentheses that
+/// aren't part of an action; anything else that is unbalanced is left for the grammar to reject.
+fn check_depth(s: &str, max_depth: usize) -> Result<()> {
+    let mut depth = 0usize;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => {
+                depth += 1;
+                if depth > max_depth {
+                    return Err(Error::Parse(format!(
+                        "actions are nested more than {} levels deep",
+                        max_depth
+                    )));
+                }
+            }
+            ')' => depth = depth.saturating_sub(1),
+            '{' => {
+                chars.find(|&c| c == '}');
+            }
+            '#' => {
+                chars.find(|&c| c == '\n');
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_action_tree(f: &mut Pairs<Rule>, registry: &ActionRegistry) -> Result<ActionTree> {
     let mut trigger = None;
     let mut action = None;
 
@@ -62,83 +239,248 @@ fn parse_action_tree(f: &mut Pairs<Rule>) -> Result<ActionTree> {
                 trigger = Some(parse_trigger(&mut part.into_inner())?);
             }
             Rule::action => {
-                action = Some(parse_action(&mut part.into_inner())?);
-            }
-            _ => {
-                unreachable!();
+                action = Some(parse_action(&mut part.into_inner(), registry)?);
             }
+            _ => return Err(unexpected(&part)),
         }
     }
 
-    Ok(ActionTree {
-        trigger: trigger.unwrap(),
-        root_action: Box::new(action.unwrap()),
-    })
+    match (trigger, action) {
+        (Some(trigger), Some(action)) => Ok(ActionTree::new(trigger, action)),
+        _ => Err(Error::Parse("incomplete action tree".to_string())),
+    }
 }
 
 fn parse_trigger(f: &mut Pairs<Rule>) -> Result<GenevaTrigger> {
-    let proto = f.next().unwrap().as_str();
-    let field = f.next().unwrap().as_str();
-    let value = f.next().unwrap().as_str();
-    match proto.to_lowercase().as_str() {
+    let proto = next_pair(f, "trigger protocol")?.as_str();
+    let field = next_pair(f, "trigger field")?.as_str();
+    let mut next = f.next();
+    let mut next_is = |rule| match &next {
+        Some(pair) if pair.as_rule() == rule => {
+            let pair = next.take();
+            next = f.next();
+            pair
+        }
+        _ => None,
+    };
+    let relative = next_is(Rule::relative).is_some();
+    let negate = next_is(Rule::negation).is_some();
+    let comparison = match next_is(Rule::comparison) {
+        Some(comparison) => Comparison::from_str(comparison.as_str())?,
+        None => Comparison::Equal,
+    };
+    // A trigger can leave its value empty, as in `[TCP:options-sackok:]`.
+    let value = next_is(Rule::value).map_or("", |value| value.as_str());
+    let gas = match next {
+        Some(gas) => gas
+            .as_str()
+            .parse()
+            .map_err(|_| Error::Parse(gas.as_str().to_string()))?,
+        None => 0,
+    };
+    let trigger: GenevaTrigger = match proto.to_lowercase().as_str() {
         "tcp" => {
             let field: TCPField = TCPField::from_str(field)?;
-            Ok(GenevaTrigger::TCP(TCPTrigger::new(
-                field,
-                value.to_string(),
-                0,
-            )?))
+            let mut trigger =
+                TCPTrigger::new(field, value.to_string(), gas)?.with_comparison(comparison)?;
+            if relative {
+                trigger = trigger.relative()?;
+            }
+            if negate { trigger.negated() } else { trigger }.into()
+        }
+        _ if relative => {
+            return Err(Error::Parse(format!(
+                "relative values need TCP:seq or TCP:ack, not {}:{}",
+                proto, field
+            )))
+        }
+        "dns" => {
+            let field: DNSField = DNSField::from_str(field)?;
+            let trigger =
+                DNSTrigger::new(field, value.to_string(), gas)?.with_comparison(comparison)?;
+            if negate { trigger.negated() } else { trigger }.into()
+        }
+        "udp" => {
+            let field: UDPField = UDPField::from_str(field)?;
+            let trigger =
+                UDPTrigger::new(field, value.to_string(), gas)?.with_comparison(comparison)?;
+            if negate { trigger.negated() } else { trigger }.into()
+        }
+        "icmp" => {
+            let field: ICMPField = ICMPField::from_str(field)?;
+            let trigger =
+                ICMPTrigger::new(field, value.to_string(), gas)?.with_comparison(comparison)?;
+            if negate { trigger.negated() } else { trigger }.into()
         }
         "ip" => {
             let field: IPField = IPField::from_str(field)?;
-            Ok(GenevaTrigger::IP(IPTrigger::new(
-                field,
-                value.to_string(),
-                0,
-                0,
-            )?))
+            let trigger =
+                IPTrigger::new(field, value.to_string(), gas)?.with_comparison(comparison)?;
+            if negate { trigger.negated() } else { trigger }.into()
         }
-        _ => unreachable!(),
-    }
+        _ => return Err(Error::Parse(format!("unknown trigger protocol: {}", proto))),
+    };
+    Ok(trigger)
 }
 
-fn parse_action(f: &mut Pairs<Rule>) -> Result<GenevaAction> {
-    let inner_rules = f.next().unwrap();
+fn parse_action(f: &mut Pairs<Rule>, registry: &ActionRegistry) -> Result<GenevaAction> {
+    let inner_rules = next_pair(f, "action")?;
     match inner_rules.as_rule() {
         Rule::send => Ok(SendAction::default().into()),
         Rule::drop => Ok(DropAction::default().into()),
         Rule::duplicate => {
-            let mut l_action = SendAction::default().into();
-            let mut r_action = SendAction::default().into();
-            let mut action = None;
-            for a in inner_rules.into_inner() {
-                match a.as_rule() {
-                    Rule::action => {
-                        action = Some(parse_action(&mut a.into_inner())?);
-                    }
-                    Rule::comma => {
-                        if let Some(action) = action {
-                            l_action = action;
-                        }
-                        action = None;
-                    }
-                    _ => unreachable!(),
+            let (l_action, r_action) = match inner_rules.into_inner().next() {
+                Some(body) => parse_rule_body(body, registry)?,
+                None => (SendAction::default().into(), SendAction::default().into()),
+            };
+            Ok(DuplicateAction::new(l_action, r_action).into())
+        }
+        Rule::fragment => {
+            let mut inner = inner_rules.into_inner();
+
+            let protocol = next_pair(&mut inner, "fragment protocol")?;
+            let protocol = match protocol.as_rule() {
+                Rule::protocol => match protocol.as_str().to_lowercase().as_str() {
+                    "tcp" => PROTO_TCP as u16,
+                    "udp" => PROTO_UDP as u16,
+                    "ip" => PROTO_IPV4 as u16,
+                    "icmp" => PROTO_ICMP as u16,
+                    _ => return Err(Error::Parse(protocol.as_str().to_string())),
+                },
+                Rule::protocol_number => protocol
+                    .as_str()
+                    .parse()
+                    .map_err(|_| Error::Parse(protocol.as_str().to_string()))?,
+                _ => return Err(unexpected(&protocol)),
+            };
+
+            let offset = next_pair(&mut inner, "fragment offset")?.as_str();
+            let offset = offset
+                .parse()
+                .map_err(|_| Error::Parse(offset.to_string()))?;
+
+            let in_order = next_pair(&mut inner, "fragment order")?.as_str() == "True";
+
+            let body = next_pair(&mut inner, "fragment body")?;
+            let (l_action, r_action) = parse_rule_body(body, registry)?;
+            Ok(FragmentAction::new(protocol, offset, in_order, 0, l_action, r_action)?.into())
+        }
+        Rule::tamper => {
+            let mut inner = inner_rules.into_inner();
+            let protocol = next_pair(&mut inner, "tamper protocol")?.as_str();
+            let field = next_pair(&mut inner, "tamper field")?.as_str();
+            let mode = TamperMode::from_str(next_pair(&mut inner, "tamper mode")?.as_str())?;
+
+            let mut new_value = "";
+            let mut options = Vec::new();
+            let mut action = SendAction::default().into();
+            for part in inner {
+                match part.as_rule() {
+                    Rule::tamper_option => options.push(part.as_str()),
+                    Rule::value => new_value = part.as_str(),
+                    Rule::action => action = parse_action(&mut part.into_inner(), registry)?,
+                    Rule::comma => {}
+                    _ => return Err(unexpected(&part)),
                 }
             }
-            if let Some(action) = action {
-                r_action = action;
+
+            let mut tamper = TamperAction::new(
+                protocol.to_string(),
+                field.to_string(),
+                new_value.to_string(),
+                mode,
+                action,
+            )?;
+            for option in options {
+                tamper = match option {
+                    "urg" => tamper.with_urgent_flag(),
+                    _ => tamper.with_stale_checksums(),
+                };
             }
-            Ok(DuplicateAction::new(l_action, r_action).into())
+            Ok(tamper.into())
         }
-        _ => unreachable!(),
+        Rule::sleep => {
+            let mut inner = inner_rules.into_inner();
+            let seconds = next_pair(&mut inner, "sleep duration")?.as_str();
+            let duration = seconds
+                .parse()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| Error::Parse(seconds.to_string()))?;
+
+            let mut action = SendAction::default().into();
+            for part in inner {
+                match part.as_rule() {
+                    Rule::action => action = parse_action(&mut part.into_inner(), registry)?,
+                    Rule::comma => {}
+                    _ => return Err(unexpected(&part)),
+                }
+            }
+
+            Ok(SleepAction::new(duration, action).into())
+        }
+        Rule::custom => {
+            let mut inner = inner_rules.into_inner();
+            let name = next_pair(&mut inner, "action name")?.as_str();
+            let args = inner.next().map(|args| args.as_str());
+            Ok(registry.parse(name, args)?.into())
+        }
+        _ => Err(unexpected(&inner_rules)),
     }
 }
 
+/// Parses the `(a1,a2)` arguments of a branching action, filling in an elided action with `send`.
+///
+/// The grammar gives the body exactly two children, `left_action` and `right_action`, each of
+/// which holds at most one action; any commas inside those actions belong to them.
+fn parse_rule_body(
+    body: Pair<Rule>,
+    registry: &ActionRegistry,
+) -> Result<(GenevaAction, GenevaAction)> {
+    let mut children = body.into_inner();
+    let mut next = || match children.next().and_then(|child| child.into_inner().next()) {
+        Some(action) => parse_action(&mut action.into_inner(), registry),
+        None => Ok(SendAction::default().into()),
+    };
+
+    let l_action = next()?;
+    let r_action = next()?;
+    Ok((l_action, r_action))
+}
+
+/// Returns the next pair of the parse tree, or an error naming the `expected` part of the strategy
+/// if there isn't one.
+///
+/// The grammar should make that impossible, but strategies can come from untrusted sources, so
+/// the parser reports errors rather than panicking.
+fn next_pair<'i>(pairs: &mut Pairs<'i, Rule>, expected: &str) -> Result<Pair<'i, Rule>> {
+    pairs
+        .next()
+        .ok_or_else(|| Error::Parse(format!("missing {}", expected)))
+}
+
+/// Returns the error for a part of the parse tree that shouldn't be where it is.
+fn unexpected(pair: &Pair<Rule>) -> Error {
+    Error::Parse(format!(
+        "unexpected {:?}: {}",
+        pair.as_rule(),
+        pair.as_str()
+    ))
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::actions::GenevaAction;
+    use crate::errors::Error;
     use crate::parse_strategy;
-    use crate::triggers::{GenevaTrigger, Trigger};
+    use crate::triggers::{Comparison, GenevaTrigger, Trigger};
+    use crate::{Direction, Packet};
+    use core::net::Ipv6Addr;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+    use std::time::Duration;
 
     #[test]
     fn parse_empty_strategy() {
@@ -178,4 +520,713 @@ mod tests {
         let action = &outbound[0].root_action;
         assert!(matches!(**action, GenevaAction::Drop(_)));
     }
+
+    #[test]
+    fn parse_tamper_round_trip() {
+        for s in [
+            r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}-| \/"#,
+            r#"[TCP:flags:S]-tamper{IP:ttl:corrupt}-| \/"#,
+            r#"[TCP:flags:S]-tamper{IP:src:replace:10.0.0.1}(drop,)-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:seq:add:100}(tamper{IP:ttl:corrupt},)-| \/"#,
+            r#"[UDP:dport:53]-tamper{DNS:qd:compress}-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:window:replace:1}(tamper{TCP:chksum:fix},)-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:flags:add:R}(tamper{TCP:flags:clear:S},)-| \/"#,
+        ] {
+            let strategy = parse_strategy(s);
+            assert!(strategy.is_ok(), "{}", s);
+            assert_eq!(strategy.unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_tamper_with_send() {
+        for s in [
+            r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(send)-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(send,)-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}()-| \/"#,
+        ] {
+            let strategy = parse_strategy(s);
+            assert!(strategy.is_ok(), "{}", s);
+            assert_eq!(
+                strategy.unwrap().to_string(),
+                r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}-| \/"#
+            );
+        }
+    }
+
+    #[test]
+    fn parse_tamper_child_forms() {
+        // The child may be written with or without the trailing comma, but is displayed with it.
+        for (s, canonical) in [
+            (
+                r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(drop)-| \/"#,
+                r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(drop,)-| \/"#,
+            ),
+            (
+                r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(tamper{IP:ttl:replace:1})-| \/"#,
+                r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(tamper{IP:ttl:replace:1},)-| \/"#,
+            ),
+        ] {
+            let strategy = parse_strategy(s).unwrap_or_else(|e| panic!("{}: {}", s, e));
+            assert_eq!(strategy.to_string(), canonical);
+            assert_eq!(parse_strategy(canonical).unwrap(), strategy);
+        }
+
+        // A tamper has no second child.
+        for s in [
+            r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(,drop)-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:flags:replace:SA}(drop,drop)-| \/"#,
+        ] {
+            assert!(parse_strategy(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_tamper_fields() {
+        let strategy = parse_strategy(r#"[TCP:flags:S]-tamper{IP:ttl:corrupt}-| \/"#).unwrap();
+        let action = &strategy.outbound.unwrap()[0].root_action;
+        assert!(matches!(**action, GenevaAction::Tamper(_)));
+    }
+
+    #[test]
+    fn parse_invalid_tamper_mode() {
+        assert!(parse_strategy(r#"[TCP:flags:S]-tamper{IP:ttl:bogus}-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_fragment_round_trip() {
+        for s in [
+            r#"[TCP:flags:PA]-fragment{6:12:True}-| \/"#,
+            r#"[TCP:flags:PA]-fragment{6:12:False}(drop,)-| \/"#,
+            r#"[TCP:flags:PA]-fragment{6:8:True}(,tamper{TCP:seq:add:1})-| \/"#,
+            r#"[TCP:flags:PA]-duplicate(fragment{6:8:True},drop)-| \/"#,
+        ] {
+            let strategy = parse_strategy(s);
+            assert!(strategy.is_ok(), "{}", s);
+            assert_eq!(strategy.unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_fragment_with_sends() {
+        for s in [
+            r#"[TCP:flags:PA]-fragment{6:12:True}(send,send)-| \/"#,
+            r#"[TCP:flags:PA]-fragment{6:12:True}(,)-| \/"#,
+            r#"[TCP:flags:PA]-fragment{tcp:12:True}-| \/"#,
+            r#"[TCP:flags:PA]-fragment{TCP:12:True}(,)-| \/"#,
+        ] {
+            let strategy = parse_strategy(s);
+            assert!(strategy.is_ok(), "{}", s);
+            assert_eq!(
+                strategy.unwrap().to_string(),
+                r#"[TCP:flags:PA]-fragment{6:12:True}-| \/"#
+            );
+        }
+    }
+
+    #[test]
+    fn parse_fragment_ip_protocol() {
+        let strategy = parse_strategy(r#"[TCP:flags:PA]-fragment{ip:8:False}-| \/"#).unwrap();
+        assert_eq!(
+            strategy.to_string(),
+            r#"[TCP:flags:PA]-fragment{4:8:False}-| \/"#
+        );
+    }
+
+    #[test]
+    fn parse_invalid_fragment() {
+        assert!(parse_strategy(r#"[TCP:flags:PA]-fragment{6:99999:True}-| \/"#).is_err());
+        assert!(parse_strategy(r#"[TCP:flags:PA]-fragment{6:8:Maybe}-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_trigger_gas() {
+        let s = r#"[TCP:flags:S:4]-drop-| \/ [IP:ttl:64:1]-drop-|"#;
+        let strategy = parse_strategy(s);
+        assert!(strategy.is_ok());
+
+        let strategy = strategy.unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        let outbound = strategy.outbound.as_ref().unwrap();
+        assert_eq!(outbound[0].trigger.gas(), 4);
+
+        let inbound = strategy.inbound.as_ref().unwrap();
+        assert_eq!(inbound[0].trigger.gas(), 1);
+    }
+
+    #[test]
+    fn parse_encoded_values() {
+        let s = r#"[TCP:load:hex:474554]-tamper{TCP:load:replace:b64:UE9TVA==}-| \/"#;
+        let strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        let tree = &strategy.outbound.as_ref().unwrap()[0];
+        let mut bytes = vec![
+            0x45, 0, 0, 43, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0,
+            80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+        ];
+        bytes.extend_from_slice(b"GET");
+        let pkt = Packet::new(bytes);
+        assert!(tree.matches(&pkt));
+        let result = tree.apply(pkt).unwrap();
+        assert_eq!(result[0].payload(), Some(&b"POST"[..]));
+
+        assert!(parse_strategy(r#"[TCP:load:hex:4]-drop-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_protocol_names() {
+        for s in [
+            r#"[IP:protocol:tcp]-drop-| \/"#,
+            r#"[IP:protocol:6]-drop-| \/"#,
+        ] {
+            assert_eq!(parse_strategy(s).unwrap().to_string(), s);
+        }
+        assert!(parse_strategy(r#"[IP:protocol:sctp]-drop-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_tos_classes() {
+        for s in [r#"[IP:tos:ef]-drop-| \/"#, r#"[IP:tos:0x10]-drop-| \/"#] {
+            assert_eq!(parse_strategy(s).unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_relative_trigger() {
+        let s = r#"[TCP:seq:rel:1]-drop-| \/ [TCP:ack:rel:!>=100:2]-drop-|"#;
+        let strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        for s in [
+            r#"[TCP:flags:rel:S]-drop-| \/"#,
+            r#"[IP:ttl:rel:5]-drop-| \/"#,
+        ] {
+            assert!(parse_strategy(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_empty_trigger_value() {
+        let s = r#"[TCP:options-sackok:]-drop-| [TCP:options-mss:!:2]-drop-| \/"#;
+        let strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        let outbound = strategy.outbound.as_ref().unwrap();
+        assert_eq!(outbound[0].trigger.field(), "options-sackok");
+        assert_eq!(outbound[1].trigger.gas(), 2);
+
+        for s in [
+            r#"[IP:ttl:]-drop-| \/"#,
+            r#"[TCP:load:]-drop-| \/"#,
+            r#"[UDP:load:]-drop-| \/"#,
+            r#"[TCP:flags:]-drop-| \/"#,
+            r#"[TCP:options-mss:<]-drop-| \/"#,
+            r#"[TCP:options-uto:>=]-drop-| \/"#,
+        ] {
+            assert!(parse_strategy(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_invalid_trigger_values() {
+        for s in [
+            r#"[TCP:flags:SX]-drop-| \/"#,
+            r#"[TCP:dport:65536]-drop-| \/"#,
+            r#"[IP:ttl:256]-drop-| \/"#,
+            r#"[IP:src:10.0.0]-drop-| \/"#,
+            r#"[UDP:sport:99999]-drop-| \/"#,
+            r#"[TCP:flags:S]-fragment{1:8:True}-| \/"#,
+        ] {
+            assert!(parse_strategy(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_unknown_trigger_field() {
+        let result = parse_strategy(r#"[UDP:bogus:1]-drop-| \/"#);
+        assert!(matches!(result, Err(Error::UnknownField { .. })));
+        assert_eq!(result.unwrap_err().to_string(), "unknown field UDP:bogus");
+    }
+
+    #[test]
+    fn parse_comparison_triggers() {
+        for (s, op) in [
+            (r#"[IP:ttl:<64]-drop-| \/"#, Comparison::Less),
+            (r#"[IP:len:>1000]-drop-| \/"#, Comparison::Greater),
+            (
+                r#"[TCP:window:<=1024:3]-drop-| \/"#,
+                Comparison::LessOrEqual,
+            ),
+            (r#"[UDP:len:>=512]-drop-| \/"#, Comparison::GreaterOrEqual),
+            (r#"[DNS:qtype:<=28]-drop-| \/"#, Comparison::LessOrEqual),
+        ] {
+            let strategy = parse_strategy(s).unwrap();
+            assert_eq!(strategy.to_string(), s);
+
+            let comparison = match &strategy.outbound.unwrap()[0].trigger {
+                GenevaTrigger::IP(t) => t.comparison(),
+                GenevaTrigger::DNS(t) => t.comparison(),
+                GenevaTrigger::ICMP(t) => t.comparison(),
+                GenevaTrigger::TCP(t) => t.comparison(),
+                GenevaTrigger::UDP(t) => t.comparison(),
+            };
+            assert_eq!(comparison, op, "{}", s);
+        }
+
+        let s = r#"[IP:ttl:!<64]-drop-| \/"#;
+        assert_eq!(parse_strategy(s).unwrap().to_string(), s);
+    }
+
+    #[test]
+    fn parse_comparison_on_non_numeric_field() {
+        for s in [
+            r#"[TCP:flags:>S]-drop-| \/"#,
+            r#"[IP:src:<10.0.0.1]-drop-| \/"#,
+            r#"[UDP:load:>=abc]-drop-| \/"#,
+        ] {
+            assert!(parse_strategy(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_trigger_without_gas() {
+        let strategy = parse_strategy(r#"[TCP:flags:S]-drop-| \/"#).unwrap();
+        assert_eq!(strategy.outbound.unwrap()[0].trigger.gas(), 0);
+    }
+
+    #[test]
+    fn parse_udp_trigger() {
+        let s = r#"[UDP:dport:53]-drop-| \/"#;
+        let strategy = parse_strategy(s);
+        assert!(strategy.is_ok());
+
+        let strategy = strategy.unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        let trigger = &strategy.outbound.unwrap()[0].trigger;
+        assert!(matches!(trigger, GenevaTrigger::UDP(_)));
+        assert_eq!(trigger.protocol(), "UDP");
+        assert_eq!(trigger.field(), "dport");
+    }
+
+    #[test]
+    fn parse_field_aliases() {
+        let s = r"[tcp:SourcePort:80]-drop-| [udp:DPORT:53]-drop-| \/ [IP:Source:10.0.0.1]-drop-|";
+        assert_eq!(
+            parse_strategy(s).unwrap().to_string(),
+            r"[TCP:sport:80]-drop-| [UDP:dport:53]-drop-| \/ [IP:src:10.0.0.1]-drop-|"
+        );
+    }
+
+    #[test]
+    fn parse_negated_trigger() {
+        let s = r#"[TCP:flags:!S:2]-drop-| \/ [IP:ttl:!64]-drop-|"#;
+        let strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        match &strategy.outbound.unwrap()[0].trigger {
+            GenevaTrigger::TCP(t) => {
+                assert!(t.is_negated());
+                assert_eq!(t.value(), "S");
+                assert_eq!(t.gas(), 2);
+            }
+            t => panic!("unexpected trigger {}", t),
+        }
+        match &strategy.inbound.unwrap()[0].trigger {
+            GenevaTrigger::IP(t) => assert!(t.is_negated()),
+            t => panic!("unexpected trigger {}", t),
+        }
+    }
+
+    #[test]
+    fn parse_icmp_trigger() {
+        let s = r#"\/ [ICMP:type:8]-drop-|"#;
+        let strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        let trigger = &strategy.inbound.unwrap()[0].trigger;
+        assert!(matches!(trigger, GenevaTrigger::ICMP(_)));
+        assert_eq!(trigger.protocol(), "ICMP");
+        assert_eq!(trigger.field(), "type");
+
+        assert!(parse_strategy(r#"[ICMP:sport:8]-drop-| \/"#).is_err());
+        assert!(parse_strategy(r#"[TCP:flags:S]-fragment{icmp:8:True}-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_nested_actions() {
+        for s in [
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1},duplicate(drop,))-| \/"#,
+            r#"[TCP:flags:S]-duplicate(duplicate(drop,),tamper{TCP:window:replace:1})-| \/"#,
+            r#"[TCP:flags:PA]-duplicate(tamper{TCP:flags:add:R}(duplicate(drop,fragment{6:8:True}(,tamper{IP:ttl:replace:1})),),duplicate(fragment{6:4:False}(drop,),tamper{TCP:seq:corrupt}))-| \/"#,
+            r#"[TCP:flags:PA]-fragment{6:2:True}(duplicate(tamper{TCP:seq:add:1}(drop,),duplicate(,drop)),duplicate(duplicate(drop,drop),fragment{6:1:False}))-| \/"#,
+            r#"[TCP:flags:S]-tamper{TCP:flags:add:A}(duplicate(fragment{6:8:True}(duplicate(drop,),),drop),)-| \/"#,
+        ] {
+            let strategy = parse_strategy(s).unwrap_or_else(|e| panic!("{}: {}", s, e));
+            assert_eq!(strategy.to_string(), s);
+        }
+
+        // Sends written out in full are elided, but the left/right assignment is the same.
+        let s = r#"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1}(send),duplicate(drop,send))-| \/"#;
+        assert_eq!(
+            parse_strategy(s).unwrap().to_string(),
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1},duplicate(drop,))-| \/"#
+        );
+    }
+
+    #[test]
+    fn parse_branching_actions_need_two_children() {
+        for s in [
+            r#"[TCP:flags:S]-duplicate(drop)-| \/"#,
+            r#"[TCP:flags:S]-duplicate(drop,drop,drop)-| \/"#,
+            r#"[TCP:flags:S]-fragment{6:8:True}(drop)-| \/"#,
+        ] {
+            assert!(parse_strategy(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parse_depth_limit() {
+        // Deep enough to overflow the stack if it reached the grammar.
+        let deep = format!(r"[TCP:flags:S]-{}-| \/", "duplicate(".repeat(100_000));
+        assert!(matches!(
+            parse_strategy(&deep),
+            Err(Error::Parse(msg)) if msg == "actions are nested more than 64 levels deep"
+        ));
+        assert!("duplicate("
+            .repeat(100_000)
+            .parse::<GenevaAction>()
+            .is_err());
+
+        let at_limit = |depth| {
+            format!(
+                r"[TCP:flags:S]-{}send{}-| \/",
+                "duplicate(".repeat(depth),
+                ",)".repeat(depth)
+            )
+        };
+        assert!(parse_strategy(&at_limit(DEFAULT_MAX_DEPTH)).is_ok());
+        assert!(parse_strategy(&at_limit(DEFAULT_MAX_DEPTH + 1)).is_err());
+
+        let s = r"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:R}(drop),)-| \/";
+        let registry = |depth| ActionRegistry::new().max_depth(depth);
+        assert!(parse_strategy_with(s, &registry(2)).is_ok());
+        assert!(matches!(
+            parse_strategy_with(s, &registry(1)),
+            Err(Error::Parse(_))
+        ));
+
+        // Parentheses in comments and custom arguments don't count.
+        let registry = registry(1).register("note", |_| Ok(SendAction::default()));
+        let s = "# ((((\n[TCP:flags:S]-duplicate(note{((((},)-| \\/";
+        assert!(parse_strategy_with(s, &registry).is_ok());
+    }
+
+    #[test]
+    fn parse_sleep() {
+        for s in [
+            r#"[TCP:flags:S]-sleep{1}-| \/"#,
+            r#"[TCP:flags:S]-sleep{0.5}(tamper{TCP:flags:replace:R},)-| \/ [TCP:flags:R]-sleep{2.25}(drop,)-|"#,
+            r#"[TCP:flags:PA]-duplicate(sleep{1},fragment{6:8:True}(,sleep{0.1}))-| \/"#,
+        ] {
+            let strategy = parse_strategy(s).unwrap_or_else(|e| panic!("{}: {}", s, e));
+            assert_eq!(strategy.to_string(), s);
+        }
+
+        let strategy = parse_strategy(r#"[TCP:flags:S]-sleep{1.50}(send)-| \/"#).unwrap();
+        assert_eq!(strategy.to_string(), r#"[TCP:flags:S]-sleep{1.5}-| \/"#);
+        let action = &strategy.outbound.unwrap()[0].root_action;
+        match &**action {
+            GenevaAction::Sleep(a) => assert_eq!(a.duration(), Duration::from_millis(1500)),
+            a => panic!("expected a sleep action, got {:?}", a),
+        }
+
+        assert!(parse_strategy(r#"[TCP:flags:S]-sleep{-1}-| \/"#).is_err());
+        assert!(parse_strategy(r#"[TCP:flags:S]-sleep{}-| \/"#).is_err());
+        assert!(parse_strategy(r#"[TCP:flags:S]-sleep-| \/"#).is_err());
+    }
+
+    /// Reverses the bytes of a packet's payload.
+    struct ReversePayload;
+
+    impl Action for ReversePayload {
+        fn run(&self, mut pkt: Packet) -> Result<Vec<Packet>> {
+            let start = pkt.len() - pkt.payload().map_or(0, |payload| payload.len());
+            pkt.as_mut_slice()[start..].reverse();
+            Ok(vec![pkt])
+        }
+    }
+
+    impl fmt::Display for ReversePayload {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("reverse")
+        }
+    }
+
+    fn registry() -> ActionRegistry {
+        ActionRegistry::new().register("reverse", |args| match args {
+            None => Ok(ReversePayload),
+            Some(args) => Err(Error::Parse(format!(
+                "reverse takes no arguments: {}",
+                args
+            ))),
+        })
+    }
+
+    #[test]
+    fn parse_custom_action() {
+        let s = r#"[TCP:flags:PA]-duplicate(reverse,tamper{TCP:flags:add:R}(reverse,))-| \/"#;
+        let strategy = parse_strategy_with(s, &registry()).unwrap();
+        assert_eq!(strategy.to_string(), s);
+        assert_eq!(strategy, parse_strategy_with(s, &registry()).unwrap());
+
+        let action = &strategy.outbound.as_ref().unwrap()[0].root_action;
+        assert_eq!(action.node_count(), 4);
+
+        // A TCP segment from 10.0.0.1:12345 to 10.0.0.2:80 with PSH+ACK set, carrying "abc".
+        let pkt = Packet::new(vec![
+            0x45, 0x00, 0x00, 0x2b, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00,
+            0x50, 0x18, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, b'a', b'b', b'c',
+        ]);
+        let result = strategy.apply(pkt, Direction::Outbound).unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].payload(), Some(&b"cba"[..]));
+        assert_eq!(result[1].payload(), Some(&b"cba"[..]));
+        assert_eq!(result[1].tcp_header().unwrap()[13], 0x1c);
+    }
+
+    #[test]
+    fn parse_unknown_custom_action() {
+        let s = r#"[TCP:flags:PA]-reverse-| \/"#;
+        assert!(matches!(parse_strategy(s), Err(Error::Parse(_))));
+        assert!(parse_strategy_with(r#"[TCP:flags:PA]-reverse{1}-| \/"#, &registry()).is_err());
+
+        // Built-in actions still win, even when a custom action's name starts with theirs.
+        let registry = registry().register("dropper", |_| Ok(ReversePayload));
+        let strategy = parse_strategy_with(r#"[TCP:flags:PA]-drop-| \/"#, &registry).unwrap();
+        let action = &strategy.outbound.as_ref().unwrap()[0].root_action;
+        assert!(matches!(**action, GenevaAction::Drop(_)));
+
+        let strategy = parse_strategy_with(r#"[TCP:flags:PA]-dropper-| \/"#, &registry).unwrap();
+        let action = &strategy.outbound.as_ref().unwrap()[0].root_action;
+        assert!(matches!(**action, GenevaAction::Custom(_)));
+    }
+
+    #[test]
+    fn parse_ip_flags_trigger() {
+        for s in [
+            r#"[IP:flags:DF]-drop-| \/"#,
+            r#"\/ [IP:flags:!DF+MF]-drop-|"#,
+        ] {
+            assert_eq!(parse_strategy(s).unwrap().to_string(), s);
+        }
+        assert!(matches!(
+            parse_strategy(r#"[IP:flags:XF]-drop-| \/"#),
+            Err(Error::InvalidValue { .. })
+        ));
+    }
+
+    /// Pieces of strategy syntax, so that random strategies get further into the parser than
+    /// random strings do.
+    const TOKENS: &[&str] = &[
+        "[",
+        "]",
+        "(",
+        ")",
+        "{",
+        "}",
+        ":",
+        ",",
+        "-",
+        "-|",
+        "\\/",
+        "!",
+        "<=",
+        ">",
+        " ",
+        "TCP",
+        "IP",
+        "UDP",
+        "DNS",
+        "flags",
+        "ttl",
+        "qname",
+        "S",
+        "DF",
+        "64",
+        "1.5",
+        "send",
+        "drop",
+        "duplicate",
+        "fragment{",
+        "tamper{",
+        "sleep{",
+        "replace",
+        "corrupt",
+        "True",
+        "6",
+        "reverse",
+    ];
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(s in "\\PC*") {
+            let _ = parse_strategy(&s);
+        }
+
+        #[test]
+        fn parse_tokens_never_panics(tokens in vec(select(TOKENS), 0..40)) {
+            let s = tokens.concat();
+            if let Ok(strategy) = parse_strategy_with(&s, &registry()) {
+                // Anything that parses must also survive a round trip.
+                let reparsed = parse_strategy_with(&strategy.to_string(), &registry());
+                prop_assert_eq!(reparsed.ok(), Some(strategy));
+            }
+        }
+    }
+
+    /// The multi-line example strategy from the `strategy` module documentation.
+    const MULTI_LINE_EXAMPLE: &str = r#"
+    [TCP:flags:S]-
+       duplicate(
+          tamper{TCP:flags:replace:SA}(
+             send),
+           send)-| \/
+    [TCP:flags:R]-drop-|
+"#;
+
+    #[test]
+    fn parse_multi_line_strategy() {
+        let expected =
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/ [TCP:flags:R]-drop-|"#;
+        let strategy = parse_strategy(MULTI_LINE_EXAMPLE).unwrap();
+        assert_eq!(strategy.to_string(), expected);
+        assert_eq!(strategy, parse_strategy(expected).unwrap());
+
+        let windows = MULTI_LINE_EXAMPLE
+            .replace('\n', "\r\n")
+            .replace("   ", "\t");
+        assert_eq!(parse_strategy(&windows).unwrap(), strategy);
+    }
+
+    #[test]
+    fn parse_comments() {
+        let s = r#"
+            # Pretend the handshake already happened.
+            [TCP:flags:S]-duplicate( # keep the SYN...
+                tamper{TCP:flags:replace:SA}, # ...but send a SYN/ACK first
+            )-|
+            \/
+            [TCP:flags:R]-drop-|  # ignore resets
+            # that's all"#;
+        let expected =
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/ [TCP:flags:R]-drop-|"#;
+        assert_eq!(parse_strategy(s).unwrap().to_string(), expected);
+
+        // Tokens themselves can't be split up.
+        assert!(parse_strategy(r#"[TCP:flags:S]-dup licate-| \/"#).is_err());
+        assert!(parse_strategy(r#"[TCP:fl#ags:S]-drop-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_dns_trigger() {
+        let s = r#"[DNS:qname:www.example-site.com]-drop-| [DNS:qtype:AAAA:2]-drop-| \/"#;
+        let strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.to_string(), s);
+
+        let trigger = &strategy.outbound.unwrap()[0].trigger;
+        assert!(matches!(trigger, GenevaTrigger::DNS(_)));
+        assert_eq!(trigger.protocol(), "DNS");
+        assert_eq!(trigger.field(), "qname");
+
+        assert!(parse_strategy(r#"[DNS:sport:53]-drop-| \/"#).is_err());
+        assert!(parse_strategy(r#"[TCP:flags:S]-fragment{dns:8:True}-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_single_action() {
+        let action: GenevaAction = "tamper{TCP:flags:replace:SA}(send)".parse().unwrap();
+        assert!(matches!(action, GenevaAction::Tamper(_)));
+        assert_eq!(action.to_string(), "tamper{TCP:flags:replace:SA}");
+
+        let action: GenevaAction = " duplicate(,drop) ".parse().unwrap();
+        assert_eq!(action.to_string(), "duplicate(,drop)");
+
+        assert!("drop-|".parse::<GenevaAction>().is_err());
+        assert!("[TCP:flags:S]".parse::<GenevaAction>().is_err());
+        assert!("".parse::<GenevaAction>().is_err());
+    }
+
+    #[test]
+    fn parse_tamper_options() {
+        for s in [
+            r#"[TCP:flags:A]-tamper{TCP:urgptr:replace+urg:5}-| \/"#,
+            r#"[TCP:flags:A]-tamper{TCP:seq:corrupt+stale}-| \/"#,
+            r#"[TCP:flags:A]-tamper{TCP:urgptr:add+urg+stale:1}(drop,)-| \/"#,
+        ] {
+            let strategy = parse_strategy(s).unwrap();
+            assert_eq!(strategy.to_string(), s);
+            assert_eq!(parse_strategy(&strategy.to_string()).unwrap(), strategy);
+        }
+
+        let action: GenevaAction = "tamper{TCP:urgptr:replace+stale+urg:5}".parse().unwrap();
+        let expected = TamperAction::new(
+            "TCP".to_string(),
+            "urgptr".to_string(),
+            "5".to_string(),
+            TamperMode::Replace,
+            SendAction::default().into(),
+        )
+        .unwrap()
+        .with_urgent_flag()
+        .with_stale_checksums();
+        assert_eq!(action, expected.into());
+        assert!("tamper{TCP:urgptr:replace+bogus:5}"
+            .parse::<GenevaAction>()
+            .is_err());
+    }
+
+    #[test]
+    fn parse_ipv6_trigger_round_trip() {
+        for s in [
+            r#"[IP:src:[2001:db8::1]]-drop-| \/"#,
+            r#"[IP:dst:[2001:db8::]/64:2]-drop-| \/"#,
+            r#"\/ [IP:src:![::ffff:192.0.2.1]]-tamper{TCP:flags:replace:R}-|"#,
+            r#"[TCP:flags:S]-tamper{IP:dst:replace:[2001:db8::2]}-| \/"#,
+        ] {
+            let strategy = parse_strategy(s).unwrap();
+            assert_eq!(strategy.to_string(), s);
+            assert_eq!(parse_strategy(&strategy.to_string()).unwrap(), strategy);
+        }
+
+        let trigger: GenevaTrigger = "[IP:src:[2001:db8::]/32]".parse().unwrap();
+        let mut bytes = vec![0x60, 0, 0, 0, 0, 0, 59, 64];
+        bytes.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&[0; 16]);
+        assert!(trigger.matches(&Packet::new(bytes)));
+
+        assert!(parse_strategy(r#"[IP:src:2001:db8::1]-drop-| \/"#).is_err());
+    }
+
+    #[test]
+    fn parse_single_trigger() {
+        let trigger: GenevaTrigger = "[TCP:flags:S]".parse().unwrap();
+        assert!(matches!(trigger, GenevaTrigger::TCP(_)));
+        assert_eq!(trigger.to_string(), "[TCP:flags:S]");
+        assert!(trigger.matches(&Packet::new(vec![
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x26, 0xcd, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00,
+            0x50, 0x02, 0x72, 0x10, 0x00, 0x00, 0x00, 0x00,
+        ])));
+
+        let trigger: GenevaTrigger = "[IP:ttl:>=64:2]".parse().unwrap();
+        assert_eq!(trigger.to_string(), "[IP:ttl:>=64:2]");
+
+        assert!("[TCP:flags:S]-drop-|".parse::<GenevaTrigger>().is_err());
+        assert!("TCP:flags:S".parse::<GenevaTrigger>().is_err());
+        assert!("[TCP:nope:S]".parse::<GenevaTrigger>().is_err());
+    }
 }
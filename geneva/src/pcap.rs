@@ -0,0 +1,218 @@
+//! Applying strategies to packet captures.
+//!
+//! This module is only available with the `pcap` feature. It reads packets from a capture file
+//! (in either the classic pcap or the pcapng format), so that a strategy can be checked against
+//! real traffic without touching the network, and can write the resulting packets back out as a
+//! new capture.
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use pcap_file::pcap::{PcapHeader, PcapPacket, PcapReader, PcapWriter};
+use pcap_file::pcapng::{Block, PcapNgReader};
+use pcap_file::DataLink;
+
+use crate::errors::*;
+use crate::packet::ethernet_payload_offset;
+use crate::strategy::{Direction, Strategy};
+use crate::Packet;
+
+/// The block type that every pcapng file starts with.
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+/// Reads every packet in the capture at `path`, runs it through `strategy` in the given
+/// direction, and returns all of the resulting packets in order.
+///
+/// Frames are stripped of their link-layer header so that each [Packet] starts with its IP
+/// header. Ethernet (including VLAN-tagged frames) and raw IP captures are supported; Ethernet
+/// frames that don't carry IP, such as ARP, are skipped.
+pub fn apply_to_pcap(
+    path: impl AsRef<Path>,
+    strategy: &Strategy,
+    direction: Direction,
+) -> Result<Vec<Packet>> {
+    let file = File::open(path).map_err(capture_error)?;
+    let mut result = vec![];
+    for pkt in read_packets(file)? {
+        result.append(&mut strategy.apply(pkt, direction)?);
+    }
+    Ok(result)
+}
+
+/// Returns the error for a capture file that couldn't be read.
+fn capture_error(e: impl std::fmt::Display) -> Error {
+    Error::Capture(e.to_string())
+}
+
+/// Reads every IP packet out of a capture in either the pcap or the pcapng format.
+///
+/// As with [apply_to_pcap], each [Packet] starts with its IP header, and frames that don't carry
+/// IP are skipped.
+pub fn read_packets(mut reader: impl Read) -> Result<Vec<Packet>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data).map_err(capture_error)?;
+    let mut packets = vec![];
+
+    if data.starts_with(&PCAPNG_MAGIC) {
+        let mut reader = PcapNgReader::new(&data[..]).map_err(capture_error)?;
+        while let Some(block) = reader.next_block() {
+            // The block borrows from the reader, which we need to look up the interface.
+            let (interface, frame) = match block.map_err(capture_error)? {
+                Block::EnhancedPacket(epb) => (epb.interface_id, epb.data.into_owned()),
+                Block::SimplePacket(spb) => (0, spb.data.into_owned()),
+                _ => continue,
+            };
+            let linktype = reader
+                .interfaces()
+                .get(interface as usize)
+                .ok_or_else(|| capture_error(format!("no interface with id {}", interface)))?
+                .linktype;
+            if let Some(ip) = strip_link_layer(linktype, &frame)? {
+                packets.push(Packet::new_from_slice(ip));
+            }
+        }
+    } else {
+        let mut reader = PcapReader::new(&data[..]).map_err(capture_error)?;
+        let linktype = reader.header().datalink;
+        while let Some(pkt) = reader.next_packet() {
+            let pkt = pkt.map_err(capture_error)?;
+            if let Some(ip) = strip_link_layer(linktype, &pkt.data)? {
+                packets.push(Packet::new_from_slice(ip));
+            }
+        }
+    }
+
+    Ok(packets)
+}
+
+/// Writes the packets to a classic pcap capture with the raw IP link type. Any link layer that a
+/// packet starts with is left out.
+///
+/// Packets don't record when they were captured, so each one is timestamped with its
+/// [delay](crate::PacketMeta::delay), or with zero if it has none.
+pub fn write_packets(writer: impl Write, packets: &[Packet]) -> Result<()> {
+    let header = PcapHeader {
+        datalink: DataLink::RAW,
+        ..Default::default()
+    };
+    let mut writer = PcapWriter::with_header(writer, header).map_err(capture_error)?;
+    for pkt in packets {
+        let timestamp = pkt.meta().delay.unwrap_or(Duration::ZERO);
+        let datagram = pkt.ip_datagram();
+        let len = u32::try_from(datagram.len()).map_err(capture_error)?;
+        writer
+            .write_packet(&PcapPacket::new(timestamp, len, datagram))
+            .map_err(capture_error)?;
+    }
+    Ok(())
+}
+
+/// Returns the part of `frame` that starts with the IP header, or `None` if the frame doesn't
+/// carry IP.
+fn strip_link_layer(linktype: DataLink, frame: &[u8]) -> Result<Option<&[u8]>> {
+    match linktype {
+        DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => Ok(Some(frame)),
+        DataLink::ETHERNET => Ok(ethernet_payload_offset(frame).and_then(|i| frame.get(i..))),
+        _ => Err(capture_error(format!(
+            "unsupported link type: {:?}",
+            linktype
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name)
+    }
+
+    fn duplicate_syns() -> Strategy {
+        r"[TCP:flags:S]-duplicate-| \/".parse().unwrap()
+    }
+
+    #[test]
+    fn ethernet_pcap() {
+        // A SYN, an ARP request, and an ACK; the ARP request is skipped.
+        let file = File::open(fixture("handshake.pcap")).unwrap();
+        let packets = read_packets(file).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|pkt| pkt.tcp_header().is_some()));
+
+        let result = apply_to_pcap(
+            fixture("handshake.pcap"),
+            &duplicate_syns(),
+            Direction::Outbound,
+        )
+        .unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0], result[1]);
+        assert_eq!(result[2], packets[1]);
+    }
+
+    #[test]
+    fn raw_ip_pcapng() {
+        let result = apply_to_pcap(
+            fixture("handshake.pcapng"),
+            &duplicate_syns(),
+            Direction::Outbound,
+        )
+        .unwrap();
+        assert_eq!(result.len(), 3);
+
+        let result = apply_to_pcap(
+            fixture("handshake.pcapng"),
+            &duplicate_syns(),
+            Direction::Inbound,
+        )
+        .unwrap();
+        assert_eq!(result.len(), 2);
+    }
+
+    #[test]
+    fn write_then_read() {
+        let file = File::open(fixture("handshake.pcap")).unwrap();
+        let mut packets = read_packets(file).unwrap();
+        packets[1].meta_mut().delay = Some(Duration::from_millis(5));
+
+        let mut capture = vec![];
+        write_packets(&mut capture, &packets).unwrap();
+        assert_eq!(read_packets(&capture[..]).unwrap(), packets);
+
+        let mut reader = PcapReader::new(&capture[..]).unwrap();
+        assert_eq!(reader.header().datalink, DataLink::RAW);
+        let mut timestamps = vec![];
+        while let Some(pkt) = reader.next_packet() {
+            timestamps.push(pkt.unwrap().timestamp);
+        }
+        assert_eq!(timestamps, [Duration::ZERO, Duration::from_millis(5)]);
+    }
+
+    #[test]
+    fn vlan_tagged_frames() {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x01, 0x08, 0x00, 0x45]);
+        let ip = strip_link_layer(DataLink::ETHERNET, &frame).unwrap();
+        assert_eq!(ip, Some(&[0x45][..]));
+
+        assert_eq!(
+            strip_link_layer(DataLink::ETHERNET, &frame[..13]).unwrap(),
+            None
+        );
+        assert!(strip_link_layer(DataLink::USB_LINUX, &frame).is_err());
+    }
+
+    #[test]
+    fn missing_file() {
+        let result = apply_to_pcap(
+            fixture("missing.pcap"),
+            &duplicate_syns(),
+            Direction::Outbound,
+        );
+        assert!(matches!(result, Err(Error::Capture(_))));
+    }
+}
@@ -0,0 +1,169 @@
+//! Converting between [Packet] and the packet types of [pnet].
+//!
+//! This module is only available with the `pnet` feature. Programs that already capture or build
+//! packets with `pnet` can turn an [Ipv4Packet], [Ipv6Packet], or [EthernetPacket] into a
+//! `Packet` with [From], which copies its bytes once, and can look at a `Packet` (say, one that a
+//! strategy produced) through `pnet`'s types again without copying it at all.
+//!
+//! ```
+//! use geneva::Packet;
+//! use pnet_packet::ipv4::Ipv4Packet;
+//!
+//! let bytes = [0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2];
+//! let pkt = Packet::from(&Ipv4Packet::new(&bytes).unwrap());
+//! assert_eq!(pkt.as_pnet_ipv4().unwrap().get_ttl(), 64);
+//! ```
+//!
+//! [pnet]: https://docs.rs/pnet
+use pnet_packet::ethernet::EthernetPacket;
+use pnet_packet::ipv4::Ipv4Packet;
+use pnet_packet::ipv6::Ipv6Packet;
+use pnet_packet::tcp::TcpPacket;
+use pnet_packet::udp::UdpPacket;
+use pnet_packet::Packet as _;
+
+use crate::packet::{PROTO_TCP, PROTO_UDP};
+use crate::{LinkType, Packet};
+
+impl From<&Ipv4Packet<'_>> for Packet {
+    fn from(pkt: &Ipv4Packet<'_>) -> Self {
+        Packet::new_from_slice(pkt.packet())
+    }
+}
+
+impl From<&Ipv6Packet<'_>> for Packet {
+    fn from(pkt: &Ipv6Packet<'_>) -> Self {
+        Packet::new_from_slice(pkt.packet())
+    }
+}
+
+/// The frame becomes a packet with an [Ethernet](LinkType::Ethernet) link layer.
+impl From<&EthernetPacket<'_>> for Packet {
+    fn from(pkt: &EthernetPacket<'_>) -> Self {
+        Packet::with_link_type(pkt.packet().to_vec(), LinkType::Ethernet)
+    }
+}
+
+impl Packet {
+    /// Returns the packet's IP datagram, without its link layer or any padding after it, if it
+    /// has a well-formed IP header of the given version.
+    fn datagram(&self, version: u8) -> Option<&[u8]> {
+        let layers = self.layers().filter(|l| l.ip_version == version)?;
+        Some(&self.as_slice()[layers.ip.start..layers.transport.end])
+    }
+
+    /// Returns a view of the packet's IPv4 datagram, or `None` if it isn't an IPv4 packet.
+    pub fn as_pnet_ipv4(&self) -> Option<Ipv4Packet<'_>> {
+        Ipv4Packet::new(self.datagram(4)?)
+    }
+
+    /// Returns a view of the packet's IPv6 datagram, or `None` if it isn't an IPv6 packet.
+    pub fn as_pnet_ipv6(&self) -> Option<Ipv6Packet<'_>> {
+        Ipv6Packet::new(self.datagram(6)?)
+    }
+
+    /// Returns a view of the packet's TCP segment, or `None` if it doesn't carry one whose header
+    /// could be parsed.
+    pub fn as_pnet_tcp(&self) -> Option<TcpPacket<'_>> {
+        self.tcp_header()?;
+        TcpPacket::new(self.ip_payload()?).filter(|_| self.ip_protocol() == Some(PROTO_TCP))
+    }
+
+    /// Returns a view of the packet's UDP datagram, or `None` if it doesn't carry one whose header
+    /// could be parsed.
+    pub fn as_pnet_udp(&self) -> Option<UdpPacket<'_>> {
+        self.udp_header()?;
+        UdpPacket::new(self.ip_payload()?).filter(|_| self.ip_protocol() == Some(PROTO_UDP))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, Strategy};
+    use core::net::Ipv4Addr;
+    use pnet_packet::ethernet::{EtherTypes, MutableEthernetPacket};
+    use pnet_packet::ip::IpNextHeaderProtocols;
+    use pnet_packet::ipv4::{self, MutableIpv4Packet};
+    use pnet_packet::tcp::{self, MutableTcpPacket, TcpFlags};
+
+    /// Builds an IPv4 SYN from 10.0.0.1:12345 to 10.0.0.2:80 with pnet, checksums included.
+    fn pnet_syn() -> Vec<u8> {
+        let mut bytes = vec![0; 40];
+        let (src, dst) = (Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        {
+            let mut tcp = MutableTcpPacket::new(&mut bytes[20..]).unwrap();
+            tcp.set_source(12345);
+            tcp.set_destination(80);
+            tcp.set_sequence(1000);
+            tcp.set_data_offset(5);
+            tcp.set_flags(TcpFlags::SYN);
+            tcp.set_window(65535);
+            let checksum = tcp::ipv4_checksum(&tcp.to_immutable(), &src, &dst);
+            tcp.set_checksum(checksum);
+        }
+        let mut ip = MutableIpv4Packet::new(&mut bytes).unwrap();
+        ip.set_version(4);
+        ip.set_header_length(5);
+        ip.set_total_length(40);
+        ip.set_ttl(64);
+        ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
+        ip.set_source(src);
+        ip.set_destination(dst);
+        let checksum = ipv4::checksum(&ip.to_immutable());
+        ip.set_checksum(checksum);
+        bytes
+    }
+
+    #[test]
+    fn ipv4_round_trip() {
+        let bytes = pnet_syn();
+        let pkt = Packet::from(&Ipv4Packet::new(&bytes).unwrap());
+        assert_eq!(pkt.as_slice(), &bytes[..]);
+        assert_eq!(pkt.tcp_header(), Some(&bytes[20..]));
+
+        let ip = pkt.as_pnet_ipv4().unwrap();
+        assert_eq!(ip.packet(), &bytes[..]);
+        assert_eq!(ip.get_source(), Ipv4Addr::new(10, 0, 0, 1));
+        assert!(pkt.as_pnet_ipv6().is_none());
+
+        let tcp = pkt.as_pnet_tcp().unwrap();
+        assert_eq!(tcp.get_destination(), 80);
+        assert_eq!(tcp.get_flags(), TcpFlags::SYN);
+        assert!(pkt.as_pnet_udp().is_none());
+    }
+
+    #[test]
+    fn strategy_output_reads_back_in_pnet() {
+        let bytes = pnet_syn();
+        let pkt = Packet::from(&Ipv4Packet::new(&bytes).unwrap());
+        let strategy: Strategy = r"[TCP:flags:S]-tamper{TCP:flags:replace:SA}-| \/"
+            .parse()
+            .unwrap();
+        let out = strategy.apply(pkt, Direction::Outbound).unwrap();
+
+        let ip = out[0].as_pnet_ipv4().unwrap();
+        assert_eq!(ipv4::checksum(&ip), ip.get_checksum());
+        let tcp = out[0].as_pnet_tcp().unwrap();
+        assert_eq!(tcp.get_flags(), TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(
+            tcp::ipv4_checksum(&tcp, &ip.get_source(), &ip.get_destination()),
+            tcp.get_checksum()
+        );
+    }
+
+    #[test]
+    fn ethernet_frames_keep_their_link_layer() {
+        let mut frame = vec![0; 14];
+        let mut ethernet = MutableEthernetPacket::new(&mut frame).unwrap();
+        ethernet.set_ethertype(EtherTypes::Ipv4);
+        frame.extend_from_slice(&pnet_syn());
+        // Link-layer padding isn't part of the datagram.
+        frame.extend_from_slice(&[0; 6]);
+
+        let pkt = Packet::from(&EthernetPacket::new(&frame).unwrap());
+        assert_eq!(pkt.link_type(), LinkType::Ethernet);
+        assert_eq!(pkt.as_pnet_ipv4().unwrap().packet(), &frame[14..54]);
+        assert_eq!(pkt.as_pnet_tcp().unwrap().get_source(), 12345);
+    }
+}
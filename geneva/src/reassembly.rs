@@ -0,0 +1,465 @@
+//! Reassembling TCP streams so that triggers can match data split across segments.
+//!
+//! A trigger only ever looks at one packet, so `[TCP:load:...]` cannot fire on a request that the
+//! sender happened to split over two segments. A [Reassembler] buffers the payload of each TCP
+//! stream it sees and, for every segment that extends a stream, builds a copy of that segment whose
+//! payload is everything the stream has carried so far. [Reassembler::apply] matches `load`
+//! triggers against that copy, while the actions themselves still run on the real segment.
+use alloc::collections::BTreeMap;
+
+use crate::actions::{fix_lengths, ActionTree};
+use crate::errors::*;
+use crate::flows::FlowTable;
+use crate::prelude::*;
+use crate::strategy::{Direction, Strategy};
+use crate::triggers::{GenevaTrigger, TCPField};
+use crate::Packet;
+
+/// The TCP flags that end a stream.
+const FIN: u8 = 0x01;
+const RST: u8 = 0x04;
+
+/// The TCP flag that starts a stream.
+const SYN: u8 = 0x02;
+
+/// The most data a reassembled segment can carry, so that it still fits in an IP datagram with
+/// the largest possible IPv4 and TCP headers.
+pub const MAX_STREAM_LEN: usize = u16::MAX as usize - 120;
+
+/// The default number of bytes a [Reassembler] buffers for a stream before flushing it.
+pub const DEFAULT_MAX_LEN: usize = 16 * 1024;
+
+/// The default number of streams a [Reassembler] buffers at once.
+pub const DEFAULT_MAX_STREAMS: usize = 1024;
+
+/// The data buffered for one direction of a TCP connection.
+#[derive(Debug)]
+struct Stream {
+    /// The sequence number of the first byte of `data`.
+    base: u32,
+
+    /// The stream's data, up to the first byte that hasn't arrived yet.
+    data: Vec<u8>,
+
+    /// Segments that arrived ahead of a gap in the stream, keyed by their offset from `base`.
+    pending: BTreeMap<usize, Vec<u8>>,
+}
+
+impl Stream {
+    fn new(base: u32) -> Self {
+        Self {
+            base,
+            data: vec![],
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the segment whose first byte has sequence number `seq` to the stream, returning
+    /// `true` if the stream's contiguous data grew as a result.
+    fn insert(&mut self, seq: u32, payload: &[u8]) -> bool {
+        let offset = seq.wrapping_sub(self.base);
+        if payload.is_empty() || offset >= 1 << 31 {
+            // Segments from before the start of the stream are retransmissions of data we never
+            // saw, so there's nothing to stitch them onto.
+            return false;
+        }
+
+        let offset = offset as usize;
+        if offset > self.data.len() {
+            self.pending
+                .entry(offset)
+                .or_insert_with(|| payload.to_vec());
+            return false;
+        }
+
+        let before = self.data.len();
+        self.append(offset, payload);
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.data.len() {
+                break;
+            }
+            let (offset, payload) = entry.remove_entry();
+            self.append(offset, &payload);
+        }
+        self.data.len() > before
+    }
+
+    /// Appends whatever part of `payload` (which starts at `offset`) isn't already in `data`.
+    fn append(&mut self, offset: usize, payload: &[u8]) {
+        let overlap = self.data.len() - offset;
+        if overlap < payload.len() {
+            self.data.extend_from_slice(&payload[overlap..]);
+        }
+    }
+
+    /// Returns the number of bytes buffered for the stream, including out-of-order segments.
+    fn buffered(&self) -> usize {
+        self.data.len() + self.pending.values().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// Buffers TCP segments per connection so that triggers can match a stream's reassembled payload.
+///
+/// Each direction of each connection (as identified by its addresses and ports) is buffered on
+/// its own. A stream starts with the first byte after the SYN or, if the SYN was never seen, with
+/// the first segment that arrives for the connection. Segments that arrive out of order are held
+/// until the gap before them is filled, and retransmitted data is only counted once. A stream is
+/// flushed, and its buffer freed, when a segment with FIN or RST set arrives, or once more than
+/// the buffer limit has been buffered for it; the next segment of the connection then starts a
+/// new stream.
+///
+/// At most [DEFAULT_MAX_STREAMS] streams are buffered at once, unless
+/// [with_max_streams](Self::with_max_streams) says otherwise. A segment of a new stream beyond
+/// that flushes whichever stream has gone longest without a segment.
+///
+/// ```
+/// use geneva::{Direction, Packet, Reassembler, Strategy};
+///
+/// # fn segment(seq: u32, payload: &[u8]) -> Packet {
+/// #     let mut bytes = vec![
+/// #         0x45, 0, 0, 0, 0, 1, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0x30, 0x39, 0,
+/// #         80, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+/// #     ];
+/// #     bytes[2..4].copy_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+/// #     bytes[24..28].copy_from_slice(&seq.to_be_bytes());
+/// #     bytes.extend_from_slice(payload);
+/// #     Packet::new(bytes)
+/// # }
+/// let strategy: Strategy = r"[TCP:load:hello]-drop-| \/".parse().unwrap();
+/// let mut reassembler = Reassembler::default();
+///
+/// // Neither segment carries "hello" on its own, but together they do.
+/// let first = reassembler.apply(&strategy, segment(1000, b"hel"), Direction::Outbound);
+/// assert_eq!(first.unwrap().len(), 1);
+/// let second = reassembler.apply(&strategy, segment(1003, b"lo"), Direction::Outbound);
+/// assert!(second.unwrap().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct Reassembler {
+    streams: FlowTable<Stream>,
+    max_len: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_LEN)
+    }
+}
+
+impl Reassembler {
+    /// Creates a new `Reassembler` that flushes a stream once `max_len` bytes have been buffered
+    /// for it. `max_len` is capped at [MAX_STREAM_LEN].
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            streams: FlowTable::new(DEFAULT_MAX_STREAMS),
+            max_len: max_len.min(MAX_STREAM_LEN),
+        }
+    }
+
+    /// Sets the most streams that are buffered at once (at least one). Any streams that are
+    /// already buffered are flushed.
+    pub fn with_max_streams(mut self, max_streams: usize) -> Self {
+        self.streams = FlowTable::new(max_streams);
+        self
+    }
+
+    /// Returns the number of streams that currently have data buffered.
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Returns `true` if no stream has any data buffered.
+    pub fn is_empty(&self) -> bool {
+        self.streams.len() == 0
+    }
+
+    /// Adds a TCP segment to its stream.
+    ///
+    /// If the segment extends the stream's data, this returns a copy of it whose payload is all
+    /// of the stream's data so far (up to the buffer limit), with the sequence number, lengths,
+    /// and checksums adjusted to match. Otherwise (for instance, if the segment carries no data,
+    /// repeats data already seen, or arrives ahead of a gap) it returns `None`, as it does for
+    /// packets that aren't TCP.
+    pub fn push(&mut self, pkt: &Packet) -> Option<Packet> {
+        let header = pkt.tcp_header()?;
+        let payload = pkt.payload()?;
+        let key = pkt.flow_key()?;
+        let flags = header[13];
+        let mut seq = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if flags & SYN != 0 {
+            // The SYN takes up a sequence number of its own, so the data starts just after it.
+            seq = seq.wrapping_add(1);
+        }
+
+        let stream = self.streams.get_or_insert_with(key, || Stream::new(seq));
+        let view = if stream.insert(seq, payload) {
+            let data = &stream.data[..stream.data.len().min(self.max_len)];
+            reassembled(pkt, stream.base, data)
+        } else {
+            None
+        };
+
+        if flags & (FIN | RST) != 0 || stream.buffered() >= self.max_len {
+            self.streams.remove(&key);
+        }
+        view
+    }
+
+    /// Adds the packet to its stream, then applies the strategy to it.
+    ///
+    /// This works like [Strategy::apply], except that `load` triggers are matched against the
+    /// reassembled payload returned by [push](Self::push), when there is one. Every other trigger
+    /// is matched against the packet itself, and the actions always run on the packet itself.
+    pub fn apply(
+        &mut self,
+        strategy: &Strategy,
+        pkt: Packet,
+        direction: Direction,
+    ) -> Result<Vec<Packet>> {
+        let view = self.push(&pkt);
+        let forest = match strategy.forest(direction) {
+            Some(forest) if !forest.is_empty() => forest,
+            _ => return Ok(vec![pkt]),
+        };
+
+        let target = |tree: &ActionTree| match &view {
+            Some(view) if reads_payload(&tree.trigger) => view,
+            _ => &pkt,
+        };
+        let mut packets = vec![];
+        strategy.apply_forest(forest, &pkt, target, &mut packets, |_, _| {})?;
+        Ok(packets)
+    }
+}
+
+/// Returns `true` if the trigger looks at a TCP segment's payload.
+fn reads_payload(trigger: &GenevaTrigger) -> bool {
+    matches!(trigger, GenevaTrigger::TCP(t) if *t.tcp_field() == TCPField::Payload)
+}
+
+/// Builds a copy of `pkt` that carries `data`, starting at sequence number `seq`, as its payload.
+fn reassembled(pkt: &Packet, seq: u32, data: &[u8]) -> Option<Packet> {
+    let layers = pkt.layers()?;
+    let payload = layers.payload.clone()?;
+
+    let mut bytes = pkt.as_slice()[..payload.start].to_vec();
+    let seq_at = layers.transport.start + 4;
+    bytes[seq_at..seq_at + 4].copy_from_slice(&seq.to_be_bytes());
+    bytes.extend_from_slice(data);
+
+    let mut view = pkt.with_bytes(bytes);
+    fix_lengths(&mut view);
+    Some(view)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{ipv4_checksum, tcp_checksum};
+
+    const ACK: u8 = 0x10;
+
+    /// Builds an IPv4 packet from 10.0.0.1 to 10.0.0.2:80 carrying a TCP segment with the given
+    /// source port, sequence number, flags, and payload.
+    fn segment(sport: u16, seq: u32, flags: u8, payload: &[u8]) -> Packet {
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        bytes[2..4].copy_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&sport.to_be_bytes());
+        bytes.extend_from_slice(&80u16.to_be_bytes());
+        bytes.extend_from_slice(&seq.to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(&[0x50, flags | ACK]);
+        bytes.extend_from_slice(&65535u16.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(payload);
+        Packet::new(bytes)
+    }
+
+    fn data(pkt: &Packet, seq: u32, flags: u8, payload: &[u8]) -> Packet {
+        let sport = u16::from_be_bytes([pkt.as_slice()[20], pkt.as_slice()[21]]);
+        segment(sport, seq, flags, payload)
+    }
+
+    fn payload_of(view: Option<Packet>) -> Option<Vec<u8>> {
+        view.map(|pkt| pkt.payload().unwrap().to_vec())
+    }
+
+    #[test]
+    fn split_payload_matches() {
+        let strategy: Strategy = r"[TCP:load:hello]-drop-| \/".parse().unwrap();
+        let first = segment(12345, 1000, 0, b"hel");
+        let second = data(&first, 1003, 0, b"lo");
+
+        // Without reassembly, neither segment matches.
+        let packets = vec![first.clone(), second.clone()];
+        let unchanged = strategy.process(packets.clone(), Direction::Outbound);
+        assert_eq!(unchanged.unwrap(), packets);
+
+        let mut reassembler = Reassembler::default();
+        let out = reassembler.apply(&strategy, first.clone(), Direction::Outbound);
+        assert_eq!(out.unwrap(), vec![first]);
+        let out = reassembler.apply(&strategy, second, Direction::Outbound);
+        assert_eq!(out.unwrap(), vec![]);
+    }
+
+    #[test]
+    fn actions_run_on_the_real_segment() {
+        let strategy: Strategy = r"[TCP:load:hello]-tamper{TCP:window:replace:7}-| \/"
+            .parse()
+            .unwrap();
+        let mut reassembler = Reassembler::default();
+        let first = segment(12345, 1000, 0, b"hel");
+        reassembler
+            .apply(&strategy, first.clone(), Direction::Outbound)
+            .unwrap();
+
+        let out = reassembler.apply(&strategy, data(&first, 1003, 0, b"lo"), Direction::Outbound);
+        let out = out.unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].payload(), Some(&b"lo"[..]));
+        assert_eq!(&out[0].tcp_header().unwrap()[14..16], &7u16.to_be_bytes());
+    }
+
+    #[test]
+    fn view_describes_the_stream() {
+        let mut reassembler = Reassembler::default();
+        let syn = segment(12345, 999, SYN, b"");
+        assert_eq!(reassembler.push(&syn), None);
+
+        let first = data(&syn, 1000, 0, b"hel");
+        let view = reassembler.push(&first).unwrap();
+        assert_eq!(view.payload(), Some(&b"hel"[..]));
+
+        let view = reassembler.push(&data(&syn, 1003, 0, b"lo")).unwrap();
+        assert_eq!(view.payload(), Some(&b"hello"[..]));
+        assert_eq!(&view.tcp_header().unwrap()[4..8], &1000u32.to_be_bytes());
+        assert_eq!(&view.as_slice()[2..4], &45u16.to_be_bytes());
+        let (ip, tcp) = (view.ip_header().unwrap(), view.ip_payload().unwrap());
+        assert_eq!(ipv4_checksum(ip), 0);
+        assert_eq!(tcp_checksum(ip, tcp), 0);
+    }
+
+    #[test]
+    fn out_of_order_and_repeated_segments() {
+        let mut reassembler = Reassembler::default();
+        let first = segment(12345, 1000, 0, b"ab");
+
+        assert_eq!(payload_of(reassembler.push(&first)), Some(b"ab".to_vec()));
+
+        // The third segment waits until the second one fills the gap before it.
+        assert_eq!(reassembler.push(&data(&first, 1004, 0, b"ef")), None);
+        let second = data(&first, 1002, 0, b"cd");
+        assert_eq!(
+            payload_of(reassembler.push(&second)),
+            Some(b"abcdef".to_vec())
+        );
+
+        // Retransmissions add nothing new, while a partial overlap only adds the new bytes.
+        assert_eq!(reassembler.push(&second), None);
+        let overlap = data(&first, 1005, 0, b"fgh");
+        assert_eq!(
+            payload_of(reassembler.push(&overlap)),
+            Some(b"abcdefgh".to_vec())
+        );
+        assert_eq!(reassembler.push(&data(&first, 900, 0, b"old")), None);
+    }
+
+    #[test]
+    fn streams_are_kept_apart() {
+        let mut reassembler = Reassembler::default();
+        let a = segment(1111, 1000, 0, b"hel");
+        let b = segment(2222, 1000, 0, b"HEL");
+        reassembler.push(&a);
+        reassembler.push(&b);
+        assert_eq!(reassembler.len(), 2);
+
+        let view = reassembler.push(&data(&b, 1003, 0, b"LO"));
+        assert_eq!(payload_of(view), Some(b"HELLO".to_vec()));
+        let view = reassembler.push(&data(&a, 1003, 0, b"lo"));
+        assert_eq!(payload_of(view), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn fin_and_rst_flush_the_stream() {
+        for flag in [FIN, RST] {
+            let mut reassembler = Reassembler::default();
+            let first = segment(12345, 1000, 0, b"hel");
+            reassembler.push(&first);
+
+            let last = data(&first, 1003, flag, b"lo");
+            assert_eq!(payload_of(reassembler.push(&last)), Some(b"hello".to_vec()));
+            assert!(reassembler.is_empty());
+
+            // The next segment starts a new stream.
+            let next = data(&first, 1005, 0, b"!");
+            assert_eq!(payload_of(reassembler.push(&next)), Some(b"!".to_vec()));
+        }
+    }
+
+    #[test]
+    fn size_cap_flushes_the_stream() {
+        let mut reassembler = Reassembler::new(4);
+        let first = segment(12345, 1000, 0, b"hel");
+        reassembler.push(&first);
+        assert_eq!(reassembler.len(), 1);
+
+        let view = reassembler.push(&data(&first, 1003, 0, b"lo"));
+        assert_eq!(payload_of(view), Some(b"hell".to_vec()));
+        assert!(reassembler.is_empty());
+
+        // Out-of-order data counts towards the cap as well.
+        reassembler.push(&data(&first, 2000, 0, b"a"));
+        assert_eq!(reassembler.push(&data(&first, 2005, 0, b"bcd")), None);
+        assert!(reassembler.is_empty());
+    }
+
+    #[test]
+    fn stream_cap_flushes_the_oldest_stream() {
+        let mut reassembler = Reassembler::default().with_max_streams(2);
+        let (a, b, c) = (
+            segment(1111, 1000, 0, b"a"),
+            segment(2222, 1000, 0, b"b"),
+            segment(3333, 1000, 0, b"c"),
+        );
+        reassembler.push(&a);
+        reassembler.push(&b);
+        reassembler.push(&data(&a, 1001, 0, b"a"));
+
+        // The stream of `b` has gone longest without a segment, so it makes way for `c`.
+        reassembler.push(&c);
+        assert_eq!(reassembler.len(), 2);
+        let view = reassembler.push(&data(&a, 1002, 0, b"a"));
+        assert_eq!(payload_of(view), Some(b"aaa".to_vec()));
+        let view = reassembler.push(&data(&b, 1001, 0, b"b"));
+        assert_eq!(payload_of(view), Some(b"b".to_vec()));
+    }
+
+    #[test]
+    fn apply_keeps_to_the_packet_limit() {
+        let mut strategy: Strategy = r"[TCP:load:hello]-duplicate-| [TCP:load:nope]-drop-| \/"
+            .parse()
+            .unwrap();
+        strategy.limits.max_packets = 2;
+        let mut reassembler = Reassembler::default();
+        let first = segment(12345, 1000, 0, b"hel");
+        let out = reassembler.apply(&strategy, first.clone(), Direction::Outbound);
+        assert_eq!(out.unwrap().len(), 2);
+
+        // The duplicate takes up the whole limit, leaving no room for the second tree's copy.
+        let out = reassembler.apply(&strategy, data(&first, 1003, 0, b"lo"), Direction::Outbound);
+        assert!(matches!(out, Err(Error::LimitExceeded(_))));
+    }
+
+    #[test]
+    fn other_packets_are_ignored() {
+        let mut reassembler = Reassembler::default();
+        let mut udp = segment(12345, 1000, 0, b"hello").as_slice().to_vec();
+        udp[9] = crate::packet::PROTO_UDP;
+        assert_eq!(reassembler.push(&Packet::new(udp)), None);
+        assert_eq!(reassembler.push(&Packet::new(vec![0x45, 0])), None);
+        assert!(reassembler.is_empty());
+    }
+}
@@ -36,14 +36,28 @@
 //! apply to TCP packets with the `RST` flag set, and would simply drop them. Each of the forests in
 //! the example are made up of a single `(trigger, action tree)` pair.
 //!
+//! As the example shows, strategies can be spread over several lines: whitespace (including
+//! newlines) is allowed between any two tokens, and everything from a `#` to the end of the line
+//! is a comment.
+//!
 //! [geneva-paper]: https://geneva.cs.umd.edu/papers/geneva_ccs19.pdf
-use std::fmt;
+use alloc::borrow::Cow;
+use core::fmt;
+use core::iter;
+use core::slice;
+#[cfg(feature = "parser")]
+use core::str::FromStr;
 
-use crate::actions::ActionTree;
+use crate::actions::{ActionTree, GenevaAction};
 use crate::errors::*;
+#[cfg(feature = "parser")]
+use crate::parse_strategy;
+use crate::prelude::*;
+use crate::triggers::GenevaTrigger;
 use crate::Packet;
 
 /// Represents the direction to which a [Forest]'s action trees applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
     /// The `Forest` applies to packets egressing the system.
     Inbound,
@@ -64,71 +78,1289 @@ impl fmt::Display for Direction {
 /// An ordered list of [ActionTree]s.
 pub type Forest = Vec<ActionTree>;
 
+/// Bounds on the work that applying a [Strategy] to a packet may do.
+///
+/// Nested `duplicate` and `fragment` actions can turn one packet into a great many, so a
+/// strategy that breaks its limits returns [Error::LimitExceeded] instead of running. The limits
+/// apply to each packet on its own; they are not part of a strategy's string form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+    /// The most packets that applying the strategy to a single packet may yield.
+    pub max_packets: usize,
+    /// The smallest offset that a `fragment` action in the strategy may split a payload at.
+    pub min_fragment_size: u16,
+}
+
+impl Limits {
+    /// Returns an error if any `fragment` action under `action` splits payloads into pieces
+    /// smaller than the limit allows.
+    fn check_fragments(&self, action: &GenevaAction) -> Result<()> {
+        if let GenevaAction::Fragment(a) = action {
+            if a.fragment_size() < self.min_fragment_size {
+                return Err(Error::LimitExceeded(format!(
+                    "fragment offset {} is below the minimum of {}",
+                    a.fragment_size(),
+                    self.min_fragment_size
+                )));
+            }
+        }
+        action
+            .children()
+            .into_iter()
+            .try_for_each(|child| self.check_fragments(child))
+    }
+
+    /// Returns an error if `count` packets are more than the limit allows.
+    fn check_packets(&self, count: usize) -> Result<()> {
+        if count > self.max_packets {
+            return Err(Error::LimitExceeded(format!(
+                "{} packets is more than the maximum of {}",
+                count, self.max_packets
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    /// Allows up to 1024 packets and fragments of at least one byte.
+    fn default() -> Self {
+        Self {
+            max_packets: 1024,
+            min_fragment_size: 1,
+        }
+    }
+}
+
+/// Returns the root action of `tree` as it is written in a strategy, except that a root `send` is
+/// written out as `send` rather than left out.
+pub(crate) fn root_action_string(tree: &ActionTree) -> String {
+    match *tree.root_action {
+        GenevaAction::Send(_) => "send".to_string(),
+        ref action => action.to_string(),
+    }
+}
+
+/// What [Strategy::explain] found out about one action tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchReport {
+    /// The tree's trigger, as it is written in a strategy.
+    pub trigger: String,
+    /// Whether the trigger matched the packet. A tree that has run out of gas doesn't match.
+    pub matched: bool,
+    /// The tree's root action, as it is written in a strategy (so a root `send` is `"send"`
+    /// rather than left out).
+    pub action: String,
+}
+
 /// Zero or more action trees that can be applied to inbound or outbound packets.
-#[derive(Default, Debug)]
+///
+/// A clone is a separate strategy whose trees start with as much gas as the original's have left.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Strategy {
     pub outbound: Option<Forest>,
     pub inbound: Option<Forest>,
+    /// The bounds on what applying the strategy may do. Parsing a strategy gives it the default
+    /// limits.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limits: Limits,
 }
 
 impl Strategy {
-    /// Applies the strategy to the given packet, returning zero or more potentially-modified packets.
-    pub fn apply(&self, pkt: Packet, direction: Direction) -> Result<Vec<Packet>> {
-        let forest = match direction {
-            Direction::Inbound => &self.inbound,
-            Direction::Outbound => &self.outbound,
+    /// Returns a simplified copy of the strategy that yields the same packets as this one.
+    ///
+    /// Two strategies that are written differently but behave the same way often have the same
+    /// canonical form, which makes it useful for spotting duplicates. Each action tree is
+    /// simplified on its own: actions whose output is only ever dropped become `drop`, a
+    /// `duplicate` with a dropped branch becomes its other branch, and a `tamper` that replaces a
+    /// field only for the next action to replace it again is removed. Empty forests become `None`.
+    /// As with a clone, each tree in the copy starts with as much gas as the original has left.
+    ///
+    /// ```
+    /// use geneva::Strategy;
+    ///
+    /// let a: Strategy = r"[TCP:flags:S]-duplicate(drop,tamper{TCP:flags:add:R})-| \/"
+    ///     .parse()
+    ///     .unwrap();
+    /// let b: Strategy = r"[TCP:flags:S]-tamper{TCP:flags:add:R}-| \/".parse().unwrap();
+    /// assert_ne!(a, b);
+    /// assert_eq!(a.canonicalize(), b.canonicalize());
+    /// ```
+    pub fn canonicalize(&self) -> Strategy {
+        let canonicalize = |forest: &Option<Forest>| {
+            let forest: Forest = forest
+                .iter()
+                .flatten()
+                .map(ActionTree::canonicalize)
+                .collect();
+            if forest.is_empty() {
+                None
+            } else {
+                Some(forest)
+            }
         };
 
-        if forest.is_none() {
-            return Ok(vec![pkt]);
+        Strategy {
+            outbound: canonicalize(&self.outbound),
+            inbound: canonicalize(&self.inbound),
+            limits: self.limits,
         }
+    }
+
+    /// Returns the trigger of every action tree in the strategy: those of the outbound forest
+    /// first, then those of the inbound forest, each in the order the trees appear.
+    pub fn triggers(&self) -> Vec<&GenevaTrigger> {
+        let outbound = self.outbound.iter().flatten();
+        let inbound = self.inbound.iter().flatten();
+        outbound.chain(inbound).map(|tree| &tree.trigger).collect()
+    }
 
-        let forest = forest.as_ref().unwrap();
-        if forest.is_empty() {
-            return Ok(vec![pkt]);
+    /// Appends the action trees of `other` to this strategy: its outbound trees go after this
+    /// strategy's outbound trees, and its inbound trees after the inbound ones. A forest that
+    /// either strategy leaves out is treated as empty, and stays `None` only if both leave it out.
+    /// The merged strategy keeps its own [Limits].
+    ///
+    /// ```
+    /// use geneva::Strategy;
+    ///
+    /// let mut strategy: Strategy = r"[TCP:flags:S]-drop-| \/".parse().unwrap();
+    /// strategy.merge(r"\/ [TCP:flags:R]-drop-|".parse().unwrap());
+    /// assert_eq!(
+    ///     strategy.to_string(),
+    ///     r"[TCP:flags:S]-drop-| \/ [TCP:flags:R]-drop-|"
+    /// );
+    /// ```
+    pub fn merge(&mut self, other: Strategy) {
+        let merge = |forest: &mut Option<Forest>, other: Option<Forest>| {
+            if let Some(trees) = other {
+                forest.get_or_insert_with(Vec::new).extend(trees);
+            }
+        };
+        merge(&mut self.outbound, other.outbound);
+        merge(&mut self.inbound, other.inbound);
+    }
+
+    /// Checks that every action tree in the strategy can run, returning an error for the first
+    /// trigger, action, or [limit](Limits) that would fail whatever packet it saw.
+    ///
+    /// Parsing a strategy already checks its triggers, but not its actions: a `tamper` of a field
+    /// its protocol doesn't have, or with a mode the field doesn't support, is only an error once
+    /// a packet reaches it. So are strategies that were deserialized or put together by hand.
+    ///
+    /// ```
+    /// use geneva::Strategy;
+    ///
+    /// let strategy: Strategy = r"[TCP:flags:S]-tamper{TCP:flags:replace:SA}-| \/".parse().unwrap();
+    /// assert!(strategy.validate().is_ok());
+    ///
+    /// let strategy: Strategy = r"[TCP:flags:S]-tamper{TCP:seq:clear:1}-| \/".parse().unwrap();
+    /// assert!(strategy.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        let trees = self.outbound.iter().chain(&self.inbound).flatten();
+        for tree in trees {
+            tree.trigger.validate()?;
+            tree.root_action.validate()?;
+            self.limits.check_fragments(&tree.root_action)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the action trees whose triggers can never match a packet, returning how many were
+    /// removed. A trigger is dead if it must match its field exactly but its value is one that
+    /// [validate](Self::validate) rejects for the field, as can happen with strategies that were
+    /// deserialized or evolved outside this library. Parsing never produces such triggers.
+    ///
+    /// The others are kept, even if their actions would fail: removing them could change what
+    /// the strategy does, since a failing tree still stops the trees after it from running.
+    pub fn prune_dead(&mut self) -> usize {
+        let mut removed = 0;
+        for forest in [&mut self.outbound, &mut self.inbound]
+            .into_iter()
+            .flatten()
+        {
+            let len = forest.len();
+            forest.retain(|tree| !tree.trigger.never_matches());
+            removed += len - forest.len();
+        }
+        removed
+    }
+
+    /// Returns the forest that applies to packets travelling in the given direction.
+    pub(crate) fn forest(&self, direction: Direction) -> Option<&[ActionTree]> {
+        match direction {
+            Direction::Inbound => self.inbound.as_deref(),
+            Direction::Outbound => self.outbound.as_deref(),
+        }
+    }
+
+    /// Returns the most packets that [apply](Self::apply) could yield for any one packet
+    /// travelling in `direction`, without looking at a packet. Every tree of the forest is given
+    /// its own copy of the packet, so this is the sum of the trees'
+    /// [max_fanout](ActionTree::max_fanout); an empty forest passes the packet through, which
+    /// counts as one.
+    ///
+    /// A [custom action](crate::CustomAction) can yield any number of packets, so if any tree of
+    /// the forest has one, there is no bound and this returns `None`.
+    ///
+    /// ```
+    /// use geneva::{Direction, Strategy};
+    ///
+    /// let strategy: Strategy = r"[TCP:flags:S]-duplicate-| [TCP:flags:R]-drop-| \/"
+    ///     .parse()
+    ///     .unwrap();
+    /// assert_eq!(strategy.max_fanout(Direction::Outbound), Some(3));
+    /// assert_eq!(strategy.max_fanout(Direction::Inbound), Some(1));
+    /// ```
+    pub fn max_fanout(&self, direction: Direction) -> Option<usize> {
+        match self.forest(direction) {
+            None | Some([]) => Some(1),
+            Some(forest) => forest
+                .iter()
+                .try_fold(0usize, |n, tree| Some(n.saturating_add(tree.max_fanout()?))),
+        }
+    }
+
+    /// Reports, for each action tree of the forest for `direction` in order, whether it matches
+    /// `pkt` and which action it would run. Nothing is run, so the packet is left alone and no gas
+    /// is used.
+    ///
+    /// ```
+    /// use geneva::{Direction, Packet, Strategy};
+    ///
+    /// let strategy: Strategy = r"[TCP:flags:S]-drop-| \/".parse().unwrap();
+    /// // An IPv4 header with nothing after it, so no TCP trigger matches.
+    /// let pkt = Packet::new(vec![
+    ///     0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2,
+    /// ]);
+    /// let report = strategy.explain(&pkt, Direction::Outbound);
+    /// assert_eq!(report[0].trigger, "[TCP:flags:S]");
+    /// assert!(!report[0].matched);
+    /// ```
+    pub fn explain(&self, pkt: &Packet, direction: Direction) -> Vec<MatchReport> {
+        let forest = self.forest(direction).unwrap_or_default();
+        forest
+            .iter()
+            .map(|tree| MatchReport {
+                trigger: tree.trigger.to_string(),
+                matched: tree.matches(pkt),
+                action: root_action_string(tree),
+            })
+            .collect()
+    }
+
+    /// Applies a matching action tree to a copy of `pkt`, appending the packets it yields to
+    /// `packets`. If that would break the strategy's [Limits], returns an error without running
+    /// the tree.
+    fn apply_tree(&self, tree: &ActionTree, pkt: &Packet, packets: &mut Vec<Packet>) -> Result<()> {
+        self.limits.check_fragments(&tree.root_action)?;
+        let expected = packets.len().saturating_add(tree.root_action.fanout(pkt));
+        self.limits.check_packets(expected)?;
+
+        packets.append(&mut tree.apply(pkt.clone())?);
+        // The count above can fall short if a tamper lengthens the payload before a fragment.
+        self.limits.check_packets(packets.len())
+    }
+
+    /// Applies the strategy to the given packet, returning zero or more potentially-modified packets.
+    ///
+    /// Returns [Error::LimitExceeded] if the strategy would break its [limits](Self::limits).
+    pub fn apply(&self, pkt: Packet, direction: Direction) -> Result<Vec<Packet>> {
+        match self.apply_ref(&pkt, direction)? {
+            // Nothing changed, so hand back the original packet rather than a copy of it.
+            Cow::Borrowed(_) => Ok(vec![pkt]),
+            Cow::Owned(packets) => Ok(packets),
         }
+    }
+
+    /// Applies the strategy to a borrowed packet. This produces the same packets as
+    /// [apply](Self::apply), but when no action tree fires, the result simply borrows `pkt`
+    /// instead of copying it.
+    pub fn apply_ref<'a>(
+        &self,
+        pkt: &'a Packet,
+        direction: Direction,
+    ) -> Result<Cow<'a, [Packet]>> {
+        let forest = match self.forest(direction) {
+            None | Some([]) => return Ok(Cow::Borrowed(slice::from_ref(pkt))),
+            Some([action_tree]) if !action_tree.matches(pkt) => {
+                return Ok(Cow::Borrowed(slice::from_ref(pkt)))
+            }
+            Some(forest) => forest,
+        };
+
+        let mut packets = vec![];
+        self.apply_forest(forest, pkt, |_| pkt, &mut packets, |_, _| {})?;
+        Ok(Cow::Owned(packets))
+    }
+
+    /// Applies the strategy like [apply](Self::apply), but pairs each resulting packet with the
+    /// index (within the forest for `direction`) of the action tree that yielded it. Packets that
+    /// are passed on by a tree whose trigger didn't match are paired with `None`, as is `pkt`
+    /// itself if the forest is empty.
+    ///
+    /// ```
+    /// use geneva::{Direction, Packet, Strategy};
+    ///
+    /// let strategy: Strategy = r"[IP:ttl:64]-duplicate-| [IP:ttl:1]-drop-| \/".parse().unwrap();
+    /// let pkt = Packet::new(vec![
+    ///     0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2,
+    /// ]);
+    /// let sources: Vec<_> = strategy
+    ///     .apply_traced(pkt, Direction::Outbound)
+    ///     .unwrap()
+    ///     .into_iter()
+    ///     .map(|(_, source)| source)
+    ///     .collect();
+    /// assert_eq!(sources, [Some(0), Some(0), None]);
+    /// ```
+    pub fn apply_traced(
+        &self,
+        pkt: Packet,
+        direction: Direction,
+    ) -> Result<Vec<(Packet, Option<usize>)>> {
+        let forest = match self.forest(direction) {
+            None | Some([]) => return Ok(vec![(pkt, None)]),
+            Some(forest) => forest,
+        };
 
         let mut packets = vec![];
+        let mut sources = vec![];
+        self.apply_forest(
+            forest,
+            &pkt,
+            |_| &pkt,
+            &mut packets,
+            |source, count| sources.extend(iter::repeat_n(source, count)),
+        )?;
+        Ok(packets.into_iter().zip(sources).collect())
+    }
 
-        // For forests with more than one action tree, we clone the packet for all but the last
-        // action tree. The last tree can take the original packet. This should avoid an extra copy.
-        // For forests with only one action tree, this first loop should not fire.
-        for action_tree in forest.iter().take(forest.len().saturating_sub(1)) {
-            let pkt = pkt.clone();
-            if action_tree.matches(&pkt) {
-                let mut pkts = action_tree.apply(pkt)?;
-                packets.append(&mut pkts);
+    /// Gives every action tree in `forest` its own copy of `pkt`, appending the packets that each
+    /// one yields to `packets` (or the copy itself, if the tree doesn't match). Each tree's
+    /// trigger is matched against the packet that `target` returns for it, which is usually `pkt`
+    /// itself. After each tree, calls `yielded` with the tree's index if it matched, and how many
+    /// packets it added.
+    pub(crate) fn apply_forest<'a>(
+        &self,
+        forest: &[ActionTree],
+        pkt: &'a Packet,
+        target: impl Fn(&ActionTree) -> &'a Packet,
+        packets: &mut Vec<Packet>,
+        mut yielded: impl FnMut(Option<usize>, usize),
+    ) -> Result<()> {
+        for (i, action_tree) in forest.iter().enumerate() {
+            let before = packets.len();
+            if action_tree.matches(target(action_tree)) {
+                self.apply_tree(action_tree, pkt, packets)?;
+                yielded(Some(i), packets.len() - before);
             } else {
-                packets.push(pkt);
+                packets.push(pkt.clone());
+                self.limits.check_packets(packets.len())?;
+                yielded(None, 1);
             }
         }
+        Ok(())
+    }
 
-        if let Some(action_tree) = forest.last() {
-            if action_tree.matches(&pkt) {
-                let mut pkts = action_tree.apply(pkt)?;
-                packets.append(&mut pkts);
-            } else {
-                packets.push(pkt);
-            }
+    /// Runs the packet through the outbound forest, then runs each of the resulting packets
+    /// through the inbound forest.
+    ///
+    /// This is useful when the caller cannot tell which direction a packet is travelling in and
+    /// wants every action tree whose trigger matches to fire. If the direction is known, use
+    /// [apply](Self::apply) instead, or [Packet::infer_direction] to work it out.
+    pub fn apply_all(&self, pkt: Packet) -> Result<Vec<Packet>> {
+        let outbound = self.apply(pkt, Direction::Outbound)?;
+        self.process(outbound, Direction::Inbound)
+    }
+
+    /// Applies the strategy to each packet of a batch in turn, returning all of the resulting
+    /// packets.
+    ///
+    /// The output preserves the order of the input: every packet produced from one input packet
+    /// (say, by a `duplicate` or `fragment` action) comes before any of the packets produced from
+    /// the next.
+    ///
+    /// Gas is kept by the action trees themselves, so it carries over from one packet of the batch
+    /// to the next (and from one batch to the next) exactly as it does across calls to
+    /// [apply](Self::apply): a tree with gas for two packets fires on the first two packets that
+    /// match it, however they are split into batches.
+    pub fn process(&self, pkts: Vec<Packet>, direction: Direction) -> Result<Vec<Packet>> {
+        let mut result = Vec::with_capacity(pkts.len());
+        for pkt in pkts {
+            result.append(&mut self.apply(pkt, direction)?);
         }
+        Ok(result)
+    }
+
+    /// Applies the strategy to a batch of packets. This is another name for
+    /// [process](Self::process), so the output keeps the order of the input, and gas carries over
+    /// from one packet of the batch to the next.
+    ///
+    /// ```
+    /// use geneva::{Direction, Packet, Strategy};
+    ///
+    /// let strategy: Strategy = r"[IP:ttl:64:1]-drop-| \/".parse().unwrap();
+    /// let pkt = Packet::new(vec![
+    ///     0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0x66, 0xe4, 10, 0, 0, 1, 10, 0, 0, 2,
+    /// ]);
+    /// let batch = vec![pkt.clone(), pkt.clone()];
+    /// // The tree only has the gas to drop the first packet.
+    /// assert_eq!(strategy.apply_batch(batch, Direction::Outbound).unwrap(), [pkt]);
+    /// ```
+    pub fn apply_batch(&self, pkts: Vec<Packet>, direction: Direction) -> Result<Vec<Packet>> {
+        self.process(pkts, direction)
+    }
+}
+
+/// Builds a [Strategy] one action tree at a time.
+///
+/// Each call to [outbound](Self::outbound) or [inbound](Self::inbound) appends an action tree to
+/// the end of the corresponding forest. A forest that never has any action trees added to it is
+/// left as `None`.
+///
+/// Here is how to build the example strategy from the Geneva paper:
+///
+/// ```
+/// use geneva::{
+///     DropAction, DuplicateAction, SendAction, StrategyBuilder, TCPField, TCPTrigger,
+///     TamperAction, TamperMode,
+/// };
+///
+/// let tamper = TamperAction::new(
+///     "TCP".to_string(),
+///     "flags".to_string(),
+///     "SA".to_string(),
+///     TamperMode::Replace,
+///     SendAction::default().into(),
+/// )
+/// .unwrap();
+///
+/// let strategy = StrategyBuilder::new()
+///     .outbound(
+///         TCPTrigger::new(TCPField::Flags, "S".to_string(), 0).unwrap(),
+///         DuplicateAction::new(tamper.into(), SendAction::default().into()),
+///     )
+///     .inbound(
+///         TCPTrigger::new(TCPField::Flags, "R".to_string(), 0).unwrap(),
+///         DropAction::default(),
+///     )
+///     .build();
+///
+/// assert_eq!(
+///     strategy.to_string(),
+///     r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/ [TCP:flags:R]-drop-|"#
+/// );
+/// ```
+#[derive(Default, Debug)]
+pub struct StrategyBuilder {
+    strategy: Strategy,
+}
+
+impl StrategyBuilder {
+    /// Creates a new `StrategyBuilder` for an empty strategy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an action tree made from `trigger` and `action` to the outbound forest.
+    pub fn outbound(
+        self,
+        trigger: impl Into<GenevaTrigger>,
+        action: impl Into<GenevaAction>,
+    ) -> Self {
+        self.tree(
+            Direction::Outbound,
+            ActionTree::new(trigger.into(), action.into()),
+        )
+    }
+
+    /// Appends an action tree made from `trigger` and `action` to the inbound forest.
+    pub fn inbound(
+        self,
+        trigger: impl Into<GenevaTrigger>,
+        action: impl Into<GenevaAction>,
+    ) -> Self {
+        self.tree(
+            Direction::Inbound,
+            ActionTree::new(trigger.into(), action.into()),
+        )
+    }
+
+    /// Appends an existing [ActionTree] to the forest for the given direction.
+    pub fn tree(mut self, direction: Direction, tree: ActionTree) -> Self {
+        let forest = match direction {
+            Direction::Inbound => &mut self.strategy.inbound,
+            Direction::Outbound => &mut self.strategy.outbound,
+        };
+        forest.get_or_insert_with(Vec::new).push(tree);
+        self
+    }
+
+    /// Sets the [Limits] of the strategy, in place of the default ones.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.strategy.limits = limits;
+        self
+    }
 
-        Ok(packets)
+    /// Builds the [Strategy].
+    pub fn build(self) -> Strategy {
+        self.strategy
     }
 }
 
 impl fmt::Display for Strategy {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let outbound = match &self.outbound {
-            Some(f) => f.iter().map(|a| a.to_string()).collect(),
-            None => "".to_string(),
+        let join = |forest: &Option<Forest>| {
+            forest
+                .iter()
+                .flatten()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
         };
+        let outbound = join(&self.outbound);
+        let inbound = join(&self.inbound);
 
-        let inbound = match &self.inbound {
-            // we don't want a trailing space at the end
-            Some(f) => format!(" {}", f.iter().map(|a| a.to_string()).collect::<String>()),
-            None => "".to_string(),
+        // Only put a space between the separator and a forest that's actually there, so that
+        // there is never any leading or trailing whitespace.
+        let outbound = if outbound.is_empty() {
+            outbound
+        } else {
+            format!("{} ", outbound)
         };
+        let inbound = if inbound.is_empty() {
+            inbound
+        } else {
+            format!(" {}", inbound)
+        };
+
+        write!(f, r#"{}\/{}"#, outbound, inbound)
+    }
+}
+
+#[cfg(feature = "parser")]
+impl FromStr for Strategy {
+    type Err = Error;
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        parse_strategy(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an IPv4 packet carrying a 20-byte TCP segment with the given flags.
+    fn tcp_packet(flags: u8) -> Packet {
+        Packet::new(vec![
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x26, 0xcd, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00,
+            0x50, flags, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+        ])
+    }
+
+    const SYN: u8 = 0x02;
+
+    #[test]
+    fn builder_matches_parsed_strategy() {
+        use crate::actions::{ActionTreeBuilder, DropAction};
+        use crate::triggers::{TCPField, TCPTrigger};
+
+        let flags = |value: &str| TCPTrigger::new(TCPField::Flags, value.to_string(), 0).unwrap();
+        let strategy = StrategyBuilder::new()
+            .outbound(flags("S"), DropAction::default())
+            .tree(
+                Direction::Outbound,
+                ActionTreeBuilder::new(flags("R"))
+                    .action(DropAction::default())
+                    .build(),
+            )
+            .build();
+
+        assert!(strategy.inbound.is_none());
+        assert_eq!(
+            strategy.to_string(),
+            r#"[TCP:flags:S]-drop-| [TCP:flags:R]-drop-| \/"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let s = r#"[TCP:flags:S:2]-duplicate(tamper{TCP:flags:replace:SA},fragment{6:8:False}(drop,))-| \/ [IP:ttl:!64]-drop-| [UDP:dport:53]-tamper{IP:ttl:corrupt}-|"#;
+        let strategy = parse_strategy(s).unwrap();
+
+        let json = serde_json::to_string(&strategy).unwrap();
+        assert!(json.contains(r#""Duplicate""#));
+
+        let decoded: Strategy = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.to_string(), s);
+        assert_eq!(decoded, strategy);
+    }
+
+    #[test]
+    fn canonicalize_equivalent_strategies() {
+        let equivalent = [
+            r#"[TCP:flags:S]-tamper{TCP:flags:add:R}-| \/ [TCP:flags:R]-drop-|"#,
+            r#"[TCP:flags:S]-duplicate(drop,tamper{TCP:flags:add:R})-| \/ [TCP:flags:R]-drop-|"#,
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:add:R},sleep{1}(drop,))-| \/ [TCP:flags:R]-tamper{TCP:window:corrupt}(drop,)-|"#,
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:add:R}(duplicate(,drop),),drop)-| \/ [TCP:flags:R]-duplicate(drop,drop)-|"#,
+        ];
+
+        let canonical = parse_strategy(equivalent[0]).unwrap();
+        assert_eq!(canonical.canonicalize(), canonical);
+
+        for s in equivalent {
+            let strategy = parse_strategy(s).unwrap();
+            assert_eq!(strategy.canonicalize(), canonical, "{}", s);
+
+            // The canonical form must behave the same way.
+            for direction in [Direction::Outbound, Direction::Inbound] {
+                for flags in [SYN, 0x04, 0x10] {
+                    let pkt = tcp_packet(flags);
+                    assert_eq!(
+                        strategy
+                            .canonicalize()
+                            .apply(pkt.clone(), direction)
+                            .unwrap(),
+                        strategy.apply(pkt, direction).unwrap(),
+                        "{} {} {:#x}",
+                        s,
+                        direction,
+                        flags
+                    );
+                }
+            }
+        }
+
+        let empty = Strategy {
+            outbound: Some(vec![]),
+            inbound: None,
+            ..Default::default()
+        };
+        assert_eq!(empty.canonicalize().outbound, None);
+    }
+
+    #[test]
+    fn triggers_cover_both_forests() {
+        use crate::triggers::Trigger;
+
+        let strategy = parse_strategy(
+            r#"[TCP:flags:S]-drop-| [UDP:dport:53]-drop-| \/ [IP:ttl:64]-drop-| [TCP:flags:R:2]-drop-|"#,
+        )
+        .unwrap();
+        let triggers = strategy.triggers();
+        assert_eq!(triggers.len(), 4);
+
+        let protocols: Vec<_> = triggers.iter().map(|t| t.protocol()).collect();
+        assert_eq!(protocols, ["TCP", "UDP", "IP", "TCP"]);
+        assert_eq!(triggers[3].to_string(), "[TCP:flags:R:2]");
+
+        assert!(Strategy::default().triggers().is_empty());
+    }
+
+    #[test]
+    fn identical_strategies_are_equal() {
+        let s =
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/ [TCP:flags:R]-drop-|"#;
+        let a = parse_strategy(s).unwrap();
+        let b = parse_strategy(s).unwrap();
+        assert_eq!(a, b);
+
+        // Running out of gas does not change what the strategy is.
+        let c = parse_strategy(r#"[TCP:flags:S:1]-drop-| \/"#).unwrap();
+        let d = parse_strategy(r#"[TCP:flags:S:1]-drop-| \/"#).unwrap();
+        c.apply(tcp_packet(SYN), Direction::Outbound).unwrap();
+        assert!(c.outbound.as_ref().unwrap()[0].is_exhausted());
+        assert_eq!(c, d);
+    }
+
+    #[test]
+    fn differing_strategies_are_not_equal() {
+        let a = parse_strategy(r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/"#)
+            .unwrap();
+        for s in [
+            r#"[TCP:flags:S]-duplicate(,tamper{TCP:flags:replace:SA})-| \/"#,
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:R},)-| \/"#,
+            r#"[TCP:flags:SA]-duplicate(tamper{TCP:flags:replace:SA},)-| \/"#,
+            r#"[TCP:flags:S:1]-duplicate(tamper{TCP:flags:replace:SA},)-| \/"#,
+            r#"\/ [TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-|"#,
+        ] {
+            assert_ne!(a, parse_strategy(s).unwrap(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn apply_ref_borrows_when_nothing_fires() {
+        let strategy = parse_strategy(r#"[TCP:flags:S]-drop-| \/"#).unwrap();
+        let ack = tcp_packet(0x10);
+
+        let result = strategy.apply_ref(&ack, Direction::Outbound).unwrap();
+        assert!(matches!(result, Cow::Borrowed(_)));
+        assert_eq!(&*result, slice::from_ref(&ack));
+
+        let result = strategy.apply_ref(&ack, Direction::Inbound).unwrap();
+        assert!(matches!(result, Cow::Borrowed(_)));
+
+        let syn = tcp_packet(SYN);
+        let result = strategy.apply_ref(&syn, Direction::Outbound).unwrap();
+        assert!(matches!(result, Cow::Owned(_)));
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn apply_ref_matches_apply() {
+        let strategies = [
+            r#"\/"#,
+            r#"[TCP:flags:S]-drop-| \/"#,
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1024},)-| \/"#,
+            r#"[TCP:flags:S]-drop-| [TCP:flags:A]-duplicate-| \/"#,
+            r#"[TCP:flags:S:1]-tamper{IP:ttl:replace:1}-| [IP:ttl:1]-drop-| \/"#,
+        ];
+        for s in strategies {
+            for flags in [SYN, 0x10, SYN | 0x10] {
+                // Use separate copies of the strategy so that both see the same amount of gas.
+                let a = parse_strategy(s).unwrap();
+                let b = parse_strategy(s).unwrap();
+                for _ in 0..2 {
+                    let pkt = tcp_packet(flags);
+                    let expected = a.apply(pkt.clone(), Direction::Outbound).unwrap();
+                    let actual = b.apply_ref(&pkt, Direction::Outbound).unwrap();
+                    assert_eq!(&*actual, &expected[..], "{} with flags {}", s, flags);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn process_preserves_order() {
+        let strategy: Strategy = r"[TCP:flags:S]-duplicate(tamper{TCP:window:replace:1024},)-| \/"
+            .parse()
+            .unwrap();
+        let (ack, syn, fin) = (tcp_packet(0x10), tcp_packet(SYN), tcp_packet(0x01));
+
+        let result = strategy
+            .process(
+                vec![ack.clone(), syn.clone(), fin.clone()],
+                Direction::Outbound,
+            )
+            .unwrap();
+        assert_eq!(result.len(), 4);
+        assert_eq!(result[0], ack);
+        assert_eq!(&result[1].as_slice()[34..36], &1024u16.to_be_bytes());
+        assert_eq!(result[2], syn);
+        assert_eq!(result[3], fin);
+
+        let result = strategy
+            .process(vec![syn, ack], Direction::Inbound)
+            .unwrap();
+        assert_eq!(result, vec![tcp_packet(SYN), tcp_packet(0x10)]);
+    }
+
+    #[test]
+    fn apply_batch_runs_out_of_gas_partway() {
+        let strategy: Strategy = r"[TCP:flags:S:1]-duplicate-| \/".parse().unwrap();
+        let (ack, syn) = (tcp_packet(0x10), tcp_packet(SYN));
+        let batch = vec![ack.clone(), syn.clone(), syn.clone()];
+        let result = strategy.apply_batch(batch, Direction::Outbound).unwrap();
+        assert_eq!(result, vec![ack, syn.clone(), syn.clone(), syn]);
+    }
+
+    #[test]
+    fn process_shares_gas_across_the_batch() {
+        let strategy: Strategy = r"[TCP:flags:S:2]-drop-| \/".parse().unwrap();
+        let (ack, syn) = (tcp_packet(0x10), tcp_packet(SYN));
+
+        // The tree runs out of gas on the third SYN, which passes through along with the rest.
+        let result = strategy
+            .process(
+                vec![
+                    syn.clone(),
+                    ack.clone(),
+                    syn.clone(),
+                    syn.clone(),
+                    ack.clone(),
+                ],
+                Direction::Outbound,
+            )
+            .unwrap();
+        assert_eq!(result, vec![ack.clone(), syn.clone(), ack.clone()]);
+
+        // The next batch picks up where the last one left off.
+        let result = strategy
+            .process(vec![syn.clone()], Direction::Outbound)
+            .unwrap();
+        assert_eq!(result, vec![syn.clone()]);
+
+        // A batch can also run out partway through, after a single apply used some of the gas.
+        let strategy: Strategy = r"[TCP:flags:S:3]-duplicate-| \/".parse().unwrap();
+        assert_eq!(
+            strategy
+                .apply(syn.clone(), Direction::Outbound)
+                .unwrap()
+                .len(),
+            2
+        );
+        let result = strategy
+            .process(vec![syn.clone(); 4], Direction::Outbound)
+            .unwrap();
+        assert_eq!(result.len(), 2 + 2 + 1 + 1);
+    }
+
+    #[test]
+    fn apply_all_runs_both_forests() {
+        let strategy: Strategy =
+            r"[TCP:flags:S]-duplicate-| \/ [TCP:flags:S]-tamper{TCP:flags:add:A}-|"
+                .parse()
+                .unwrap();
+
+        let result = strategy.apply_all(tcp_packet(SYN)).unwrap();
+        assert_eq!(result.len(), 2);
+        for pkt in &result {
+            assert_eq!(pkt.tcp_header().unwrap()[13], SYN | 0x10);
+        }
+
+        let ack = tcp_packet(0x10);
+        assert_eq!(strategy.apply_all(ack.clone()).unwrap(), vec![ack]);
+    }
+
+    #[test]
+    fn max_fanout_sums_the_forest() {
+        let strategy: Strategy = r"[TCP:flags:S]-duplicate(fragment{6:8:True},)-| [TCP:flags:S]-drop-| [TCP:flags:R]-duplicate(drop,drop)-| \/"
+            .parse()
+            .unwrap();
+        assert_eq!(strategy.max_fanout(Direction::Outbound), Some(5));
+        assert_eq!(strategy.max_fanout(Direction::Inbound), Some(1));
+
+        // The SYN has no payload to fragment, so it stays under the bound.
+        let result = strategy
+            .apply(tcp_packet(SYN), Direction::Outbound)
+            .unwrap();
+        assert_eq!(result.len(), 3);
+    }
+
+    #[test]
+    fn empty_builder() {
+        let strategy = StrategyBuilder::new().build();
+        assert!(strategy.outbound.is_none());
+        assert!(strategy.inbound.is_none());
+    }
+
+    #[test]
+    fn gas_limits_firings() {
+        let strategy = parse_strategy(r#"[TCP:flags:S:2]-drop-| \/"#).unwrap();
+        let pkt = tcp_packet(SYN);
+
+        for _ in 0..2 {
+            let result = strategy.apply(pkt.clone(), Direction::Outbound).unwrap();
+            assert!(result.is_empty());
+        }
+
+        let result = strategy.apply(pkt.clone(), Direction::Outbound).unwrap();
+        assert_eq!(result, vec![pkt]);
+    }
+
+    #[test]
+    fn canonicalize_keeps_the_gas_left() {
+        let strategy = parse_strategy(r#"[TCP:flags:S:2]-duplicate(drop,drop)-| \/"#).unwrap();
+        let pkt = tcp_packet(SYN);
+        strategy.apply(pkt.clone(), Direction::Outbound).unwrap();
+
+        let canonical = strategy.canonicalize();
+        assert!(canonical
+            .apply(pkt.clone(), Direction::Outbound)
+            .unwrap()
+            .is_empty());
+        let result = canonical.apply(pkt.clone(), Direction::Outbound).unwrap();
+        assert_eq!(result, vec![pkt]);
+    }
+
+    #[test]
+    fn gas_is_kept_per_direction() {
+        let pkt = tcp_packet(SYN);
+        let mut strategy =
+            parse_strategy(r#"[TCP:flags:S:2]-drop-| \/ [TCP:flags:S:1]-drop-|"#).unwrap();
+        // The same tree, merged into the inbound forest as well.
+        let tree = strategy.outbound.as_ref().unwrap()[0].clone();
+        strategy.merge(
+            StrategyBuilder::new()
+                .tree(Direction::Inbound, tree)
+                .build(),
+        );
+
+        // Using up the outbound gas leaves the inbound trees alone.
+        for _ in 0..2 {
+            assert!(strategy
+                .apply(pkt.clone(), Direction::Outbound)
+                .unwrap()
+                .is_empty());
+        }
+        assert_eq!(
+            strategy.apply(pkt.clone(), Direction::Outbound).unwrap(),
+            vec![pkt.clone()]
+        );
+
+        // Each inbound tree drops one copy of the packet until it runs out of gas.
+        let inbound =
+            |strategy: &Strategy| strategy.apply(pkt.clone(), Direction::Inbound).unwrap();
+        assert!(inbound(&strategy).is_empty());
+        assert_eq!(inbound(&strategy), vec![pkt.clone()]);
+        assert_eq!(inbound(&strategy), vec![pkt.clone(), pkt.clone()]);
+        assert!(strategy
+            .inbound
+            .iter()
+            .flatten()
+            .all(ActionTree::is_exhausted));
+    }
+
+    #[test]
+    fn explain_reports_each_tree() {
+        let strategy = parse_strategy(
+            r#"[TCP:flags:S:1]-duplicate(,tamper{TCP:flags:add:R})-| [TCP:flags:R]-drop-| [TCP:flags:S]-send-| \/"#,
+        )
+        .unwrap();
+        let pkt = tcp_packet(SYN);
+        let report = strategy.explain(&pkt, Direction::Outbound);
+        assert_eq!(
+            report,
+            vec![
+                MatchReport {
+                    trigger: "[TCP:flags:S:1]".to_string(),
+                    matched: true,
+                    action: "duplicate(,tamper{TCP:flags:add:R})".to_string(),
+                },
+                MatchReport {
+                    trigger: "[TCP:flags:R]".to_string(),
+                    matched: false,
+                    action: "drop".to_string(),
+                },
+                MatchReport {
+                    trigger: "[TCP:flags:S]".to_string(),
+                    matched: true,
+                    action: "send".to_string(),
+                },
+            ]
+        );
+
+        // Explaining uses no gas, and a tree without gas left doesn't match.
+        assert_eq!(strategy.explain(&pkt, Direction::Outbound), report);
+        strategy.apply(pkt.clone(), Direction::Outbound).unwrap();
+        assert!(!strategy.explain(&pkt, Direction::Outbound)[0].matched);
+        assert!(strategy.explain(&pkt, Direction::Inbound).is_empty());
+    }
+
+    #[test]
+    fn zero_gas_is_unlimited() {
+        let strategy = parse_strategy(r#"[TCP:flags:S]-drop-| \/"#).unwrap();
+        for _ in 0..10 {
+            let result = strategy
+                .apply(tcp_packet(SYN), Direction::Outbound)
+                .unwrap();
+            assert!(result.is_empty());
+        }
+    }
+
+    #[test]
+    fn non_matching_packets_do_not_consume_gas() {
+        let strategy = parse_strategy(r#"[TCP:flags:S:1]-drop-| \/"#).unwrap();
+        let ack = tcp_packet(0x10);
+
+        for _ in 0..3 {
+            let result = strategy.apply(ack.clone(), Direction::Outbound).unwrap();
+            assert_eq!(result, vec![ack.clone()]);
+        }
+
+        let result = strategy
+            .apply(tcp_packet(SYN), Direction::Outbound)
+            .unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn from_str_round_trip() {
+        let s =
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},)-| \/ [TCP:flags:R]-drop-|"#;
+        let strategy: Strategy = s.parse().unwrap();
+        assert_eq!(strategy.to_string(), s);
+    }
+
+    #[test]
+    fn from_str_error() {
+        assert!("not a strategy".parse::<Strategy>().is_err());
+    }
+
+    #[test]
+    fn display_empty() {
+        assert_eq!(Strategy::default().to_string(), r#"\/"#);
+
+        let strategy = Strategy {
+            outbound: Some(vec![]),
+            inbound: Some(vec![]),
+            ..Default::default()
+        };
+        assert_eq!(strategy.to_string(), r#"\/"#);
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in [
+            r#"\/"#,
+            r#"[TCP:flags:S]-drop-| \/"#,
+            r#"\/ [TCP:flags:R]-drop-|"#,
+            r#"[TCP:flags:S]-drop-| \/ [TCP:flags:R]-drop-|"#,
+            r#"[TCP:flags:S]-drop-| [TCP:flags:SA]-duplicate-| \/ [TCP:flags:R]-drop-| [IP:ttl:1]-drop-|"#,
+        ] {
+            let strategy = parse_strategy(s).unwrap();
+            assert_eq!(strategy.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn packet_count_is_limited() {
+        // Eleven levels of duplicates turn one packet into 2048.
+        let mut action = "duplicate".to_string();
+        for _ in 0..10 {
+            action = format!("duplicate({},{})", action, action);
+        }
+        let mut strategy = parse_strategy(&format!(r"[TCP:flags:S:1]-{}-| \/", action)).unwrap();
+        assert!(matches!(
+            strategy.apply(tcp_packet(SYN), Direction::Outbound),
+            Err(Error::LimitExceeded(_))
+        ));
+        // The limit was caught before the tree ran, so its gas is untouched.
+        assert!(!strategy.outbound.as_ref().unwrap()[0].is_exhausted());
+
+        strategy.limits.max_packets = 2048;
+        let packets = strategy
+            .apply(tcp_packet(SYN), Direction::Outbound)
+            .unwrap();
+        assert_eq!(packets.len(), 2048);
+
+        // Packets that pass through unmatched count towards the limit too.
+        let mut strategy =
+            parse_strategy(r"[TCP:flags:S]-duplicate-| [TCP:flags:R]-drop-| \/").unwrap();
+        strategy.limits.max_packets = 2;
+        assert!(strategy
+            .apply(tcp_packet(SYN), Direction::Outbound)
+            .is_err());
+    }
+
+    #[test]
+    fn fragment_size_is_limited() {
+        let strategy = parse_strategy(r"[TCP:flags:S]-fragment{tcp:0:True}-| \/").unwrap();
+        assert!(matches!(
+            strategy.apply(tcp_packet(SYN), Direction::Outbound),
+            Err(Error::LimitExceeded(_))
+        ));
+
+        let limits = Limits {
+            min_fragment_size: 8,
+            ..Default::default()
+        };
+        let strategy = StrategyBuilder::new().limits(limits).build();
+        assert_eq!(strategy.limits, limits);
+        let mut strategy = Strategy {
+            limits,
+            ..parse_strategy(r"[TCP:flags:S]-duplicate(,fragment{tcp:4:True})-| \/").unwrap()
+        };
+        assert!(strategy
+            .apply(tcp_packet(SYN), Direction::Outbound)
+            .is_err());
+        strategy.limits.min_fragment_size = 4;
+        assert!(strategy.apply(tcp_packet(SYN), Direction::Outbound).is_ok());
+    }
+
+    #[test]
+    fn merge_concatenates_forests() {
+        let mut strategy = parse_strategy(r#"[TCP:flags:S]-drop-| \/"#).unwrap();
+        strategy.merge(parse_strategy(r#"\/ [TCP:flags:R]-drop-|"#).unwrap());
+        let s = r#"[TCP:flags:S]-drop-| \/ [TCP:flags:R]-drop-|"#;
+        assert_eq!(strategy.to_string(), s);
+        assert_eq!(parse_strategy(s).unwrap(), strategy);
+
+        strategy
+            .merge(parse_strategy(r#"[IP:ttl:1]-drop-| \/ [TCP:flags:SA]-duplicate-|"#).unwrap());
+        assert_eq!(
+            strategy.to_string(),
+            r#"[TCP:flags:S]-drop-| [IP:ttl:1]-drop-| \/ [TCP:flags:R]-drop-| [TCP:flags:SA]-duplicate-|"#
+        );
+
+        let mut empty = Strategy::default();
+        empty.merge(Strategy::default());
+        assert_eq!(empty, Strategy::default());
+        empty.merge(strategy);
+        assert_eq!(empty.triggers().len(), 4);
+    }
+
+    #[test]
+    fn display_joins_trees_with_spaces() {
+        let strategy = parse_strategy(r#"[TCP:flags:S]-drop-|[TCP:flags:R]-drop-|\/"#).unwrap();
+        assert_eq!(
+            strategy.to_string(),
+            r#"[TCP:flags:S]-drop-| [TCP:flags:R]-drop-| \/"#
+        );
+    }
+
+    #[test]
+    fn validate_checks_every_tree() {
+        for s in [
+            r#"\/"#,
+            r#"[TCP:flags:S]-duplicate(tamper{TCP:flags:replace:SA},fragment{tcp:8:True})-| \/ [IP:ttl:<64]-tamper{IP:src:replace:192.0.2.1}-|"#,
+            r#"[TCP:flags:PA]-tamper{TCP:load:corrupt}(tamper{TCP:dataofs:replace:15},)-| \/"#,
+            r#"[UDP:dport:53]-tamper{DNS:qd:compress}-| \/"#,
+            r#"[TCP:flags:S]-drop-| [TCP:flags:R]-tamper{IP:ttl:corrupt}-| \/"#,
+        ] {
+            let strategy = parse_strategy(s).unwrap();
+            assert!(strategy.validate().is_ok(), "{}", s);
+        }
+
+        for (s, message) in [
+            (
+                r#"[TCP:flags:S]-tamper{TCP:seq:clear:1}-| \/"#,
+                "TCP:seq does not support clear",
+            ),
+            (
+                r#"[TCP:flags:S]-drop-| \/ [TCP:flags:S]-duplicate(,tamper{TCP:nope:replace:1})-|"#,
+                "TCP:nope",
+            ),
+            (
+                r#"[TCP:flags:S]-tamper{TCP:flags:replace:X}-| \/"#,
+                "TCP:flags",
+            ),
+            (
+                r#"[TCP:flags:S]-tamper{TCP:dataofs:replace:16}-| \/"#,
+                "TCP:dataofs",
+            ),
+            (r#"[TCP:flags:S]-tamper{IP:ttl:replace:256}-| \/"#, "IP:ttl"),
+            (
+                r#"[TCP:flags:S]-fragment{tcp:0:True}-| \/"#,
+                "fragment:offset",
+            ),
+            (
+                r#"[UDP:dport:53]-fragment{udp:12:True}-| \/"#,
+                "fragment:offset",
+            ),
+            (
+                r#"[TCP:flags:S]-fragment{ip:7:True}-| \/"#,
+                "fragment:offset",
+            ),
+            (
+                r#"[TCP:flags:S]-sleep{1}(tamper{DNS:qd:replace:1},)-| \/"#,
+                "DNS:qd does not support replace",
+            ),
+        ] {
+            let error = parse_strategy(s).unwrap().validate().unwrap_err();
+            assert!(error.to_string().contains(message), "{}: {}", s, error);
+        }
+
+        let mut strategy = parse_strategy(r#"[TCP:flags:S]-fragment{tcp:8:True}-| \/"#).unwrap();
+        strategy.limits.min_fragment_size = 16;
+        assert!(matches!(strategy.validate(), Err(Error::LimitExceeded(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn validate_checks_deserialized_triggers() {
+        let strategy = parse_strategy(r#"[TCP:sport:80]-drop-| \/"#).unwrap();
+        let json = serde_json::to_string(&strategy).unwrap();
+        assert!(json.contains(r#""value":"80""#));
+
+        let decoded: Strategy = serde_json::from_str(&json.replace("80", "70000")).unwrap();
+        assert!(matches!(
+            decoded.validate(),
+            Err(Error::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn prune_dead_keeps_live_trees() {
+        let s = r#"[TCP:flags:S]-drop-| [IP:ttl:!64]-drop-| \/ [TCP:flags:R]-drop-|"#;
+        let mut strategy = parse_strategy(s).unwrap();
+        assert_eq!(strategy.prune_dead(), 0);
+        assert_eq!(strategy.to_string(), s);
+
+        let mut strategy = parse_strategy(r#"\/"#).unwrap();
+        assert_eq!(strategy.prune_dead(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn prune_dead_removes_impossible_triggers() {
+        let strategy =
+            parse_strategy(r#"[IP:ttl:64]-drop-| [TCP:sport:80]-drop-| \/ [IP:ttl:!64]-drop-|"#)
+                .unwrap();
+        let json = serde_json::to_string(&strategy).unwrap();
+        let mut decoded: Strategy = serde_json::from_str(&json.replace("64", "300")).unwrap();
+
+        // A TTL can never be 300, but it is never not 300 either.
+        assert_eq!(decoded.prune_dead(), 1);
+        assert_eq!(
+            decoded.to_string(),
+            r#"[TCP:sport:80]-drop-| \/ [IP:ttl:!300]-drop-|"#
+        );
+        assert_eq!(decoded.prune_dead(), 0);
+        assert!(decoded.inbound.as_ref().unwrap()[0].matches(&tcp_packet(SYN)));
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let original = parse_strategy(r#"[TCP:flags:S:1]-drop-| \/ [TCP:flags:R]-drop-|"#).unwrap();
+        let mut copy = original.clone();
+        assert_eq!(copy, original);
+
+        *copy.outbound.as_mut().unwrap()[0].root_action =
+            crate::actions::SendAction::default().into();
+        copy.inbound = None;
+        copy.limits.max_packets = 1;
+        assert_eq!(
+            original.to_string(),
+            r#"[TCP:flags:S:1]-drop-| \/ [TCP:flags:R]-drop-|"#
+        );
+        assert_eq!(original.limits, Limits::default());
+
+        // Using up the copy's gas leaves the original's alone.
+        let syn = tcp_packet(SYN);
+        assert_eq!(
+            copy.apply(syn.clone(), Direction::Outbound).unwrap().len(),
+            1
+        );
+        assert!(!copy.explain(&syn, Direction::Outbound)[0].matched);
+        assert!(original.explain(&syn, Direction::Outbound)[0].matched);
+    }
+
+    #[test]
+    fn apply_traced_reports_each_tree() {
+        let strategy = parse_strategy(
+            r#"[TCP:flags:R]-drop-| [TCP:flags:S]-duplicate(,tamper{TCP:flags:add:R})-| [TCP:flags:S]-drop-| [TCP:flags:S]-tamper{TCP:window:replace:1}-| \/"#,
+        )
+        .unwrap();
+        let syn = tcp_packet(SYN);
+        let traced = strategy
+            .apply_traced(syn.clone(), Direction::Outbound)
+            .unwrap();
+        let sources: Vec<_> = traced.iter().map(|(_, source)| *source).collect();
+        assert_eq!(sources, [None, Some(1), Some(1), Some(3)]);
+
+        // The packets are the ones that apply yields, in the same order.
+        let packets: Vec<_> = traced.into_iter().map(|(pkt, _)| pkt).collect();
+        assert_eq!(packets[0], syn);
+        assert_eq!(packets[1], syn);
+        assert_eq!(packets[2].tcp_header().unwrap()[13], SYN | 0x04);
+        assert_eq!(&packets[3].tcp_header().unwrap()[14..16], &[0, 1]);
+        assert_eq!(
+            packets,
+            strategy.apply(syn.clone(), Direction::Outbound).unwrap()
+        );
 
-        write!(f, r#"{} \/{}"#, outbound, inbound)
+        // With no trees for the direction, the packet passes through untouched.
+        assert_eq!(
+            strategy
+                .apply_traced(syn.clone(), Direction::Inbound)
+                .unwrap(),
+            vec![(syn, None)]
+        );
     }
 }
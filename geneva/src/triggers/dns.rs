@@ -0,0 +1,401 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::dns::{read_name, udp_message, HEADER_LEN};
+use crate::errors::*;
+use crate::prelude::*;
+use crate::triggers::{invalid_value, not_comparable, numeric_at_most, Comparison, Trigger};
+use crate::Packet;
+
+/// Supported fields of a DNS message that can be used for triggers.
+///
+/// The header fields come from the fixed DNS header, while the `Q*` fields come from the first
+/// entry in the question section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DNSField {
+    ID,
+    QR,
+    Opcode,
+    AA,
+    TC,
+    RD,
+    RA,
+    RCode,
+    QDCount,
+    QName,
+    QType,
+    QClass,
+}
+
+impl fmt::Display for DNSField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DNSField::*;
+        match self {
+            ID => "id",
+            QR => "qr",
+            Opcode => "opcode",
+            AA => "aa",
+            TC => "tc",
+            RD => "rd",
+            RA => "ra",
+            RCode => "rcode",
+            QDCount => "qdcount",
+            QName => "qname",
+            QType => "qtype",
+            QClass => "qclass",
+        }
+        .fmt(f)
+    }
+}
+
+impl FromStr for DNSField {
+    type Err = Error;
+
+    /// Parses a field name, ignoring case.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        use DNSField::*;
+        match s.to_ascii_lowercase().as_str() {
+            "id" => Ok(ID),
+            "qr" => Ok(QR),
+            "opcode" => Ok(Opcode),
+            "aa" => Ok(AA),
+            "tc" => Ok(TC),
+            "rd" => Ok(RD),
+            "ra" => Ok(RA),
+            "rcode" => Ok(RCode),
+            "qdcount" => Ok(QDCount),
+            "qname" => Ok(QName),
+            "qtype" => Ok(QType),
+            "qclass" => Ok(QClass),
+            _ => Err(Error::UnknownField {
+                protocol: "DNS".to_string(),
+                field: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Returns `true` if `field` holds a number, and so can be used with a [Comparison].
+fn is_numeric(field: &DNSField) -> bool {
+    *field != DNSField::QName
+}
+
+/// Returns the number for a query type, which may be given either as a number or as one of the
+/// common mnemonics (such as `AAAA`).
+fn qtype_number(value: &str) -> Option<u16> {
+    let number = match value.to_ascii_uppercase().as_str() {
+        "A" => 1,
+        "NS" => 2,
+        "CNAME" => 5,
+        "SOA" => 6,
+        "PTR" => 12,
+        "MX" => 15,
+        "TXT" => 16,
+        "AAAA" => 28,
+        "SRV" => 33,
+        "HTTPS" => 65,
+        "ANY" => 255,
+        _ => return value.parse().ok(),
+    };
+    Some(number)
+}
+
+/// A [Trigger] that matches on a DNS message carried by a UDP datagram to or from port 53.
+///
+/// A `qname` value is compared to the first question's name without regard to case or a
+/// trailing dot, so `[DNS:qname:example.com]` matches a query for `Example.COM.`. A `qtype`
+/// value may be a number or a mnemonic such as `A` or `AAAA`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DNSTrigger {
+    field: DNSField,
+    value: String,
+    gas: usize,
+    negate: bool,
+    comparison: Comparison,
+}
+
+impl DNSTrigger {
+    /// Creates a new `DNSTrigger`.
+    ///
+    /// Returns an error if `value` could never match `field`, such as an opcode larger than 15.
+    /// Every field needs a value.
+    pub fn new(field: DNSField, value: String, gas: usize) -> Result<Self> {
+        use DNSField::*;
+        let valid = !value.is_empty()
+            && match field {
+                QR | AA | TC | RD | RA => numeric_at_most(&value, 1u8),
+                Opcode | RCode => numeric_at_most(&value, 15u8),
+                ID | QDCount | QClass => value.parse::<u16>().is_ok(),
+                QType => qtype_number(&value).is_some(),
+                QName => true,
+            };
+        if !valid {
+            return Err(invalid_value("DNS", field, &value));
+        }
+
+        Ok(Self {
+            field,
+            value,
+            gas,
+            negate: false,
+            comparison: Comparison::Equal,
+        })
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Inverts the trigger, so that it matches DNS messages whose field does *not* match the
+    /// value.
+    pub fn negated(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    /// Returns `true` if the trigger matches packets whose field does not match the value.
+    pub fn is_negated(&self) -> bool {
+        self.negate
+    }
+
+    /// Sets how the field is compared to the value. Returns an error if the field isn't a number,
+    /// unless `comparison` is [Comparison::Equal].
+    pub fn with_comparison(mut self, comparison: Comparison) -> Result<Self> {
+        let field = &self.field;
+        if comparison != Comparison::Equal && !is_numeric(field) {
+            return Err(not_comparable("DNS", field, comparison));
+        }
+        self.comparison = comparison;
+        Ok(self)
+    }
+
+    /// Returns how the field is compared to the value.
+    pub fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+
+    /// Checks that a header field's value fits its width and that a `qtype` names a query type,
+    /// and that only numeric fields are compared.
+    /// See [GenevaTrigger::validate](super::GenevaTrigger::validate).
+    pub(crate) fn validate(&self) -> Result<()> {
+        Self::new(self.field.clone(), self.value.clone(), self.gas)?
+            .with_comparison(self.comparison)?;
+        Ok(())
+    }
+
+    /// Returns whether the first question of `msg` matches, or `None` if there is no question.
+    fn matches_question(&self, msg: &[u8]) -> Option<bool> {
+        if u16::from_be_bytes([msg[4], msg[5]]) == 0 {
+            return None;
+        }
+
+        let (labels, end) = read_name(msg, HEADER_LEN)?;
+        let u16_at = |i: usize| Some(u16::from_be_bytes([*msg.get(i)?, *msg.get(i + 1)?]));
+
+        let matched = match self.field {
+            DNSField::QName => {
+                let name = labels
+                    .iter()
+                    .map(|label| String::from_utf8_lossy(label))
+                    .collect::<Vec<_>>()
+                    .join(".");
+                name.eq_ignore_ascii_case(self.value.trim_end_matches('.'))
+            }
+            DNSField::QType => {
+                let value = qtype_number(&self.value)?.to_string();
+                self.comparison.matches(u16_at(end)?, &value)
+            }
+            _ => self.comparison.matches(u16_at(end + 2)?, &self.value),
+        };
+        Some(matched)
+    }
+}
+
+impl Trigger for DNSTrigger {
+    fn protocol(&self) -> String {
+        "DNS".to_string()
+    }
+
+    fn field(&self) -> String {
+        self.field.to_string()
+    }
+
+    fn gas(&self) -> usize {
+        self.gas
+    }
+
+    fn matches(&self, pkt: &Packet) -> bool {
+        let msg = match udp_message(pkt) {
+            Some(msg) => msg,
+            None => return false,
+        };
+
+        let u16_at = |i: usize| u16::from_be_bytes([msg[i], msg[i + 1]]);
+        let bit = |byte: usize, shift: u8| (msg[byte] >> shift) & 1;
+
+        use DNSField::*;
+        let matched = match self.field {
+            ID => self.comparison.matches(u16_at(0), &self.value),
+            QR => self.comparison.matches(bit(2, 7), &self.value),
+            Opcode => self.comparison.matches((msg[2] >> 3) & 0x0f, &self.value),
+            AA => self.comparison.matches(bit(2, 2), &self.value),
+            TC => self.comparison.matches(bit(2, 1), &self.value),
+            RD => self.comparison.matches(bit(2, 0), &self.value),
+            RA => self.comparison.matches(bit(3, 7), &self.value),
+            RCode => self.comparison.matches(msg[3] & 0x0f, &self.value),
+            QDCount => self.comparison.matches(u16_at(4), &self.value),
+            QName | QType | QClass => match self.matches_question(msg) {
+                Some(matched) => matched,
+                None => return false,
+            },
+        };
+
+        // A negated trigger still only applies to packets that have the layer in question.
+        matched != self.negate
+    }
+}
+
+impl fmt::Display for DNSTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gas = if self.gas > 0 {
+            format!(":{}", self.gas)
+        } else {
+            "".to_string()
+        };
+        write!(
+            f,
+            "[{}:{}:{}{}{}{}]",
+            self.protocol(),
+            self.field,
+            if self.negate { "!" } else { "" },
+            self.comparison,
+            self.value,
+            gas
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::compress_question;
+
+    /// A query for `www.example.com` (type A, class IN) with the RD bit set.
+    fn query() -> Vec<u8> {
+        let mut msg = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(b"\x03www\x07example\x03com\x00");
+        msg.extend_from_slice(&[0, 1, 0, 1]);
+        msg
+    }
+
+    /// Builds an IPv4 packet carrying a UDP datagram with the given ports and payload.
+    fn udp_packet(sport: u16, dport: u16, payload: &[u8]) -> Packet {
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        bytes.extend_from_slice(&sport.to_be_bytes());
+        bytes.extend_from_slice(&dport.to_be_bytes());
+        bytes.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(payload);
+        let len = bytes.len() as u16;
+        bytes[2..4].copy_from_slice(&len.to_be_bytes());
+        Packet::new(bytes)
+    }
+
+    fn trigger(field: DNSField, value: &str) -> DNSTrigger {
+        DNSTrigger::new(field, value.to_string(), 0).unwrap()
+    }
+
+    #[test]
+    fn dns_str() {
+        let t = trigger(DNSField::QName, "example.com");
+        assert_eq!(t.to_string(), "[DNS:qname:example.com]");
+    }
+
+    #[test]
+    fn qname_matches() {
+        let pkt = udp_packet(40000, 53, &query());
+        assert!(trigger(DNSField::QName, "www.example.com").matches(&pkt));
+        assert!(trigger(DNSField::QName, "WWW.Example.com.").matches(&pkt));
+        assert!(!trigger(DNSField::QName, "example.com").matches(&pkt));
+        assert!(trigger(DNSField::QName, "example.com")
+            .negated()
+            .matches(&pkt));
+
+        // Responses come from port 53, and a compressed question still has the same name.
+        let compressed = compress_question(&query()).unwrap();
+        let pkt = udp_packet(53, 40000, &compressed);
+        assert!(trigger(DNSField::QName, "www.example.com").matches(&pkt));
+        assert!(trigger(DNSField::QType, "A").matches(&pkt));
+    }
+
+    #[test]
+    fn qtype_and_qclass_match() {
+        let pkt = udp_packet(40000, 53, &query());
+        assert!(trigger(DNSField::QType, "1").matches(&pkt));
+        assert!(trigger(DNSField::QType, "a").matches(&pkt));
+        assert!(!trigger(DNSField::QType, "AAAA").matches(&pkt));
+        assert!(trigger(DNSField::QClass, "1").matches(&pkt));
+        assert!(trigger(DNSField::QType, "5")
+            .with_comparison(Comparison::Less)
+            .unwrap()
+            .matches(&pkt));
+    }
+
+    #[test]
+    fn header_fields_match() {
+        let pkt = udp_packet(40000, 53, &query());
+        assert!(trigger(DNSField::ID, "4660").matches(&pkt));
+        assert!(trigger(DNSField::QR, "0").matches(&pkt));
+        assert!(trigger(DNSField::RD, "1").matches(&pkt));
+        assert!(trigger(DNSField::RA, "0").matches(&pkt));
+        assert!(trigger(DNSField::Opcode, "0").matches(&pkt));
+        assert!(trigger(DNSField::RCode, "0").matches(&pkt));
+        assert!(trigger(DNSField::QDCount, "1").matches(&pkt));
+    }
+
+    #[test]
+    fn non_dns_packets_do_not_match() {
+        let t = trigger(DNSField::QName, "www.example.com");
+        assert!(!t.matches(&udp_packet(40000, 80, &query())));
+        assert!(!t.matches(&udp_packet(40000, 53, &query()[..8])));
+        assert!(!t
+            .clone()
+            .negated()
+            .matches(&udp_packet(40000, 80, &query())));
+
+        // Without a question, only the header fields can match.
+        let mut msg = query();
+        msg[5] = 0;
+        let pkt = udp_packet(40000, 53, &msg);
+        assert!(!t.matches(&pkt));
+        assert!(!t.negated().matches(&pkt));
+        assert!(trigger(DNSField::QDCount, "0").matches(&pkt));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (field, value) in [
+            (DNSField::QR, "2"),
+            (DNSField::Opcode, "16"),
+            (DNSField::ID, "65536"),
+            (DNSField::QType, "BOGUS"),
+        ] {
+            let result = DNSTrigger::new(field.clone(), value.to_string(), 0);
+            assert!(
+                matches!(result, Err(Error::InvalidValue { .. })),
+                "{}:{}",
+                field,
+                value
+            );
+        }
+
+        assert!(trigger(DNSField::QName, "example.com")
+            .with_comparison(Comparison::Less)
+            .is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::encoding::decode_bytes;
+use crate::errors::*;
+use crate::prelude::*;
+use crate::triggers::{invalid_value, not_comparable, Comparison, Trigger};
+use crate::Packet;
+
+/// Supported fields in the ICMP header that can be used for triggers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ICMPField {
+    Type,
+    Code,
+    Checksum,
+    Payload,
+}
+
+impl fmt::Display for ICMPField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ICMPField::*;
+        match self {
+            Type => "type",
+            Code => "code",
+            Checksum => "chksum",
+            Payload => "load",
+        }
+        .fmt(f)
+    }
+}
+
+impl FromStr for ICMPField {
+    type Err = Error;
+
+    /// Parses a field name, ignoring case.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        use ICMPField::*;
+        match s.to_ascii_lowercase().as_str() {
+            "type" => Ok(Type),
+            "code" => Ok(Code),
+            "chksum" => Ok(Checksum),
+            "load" => Ok(Payload),
+            _ => Err(Error::UnknownField {
+                protocol: "ICMP".to_string(),
+                field: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Returns `true` if `field` holds a number, and so can be used with a [Comparison].
+fn is_numeric(field: &ICMPField) -> bool {
+    *field != ICMPField::Payload
+}
+
+/// A [Trigger] that matches on the ICMP layer of an IPv4 packet.
+///
+/// The payload of an ICMP message is everything after the first eight bytes of its header; for
+/// instance, the identifier and sequence number of an echo request are not part of the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ICMPTrigger {
+    field: ICMPField,
+    value: String,
+    gas: usize,
+    negate: bool,
+    comparison: Comparison,
+}
+
+impl ICMPTrigger {
+    /// Creates a new `ICMPTrigger`.
+    ///
+    /// Returns an error if `value` could never match `field`, such as a type larger than 255.
+    /// Every field needs a value.
+    pub fn new(field: ICMPField, value: String, gas: usize) -> Result<Self> {
+        use ICMPField::*;
+        let valid = !value.is_empty()
+            && match field {
+                Type | Code => value.parse::<u8>().is_ok(),
+                Checksum => value.parse::<u16>().is_ok(),
+                Payload => decode_bytes(&value).is_some(),
+            };
+        if !valid {
+            return Err(invalid_value("ICMP", field, &value));
+        }
+
+        Ok(Self {
+            field,
+            value,
+            gas,
+            negate: false,
+            comparison: Comparison::Equal,
+        })
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Inverts the trigger, so that it matches ICMP packets whose field does *not* match the value.
+    pub fn negated(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    /// Returns `true` if the trigger matches packets whose field does not match the value.
+    pub fn is_negated(&self) -> bool {
+        self.negate
+    }
+
+    /// Sets how the field is compared to the value. Returns an error if the field isn't a number,
+    /// unless `comparison` is [Comparison::Equal].
+    pub fn with_comparison(mut self, comparison: Comparison) -> Result<Self> {
+        let field = &self.field;
+        if comparison != Comparison::Equal && !is_numeric(field) {
+            return Err(not_comparable("ICMP", field, comparison));
+        }
+        self.comparison = comparison;
+        Ok(self)
+    }
+
+    /// Returns how the field is compared to the value.
+    pub fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+
+    /// Checks that the type, code, or checksum fits its field and that a `load` value decodes,
+    /// and that `load` isn't compared.
+    /// See [GenevaTrigger::validate](super::GenevaTrigger::validate).
+    pub(crate) fn validate(&self) -> Result<()> {
+        Self::new(self.field.clone(), self.value.clone(), self.gas)?
+            .with_comparison(self.comparison)?;
+        Ok(())
+    }
+}
+
+impl Trigger for ICMPTrigger {
+    fn protocol(&self) -> String {
+        "ICMP".to_string()
+    }
+
+    fn field(&self) -> String {
+        self.field.to_string()
+    }
+
+    fn gas(&self) -> usize {
+        self.gas
+    }
+
+    fn matches(&self, pkt: &Packet) -> bool {
+        let (header, payload) = match (pkt.icmp_header(), pkt.payload()) {
+            (Some(header), Some(payload)) => (header, payload),
+            _ => return false,
+        };
+
+        use ICMPField::*;
+        let matched = match self.field {
+            Type => self.comparison.matches(header[0], &self.value),
+            Code => self.comparison.matches(header[1], &self.value),
+            Checksum => self
+                .comparison
+                .matches(u16::from_be_bytes([header[2], header[3]]), &self.value),
+            Payload => decode_bytes(&self.value).is_some_and(|value| payload == &*value),
+        };
+
+        // A negated trigger still only applies to packets that have the layer in question.
+        matched != self.negate
+    }
+}
+
+impl fmt::Display for ICMPTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gas = if self.gas > 0 {
+            format!(":{}", self.gas)
+        } else {
+            "".to_string()
+        };
+        write!(
+            f,
+            "[{}:{}:{}{}{}{}]",
+            self.protocol(),
+            self.field,
+            if self.negate { "!" } else { "" },
+            self.comparison,
+            self.value,
+            gas
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an IPv4 packet from 10.0.0.1 to 10.0.0.2 carrying an ICMP message with the given
+    /// type and code, an identifier of 1, a sequence number of 1, and the given payload.
+    fn icmp_packet(icmp_type: u8, code: u8, payload: &[u8]) -> Packet {
+        let total_len = (28 + payload.len()) as u16;
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x01, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(&[icmp_type, code, 0x12, 0x34, 0, 1, 0, 1]);
+        bytes.extend_from_slice(payload);
+        Packet::new(bytes)
+    }
+
+    fn trigger(field: ICMPField, value: &str) -> ICMPTrigger {
+        ICMPTrigger::new(field, value.to_string(), 0).unwrap()
+    }
+
+    #[test]
+    fn field_round_trip() {
+        for name in ["type", "code", "chksum", "load"] {
+            let field = ICMPField::from_str(name);
+            assert!(field.is_ok(), "{}", name);
+            assert_eq!(field.unwrap().to_string(), name);
+        }
+        assert!(ICMPField::from_str("sport").is_err());
+    }
+
+    #[test]
+    fn echo_request_matches() {
+        let echo_request = icmp_packet(8, 0, b"ping");
+        let echo_reply = icmp_packet(0, 0, b"ping");
+
+        let t = trigger(ICMPField::Type, "8");
+        assert!(t.matches(&echo_request));
+        assert!(!t.matches(&echo_reply));
+
+        assert!(trigger(ICMPField::Code, "0").matches(&echo_request));
+        assert!(!trigger(ICMPField::Code, "1").matches(&echo_request));
+    }
+
+    #[test]
+    fn other_fields_match() {
+        let pkt = icmp_packet(3, 3, b"data");
+        assert!(trigger(ICMPField::Code, "3").matches(&pkt));
+        assert!(trigger(ICMPField::Checksum, "4660").matches(&pkt));
+        assert!(trigger(ICMPField::Payload, "data").matches(&pkt));
+        assert!(trigger(ICMPField::Payload, "hex:64617461").matches(&pkt));
+
+        // Unlike TCP and UDP, the whole payload has to match.
+        assert!(!trigger(ICMPField::Payload, "dat").matches(&pkt));
+        assert!(ICMPTrigger::new(ICMPField::Payload, "hex:6".to_string(), 0).is_err());
+    }
+
+    #[test]
+    fn non_icmp_does_not_match() {
+        let mut bytes = icmp_packet(8, 0, &[]).as_slice().to_vec();
+        bytes[9] = crate::packet::PROTO_UDP;
+        assert!(!trigger(ICMPField::Type, "8").matches(&Packet::new(bytes)));
+        assert!(!trigger(ICMPField::Type, "8")
+            .negated()
+            .matches(&Packet::new(vec![])));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(ICMPTrigger::new(ICMPField::Type, "256".to_string(), 0).is_err());
+        assert!(ICMPTrigger::new(ICMPField::Code, "echo".to_string(), 0).is_err());
+        assert!(ICMPTrigger::new(ICMPField::Checksum, "65536".to_string(), 0).is_err());
+        assert!(ICMPTrigger::new(ICMPField::Type, "255".to_string(), 0).is_ok());
+    }
+}
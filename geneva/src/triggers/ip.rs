@@ -1,12 +1,156 @@
-use std::fmt;
-use std::str::FromStr;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
 
+use crate::encoding::decode_bytes;
 use crate::errors::*;
-use crate::triggers::Trigger;
+use crate::packet::{PROTO_ICMP, PROTO_ICMPV6, PROTO_TCP, PROTO_UDP};
+use crate::prelude::*;
+use crate::triggers::{invalid_value, not_comparable, numeric_at_most, Comparison, Trigger};
 use crate::Packet;
 
+/// Parses a Geneva IP flags string (e.g., `"DF"`) into the value of the 3-bit IPv4 flags field.
+///
+/// The string is one or more flag names joined by `+`: `DF` (Don't Fragment), `MF` (More
+/// Fragments), and `RB` (the reserved bit). The order of the names does not matter, so `DF+MF`
+/// and `MF+DF` are the same.
+pub fn parse_ip_flags(s: &str) -> Result<u8> {
+    s.split('+').try_fold(0u8, |flags, name| {
+        let bit = match name {
+            "MF" => 0b001,
+            "DF" => 0b010,
+            "RB" => 0b100,
+            _ => return Err(Error::Parse(s.to_string())),
+        };
+        Ok(flags | bit)
+    })
+}
+
+/// Parses an IP protocol number, or the name of one of the protocols that carry the common
+/// transport layers: `tcp` (6), `udp` (17), `icmp` (1), or `icmpv6` (58). Names are matched
+/// without regard to case.
+pub fn parse_ip_protocol(s: &str) -> Result<u8> {
+    match s.to_ascii_lowercase().as_str() {
+        "icmp" => Ok(PROTO_ICMP),
+        "tcp" => Ok(PROTO_TCP),
+        "udp" => Ok(PROTO_UDP),
+        "icmpv6" => Ok(PROTO_ICMPV6),
+        _ => s.parse().map_err(|_| Error::Parse(s.to_string())),
+    }
+}
+
+/// The DSCP classes that a TOS trigger can name, with their code points: the class selectors
+/// (RFC 2474), the assured forwarding classes (RFC 2597), and expedited forwarding (RFC 3246).
+const DSCP_CLASSES: &[(&str, u8)] = &[
+    ("cs0", 0),
+    ("cs1", 8),
+    ("af11", 10),
+    ("af12", 12),
+    ("af13", 14),
+    ("cs2", 16),
+    ("af21", 18),
+    ("af22", 20),
+    ("af23", 22),
+    ("cs3", 24),
+    ("af31", 26),
+    ("af32", 28),
+    ("af33", 30),
+    ("cs4", 32),
+    ("af41", 34),
+    ("af42", 36),
+    ("af43", 38),
+    ("cs5", 40),
+    ("ef", 46),
+    ("cs6", 48),
+    ("cs7", 56),
+];
+
+/// What the value of a TOS trigger is compared with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TosValue {
+    /// The whole TOS (or IPv6 traffic class) byte.
+    Byte(u8),
+    /// Just the DSCP code point in its upper six bits, leaving out the ECN bits.
+    Dscp(u8),
+}
+
+/// Parses the value of a TOS trigger: either a byte, in decimal or in hex with a `0x` prefix, or
+/// the name of a DSCP class such as `ef` or `af41`, matched without regard to case.
+fn parse_tos(s: &str) -> Option<TosValue> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return u8::from_str_radix(hex, 16).ok().map(TosValue::Byte);
+    }
+    if let Ok(byte) = s.parse() {
+        return Some(TosValue::Byte(byte));
+    }
+    DSCP_CLASSES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        .map(|&(_, code_point)| TosValue::Dscp(code_point))
+}
+
+/// Parses `value`, which is either a single address (`10.0.0.1`) or a network in CIDR notation
+/// (`10.0.0.0/8`), into a network address and prefix length. A single address is treated as a
+/// network with a full-length prefix. IPv6 addresses may be bracketed, as in `[2001:db8::]/32`.
+fn parse_network(value: &str) -> Option<(IpAddr, u32)> {
+    let (network, prefix_len) = match value.split_once('/') {
+        Some((network, prefix_len)) => (network, Some(prefix_len.parse::<u32>().ok()?)),
+        None => (value, None),
+    };
+
+    let network = parse_ip_addr(network)?;
+    let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+    let prefix_len = prefix_len.unwrap_or(max_prefix_len);
+    if prefix_len > max_prefix_len {
+        return None;
+    }
+    Some((network, prefix_len))
+}
+
+/// Returns `true` if `addr` matches `value`, which is either a single address (`10.0.0.1`) or a
+/// network in CIDR notation (`10.0.0.0/8`). Addresses never match networks of the other family.
+fn addr_matches(addr: IpAddr, value: &str) -> bool {
+    let (network, prefix_len) = match parse_network(value) {
+        Some(network) => network,
+        None => return false,
+    };
+
+    match (addr, network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+            u32::from(addr) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(addr), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+            u128::from(addr) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Parses a single IP address, which may be an IPv6 address in brackets (`[2001:db8::1]`).
+pub(crate) fn parse_ip_addr(value: &str) -> Option<IpAddr> {
+    match value.strip_prefix('[') {
+        Some(addr) => Some(IpAddr::V6(addr.strip_suffix(']')?.parse().ok()?)),
+        None => value.parse().ok(),
+    }
+}
+
+/// Returns `value` the way a strategy writes it. The colons in an IPv6 address would end the
+/// value early, so the address goes in brackets: `2001:db8::/32` becomes `[2001:db8::]/32`.
+pub(crate) fn bracketed(value: &str) -> String {
+    if !value.contains(':') || value.starts_with('[') {
+        return value.to_string();
+    }
+    match value.split_once('/') {
+        Some((network, prefix_len)) => format!("[{}]/{}", network, prefix_len),
+        None => format!("[{}]", value),
+    }
+}
+
 /// Supported fields in the IP header that can be used for triggers.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IPField {
     Version,
     IHL,
@@ -21,6 +165,9 @@ pub enum IPField {
     SourceAddress,
     DestAddress,
     Payload,
+    /// Not a header field: the length of the whole packet as captured, whatever its headers
+    /// claim.
+    WireLength,
 }
 
 impl fmt::Display for IPField {
@@ -40,6 +187,7 @@ impl fmt::Display for IPField {
             SourceAddress => "src",
             DestAddress => "dst",
             Payload => "load",
+            WireLength => "wirelen",
         }
         .fmt(f)
     }
@@ -47,12 +195,15 @@ impl fmt::Display for IPField {
 
 impl FromStr for IPField {
     type Err = Error;
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+
+    /// Parses a field name, ignoring case. `source` and `destination` are accepted for `src` and
+    /// `dst`.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
         use IPField::*;
-        match s {
+        match s.to_ascii_lowercase().as_str() {
             "version" => Ok(Version),
             "ihl" => Ok(IHL),
-            "TOS" => Ok(TOS),
+            "tos" => Ok(TOS),
             "len" => Ok(Length),
             "id" => Ok(Identification),
             "flags" => Ok(Flags),
@@ -60,34 +211,116 @@ impl FromStr for IPField {
             "ttl" => Ok(TTL),
             "protocol" => Ok(Protocol),
             "chksum" => Ok(Checksum),
-            "src" => Ok(SourceAddress),
-            "dst" => Ok(DestAddress),
+            "src" | "source" => Ok(SourceAddress),
+            "dst" | "destination" => Ok(DestAddress),
             "load" => Ok(Payload),
-            _ => Err(Error::Parse(s.to_string())),
+            "wirelen" => Ok(WireLength),
+            _ => Err(Error::UnknownField {
+                protocol: "IP".to_string(),
+                field: s.to_string(),
+            }),
         }
     }
 }
 
+/// Returns `true` if `field` holds a number, and so can be used with a [Comparison].
+fn is_numeric(field: &IPField) -> bool {
+    use IPField::*;
+    matches!(
+        field,
+        Version
+            | IHL
+            | TOS
+            | Length
+            | Identification
+            | FragmentOffset
+            | TTL
+            | Protocol
+            | Checksum
+            | WireLength
+    )
+}
+
 /// A [Trigger] that matches on the IP layer.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IPTrigger {
     field: IPField,
     value: String,
     gas: usize,
-    _ip_field: u8,
+    negate: bool,
+    comparison: Comparison,
 }
 
 impl IPTrigger {
-    /// Creates a new `IPTrigger`.
-    pub fn new(field: IPField, value: String, gas: usize, _ip_field: u8) -> Result<Self> {
-        // TODO: validate fields
+    /// Creates a new `IPTrigger`. As with the other triggers, `gas` is the number of times the
+    /// trigger may fire, with `0` meaning it never runs out.
+    ///
+    /// Returns an error if `value` could never match `field`; for instance, a TTL larger than 255
+    /// or a source address that isn't an address. Every field needs a value.
+    pub fn new(field: IPField, value: String, gas: usize) -> Result<Self> {
+        use IPField::*;
+        let valid = !value.is_empty()
+            && match field {
+                Version | IHL => numeric_at_most(&value, 15u8),
+                TOS => parse_tos(&value).is_some(),
+                TTL => value.parse::<u8>().is_ok(),
+                Protocol => parse_ip_protocol(&value).is_ok(),
+                Length | Identification | Checksum => value.parse::<u16>().is_ok(),
+                FragmentOffset => numeric_at_most(&value, 0x1fffu16),
+                SourceAddress | DestAddress => parse_network(&value).is_some(),
+                Flags => parse_ip_flags(&value).is_ok(),
+                Payload => decode_bytes(&value).is_some(),
+                WireLength => value.parse::<usize>().is_ok(),
+            };
+        if !valid {
+            return Err(invalid_value("IP", field, &value));
+        }
+
         Ok(Self {
             field,
             value,
             gas,
-            _ip_field,
+            negate: false,
+            comparison: Comparison::Equal,
         })
     }
+
+    /// Inverts the trigger, so that it matches IP packets whose field does *not* match the value.
+    pub fn negated(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    /// Returns `true` if the trigger matches packets whose field does not match the value.
+    pub fn is_negated(&self) -> bool {
+        self.negate
+    }
+
+    /// Sets how the field is compared to the value. Returns an error if the field isn't a number,
+    /// unless `comparison` is [Comparison::Equal].
+    pub fn with_comparison(mut self, comparison: Comparison) -> Result<Self> {
+        let field = &self.field;
+        if comparison != Comparison::Equal && !is_numeric(field) {
+            return Err(not_comparable("IP", field, comparison));
+        }
+        self.comparison = comparison;
+        Ok(self)
+    }
+
+    /// Returns how the field is compared to the value.
+    pub fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+
+    /// Checks that the value fits the field, such as an address or network for `src` and `dst`,
+    /// and that only numeric fields are compared.
+    /// See [GenevaTrigger::validate](super::GenevaTrigger::validate).
+    pub(crate) fn validate(&self) -> Result<()> {
+        Self::new(self.field.clone(), self.value.clone(), self.gas)?
+            .with_comparison(self.comparison)?;
+        Ok(())
+    }
 }
 
 impl Trigger for IPTrigger {
@@ -103,8 +336,99 @@ impl Trigger for IPTrigger {
         self.gas
     }
 
-    fn matches(&self, _pkt: &Packet) -> bool {
-        unimplemented!()
+    fn matches(&self, pkt: &Packet) -> bool {
+        // The captured length doesn't depend on the headers, so it matches even if they are
+        // truncated or aren't IP at all.
+        if self.field == IPField::WireLength {
+            return self.comparison.matches(pkt.as_slice().len(), &self.value) != self.negate;
+        }
+
+        let (header, payload) = match (pkt.ip_header(), pkt.ip_payload()) {
+            (Some(header), Some(payload)) => (header, payload),
+            _ => return false,
+        };
+
+        let matched = match pkt.ip_version() {
+            Some(4) => self.matches_ipv4(header, payload),
+            Some(6) => self.matches_ipv6(header, payload),
+            _ => return false,
+        };
+        matched != self.negate
+    }
+}
+
+impl IPTrigger {
+    /// Returns `true` if the protocol number compares to the trigger's value, which may name the
+    /// protocol instead of giving its number.
+    fn protocol_matches(&self, protocol: u8) -> bool {
+        parse_ip_protocol(&self.value).is_ok_and(|value| self.comparison.compare(protocol, value))
+    }
+
+    /// Returns `true` if the TOS byte compares to the trigger's value, which may be a DSCP class
+    /// instead of a whole byte.
+    fn tos_matches(&self, tos: u8) -> bool {
+        match parse_tos(&self.value) {
+            Some(TosValue::Byte(value)) => self.comparison.compare(tos, value),
+            Some(TosValue::Dscp(code_point)) => self.comparison.compare(tos >> 2, code_point),
+            None => false,
+        }
+    }
+
+    fn matches_ipv4(&self, header: &[u8], payload: &[u8]) -> bool {
+        let u16_at = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+        let addr_at = |i: usize| {
+            IpAddr::V4(Ipv4Addr::new(
+                header[i],
+                header[i + 1],
+                header[i + 2],
+                header[i + 3],
+            ))
+        };
+
+        use IPField::*;
+        match self.field {
+            Version => self.comparison.matches(header[0] >> 4, &self.value),
+            IHL => self.comparison.matches(header[0] & 0x0f, &self.value),
+            TOS => self.tos_matches(header[1]),
+            Length => self.comparison.matches(u16_at(2), &self.value),
+            Identification => self.comparison.matches(u16_at(4), &self.value),
+            Flags => parse_ip_flags(&self.value).ok() == Some(header[6] >> 5),
+            // The offset is compared as it's written in the header, in units of 8 bytes.
+            FragmentOffset => self.comparison.matches(u16_at(6) & 0x1fff, &self.value),
+            TTL => self.comparison.matches(header[8], &self.value),
+            Protocol => self.protocol_matches(header[9]),
+            Checksum => self.comparison.matches(u16_at(10), &self.value),
+            SourceAddress => addr_matches(addr_at(12), &self.value),
+            DestAddress => addr_matches(addr_at(16), &self.value),
+            Payload => decode_bytes(&self.value).is_some_and(|value| payload == &*value),
+            WireLength => unreachable!("the wire length is matched before the headers"),
+        }
+    }
+
+    /// Matches against the fixed IPv6 header, mapping the IPv4 field names onto their IPv6
+    /// equivalents: `tos` is the traffic class, `ttl` is the hop limit, `len` is the payload
+    /// length, and `protocol` is the next header. Fields without an IPv6 equivalent never match.
+    fn matches_ipv6(&self, header: &[u8], payload: &[u8]) -> bool {
+        let addr_at = |i: usize| {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&header[i..i + 16]);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        };
+
+        use IPField::*;
+        match self.field {
+            Version => self.comparison.matches(header[0] >> 4, &self.value),
+            TOS => self.tos_matches(header[0] << 4 | header[1] >> 4),
+            Length => self
+                .comparison
+                .matches(u16::from_be_bytes([header[4], header[5]]), &self.value),
+            Protocol => self.protocol_matches(header[6]),
+            TTL => self.comparison.matches(header[7], &self.value),
+            SourceAddress => addr_matches(addr_at(8), &self.value),
+            DestAddress => addr_matches(addr_at(24), &self.value),
+            Payload => decode_bytes(&self.value).is_some_and(|value| payload == &*value),
+            _ => false,
+        }
     }
 }
 
@@ -115,13 +439,491 @@ impl fmt::Display for IPTrigger {
         } else {
             "".to_string()
         };
+        let value = match self.field {
+            IPField::SourceAddress | IPField::DestAddress => bracketed(&self.value),
+            _ => self.value.clone(),
+        };
         write!(
             f,
-            "[{}:{}:{}{}]",
+            "[{}:{}:{}{}{}{}]",
             self.protocol(),
             self.field,
-            self.value,
+            if self.negate { "!" } else { "" },
+            self.comparison,
+            value,
             gas
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a 20-byte IPv4 header from 192.168.1.10 to 10.0.0.2 with a TTL of 64 that claims
+    /// to carry a TCP segment, followed by the given payload.
+    fn ipv4_packet(payload: &[u8]) -> Packet {
+        let total_len = (20 + payload.len()) as u16;
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x1c, 0x46, 0x40, 0x00, 0x40, 0x06, 0xb1, 0xe6, 192, 168, 1,
+            10, 10, 0, 0, 2,
+        ];
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(payload);
+        Packet::new(bytes)
+    }
+
+    fn trigger(field: IPField, value: &str) -> IPTrigger {
+        IPTrigger::new(field, value.to_string(), 0).unwrap()
+    }
+
+    #[test]
+    fn field_names_ignore_case() {
+        for (name, field) in [
+            ("tos", IPField::TOS),
+            ("TOS", IPField::TOS),
+            ("TtL", IPField::TTL),
+            ("src", IPField::SourceAddress),
+            ("Source", IPField::SourceAddress),
+            ("DST", IPField::DestAddress),
+            ("destination", IPField::DestAddress),
+        ] {
+            assert_eq!(name.parse::<IPField>().unwrap(), field, "{}", name);
+        }
+        // Aliases are written back out under their usual names.
+        assert_eq!("SOURCE".parse::<IPField>().unwrap().to_string(), "src");
+        assert!("sourceaddr".parse::<IPField>().is_err());
+    }
+
+    #[test]
+    fn ttl_matches() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::TTL, "64").matches(&pkt));
+        assert!(!trigger(IPField::TTL, "63").matches(&pkt));
+    }
+
+    #[test]
+    fn protocol_matches() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::Protocol, "6").matches(&pkt));
+        assert!(!trigger(IPField::Protocol, "17").matches(&pkt));
+    }
+
+    #[test]
+    fn protocol_matches_by_name() {
+        let tcp = ipv4_packet(&[]);
+        let mut udp = tcp.as_slice().to_vec();
+        udp[9] = 17;
+        let udp = Packet::new(udp);
+
+        let t = trigger(IPField::Protocol, "tcp");
+        assert!(t.matches(&tcp));
+        assert!(!t.matches(&udp));
+        assert_eq!(t.to_string(), "[IP:protocol:tcp]");
+
+        assert!(trigger(IPField::Protocol, "UDP").matches(&udp));
+        assert!(!trigger(IPField::Protocol, "icmp").matches(&tcp));
+        assert!(trigger(IPField::Protocol, "tcp").negated().matches(&udp));
+        assert!(trigger(IPField::Protocol, "tcp").matches(&ipv6_tcp_packet()));
+
+        assert_eq!(parse_ip_protocol("icmp").unwrap(), 1);
+        assert_eq!(parse_ip_protocol("icmpv6").unwrap(), 58);
+        assert_eq!(parse_ip_protocol("41").unwrap(), 41);
+    }
+
+    #[test]
+    fn length_matches() {
+        let pkt = ipv4_packet(&[0; 8]);
+        assert!(trigger(IPField::Length, "28").matches(&pkt));
+        assert!(!trigger(IPField::Length, "20").matches(&pkt));
+    }
+
+    #[test]
+    fn id_matches() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::Identification, "7238").matches(&pkt));
+        assert!(!trigger(IPField::Identification, "0").matches(&pkt));
+    }
+
+    #[test]
+    fn addresses_match() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::SourceAddress, "192.168.1.10").matches(&pkt));
+        assert!(trigger(IPField::DestAddress, "10.0.0.2").matches(&pkt));
+
+        assert!(!trigger(IPField::SourceAddress, "10.0.0.2").matches(&pkt));
+    }
+
+    #[test]
+    fn cidr_addresses_match() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::SourceAddress, "192.168.1.0/24").matches(&pkt));
+        assert!(trigger(IPField::DestAddress, "10.0.0.0/8").matches(&pkt));
+        assert!(trigger(IPField::DestAddress, "0.0.0.0/0").matches(&pkt));
+    }
+
+    #[test]
+    fn cidr_host_route_matches() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::SourceAddress, "192.168.1.10/32").matches(&pkt));
+        assert!(!trigger(IPField::SourceAddress, "192.168.1.11/32").matches(&pkt));
+    }
+
+    #[test]
+    fn cidr_non_matching_address() {
+        let pkt = ipv4_packet(&[]);
+        assert!(!trigger(IPField::SourceAddress, "192.168.2.0/24").matches(&pkt));
+        assert!(!trigger(IPField::DestAddress, "172.16.0.0/12").matches(&pkt));
+    }
+
+    fn compare(field: IPField, op: &str, value: &str) -> IPTrigger {
+        trigger(field, value)
+            .with_comparison(Comparison::from_str(op).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn ethernet_frames_match() {
+        let mut frame = vec![0x02, 0, 0, 0, 0, 2, 0x02, 0, 0, 0, 0, 1, 0x08, 0x00];
+        frame.extend_from_slice(ipv4_packet(&[]).as_slice());
+        let pkt = Packet::with_link_type(frame, crate::LinkType::Ethernet);
+        assert!(trigger(IPField::SourceAddress, "192.168.1.10").matches(&pkt));
+        assert!(trigger(IPField::TTL, "64").matches(&pkt));
+        assert!(!trigger(IPField::TTL, "63").matches(&pkt));
+        // The captured length takes in the whole frame.
+        assert!(trigger(IPField::WireLength, "34").matches(&pkt));
+    }
+
+    #[test]
+    fn tos_matches() {
+        let mut bytes = ipv4_packet(&[]).as_slice().to_vec();
+        bytes[1] = 0x10;
+        let pkt = Packet::new(bytes.clone());
+        assert!(trigger(IPField::TOS, "0x10").matches(&pkt));
+        assert!(trigger(IPField::TOS, "16").matches(&pkt));
+        assert!(!trigger(IPField::TOS, "0x08").matches(&pkt));
+        assert!(compare(IPField::TOS, ">", "0x0f").matches(&pkt));
+
+        // Expedited forwarding, with and without an ECN codepoint in the low bits.
+        for tos in [0xb8, 0xb9] {
+            bytes[1] = tos;
+            let pkt = Packet::new(bytes.clone());
+            assert!(trigger(IPField::TOS, "ef").matches(&pkt));
+            assert!(trigger(IPField::TOS, "EF").matches(&pkt));
+            assert!(!trigger(IPField::TOS, "cs5").matches(&pkt));
+            assert!(compare(IPField::TOS, ">", "af41").matches(&pkt));
+        }
+        assert_eq!(trigger(IPField::TOS, "ef").to_string(), "[IP:tos:ef]");
+
+        // On IPv6, the field is the traffic class.
+        let mut bytes = ipv6_tcp_packet().as_slice().to_vec();
+        bytes[0] = 0x6b;
+        bytes[1] = 0x80;
+        let pkt = Packet::new(bytes);
+        assert!(trigger(IPField::TOS, "ef").matches(&pkt));
+        assert!(trigger(IPField::TOS, "0xb8").matches(&pkt));
+
+        for invalid in ["0x100", "256", "ef1", "af44", "0x"] {
+            assert!(IPTrigger::new(IPField::TOS, invalid.to_string(), 0).is_err());
+        }
+    }
+
+    #[test]
+    fn wire_length_matches() {
+        let small = ipv4_packet(&[]);
+        let large = ipv4_packet(&[0; 1400]);
+        let t = compare(IPField::WireLength, ">", "1400");
+        assert!(!t.matches(&small));
+        assert!(t.matches(&large));
+        assert!(trigger(IPField::WireLength, "20").matches(&small));
+        assert_eq!(t.to_string(), "[IP:wirelen:>1400]");
+
+        // The length is what was captured, not what the header says.
+        let mut truncated = large.as_slice().to_vec();
+        truncated.truncate(100);
+        let truncated = Packet::new(truncated);
+        assert!(compare(IPField::WireLength, "<", "101").matches(&truncated));
+        assert!(!compare(IPField::Length, "<", "101").matches(&truncated));
+        assert!(trigger(IPField::WireLength, "3").matches(&Packet::new(vec![0; 3])));
+    }
+
+    #[test]
+    fn ttl_comparisons() {
+        let pkt = ipv4_packet(&[]);
+        assert!(compare(IPField::TTL, "<", "65").matches(&pkt));
+        assert!(!compare(IPField::TTL, "<", "64").matches(&pkt));
+        assert!(compare(IPField::TTL, "<=", "64").matches(&pkt));
+        assert!(!compare(IPField::TTL, "<=", "63").matches(&pkt));
+        assert!(compare(IPField::TTL, ">", "63").matches(&pkt));
+        assert!(!compare(IPField::TTL, ">", "64").matches(&pkt));
+        assert!(compare(IPField::TTL, ">=", "64").matches(&pkt));
+        assert!(!compare(IPField::TTL, ">=", "65").matches(&pkt));
+        assert_eq!(compare(IPField::TTL, "<", "64").to_string(), "[IP:ttl:<64]");
+    }
+
+    #[test]
+    fn length_comparisons() {
+        let pkt = ipv4_packet(&[0; 1000]);
+        assert!(compare(IPField::Length, ">", "1000").matches(&pkt));
+        assert!(!compare(IPField::Length, ">", "1000").matches(&ipv4_packet(&[])));
+    }
+
+    #[test]
+    fn comparisons_require_numeric_fields() {
+        for field in [
+            IPField::SourceAddress,
+            IPField::DestAddress,
+            IPField::Payload,
+        ] {
+            let t = IPTrigger::new(field.clone(), "10.0.0.1".to_string(), 0).unwrap();
+            assert!(t.with_comparison(Comparison::Less).is_err(), "{}", field);
+        }
+        let t = trigger(IPField::SourceAddress, "10.0.0.1");
+        assert!(t.with_comparison(Comparison::Equal).is_ok());
+    }
+
+    #[test]
+    fn gas_round_trips() {
+        let t = IPTrigger::new(IPField::TTL, "64".to_string(), 3).unwrap();
+        assert_eq!(t.gas(), 3);
+        assert_eq!(t.to_string(), "[IP:ttl:64:3]");
+
+        let t = compare(IPField::TTL, "<=", "64").negated();
+        let t = IPTrigger { gas: 2, ..t };
+        assert_eq!(t.to_string(), "[IP:ttl:!<=64:2]");
+        let parsed: crate::Strategy = format!(r"{}-drop-| \/", t).parse().unwrap();
+        assert_eq!(parsed.outbound.unwrap()[0].trigger, t.into());
+    }
+
+    #[test]
+    fn parse_flags() {
+        assert_eq!(parse_ip_flags("MF").unwrap(), 0b001);
+        assert_eq!(parse_ip_flags("DF").unwrap(), 0b010);
+        assert_eq!(parse_ip_flags("RB").unwrap(), 0b100);
+        assert_eq!(parse_ip_flags("DF+MF").unwrap(), 0b011);
+        assert_eq!(parse_ip_flags("MF+DF").unwrap(), 0b011);
+        assert!(parse_ip_flags("DFMF").is_err());
+        assert!(parse_ip_flags("").is_err());
+    }
+
+    #[test]
+    fn flags_match() {
+        let with_flags = |flags: u8| {
+            let mut pkt = ipv4_packet(&[]);
+            pkt.as_mut_slice()[6] = flags;
+            pkt
+        };
+        let df = trigger(IPField::Flags, "DF");
+        let mf = trigger(IPField::Flags, "MF");
+        let both = trigger(IPField::Flags, "DF+MF");
+
+        // The sample packet has DF set.
+        assert!(df.matches(&ipv4_packet(&[])));
+        assert!(!mf.matches(&ipv4_packet(&[])));
+
+        let clean = with_flags(0x00);
+        assert!(!df.matches(&clean));
+        assert!(!mf.matches(&clean));
+        assert!(df.clone().negated().matches(&clean));
+
+        // The low bits of the byte are part of the fragment offset, not the flags.
+        assert!(mf.matches(&with_flags(0x20)));
+        assert!(mf.matches(&with_flags(0x3f)));
+        assert!(both.matches(&with_flags(0x60)));
+        assert!(!df.matches(&with_flags(0x60)));
+
+        assert!(!df.matches(&ipv6_tcp_packet()));
+    }
+
+    #[test]
+    fn fragment_offset_matches() {
+        // A middle fragment: MF is set, and it starts 185 * 8 = 1480 bytes into the datagram.
+        let mut fragment = ipv4_packet(&[0; 16]);
+        fragment.as_mut_slice()[6..8].copy_from_slice(&(0x2000u16 | 185).to_be_bytes());
+
+        assert!(trigger(IPField::FragmentOffset, "185").matches(&fragment));
+        assert!(!trigger(IPField::FragmentOffset, "1480").matches(&fragment));
+        assert!(!trigger(IPField::FragmentOffset, "0").matches(&fragment));
+        assert!(compare(IPField::FragmentOffset, ">", "0").matches(&fragment));
+        assert!(trigger(IPField::Flags, "MF").matches(&fragment));
+
+        // The first fragment has an offset of zero, whatever the flags say.
+        fragment.as_mut_slice()[6..8].copy_from_slice(&0x2000u16.to_be_bytes());
+        assert!(trigger(IPField::FragmentOffset, "0").matches(&fragment));
+        assert!(trigger(IPField::FragmentOffset, "0").matches(&ipv4_packet(&[])));
+
+        fragment.as_mut_slice()[6..8].copy_from_slice(&0x1fffu16.to_be_bytes());
+        assert!(trigger(IPField::FragmentOffset, "8191").matches(&fragment));
+        assert!(!trigger(IPField::Flags, "MF").matches(&fragment));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (field, value) in [
+            (IPField::TTL, "256"),
+            (IPField::TTL, "-1"),
+            (IPField::Protocol, "sctp"),
+            (IPField::Protocol, "256"),
+            (IPField::Length, "65536"),
+            (IPField::IHL, "16"),
+            (IPField::FragmentOffset, "8192"),
+            (IPField::SourceAddress, "1.2.3"),
+            (IPField::DestAddress, "not-an-address"),
+            (IPField::DestAddress, "10.0.0.0/33"),
+            (IPField::DestAddress, "2001:db8::/129"),
+            (IPField::Flags, "D"),
+            (IPField::Flags, "DF+"),
+            (IPField::Flags, "df"),
+        ] {
+            let result = IPTrigger::new(field.clone(), value.to_string(), 0);
+            assert!(result.is_err(), "{}:{}", field, value);
+        }
+    }
+
+    #[test]
+    fn boundary_values_are_accepted() {
+        for (field, value) in [
+            (IPField::TTL, "0"),
+            (IPField::TTL, "255"),
+            (IPField::Length, "65535"),
+            (IPField::IHL, "15"),
+            (IPField::FragmentOffset, "8191"),
+            (IPField::SourceAddress, "0.0.0.0/0"),
+            (IPField::DestAddress, "10.0.0.0/32"),
+            (IPField::DestAddress, "2001:db8::/128"),
+            (IPField::Payload, "anything"),
+        ] {
+            let result = IPTrigger::new(field.clone(), value.to_string(), 0);
+            assert!(result.is_ok(), "{}:{}", field, value);
+        }
+    }
+
+    #[test]
+    fn encoded_payload_matches() {
+        let pkt = ipv4_packet(&[0x00, 0x50, 0x00, 0x50]);
+        assert!(trigger(IPField::Payload, "hex:00500050").matches(&pkt));
+        assert!(trigger(IPField::Payload, "b64:AFAAUA").matches(&pkt));
+        assert!(!trigger(IPField::Payload, "b64:AFAA").matches(&pkt));
+        assert!(IPTrigger::new(IPField::Payload, "hex:0".to_string(), 0).is_err());
+    }
+
+    /// Builds an IPv6 packet from 2001:db8::1 to 2001:db8::2 with a hop limit of 64 carrying a
+    /// 20-byte TCP SYN segment.
+    fn ipv6_tcp_packet() -> Packet {
+        let mut bytes = vec![0x60, 0x00, 0x00, 0x00, 0x00, 20, 6, 64];
+        bytes.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        bytes.extend_from_slice(&[
+            0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00, 0x50, 0x02,
+            0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+        ]);
+        Packet::new(bytes)
+    }
+
+    #[test]
+    fn version_and_ihl_match() {
+        let pkt = ipv4_packet(&[]);
+        assert!(trigger(IPField::Version, "4").matches(&pkt));
+        assert!(!trigger(IPField::Version, "6").matches(&pkt));
+        assert!(trigger(IPField::IHL, "5").matches(&pkt));
+        assert!(trigger(IPField::IHL, "5")
+            .with_comparison(Comparison::Greater)
+            .unwrap()
+            .negated()
+            .matches(&pkt));
+
+        // A header with options is longer.
+        let mut bytes = ipv4_packet(&[]).as_slice().to_vec();
+        bytes[0] = 0x46;
+        bytes.splice(20..20, [1, 1, 1, 0]);
+        bytes[3] = 24;
+        let pkt = Packet::new(bytes);
+        assert!(trigger(IPField::IHL, "6").matches(&pkt));
+        assert!(!trigger(IPField::IHL, "5").matches(&pkt));
+
+        assert!(trigger(IPField::Version, "6").matches(&ipv6_tcp_packet()));
+    }
+
+    #[test]
+    fn ipv6_hop_limit_matches() {
+        let pkt = ipv6_tcp_packet();
+        assert!(trigger(IPField::TTL, "64").matches(&pkt));
+        assert!(!trigger(IPField::TTL, "63").matches(&pkt));
+    }
+
+    #[test]
+    fn ipv6_addresses_match() {
+        let pkt = ipv6_tcp_packet();
+        assert!(trigger(IPField::SourceAddress, "2001:db8::1").matches(&pkt));
+        assert!(trigger(IPField::DestAddress, "2001:db8::2").matches(&pkt));
+        assert!(trigger(IPField::DestAddress, "2001:db8::/32").matches(&pkt));
+
+        assert!(!trigger(IPField::SourceAddress, "2001:db8::2").matches(&pkt));
+        assert!(!trigger(IPField::SourceAddress, "10.0.0.1").matches(&pkt));
+        assert!(!trigger(IPField::DestAddress, "2001:db9::/32").matches(&pkt));
+    }
+
+    #[test]
+    fn bracketed_ipv6_addresses_match() {
+        let pkt = ipv6_tcp_packet();
+        assert!(trigger(IPField::SourceAddress, "[2001:db8::1]").matches(&pkt));
+        assert!(trigger(IPField::DestAddress, "[2001:db8::]/64").matches(&pkt));
+        assert!(!trigger(IPField::DestAddress, "[2001:db9::]/64").matches(&pkt));
+        for value in ["[10.0.0.1]", "[2001:db8::1", "[2001:db8::1]/129"] {
+            assert!(
+                IPTrigger::new(IPField::SourceAddress, value.to_string(), 0).is_err(),
+                "{}",
+                value
+            );
+        }
+
+        // IPv6 addresses are written back out in brackets, however they were given.
+        for value in ["2001:db8::1", "[2001:db8::1]"] {
+            let s = trigger(IPField::SourceAddress, value).to_string();
+            assert_eq!(s, "[IP:src:[2001:db8::1]]");
+        }
+        let s = trigger(IPField::DestAddress, "2001:db8::/64")
+            .negated()
+            .to_string();
+        assert_eq!(s, "[IP:dst:![2001:db8::]/64]");
+        let s = trigger(IPField::SourceAddress, "10.0.0.0/8").to_string();
+        assert_eq!(s, "[IP:src:10.0.0.0/8]");
+    }
+
+    #[test]
+    fn ipv6_length_and_next_header_match() {
+        let pkt = ipv6_tcp_packet();
+        assert!(trigger(IPField::Length, "20").matches(&pkt));
+        assert!(trigger(IPField::Protocol, "6").matches(&pkt));
+    }
+
+    #[test]
+    fn ipv6_fields_without_equivalent_do_not_match() {
+        let pkt = ipv6_tcp_packet();
+        assert!(!trigger(IPField::IHL, "5").matches(&pkt));
+        assert!(!trigger(IPField::FragmentOffset, "0").matches(&pkt));
+        assert!(!trigger(IPField::Checksum, "0").matches(&pkt));
+    }
+
+    #[test]
+    fn ipv4_does_not_match_ipv6_network() {
+        assert!(!trigger(IPField::SourceAddress, "::/0").matches(&ipv4_packet(&[])));
+    }
+
+    #[test]
+    fn wrong_version_does_not_match() {
+        let mut bytes = ipv4_packet(&[]).as_slice().to_vec();
+        bytes[0] = 0x65;
+        assert!(!trigger(IPField::TTL, "64").matches(&Packet::new(bytes)));
+    }
+
+    #[test]
+    fn truncated_header_does_not_match() {
+        // An IHL of 6 claims a 24-byte header, but only 20 bytes are present.
+        let mut bytes = ipv4_packet(&[]).as_slice().to_vec();
+        bytes[0] = 0x46;
+        assert!(!trigger(IPField::TTL, "64").matches(&Packet::new(bytes)));
+
+        assert!(!trigger(IPField::TTL, "64").matches(&Packet::new(vec![0x45, 0x00])));
+    }
+}
@@ -1,13 +1,126 @@
-use std::fmt;
+use core::fmt;
+use core::str::FromStr;
 
+use crate::encoding::decode_bytes;
+use crate::errors::*;
+use crate::prelude::*;
 use crate::Packet;
 
-mod ip;
+pub(crate) mod dns;
+pub use dns::*;
+
+pub(crate) mod icmp;
+pub use icmp::*;
+
+pub(crate) mod ip;
 pub use ip::*;
 
-mod tcp;
+pub(crate) mod tcp;
 pub use tcp::*;
 
+pub(crate) mod udp;
+pub use udp::*;
+
+/// How a trigger compares a numeric header field against its value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    /// The field must equal the value. This is the default, and is written without an operator.
+    #[default]
+    Equal,
+
+    /// The field must be less than the value (`<`).
+    Less,
+
+    /// The field must be less than or equal to the value (`<=`).
+    LessOrEqual,
+
+    /// The field must be greater than the value (`>`).
+    Greater,
+
+    /// The field must be greater than or equal to the value (`>=`).
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    /// Returns `true` if `value` parses as a number and `actual` compares to it as required.
+    pub(crate) fn matches<T: FromStr + PartialOrd>(self, actual: T, value: &str) -> bool {
+        match value.parse::<T>() {
+            Ok(value) => self.compare(actual, value),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns `true` if `actual` compares to `value` as required.
+    pub(crate) fn compare<T: PartialOrd>(self, actual: T, value: T) -> bool {
+        match self {
+            Self::Equal => actual == value,
+            Self::Less => actual < value,
+            Self::LessOrEqual => actual <= value,
+            Self::Greater => actual > value,
+            Self::GreaterOrEqual => actual >= value,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equal => "",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+        }
+        .fmt(f)
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = Error;
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s {
+            "" => Ok(Self::Equal),
+            "<" => Ok(Self::Less),
+            "<=" => Ok(Self::LessOrEqual),
+            ">" => Ok(Self::Greater),
+            ">=" => Ok(Self::GreaterOrEqual),
+            _ => Err(Error::Parse(s.to_string())),
+        }
+    }
+}
+
+/// Returns the error for a trigger that uses a comparison operator on a field that isn't a number.
+pub(crate) fn not_comparable(protocol: &str, field: impl fmt::Display, op: Comparison) -> Error {
+    Error::Parse(format!(
+        "cannot use {} with non-numeric field {}:{}",
+        op, protocol, field
+    ))
+}
+
+/// Returns the error for a trigger whose value does not make sense for its field.
+pub(crate) fn invalid_value(protocol: &str, field: impl fmt::Display, value: &str) -> Error {
+    Error::InvalidValue {
+        field: format!("{}:{}", protocol, field),
+        value: value.to_string(),
+    }
+}
+
+/// Returns `true` if `payload` contains the bytes that the `load` value `value` stands for. An
+/// empty value is found in any payload.
+pub(crate) fn payload_contains(payload: &[u8], value: &str) -> bool {
+    match decode_bytes(value) {
+        Some(needle) if needle.is_empty() => true,
+        Some(needle) => payload.windows(needle.len()).any(|w| w == &*needle),
+        None => false,
+    }
+}
+
+/// Returns `true` if `value` parses as a `T` no greater than `max`.
+pub(crate) fn numeric_at_most<T: FromStr + PartialOrd>(value: &str, max: T) -> bool {
+    value.parse::<T>().is_ok_and(|v| v <= max)
+}
+
 /// Describes a Geneva trigger, which is responsible for deciding which packets an
 /// [Action](crate::actions::Action) should apply to.
 pub trait Trigger: fmt::Display {
@@ -25,13 +138,23 @@ pub trait Trigger: fmt::Display {
 }
 
 /// Represents one of the Geneva triggers.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GenevaTrigger {
     /// A trigger that applies to a packet's IP layer.
     IP(IPTrigger),
 
+    /// A trigger that applies to a DNS message carried by a packet.
+    DNS(DNSTrigger),
+
+    /// A trigger that applies to a packet's ICMP layer.
+    ICMP(ICMPTrigger),
+
     /// A trigger that applies to a packet's TCP layer.
     TCP(TCPTrigger),
+
+    /// A trigger that applies to a packet's UDP layer.
+    UDP(UDPTrigger),
 }
 
 impl From<TCPTrigger> for GenevaTrigger {
@@ -46,32 +169,97 @@ impl From<IPTrigger> for GenevaTrigger {
     }
 }
 
+impl From<DNSTrigger> for GenevaTrigger {
+    fn from(t: DNSTrigger) -> Self {
+        Self::DNS(t)
+    }
+}
+
+impl From<ICMPTrigger> for GenevaTrigger {
+    fn from(t: ICMPTrigger) -> Self {
+        Self::ICMP(t)
+    }
+}
+
+impl From<UDPTrigger> for GenevaTrigger {
+    fn from(t: UDPTrigger) -> Self {
+        Self::UDP(t)
+    }
+}
+
+impl GenevaTrigger {
+    /// Returns an error if the trigger's value could never match its field, or its comparison
+    /// doesn't make sense for it. Parsing or building a trigger already checks this, but
+    /// deserializing one doesn't.
+    ///
+    /// Each protocol checks its trigger by building it again from its parts, so `validate` rejects
+    /// exactly what that protocol's `new` and `with_comparison` would.
+    pub(crate) fn validate(&self) -> Result<()> {
+        match self {
+            GenevaTrigger::IP(t) => t.validate(),
+            GenevaTrigger::DNS(t) => t.validate(),
+            GenevaTrigger::ICMP(t) => t.validate(),
+            GenevaTrigger::TCP(t) => t.validate(),
+            GenevaTrigger::UDP(t) => t.validate(),
+        }
+    }
+
+    /// Returns `true` if the trigger can never match a packet: it matches its field exactly, isn't
+    /// negated, and has a value that the field can't hold (which [validate](Self::validate) finds),
+    /// such as a TTL of 300.
+    pub(crate) fn never_matches(&self) -> bool {
+        let (negated, comparison) = match self {
+            GenevaTrigger::IP(t) => (t.is_negated(), t.comparison()),
+            GenevaTrigger::DNS(t) => (t.is_negated(), t.comparison()),
+            GenevaTrigger::ICMP(t) => (t.is_negated(), t.comparison()),
+            GenevaTrigger::TCP(t) => (t.is_negated(), t.comparison()),
+            GenevaTrigger::UDP(t) => (t.is_negated(), t.comparison()),
+        };
+        // Anything but an exact match may still be satisfied by a value out of the field's range.
+        !negated
+            && comparison == Comparison::Equal
+            && matches!(self.validate(), Err(Error::InvalidValue { .. }))
+    }
+}
+
 impl Trigger for GenevaTrigger {
     fn protocol(&self) -> String {
         match self {
             GenevaTrigger::IP(t) => t.protocol(),
+            GenevaTrigger::DNS(t) => t.protocol(),
+            GenevaTrigger::ICMP(t) => t.protocol(),
             GenevaTrigger::TCP(t) => t.protocol(),
+            GenevaTrigger::UDP(t) => t.protocol(),
         }
     }
 
     fn field(&self) -> String {
         match self {
             GenevaTrigger::IP(t) => t.field(),
+            GenevaTrigger::DNS(t) => t.field(),
+            GenevaTrigger::ICMP(t) => t.field(),
             GenevaTrigger::TCP(t) => t.field(),
+            GenevaTrigger::UDP(t) => t.field(),
         }
     }
 
     fn gas(&self) -> usize {
         match self {
             GenevaTrigger::IP(t) => t.gas(),
+            GenevaTrigger::DNS(t) => t.gas(),
+            GenevaTrigger::ICMP(t) => t.gas(),
             GenevaTrigger::TCP(t) => t.gas(),
+            GenevaTrigger::UDP(t) => t.gas(),
         }
     }
 
     fn matches(&self, pkt: &Packet) -> bool {
         match self {
             GenevaTrigger::IP(t) => t.matches(pkt),
+            GenevaTrigger::DNS(t) => t.matches(pkt),
+            GenevaTrigger::ICMP(t) => t.matches(pkt),
             GenevaTrigger::TCP(t) => t.matches(pkt),
+            GenevaTrigger::UDP(t) => t.matches(pkt),
         }
     }
 }
@@ -80,7 +268,39 @@ impl fmt::Display for GenevaTrigger {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::IP(t) => t.fmt(f),
+            Self::DNS(t) => t.fmt(f),
+            Self::ICMP(t) => t.fmt(f),
             Self::TCP(t) => t.fmt(f),
+            Self::UDP(t) => t.fmt(f),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparison_round_trip() {
+        for op in ["", "<", "<=", ">", ">="] {
+            assert_eq!(Comparison::from_str(op).unwrap().to_string(), op);
+        }
+        assert!(Comparison::from_str("=").is_err());
+    }
+
+    #[test]
+    fn comparisons() {
+        use Comparison::*;
+        assert!(Equal.matches(64u8, "64"));
+        assert!(!Equal.matches(64u8, "63"));
+        assert!(Less.matches(63u8, "64"));
+        assert!(!Less.matches(64u8, "64"));
+        assert!(LessOrEqual.matches(64u8, "64"));
+        assert!(!LessOrEqual.matches(65u8, "64"));
+        assert!(Greater.matches(65u8, "64"));
+        assert!(!Greater.matches(64u8, "64"));
+        assert!(GreaterOrEqual.matches(64u8, "64"));
+        assert!(!GreaterOrEqual.matches(63u8, "64"));
+        assert!(!Less.matches(0u8, "bogus"));
+    }
+}
@@ -1,12 +1,41 @@
-use std::fmt;
-use std::str::FromStr;
+use core::cell::RefCell;
+use core::fmt;
+use core::str::FromStr;
 
+use crate::encoding::decode_bytes;
 use crate::errors::*;
-use crate::triggers::Trigger;
-use crate::Packet;
+use crate::flows::FlowTable;
+use crate::prelude::*;
+use crate::triggers::{
+    invalid_value, not_comparable, numeric_at_most, payload_contains, Comparison, Trigger,
+};
+use crate::{FlowKey, Packet};
+
+/// Parses a Geneva TCP flags string (e.g., `"SA"`) into the corresponding TCP flags byte.
+///
+/// Each letter names a single flag: `F` (FIN), `S` (SYN), `R` (RST), `P` (PSH), `A` (ACK), `U`
+/// (URG), `E` (ECE), and `C` (CWR). The order of the letters does not matter, and an empty string
+/// means that no flags are set.
+pub fn parse_tcp_flags(s: &str) -> Result<u8> {
+    s.chars().try_fold(0u8, |flags, c| {
+        let bit = match c {
+            'F' => 0x01,
+            'S' => 0x02,
+            'R' => 0x04,
+            'P' => 0x08,
+            'A' => 0x10,
+            'U' => 0x20,
+            'E' => 0x40,
+            'C' => 0x80,
+            _ => return Err(Error::Parse(s.to_string())),
+        };
+        Ok(flags | bit)
+    })
+}
 
 /// Supported fields in the TCP header that can be used for triggers.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TCPField {
     SourcePort,
     DestPort,
@@ -30,6 +59,9 @@ pub enum TCPField {
     OptionAltChecksumOpt,
     OptionMD5Header,
     OptionUTO,
+    /// Not a header field, but the window scaled by the window scale option from the SYN that
+    /// started the flow.
+    EffectiveWindow,
 }
 
 impl fmt::Display for TCPField {
@@ -58,6 +90,7 @@ impl fmt::Display for TCPField {
             OptionAltChecksumOpt => "options-altchksumopt",
             OptionMD5Header => "options-md5header",
             OptionUTO => "options-uto",
+            EffectiveWindow => "effwindow",
         }
         .fmt(f)
     }
@@ -65,11 +98,14 @@ impl fmt::Display for TCPField {
 
 impl FromStr for TCPField {
     type Err = Error;
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+
+    /// Parses a field name, ignoring case. `sourceport` and `destinationport` are accepted for
+    /// `sport` and `dport`.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
         use TCPField::*;
-        match s {
-            "sport" => Ok(SourcePort),
-            "dport" => Ok(DestPort),
+        match s.to_ascii_lowercase().as_str() {
+            "sport" | "sourceport" => Ok(SourcePort),
+            "dport" | "destinationport" => Ok(DestPort),
             "seq" => Ok(Seq),
             "ack" => Ok(Ack),
             "dataofs" => Ok(DataOffset),
@@ -90,29 +126,329 @@ impl FromStr for TCPField {
             "options-altchksumopt" => Ok(OptionAltChecksumOpt),
             "options-md5header" => Ok(OptionMD5Header),
             "options-uto" => Ok(OptionUTO),
-            _ => Err(Error::Parse(s.to_string())),
+            "effwindow" => Ok(EffectiveWindow),
+            _ => Err(Error::UnknownField {
+                protocol: "TCP".to_string(),
+                field: s.to_string(),
+            }),
         }
     }
 }
 
-/// A [Trigger] that matches on the TCP layer.
+/// Returns `true` if `field` holds a number, and so can be used with a [Comparison].
+fn is_numeric(field: &TCPField) -> bool {
+    use TCPField::*;
+    matches!(
+        field,
+        SourcePort
+            | DestPort
+            | Seq
+            | Ack
+            | DataOffset
+            | Reserved
+            | Window
+            | Checksum
+            | UrgentPointer
+            | EffectiveWindow
+    ) || numeric_option(field).is_some()
+}
+
+/// For the TCP options whose value is a number, returns the option's kind and the length of its
+/// data. The option's value is the first (up to) four bytes of its data, so for timestamps it is
+/// the sender's timestamp (TSval) and for the user timeout it includes the granularity bit.
+fn numeric_option(field: &TCPField) -> Option<(u8, usize)> {
+    use TCPField::*;
+    match field {
+        OptionMSS => Some((2, 2)),
+        OptionWScale => Some((3, 1)),
+        OptionTimestamp => Some((8, 8)),
+        OptionAltChecksum => Some((14, 1)),
+        OptionUTO => Some((28, 2)),
+        _ => None,
+    }
+}
+
+/// Returns the kind of the TCP option that `field` names, or `None` if it isn't an option.
+fn option_kind(field: &TCPField) -> Option<u8> {
+    use TCPField::*;
+    match field {
+        OptionEOL => Some(0),
+        OptionNOP => Some(1),
+        OptionMSS => Some(2),
+        OptionWScale => Some(3),
+        OptionSackOk => Some(4),
+        OptionSack => Some(5),
+        OptionTimestamp => Some(8),
+        OptionAltChecksum => Some(14),
+        OptionAltChecksumOpt => Some(15),
+        OptionMD5Header => Some(19),
+        OptionUTO => Some(28),
+        _ => None,
+    }
+}
+
+/// Finds the first option of the given kind in a TCP header (including options), returning the
+/// option's data. The search stops at the end-of-option-list option or at the first malformed
+/// option.
+fn find_tcp_option(header: &[u8], kind: u8) -> Option<&[u8]> {
+    let mut options = header.get(20..)?;
+    while let Some(&k) = options.first() {
+        // The end-of-option-list and no-operation options are a single byte with no data.
+        match k {
+            _ if k == kind && k <= 1 => return Some(&[]),
+            0 => return None,
+            1 => {
+                options = &options[1..];
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = *options.get(1)? as usize;
+        if len < 2 || len > options.len() {
+            return None;
+        }
+        if k == kind {
+            return Some(&options[2..len]);
+        }
+        options = &options[len..];
+    }
+    None
+}
+
+/// What a trigger needs to remember about the SYN that started a flow.
+#[derive(Debug, Clone, Copy)]
+struct Syn {
+    /// The initial sequence number.
+    seq: u32,
+    /// The shift count from the window scale option, or 0 without one.
+    wscale: u8,
+}
+
+/// The most flows whose SYN a trigger remembers. Past this, the flow that was least recently
+/// matched against is forgotten, and its segments are matched as if its SYN was never seen.
+const MAX_SYNS: usize = 4096;
+
+/// The SYNs of the flows that a relative or `effwindow` trigger has seen.
+///
+/// This is state rather than configuration, so two triggers compare equal whatever they have seen.
 #[derive(Debug, Clone)]
+struct Syns(RefCell<FlowTable<Syn>>);
+
+impl Default for Syns {
+    fn default() -> Self {
+        Self(RefCell::new(FlowTable::new(MAX_SYNS)))
+    }
+}
+
+impl PartialEq for Syns {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Syns {}
+
+/// The TCP flags that end what the sender of a segment has to send.
+const FIN: u8 = 0x01;
+const RST: u8 = 0x04;
+
+/// The largest window scale shift count that RFC 7323 allows; larger ones are treated as this.
+const MAX_WSCALE: u8 = 14;
+
+/// A [Trigger] that matches on the TCP layer.
+///
+/// A `seq` or `ack` trigger can be made [relative](TCPTrigger::relative), in which case it is
+/// written with `rel:` before its value (e.g., `[TCP:seq:rel:1]`) and matches sequence numbers
+/// counted from the start of the connection rather than absolute ones.
+///
+/// The `effwindow` field is the window that the sender really means: the `window` field shifted
+/// left by the window scale option of the SYN that opened the flow, as in `[TCP:effwindow:>65535]`.
+/// The trigger remembers that option from every SYN it sees, so it only scales the windows of
+/// flows whose SYN it has seen.
+///
+/// Relative and `effwindow` triggers remember the SYNs of the few thousand flows they have matched
+/// against most recently, and forget a flow's SYN once a FIN or RST from its sender ends it.
+///
+/// A trigger on one of the `options-*` fields with an empty value, such as `[TCP:options-sackok:]`,
+/// matches segments that carry the option, whatever its value. Negated, as in
+/// `[TCP:options-sackok:!]`, it matches segments that don't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TCPTrigger {
     field: TCPField,
     value: String,
     gas: usize,
+    negate: bool,
+    comparison: Comparison,
+    #[cfg_attr(feature = "serde", serde(default))]
+    relative: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    syns: Syns,
 }
 
 impl TCPTrigger {
     /// Creates a new `TCPTrigger`.
+    ///
+    /// Returns an error if `value` could never match `field`; for instance, a port larger than
+    /// 65535 or a flags string with letters that don't name a TCP flag. Only the `options-*`
+    /// fields may have an empty value.
     pub fn new(field: TCPField, value: String, gas: usize) -> Result<Self> {
-        // TODO: validate fields
-        Ok(Self { field, value, gas })
+        use TCPField::*;
+        let valid = match field {
+            // An option with an empty value matches on whether the option is there at all.
+            _ if value.is_empty() => option_kind(&field).is_some(),
+            SourcePort | DestPort | Window | Checksum | UrgentPointer => {
+                value.parse::<u16>().is_ok()
+            }
+            Seq | Ack | EffectiveWindow => value.parse::<u32>().is_ok(),
+            DataOffset => numeric_at_most(&value, 15u8),
+            Reserved => numeric_at_most(&value, 7u8),
+            Flags => parse_tcp_flags(&value).is_ok(),
+            Payload => decode_bytes(&value).is_some(),
+            _ => match numeric_option(&field) {
+                Some((_, len)) => numeric_at_most(&value, u32::MAX >> (32 - 8 * len.min(4))),
+                None => true,
+            },
+        };
+        if !valid {
+            return Err(invalid_value("TCP", field, &value));
+        }
+
+        Ok(Self {
+            field,
+            value,
+            gas,
+            negate: false,
+            comparison: Comparison::Equal,
+            relative: false,
+            syns: Syns::default(),
+        })
     }
 
     pub fn value(&self) -> &str {
         &self.value
     }
+
+    /// Returns the field that the trigger matches on.
+    pub(crate) fn tcp_field(&self) -> &TCPField {
+        &self.field
+    }
+
+    /// Inverts the trigger, so that it matches TCP packets whose field does *not* match the value.
+    pub fn negated(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    /// Returns `true` if the trigger matches packets whose field does not match the value.
+    pub fn is_negated(&self) -> bool {
+        self.negate
+    }
+
+    /// Sets how the field is compared to the value. Returns an error if the field isn't a number,
+    /// or if the value is empty, unless `comparison` is [Comparison::Equal].
+    pub fn with_comparison(mut self, comparison: Comparison) -> Result<Self> {
+        let field = &self.field;
+        if comparison != Comparison::Equal && !is_numeric(field) {
+            return Err(not_comparable("TCP", field, comparison));
+        }
+        if comparison != Comparison::Equal && self.value.is_empty() {
+            return Err(Error::Parse(format!(
+                "cannot use {} without a value for TCP:{}",
+                comparison, field
+            )));
+        }
+        self.comparison = comparison;
+        Ok(self)
+    }
+
+    /// Returns how the field is compared to the value.
+    pub fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+
+    /// Checks that the value fits the field, that only numeric fields with a value are compared,
+    /// and that only `seq` and `ack` are relative.
+    /// See [GenevaTrigger::validate](super::GenevaTrigger::validate).
+    pub(crate) fn validate(&self) -> Result<()> {
+        let trigger = Self::new(self.field.clone(), self.value.clone(), self.gas)?
+            .with_comparison(self.comparison)?;
+        if self.relative {
+            trigger.relative()?;
+        }
+        Ok(())
+    }
+
+    /// Makes a `seq` or `ack` trigger match sequence numbers relative to the start of the
+    /// connection, the way Wireshark shows them. Returns an error for any other field.
+    ///
+    /// The trigger remembers the sequence number of every SYN it sees. Relative to that, the SYN
+    /// itself has sequence number 0 and the first byte of data has sequence number 1; an `ack` is
+    /// counted from the SYN sent the other way. Until the trigger has seen the SYN it needs (for
+    /// instance, for connections that were already open), it matches absolute values instead.
+    pub fn relative(mut self) -> Result<Self> {
+        if !matches!(self.field, TCPField::Seq | TCPField::Ack) {
+            return Err(Error::Parse(format!(
+                "relative values need TCP:seq or TCP:ack, not TCP:{}",
+                self.field
+            )));
+        }
+        self.relative = true;
+        Ok(self)
+    }
+
+    /// Returns `true` if the trigger matches sequence numbers relative to the start of the
+    /// connection.
+    pub fn is_relative(&self) -> bool {
+        self.relative
+    }
+
+    /// Returns `value` relative to the initial sequence number of `flow`, or `value` itself if
+    /// the trigger isn't relative or hasn't seen the flow's SYN.
+    fn relative_to(&self, flow: Option<FlowKey>, value: u32) -> u32 {
+        let syn = flow.and_then(|flow| self.syns.0.borrow_mut().get(&flow).copied());
+        match syn {
+            Some(syn) if self.relative => value.wrapping_sub(syn.seq),
+            _ => value,
+        }
+    }
+
+    /// Returns the window of a segment on `flow` scaled by the window scale option of the flow's
+    /// SYN. The window of a SYN itself is never scaled, and neither is that of a segment whose SYN
+    /// the trigger hasn't seen.
+    fn effective_window(&self, flow: Option<FlowKey>, header: &[u8]) -> u32 {
+        let window = u16::from_be_bytes([header[14], header[15]]) as u32;
+        if header[13] & 0x02 != 0 {
+            return window;
+        }
+        let syn = flow.and_then(|flow| self.syns.0.borrow_mut().get(&flow).copied());
+        match syn {
+            Some(syn) => window << syn.wscale,
+            None => window,
+        }
+    }
+
+    /// Remembers the initial sequence number and window scale of the packet's flow if it is a
+    /// SYN. (A FIN or RST makes the trigger [forget](Self::forget) them again.)
+    fn observe(&self, flow: Option<FlowKey>, header: &[u8]) {
+        if let Some(flow) = flow.filter(|_| header[13] & 0x02 != 0) {
+            let seq = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+            let wscale = match find_tcp_option(header, 3) {
+                Some(&[shift]) => shift.min(MAX_WSCALE),
+                _ => 0,
+            };
+            self.syns.0.borrow_mut().insert(flow, Syn { seq, wscale });
+        }
+    }
+
+    /// Forgets the SYN of the packet's flow if it is a FIN or RST, after which the sender has
+    /// nothing more to send.
+    fn forget(&self, flow: Option<FlowKey>, header: &[u8]) {
+        if let Some(flow) = flow.filter(|_| header[13] & (FIN | RST) != 0) {
+            self.syns.0.borrow_mut().remove(&flow);
+        }
+    }
 }
 
 impl Trigger for TCPTrigger {
@@ -128,8 +464,78 @@ impl Trigger for TCPTrigger {
         self.gas
     }
 
-    fn matches(&self, _pkt: &Packet) -> bool {
-        unimplemented!()
+    fn matches(&self, pkt: &Packet) -> bool {
+        let (header, payload) = match (pkt.tcp_header(), pkt.payload()) {
+            (Some(header), Some(payload)) => (header, payload),
+            _ => return false,
+        };
+
+        let u16_at = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_be_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+
+        let stateful = self.relative || self.field == TCPField::EffectiveWindow;
+        let flow = pkt.flow_key().filter(|_| stateful);
+        let reverse_flow = flow.map(|flow| flow.reversed());
+        self.observe(flow, header);
+
+        use TCPField::*;
+        let matched = match self.field {
+            SourcePort => self.comparison.matches(u16_at(0), &self.value),
+            DestPort => self.comparison.matches(u16_at(2), &self.value),
+            Seq => {
+                let seq = self.relative_to(flow, u32_at(4));
+                self.comparison.matches(seq, &self.value)
+            }
+            Ack => {
+                let ack = self.relative_to(reverse_flow, u32_at(8));
+                self.comparison.matches(ack, &self.value)
+            }
+            DataOffset => self.comparison.matches(header[12] >> 4, &self.value),
+            Reserved => self
+                .comparison
+                .matches((header[12] >> 1) & 0x07, &self.value),
+            Flags => parse_tcp_flags(&self.value).ok() == Some(header[13]),
+            Window => self.comparison.matches(u16_at(14), &self.value),
+            EffectiveWindow => {
+                let window = self.effective_window(flow, header);
+                self.comparison.matches(window, &self.value)
+            }
+            Checksum => self.comparison.matches(u16_at(16), &self.value),
+            UrgentPointer => self.comparison.matches(u16_at(18), &self.value),
+            Payload => payload_contains(payload, &self.value),
+            _ => self.matches_option(header),
+        };
+        self.forget(flow, header);
+
+        // A negated trigger still only applies to packets that have the layer in question.
+        matched != self.negate
+    }
+}
+
+impl TCPTrigger {
+    /// Returns `true` if the header carries the option named by the trigger's field, and the
+    /// option's value matches. An empty value matches any option of the right kind; otherwise,
+    /// options without a numeric value never match.
+    fn matches_option(&self, header: &[u8]) -> bool {
+        if self.value.is_empty() {
+            return option_kind(&self.field)
+                .is_some_and(|kind| find_tcp_option(header, kind).is_some());
+        }
+
+        let (kind, len) = match numeric_option(&self.field) {
+            Some(option) => option,
+            None => return false,
+        };
+        let data = match find_tcp_option(header, kind) {
+            Some(data) if data.len() == len => data,
+            _ => return false,
+        };
+
+        let value = data[..len.min(4)]
+            .iter()
+            .fold(0u32, |value, &b| (value << 8) | b as u32);
+        self.comparison.matches(value, &self.value)
     }
 }
 
@@ -142,11 +548,529 @@ impl fmt::Display for TCPTrigger {
         };
         write!(
             f,
-            "[{}:{}:{}{}]",
+            "[{}:{}:{}{}{}{}{}]",
             self.protocol(),
             self.field,
+            if self.relative { "rel:" } else { "" },
+            if self.negate { "!" } else { "" },
+            self.comparison,
             self.value,
             gas
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYN: u8 = 0x02;
+    const ACK: u8 = 0x10;
+
+    /// Builds an IPv4 packet from 10.0.0.1:12345 to 10.0.0.2:80 carrying a TCP segment with the
+    /// given flags and payload.
+    fn tcp_packet(flags: u8, payload: &[u8]) -> Packet {
+        let total_len = (40 + payload.len()) as u16;
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(&12345u16.to_be_bytes());
+        bytes.extend_from_slice(&80u16.to_be_bytes());
+        bytes.extend_from_slice(&1000u32.to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(&[0x50, flags]);
+        bytes.extend_from_slice(&65535u16.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(payload);
+        Packet::new(bytes)
+    }
+
+    fn trigger(field: TCPField, value: &str) -> TCPTrigger {
+        TCPTrigger::new(field, value.to_string(), 0).unwrap()
+    }
+
+    #[test]
+    fn field_names_ignore_case() {
+        for (name, field) in [
+            ("Flags", TCPField::Flags),
+            ("SPORT", TCPField::SourcePort),
+            ("sourceport", TCPField::SourcePort),
+            ("SourcePort", TCPField::SourcePort),
+            ("destinationPort", TCPField::DestPort),
+            ("Options-MSS", TCPField::OptionMSS),
+        ] {
+            assert_eq!(name.parse::<TCPField>().unwrap(), field, "{}", name);
+        }
+        assert_eq!(
+            "SourcePort".parse::<TCPField>().unwrap().to_string(),
+            "sport"
+        );
+        assert!("source".parse::<TCPField>().is_err());
+    }
+
+    #[test]
+    fn flags_match_syn() {
+        let t = trigger(TCPField::Flags, "S");
+        assert!(t.matches(&tcp_packet(SYN, &[])));
+        assert!(!t.matches(&tcp_packet(SYN | ACK, &[])));
+        assert!(!t.matches(&tcp_packet(ACK, &[])));
+    }
+
+    #[test]
+    fn flags_match_syn_ack() {
+        let t = trigger(TCPField::Flags, "SA");
+        assert!(t.matches(&tcp_packet(SYN | ACK, &[])));
+        assert!(!t.matches(&tcp_packet(SYN, &[])));
+    }
+
+    #[test]
+    fn negated_flags_match_everything_else() {
+        let t = trigger(TCPField::Flags, "S").negated();
+        assert!(t.is_negated());
+        assert!(t.matches(&tcp_packet(ACK, &[])));
+        assert!(t.matches(&tcp_packet(SYN | ACK, &[])));
+        assert!(!t.matches(&tcp_packet(SYN, &[])));
+        assert_eq!(t.to_string(), "[TCP:flags:!S]");
+    }
+
+    #[test]
+    fn negated_trigger_requires_tcp_layer() {
+        let t = trigger(TCPField::Flags, "S").negated();
+        assert!(!t.matches(&Packet::new(vec![0x45, 0x00])));
+    }
+
+    #[test]
+    fn parse_empty_flags() {
+        assert_eq!(parse_tcp_flags("").unwrap(), 0);
+    }
+
+    #[test]
+    fn parse_single_flags() {
+        assert_eq!(parse_tcp_flags("F").unwrap(), 0x01);
+        assert_eq!(parse_tcp_flags("S").unwrap(), 0x02);
+        assert_eq!(parse_tcp_flags("R").unwrap(), 0x04);
+        assert_eq!(parse_tcp_flags("P").unwrap(), 0x08);
+        assert_eq!(parse_tcp_flags("A").unwrap(), 0x10);
+        assert_eq!(parse_tcp_flags("U").unwrap(), 0x20);
+        assert_eq!(parse_tcp_flags("E").unwrap(), 0x40);
+        assert_eq!(parse_tcp_flags("C").unwrap(), 0x80);
+    }
+
+    #[test]
+    fn parse_all_flags() {
+        assert_eq!(parse_tcp_flags("FSRPAUEC").unwrap(), 0xff);
+    }
+
+    #[test]
+    fn parse_flags_order_does_not_matter() {
+        assert_eq!(
+            parse_tcp_flags("SA").unwrap(),
+            parse_tcp_flags("AS").unwrap()
+        );
+        assert_eq!(
+            parse_tcp_flags("FPU").unwrap(),
+            parse_tcp_flags("UPF").unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_invalid_flags() {
+        assert!(parse_tcp_flags("SX").is_err());
+        assert!(parse_tcp_flags("s").is_err());
+    }
+
+    #[test]
+    fn flags_match_regardless_of_order() {
+        assert!(trigger(TCPField::Flags, "AS").matches(&tcp_packet(SYN | ACK, &[])));
+    }
+
+    #[test]
+    fn numeric_fields_match() {
+        let pkt = tcp_packet(SYN, &[]);
+        assert!(trigger(TCPField::SourcePort, "12345").matches(&pkt));
+        assert!(trigger(TCPField::DestPort, "80").matches(&pkt));
+        assert!(trigger(TCPField::Seq, "1000").matches(&pkt));
+        assert!(trigger(TCPField::Ack, "0").matches(&pkt));
+        assert!(trigger(TCPField::Window, "65535").matches(&pkt));
+        assert!(trigger(TCPField::DataOffset, "5").matches(&pkt));
+
+        assert!(!trigger(TCPField::DestPort, "443").matches(&pkt));
+    }
+
+    #[test]
+    fn numeric_comparisons() {
+        let pkt = tcp_packet(SYN, &[]);
+        let compare = |field, op, value| {
+            trigger(field, value)
+                .with_comparison(Comparison::from_str(op).unwrap())
+                .unwrap()
+        };
+        assert!(compare(TCPField::DestPort, "<", "1024").matches(&pkt));
+        assert!(compare(TCPField::SourcePort, ">=", "12345").matches(&pkt));
+        assert!(!compare(TCPField::SourcePort, ">", "12345").matches(&pkt));
+        assert!(compare(TCPField::Seq, "<=", "1000").matches(&pkt));
+        assert!(!compare(TCPField::Window, "<", "65535").matches(&pkt));
+
+        let t = trigger(TCPField::Flags, "S");
+        assert!(t.with_comparison(Comparison::Greater).is_err());
+    }
+
+    /// Builds an IPv4 SYN from 10.0.0.1:12345 to 10.0.0.2:80 with the given TCP options, which
+    /// must be padded to a multiple of four bytes.
+    fn syn_with_options(options: &[u8]) -> Packet {
+        let mut bytes = tcp_packet(SYN, &[]).as_slice().to_vec();
+        bytes.extend_from_slice(options);
+        let total_len = bytes.len() as u16;
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes[32] = ((20 + options.len() as u8) / 4) << 4;
+        Packet::new(bytes)
+    }
+
+    #[test]
+    fn mss_option_matches() {
+        let pkt = syn_with_options(&[2, 4, 0x05, 0xb4]);
+        assert!(trigger(TCPField::OptionMSS, "1460").matches(&pkt));
+        assert!(!trigger(TCPField::OptionMSS, "1400").matches(&pkt));
+        assert!(!trigger(TCPField::OptionWScale, "0").matches(&pkt));
+        assert!(trigger(TCPField::OptionMSS, "1400")
+            .with_comparison(Comparison::Greater)
+            .unwrap()
+            .matches(&pkt));
+    }
+
+    #[test]
+    fn options_after_padding_match() {
+        // MSS, SACK permitted, timestamps, NOP, and window scale, as sent by Linux.
+        let pkt = syn_with_options(&[
+            2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0x00, 0x01, 0x02, 0x03, 0, 0, 0, 0, 1, 3, 3, 7,
+        ]);
+        assert!(trigger(TCPField::OptionMSS, "1460").matches(&pkt));
+        assert!(trigger(TCPField::OptionTimestamp, "66051").matches(&pkt));
+        assert!(trigger(TCPField::OptionWScale, "7").matches(&pkt));
+    }
+
+    #[test]
+    fn absent_or_malformed_options_do_not_match() {
+        let t = trigger(TCPField::OptionMSS, "1460");
+        assert!(!t.matches(&tcp_packet(SYN, &[])));
+
+        // The end-of-option-list option hides anything after it.
+        assert!(!t.matches(&syn_with_options(&[0, 0, 0, 0, 2, 4, 0x05, 0xb4])));
+
+        // An MSS option with the wrong length, and one that runs off the end of the header.
+        assert!(!t.matches(&syn_with_options(&[2, 3, 0x05, 1])));
+        assert!(!t.matches(&syn_with_options(&[1, 1, 2, 8])));
+    }
+
+    #[test]
+    fn empty_value_matches_option_presence() {
+        let with_sackok = syn_with_options(&[2, 4, 0x05, 0xb4, 4, 2, 1, 1]);
+        let without = syn_with_options(&[2, 4, 0x05, 0xb4]);
+
+        let t = trigger(TCPField::OptionSackOk, "");
+        assert!(t.matches(&with_sackok));
+        assert!(!t.matches(&without));
+        assert!(!t.matches(&tcp_packet(SYN, &[])));
+
+        let t = t.negated();
+        assert!(!t.matches(&with_sackok));
+        assert!(t.matches(&without));
+
+        // Options with a value match whatever the value is.
+        assert!(trigger(TCPField::OptionMSS, "").matches(&without));
+        assert!(!trigger(TCPField::OptionWScale, "").matches(&without));
+        assert!(trigger(TCPField::OptionNOP, "").matches(&with_sackok));
+        assert!(!trigger(TCPField::OptionNOP, "").matches(&without));
+
+        // Other fields still need a value, and comparisons need one too.
+        assert!(TCPTrigger::new(TCPField::Window, "".to_string(), 0).is_err());
+        assert!(TCPTrigger::new(TCPField::Flags, "".to_string(), 0).is_err());
+        assert!(TCPTrigger::new(TCPField::Payload, "".to_string(), 0).is_err());
+        assert!(trigger(TCPField::OptionMSS, "")
+            .with_comparison(Comparison::Less)
+            .is_err());
+    }
+
+    #[test]
+    fn find_options() {
+        let header = syn_with_options(&[1, 1, 4, 2, 2, 4, 0x05, 0xb4])
+            .tcp_header()
+            .unwrap()
+            .to_vec();
+        assert_eq!(find_tcp_option(&header, 1), Some(&[][..]));
+        assert_eq!(find_tcp_option(&header, 4), Some(&[][..]));
+        assert_eq!(find_tcp_option(&header, 2), Some(&[0x05, 0xb4][..]));
+        assert_eq!(find_tcp_option(&header, 3), None);
+        assert_eq!(find_tcp_option(&header[..20], 2), None);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (field, value) in [
+            (TCPField::Flags, "SX"),
+            (TCPField::Flags, "s"),
+            (TCPField::SourcePort, "65536"),
+            (TCPField::DestPort, "http"),
+            (TCPField::Seq, "4294967296"),
+            (TCPField::DataOffset, "16"),
+            (TCPField::Reserved, "8"),
+            (TCPField::OptionMSS, "65536"),
+            (TCPField::OptionWScale, "256"),
+            (TCPField::OptionTimestamp, "4294967296"),
+        ] {
+            let result = TCPTrigger::new(field.clone(), value.to_string(), 0);
+            assert!(
+                matches!(result, Err(Error::InvalidValue { .. })),
+                "{}:{}",
+                field,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_field_is_rejected() {
+        let result = TCPField::from_str("bogus");
+        assert!(matches!(
+            result,
+            Err(Error::UnknownField { protocol, field }) if protocol == "TCP" && field == "bogus"
+        ));
+    }
+
+    #[test]
+    fn boundary_values_are_accepted() {
+        for (field, value) in [
+            (TCPField::Flags, "FSRPAUEC"),
+            (TCPField::SourcePort, "0"),
+            (TCPField::DestPort, "65535"),
+            (TCPField::Seq, "4294967295"),
+            (TCPField::DataOffset, "15"),
+            (TCPField::Reserved, "7"),
+            (TCPField::OptionMSS, "65535"),
+            (TCPField::OptionWScale, "255"),
+            (TCPField::OptionTimestamp, "4294967295"),
+            (TCPField::Payload, "anything"),
+        ] {
+            let result = TCPTrigger::new(field.clone(), value.to_string(), 0);
+            assert!(result.is_ok(), "{}:{}", field, value);
+        }
+    }
+
+    #[test]
+    fn payload_matches() {
+        let pkt = tcp_packet(ACK, b"hello");
+        assert!(trigger(TCPField::Payload, "hello").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "hell").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "llo").matches(&pkt));
+        assert!(!trigger(TCPField::Payload, "help").matches(&pkt));
+        assert!(!trigger(TCPField::Payload, "hello-world").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "help").negated().matches(&pkt));
+    }
+
+    #[test]
+    fn payload_matches_http_request() {
+        let pkt = tcp_packet(
+            ACK,
+            b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        );
+        // "GET " needs an encoding, since a strategy string can't hold the space.
+        assert!(trigger(TCPField::Payload, "hex:47455420").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "b64:SG9zdDog").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "example.com").matches(&pkt));
+        assert!(!trigger(TCPField::Payload, "hex:504f535420").matches(&pkt));
+
+        // The needle is matched against the payload only, not the headers before it.
+        assert!(!trigger(TCPField::Payload, "hex:4500").matches(&pkt));
+        assert!(!trigger(TCPField::Payload, "GET").matches(&tcp_packet(ACK, b"")));
+    }
+
+    #[test]
+    fn encoded_payload_matches() {
+        let pkt = tcp_packet(ACK, &[0x16, 0x03, 0x01, 0x00, 0xff]);
+        assert!(trigger(TCPField::Payload, "hex:16030100ff").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "hex:16030100FF").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "b64:FgMBAP8=").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "hex:16030100").matches(&pkt));
+        assert!(!trigger(TCPField::Payload, "hex:160301ff").matches(&pkt));
+
+        // The prefix is what decodes the value, not the look of it.
+        let pkt = tcp_packet(ACK, b"16030100ff");
+        assert!(!trigger(TCPField::Payload, "hex:16030100ff").matches(&pkt));
+        assert!(trigger(TCPField::Payload, "16030100ff").matches(&pkt));
+
+        for value in ["hex:abc", "b64:F"] {
+            assert!(TCPTrigger::new(TCPField::Payload, value.to_string(), 0).is_err());
+        }
+    }
+
+    #[test]
+    fn non_tcp_does_not_match() {
+        let mut bytes = tcp_packet(SYN, &[]).as_slice().to_vec();
+        bytes[9] = crate::packet::PROTO_UDP;
+        assert!(!trigger(TCPField::Flags, "S").matches(&Packet::new(bytes)));
+    }
+
+    #[test]
+    fn short_packet_does_not_match() {
+        let t = trigger(TCPField::Flags, "S");
+        assert!(!t.matches(&Packet::new(vec![])));
+
+        let bytes = tcp_packet(SYN, &[]).as_slice()[..30].to_vec();
+        assert!(!t.matches(&Packet::new(bytes)));
+    }
+
+    /// Builds a segment of the same connection as [tcp_packet] with the given sequence and
+    /// acknowledgement numbers, sent by the server if `reply` is `true`.
+    fn segment(flags: u8, seq: u32, ack: u32, reply: bool) -> Packet {
+        let mut bytes = tcp_packet(flags, &[]).as_slice().to_vec();
+        if reply {
+            let (src, dst) = (bytes[12..16].to_vec(), bytes[16..20].to_vec());
+            bytes[12..16].copy_from_slice(&dst);
+            bytes[16..20].copy_from_slice(&src);
+            bytes[20..24].copy_from_slice(&[0x00, 0x50, 0x30, 0x39]);
+        }
+        bytes[24..28].copy_from_slice(&seq.to_be_bytes());
+        bytes[28..32].copy_from_slice(&ack.to_be_bytes());
+        Packet::new(bytes)
+    }
+
+    fn relative(field: TCPField, value: &str) -> TCPTrigger {
+        trigger(field, value).relative().unwrap()
+    }
+
+    #[test]
+    fn relative_seq_counts_from_the_syn() {
+        let t = relative(TCPField::Seq, "1");
+        let syn = segment(SYN, 1000, 0, false);
+        assert!(!t.matches(&syn));
+        assert!(relative(TCPField::Seq, "0").matches(&syn));
+
+        // The trigger has seen the SYN now, so the first data segment is at relative offset 1.
+        let data = segment(ACK, 1001, 0, false);
+        assert!(t.matches(&data));
+        assert!(!t.matches(&segment(ACK, 1501, 0, false)));
+
+        let t = relative(TCPField::Seq, "500").with_comparison(Comparison::Greater);
+        let t = t.unwrap();
+        t.matches(&syn);
+        assert!(!t.matches(&data));
+        assert!(t.matches(&segment(ACK, 1502, 0, false)));
+
+        // The server's segments would be counted from its own SYN, which this trigger hasn't
+        // seen, so they're matched on their absolute values instead.
+        assert!(!t.matches(&segment(ACK, 400, 0, true)));
+        assert!(t.matches(&segment(ACK, 501, 0, true)));
+    }
+
+    #[test]
+    fn relative_ack_counts_from_the_other_syn() {
+        let t = relative(TCPField::Ack, "1");
+        assert!(!t.matches(&segment(SYN | ACK, 5000, 1001, true)));
+        assert!(t.matches(&segment(ACK, 1001, 5001, false)));
+        assert!(!t.matches(&segment(ACK, 1001, 5002, false)));
+    }
+
+    #[test]
+    fn relative_without_a_syn_is_absolute() {
+        let data = segment(ACK, 1001, 0, false);
+        assert!(!relative(TCPField::Seq, "1").matches(&data));
+        assert!(relative(TCPField::Seq, "1001").matches(&data));
+        assert!(!trigger(TCPField::Seq, "1001").is_relative());
+    }
+
+    #[test]
+    fn fin_and_rst_forget_the_syn() {
+        for flags in [FIN | ACK, RST] {
+            let t = relative(TCPField::Seq, "1");
+            t.matches(&segment(SYN, 1000, 0, false));
+
+            // The segment that ends the flow is still counted from the SYN, but later ones aren't.
+            assert!(t.matches(&segment(flags, 1001, 0, false)));
+            assert!(!t.matches(&segment(ACK, 1001, 0, false)));
+        }
+    }
+
+    #[test]
+    fn syns_of_old_flows_are_forgotten() {
+        let from_port = |pkt: Packet, port: u16| {
+            let mut bytes = pkt.as_slice().to_vec();
+            bytes[20..22].copy_from_slice(&port.to_be_bytes());
+            Packet::new(bytes)
+        };
+        let t = relative(TCPField::Seq, "1");
+        for port in 0..=MAX_SYNS as u16 {
+            t.matches(&from_port(segment(SYN, 1000, 0, false), port));
+        }
+
+        // Only the first flow has been pushed out.
+        assert!(!t.matches(&from_port(segment(ACK, 1001, 0, false), 0)));
+        assert!(t.matches(&from_port(segment(ACK, 1001, 0, false), 1)));
+        assert!(t.matches(&from_port(segment(ACK, 1001, 0, false), MAX_SYNS as u16)));
+    }
+
+    #[test]
+    fn relative_needs_seq_or_ack() {
+        assert!(trigger(TCPField::Window, "1").relative().is_err());
+        assert!(trigger(TCPField::Flags, "S").relative().is_err());
+
+        let t = relative(TCPField::Seq, "1");
+        assert_eq!(t.to_string(), "[TCP:seq:rel:1]");
+        assert_eq!(t, trigger(TCPField::Seq, "1").relative().unwrap());
+    }
+
+    /// Sets the window of a TCP segment built by [tcp_packet].
+    fn with_window(pkt: Packet, window: u16) -> Packet {
+        let mut bytes = pkt.as_slice().to_vec();
+        bytes[34..36].copy_from_slice(&window.to_be_bytes());
+        Packet::new(bytes)
+    }
+
+    #[test]
+    fn effective_window_uses_the_syn_scale() {
+        let t = trigger(TCPField::EffectiveWindow, "65535")
+            .with_comparison(Comparison::Greater)
+            .unwrap();
+        assert_eq!(t.to_string(), "[TCP:effwindow:>65535]");
+        let data = segment(ACK, 1001, 0, false);
+
+        // Until the trigger has seen the SYN, windows aren't scaled.
+        assert!(!t.matches(&data));
+
+        // A SYN's own window is never scaled, even though it carries the option.
+        let syn = with_window(syn_with_options(&[1, 3, 3, 7]), 65535);
+        assert!(!t.matches(&syn));
+        assert!(t.matches(&data));
+        assert!(t.matches(&with_window(data.clone(), 1024)));
+        assert!(!t.matches(&with_window(data.clone(), 511)));
+
+        let exact = trigger(TCPField::EffectiveWindow, "131072");
+        exact.matches(&syn);
+        assert!(exact.matches(&with_window(data.clone(), 1024)));
+        assert!(!exact.matches(&with_window(data.clone(), 1023)));
+
+        // The server's segments would be scaled by the server's own SYN, which hasn't been seen.
+        assert!(!t.matches(&segment(ACK, 5001, 1001, true)));
+
+        // A new SYN for the flow without the option turns scaling off again.
+        t.matches(&tcp_packet(SYN, &[]));
+        assert!(!t.matches(&data));
+    }
+
+    #[test]
+    fn effective_window_scale_is_capped() {
+        let t = trigger(TCPField::EffectiveWindow, "16384");
+        t.matches(&syn_with_options(&[1, 3, 3, 15]));
+        assert!(t.matches(&with_window(segment(ACK, 1001, 0, false), 1)));
+
+        for value in ["-1", "4294967296", "big"] {
+            let result = TCPTrigger::new(TCPField::EffectiveWindow, value.to_string(), 0);
+            assert!(result.is_err(), "{}", value);
+        }
+        assert_eq!(
+            "effwindow".parse::<TCPField>().unwrap(),
+            TCPField::EffectiveWindow
+        );
+    }
+}
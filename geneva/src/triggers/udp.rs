@@ -0,0 +1,283 @@
+use core::fmt;
+use core::str::FromStr;
+
+use crate::encoding::decode_bytes;
+use crate::errors::*;
+use crate::prelude::*;
+use crate::triggers::{invalid_value, not_comparable, payload_contains, Comparison, Trigger};
+use crate::Packet;
+
+/// Supported fields in the UDP header that can be used for triggers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UDPField {
+    SourcePort,
+    DestPort,
+    Length,
+    Checksum,
+    Payload,
+}
+
+impl fmt::Display for UDPField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use UDPField::*;
+        match self {
+            SourcePort => "sport",
+            DestPort => "dport",
+            Length => "len",
+            Checksum => "chksum",
+            Payload => "load",
+        }
+        .fmt(f)
+    }
+}
+
+impl FromStr for UDPField {
+    type Err = Error;
+
+    /// Parses a field name, ignoring case. As with TCP, the ports can also be spelled out as
+    /// `sourceport` and `destinationport`.
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        use UDPField::*;
+        match s.to_ascii_lowercase().as_str() {
+            "sport" | "sourceport" => Ok(SourcePort),
+            "dport" | "destinationport" => Ok(DestPort),
+            "len" => Ok(Length),
+            "chksum" => Ok(Checksum),
+            "load" => Ok(Payload),
+            _ => Err(Error::UnknownField {
+                protocol: "UDP".to_string(),
+                field: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// Returns `true` if `field` holds a number, and so can be used with a [Comparison].
+fn is_numeric(field: &UDPField) -> bool {
+    use UDPField::*;
+    matches!(field, SourcePort | DestPort | Length | Checksum)
+}
+
+/// A [Trigger] that matches on the UDP layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UDPTrigger {
+    field: UDPField,
+    value: String,
+    gas: usize,
+    negate: bool,
+    comparison: Comparison,
+}
+
+impl UDPTrigger {
+    /// Creates a new `UDPTrigger`.
+    ///
+    /// Returns an error if `value` could never match `field`, such as a port larger than 65535 or
+    /// a `load` value that isn't valid in its encoding. Every field needs a value.
+    pub fn new(field: UDPField, value: String, gas: usize) -> Result<Self> {
+        let valid = !value.is_empty()
+            && match field {
+                UDPField::Payload => decode_bytes(&value).is_some(),
+                _ => value.parse::<u16>().is_ok(),
+            };
+        if !valid {
+            return Err(invalid_value("UDP", field, &value));
+        }
+
+        Ok(Self {
+            field,
+            value,
+            gas,
+            negate: false,
+            comparison: Comparison::Equal,
+        })
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Inverts the trigger, so that it matches UDP packets whose field does *not* match the value.
+    pub fn negated(mut self) -> Self {
+        self.negate = !self.negate;
+        self
+    }
+
+    /// Returns `true` if the trigger matches packets whose field does not match the value.
+    pub fn is_negated(&self) -> bool {
+        self.negate
+    }
+
+    /// Sets how the field is compared to the value. Returns an error if the field isn't a number,
+    /// unless `comparison` is [Comparison::Equal].
+    pub fn with_comparison(mut self, comparison: Comparison) -> Result<Self> {
+        let field = &self.field;
+        if comparison != Comparison::Equal && !is_numeric(field) {
+            return Err(not_comparable("UDP", field, comparison));
+        }
+        self.comparison = comparison;
+        Ok(self)
+    }
+
+    /// Returns how the field is compared to the value.
+    pub fn comparison(&self) -> Comparison {
+        self.comparison
+    }
+
+    /// Checks that a port or length fits in 16 bits and that a `load` value decodes, and that
+    /// `load` isn't compared.
+    /// See [GenevaTrigger::validate](super::GenevaTrigger::validate).
+    pub(crate) fn validate(&self) -> Result<()> {
+        Self::new(self.field.clone(), self.value.clone(), self.gas)?
+            .with_comparison(self.comparison)?;
+        Ok(())
+    }
+}
+
+impl Trigger for UDPTrigger {
+    fn protocol(&self) -> String {
+        "UDP".to_string()
+    }
+
+    fn field(&self) -> String {
+        self.field.to_string()
+    }
+
+    fn gas(&self) -> usize {
+        self.gas
+    }
+
+    fn matches(&self, pkt: &Packet) -> bool {
+        let (header, payload) = match (pkt.udp_header(), pkt.payload()) {
+            (Some(header), Some(payload)) => (header, payload),
+            _ => return false,
+        };
+
+        let u16_at = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+
+        use UDPField::*;
+        let matched = match self.field {
+            SourcePort => self.comparison.matches(u16_at(0), &self.value),
+            DestPort => self.comparison.matches(u16_at(2), &self.value),
+            Length => self.comparison.matches(u16_at(4), &self.value),
+            Checksum => self.comparison.matches(u16_at(6), &self.value),
+            Payload => payload_contains(payload, &self.value),
+        };
+
+        // A negated trigger still only applies to packets that have the layer in question.
+        matched != self.negate
+    }
+}
+
+impl fmt::Display for UDPTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gas = if self.gas > 0 {
+            format!(":{}", self.gas)
+        } else {
+            "".to_string()
+        };
+        write!(
+            f,
+            "[{}:{}:{}{}{}{}]",
+            self.protocol(),
+            self.field,
+            if self.negate { "!" } else { "" },
+            self.comparison,
+            self.value,
+            gas
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an IPv4 packet carrying a UDP datagram from 10.0.0.1:5353 to 10.0.0.2:53 with the
+    /// given payload.
+    fn udp_packet(payload: &[u8]) -> Packet {
+        let total_len = (28 + payload.len()) as u16;
+        let udp_len = (8 + payload.len()) as u16;
+        let mut bytes = vec![
+            0x45, 0x00, 0x00, 0x00, 0x00, 0x01, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2,
+        ];
+        bytes[2..4].copy_from_slice(&total_len.to_be_bytes());
+        bytes.extend_from_slice(&5353u16.to_be_bytes());
+        bytes.extend_from_slice(&53u16.to_be_bytes());
+        bytes.extend_from_slice(&udp_len.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(payload);
+        Packet::new(bytes)
+    }
+
+    fn trigger(field: UDPField, value: &str) -> UDPTrigger {
+        UDPTrigger::new(field, value.to_string(), 0).unwrap()
+    }
+
+    #[test]
+    fn field_round_trip() {
+        for name in ["sport", "dport", "len", "chksum", "load"] {
+            let field = UDPField::from_str(name);
+            assert!(field.is_ok(), "{}", name);
+            assert_eq!(field.unwrap().to_string(), name);
+        }
+        assert!(UDPField::from_str("seq").is_err());
+    }
+
+    #[test]
+    fn invalid_ports_are_rejected() {
+        assert!(UDPTrigger::new(UDPField::DestPort, "65536".to_string(), 0).is_err());
+        assert!(UDPTrigger::new(UDPField::SourcePort, "dns".to_string(), 0).is_err());
+        assert!(UDPTrigger::new(UDPField::Length, "-1".to_string(), 0).is_err());
+
+        assert!(UDPTrigger::new(UDPField::DestPort, "65535".to_string(), 0).is_ok());
+        assert!(UDPTrigger::new(UDPField::SourcePort, "0".to_string(), 0).is_ok());
+        assert!(UDPTrigger::new(UDPField::Payload, "dns".to_string(), 0).is_ok());
+    }
+
+    #[test]
+    fn dport_matches() {
+        let pkt = udp_packet(&[]);
+        assert!(trigger(UDPField::DestPort, "53").matches(&pkt));
+        assert!(!trigger(UDPField::DestPort, "5353").matches(&pkt));
+    }
+
+    #[test]
+    fn other_fields_match() {
+        let pkt = udp_packet(b"hello");
+        assert!(trigger(UDPField::SourcePort, "5353").matches(&pkt));
+        assert!(trigger(UDPField::Length, "13").matches(&pkt));
+        assert!(trigger(UDPField::Checksum, "0").matches(&pkt));
+        assert!(trigger(UDPField::Payload, "hello").matches(&pkt));
+    }
+
+    #[test]
+    fn payload_contains_value() {
+        let pkt = udp_packet(b"\x00\x01GET / HTTP/1.1\r\n");
+        assert!(trigger(UDPField::Payload, "GET").matches(&pkt));
+        assert!(trigger(UDPField::Payload, "hex:0001474554").matches(&pkt));
+        assert!(trigger(UDPField::Payload, "b64:SFRUUC8xLjE=").matches(&pkt));
+        assert!(!trigger(UDPField::Payload, "POST").matches(&pkt));
+
+        for value in ["hex:abc", "b64:F"] {
+            assert!(UDPTrigger::new(UDPField::Payload, value.to_string(), 0).is_err());
+        }
+    }
+
+    #[test]
+    fn non_udp_does_not_match() {
+        let mut bytes = udp_packet(&[]).as_slice().to_vec();
+        bytes[9] = crate::packet::PROTO_TCP;
+        assert!(!trigger(UDPField::DestPort, "53").matches(&Packet::new(bytes)));
+    }
+
+    #[test]
+    fn trigger_str() {
+        assert_eq!(
+            trigger(UDPField::DestPort, "53").to_string(),
+            "[UDP:dport:53]"
+        );
+    }
+}
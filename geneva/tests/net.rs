@@ -0,0 +1,60 @@
+//! Sends a packet over the loopback interface with a [Transmitter] and checks that it arrives.
+//!
+//! Raw sockets need privileges, so these tests do nothing when run without them.
+#![cfg(feature = "net")]
+
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use geneva::checksum::ipv4_checksum;
+use geneva::net::Transmitter;
+use geneva::Packet;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// Builds an IPv4 packet from 127.0.0.1 to itself, carrying a UDP datagram to the discard port.
+fn udp_packet(payload: &[u8]) -> Packet {
+    let total_len = (28 + payload.len()) as u16;
+    let mut bytes = vec![0x45, 0x00];
+    bytes.extend_from_slice(&total_len.to_be_bytes());
+    bytes.extend_from_slice(&[0x00, 0x01, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00]);
+    bytes.extend_from_slice(&[127, 0, 0, 1, 127, 0, 0, 1]);
+    let checksum = ipv4_checksum(&bytes);
+    bytes[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    bytes.extend_from_slice(&[0x9c, 0x40, 0x00, 0x09]);
+    bytes.extend_from_slice(&(total_len - 20).to_be_bytes());
+    bytes.extend_from_slice(&[0x00, 0x00]);
+    bytes.extend_from_slice(payload);
+    Packet::new(bytes)
+}
+
+#[test]
+fn loops_back_over_ipv4() {
+    // A raw UDP socket sees a copy of every UDP datagram the host receives.
+    let mut receiver = match Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::UDP)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("skipping loopback test: {}", e);
+            return;
+        }
+    };
+    receiver
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+
+    let payload = format!("geneva loopback test {}", std::process::id());
+    let pkt = udp_packet(payload.as_bytes());
+    Transmitter::new().send(&pkt).unwrap();
+
+    // Other UDP traffic on the host may arrive first, so look for our payload for a little while.
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut buf = [0u8; 2048];
+    while Instant::now() < deadline {
+        if let Ok(n) = receiver.read(&mut buf) {
+            if buf[..n].ends_with(payload.as_bytes()) {
+                return;
+            }
+        }
+    }
+    panic!("packet was not looped back");
+}
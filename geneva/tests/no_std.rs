@@ -0,0 +1,58 @@
+//! Builds and applies a strategy without anything from the standard library, the way a `no_std`
+//! user of the crate would. CI runs this with `--no-default-features` to make sure that the core
+//! of the crate only needs `alloc`.
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::ToString;
+use alloc::vec;
+
+use geneva::{
+    Direction, DuplicateAction, FragmentAction, Packet, SendAction, StrategyBuilder, TCPField,
+    TCPTrigger, TamperAction, TamperMode,
+};
+
+/// Builds an IPv4 packet from 10.0.0.1:12345 to 10.0.0.2:80 carrying a SYN with an 8-byte
+/// payload.
+fn syn_packet() -> Packet {
+    let mut bytes = vec![
+        0x45, 0x00, 0x00, 0x30, 0x00, 0x01, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, 10, 0, 0, 1, 10, 0,
+        0, 2, 0x30, 0x39, 0x00, 0x50, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0x00, 0x50, 0x02,
+        0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+    ];
+    bytes.extend_from_slice(b"abcdefgh");
+    Packet::new(bytes)
+}
+
+#[test]
+fn apply_strategy() {
+    let tamper = TamperAction::new(
+        "TCP".to_string(),
+        "flags".to_string(),
+        "A".to_string(),
+        TamperMode::Add,
+        SendAction::default().into(),
+    )
+    .unwrap();
+    // Split the TCP payload after four bytes, and turn the first segment into a SYN/ACK.
+    let fragment =
+        FragmentAction::new(6, 4, true, 0, tamper.into(), SendAction::default().into()).unwrap();
+    let strategy = StrategyBuilder::new()
+        .outbound(
+            TCPTrigger::new(TCPField::Flags, "S".to_string(), 0).unwrap(),
+            DuplicateAction::new(fragment.into(), SendAction::default().into()),
+        )
+        .build();
+
+    let packets = strategy.apply(syn_packet(), Direction::Outbound).unwrap();
+    assert_eq!(packets.len(), 3);
+    assert_eq!(packets[0].tcp_header().unwrap()[13], 0x12);
+    assert_eq!(packets[0].payload(), Some(&b"abcd"[..]));
+    assert_eq!(packets[1].payload(), Some(&b"efgh"[..]));
+    assert_eq!(packets[2], syn_packet());
+
+    // Packets that the trigger doesn't match pass through untouched.
+    let inbound = strategy.apply(syn_packet(), Direction::Inbound).unwrap();
+    assert_eq!(inbound, vec![syn_packet()]);
+}